@@ -0,0 +1,72 @@
+// The benchmark suite, run by `cargo bench`: each benchmark (see workload.rs) with
+// small and large values, then each YCSB workload.  `cargo bench -- <filter>`
+// only runs the ones whose name contains the filter, e.g. `cargo bench -- ycsb`;
+// the read benchmarks need a fill to run first, e.g. `cargo bench -- /100`.
+//
+// For other options, or to compare against a saved baseline, use the bench
+// binary (see src/bin/bench.rs).
+//
+// This isn't a criterion benchmark: criterion can't be fetched without network
+// access, so this is a plain `harness = false` binary, which runs each benchmark
+// once and reports no statistics (see the README's TODO).
+use std::error::Error;
+
+use lsmdb::{
+    workload::{Benchmark, BenchmarkOptions, RunStats, Workload, WorkloadDriver},
+    RawDB,
+};
+
+const NUM: u64 = 50_000;
+const VALUE_SIZES: [usize; 2] = [100, 4096];
+
+fn report(name: &str, stats: &RunStats) {
+    println!(
+        "{:<24} {:>10} ops in {:>8.3}s: {:>12.0} ops/s",
+        name,
+        stats.num_ops(),
+        stats.elapsed.as_secs_f64(),
+        stats.ops_per_second()
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // cargo passes `--bench`.
+    let filter = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or_default();
+
+    for value_size in VALUE_SIZES {
+        let options = BenchmarkOptions {
+            num: NUM,
+            value_size,
+            ..BenchmarkOptions::default()
+        };
+        let tempdir = tempdir::TempDir::new("lsmdb_bench")?;
+        let mut db = RawDB::open(tempdir.path())?;
+        // Each benchmark runs on the database the ones before it filled.
+        for benchmark in Benchmark::ALL {
+            let name = format!("{}/{}", benchmark.name(), value_size);
+            if name.contains(&filter) {
+                report(&name, &benchmark.run(&mut db, &options)?);
+            }
+        }
+    }
+
+    let options = BenchmarkOptions {
+        num: NUM,
+        ..BenchmarkOptions::default()
+    };
+    for name in ["a", "b", "c", "d", "e", "f"] {
+        let name_with_prefix = format!("ycsb-{}", name);
+        if !name_with_prefix.contains(&filter) {
+            continue;
+        }
+        let tempdir = tempdir::TempDir::new("lsmdb_bench")?;
+        let mut db = RawDB::open(tempdir.path())?;
+        let mut driver = WorkloadDriver::new(Workload::ycsb(name).unwrap(), &options)?;
+        driver.load(&mut db)?;
+        report(&name_with_prefix, &driver.run(&mut db, NUM)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::{
+    alloc::{self, Layout},
+    mem::size_of,
+    ptr::{self, NonNull},
+};
+
+// Hands out memory from big blocks, rather than going to the global allocator for
+// every allocation; everything is freed at once when the arena is dropped.  The
+// skiplist memtable (see memtable.rs) keeps its nodes, keys and values in one.
+//
+// Values put in the arena are never dropped, so they must not own anything
+// outside of it.
+pub(crate) struct Arena {
+    block_size: usize,
+    // Free space in the current block.
+    next: *mut u8,
+    remaining: usize,
+    blocks: Vec<(NonNull<u8>, Layout)>,
+    memory_usage: usize,
+}
+
+// Blocks are aligned for any value the memtable puts in them.
+const BLOCK_ALIGN: usize = 8;
+
+impl Arena {
+    pub fn new(block_size: usize) -> Self {
+        Arena {
+            block_size: block_size.max(BLOCK_ALIGN),
+            next: ptr::null_mut(),
+            remaining: 0,
+            blocks: Vec::new(),
+            memory_usage: 0,
+        }
+    }
+
+    // Bytes allocated from the global allocator, including the bookkeeping of the
+    // blocks and their unused space.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    // Returns uninitialized memory for `layout`, which lives as long as the arena.
+    pub fn alloc(&mut self, layout: Layout) -> NonNull<u8> {
+        if layout.size() == 0 {
+            return NonNull::new(ptr::without_provenance_mut(layout.align()))
+                .expect("alignments aren't zero");
+        }
+        let padding = self.next.align_offset(layout.align());
+        if padding.saturating_add(layout.size()) <= self.remaining {
+            // SAFETY: the allocation fits in the current block.
+            let allocation = unsafe { self.next.add(padding) };
+            self.next = unsafe { allocation.add(layout.size()) };
+            self.remaining -= padding + layout.size();
+            return NonNull::new(allocation).expect("arena blocks aren't null");
+        }
+
+        // Big allocations get a block of their own, so the rest of the current
+        // block isn't wasted.
+        if layout.size() > self.block_size / 4 {
+            return self.alloc_block(layout.size(), layout.align());
+        }
+        let block = self.alloc_block(self.block_size, layout.align().max(BLOCK_ALIGN));
+        // SAFETY: the block is bigger than the allocation.
+        self.next = unsafe { block.as_ptr().add(layout.size()) };
+        self.remaining = self.block_size - layout.size();
+        block
+    }
+
+    // Moves `value` into the arena.
+    pub fn alloc_value<T>(&mut self, value: T) -> NonNull<T> {
+        let allocation = self.alloc(Layout::new::<T>()).cast::<T>();
+        // SAFETY: the allocation is fit for a T, and not used by anything else.
+        unsafe { allocation.as_ptr().write(value) };
+        allocation
+    }
+
+    // Copies `bytes` into the arena.
+    pub fn alloc_bytes(&mut self, bytes: &[u8]) -> NonNull<u8> {
+        let allocation = self.alloc(Layout::for_value(bytes));
+        // SAFETY: the allocation is `bytes.len()` long, and not used by anything else.
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), allocation.as_ptr(), bytes.len()) };
+        allocation
+    }
+
+    fn alloc_block(&mut self, size: usize, align: usize) -> NonNull<u8> {
+        let layout = Layout::from_size_align(size.max(1), align).expect("arena block is too big");
+        // SAFETY: the layout isn't zero-sized.
+        let block = NonNull::new(unsafe { alloc::alloc(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        self.blocks.push((block, layout));
+        self.memory_usage += layout.size() + size_of::<(NonNull<u8>, Layout)>();
+        block
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for (block, layout) in self.blocks.drain(..) {
+            // SAFETY: the block was allocated with this layout by `alloc_block()`.
+            unsafe { alloc::dealloc(block.as_ptr(), layout) };
+        }
+    }
+}
+
+// The blocks are only reached through the arena, or through the references it
+// handed out.
+unsafe impl Send for Arena {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arena() {
+        let mut arena = Arena::new(1024);
+        assert_eq!(arena.memory_usage(), 0);
+
+        let first = arena.alloc_bytes(b"hello");
+        let block_usage = arena.memory_usage();
+        assert!(block_usage > 1024);
+        // Small allocations share the block, and are aligned.
+        let number = arena.alloc_value(42u64);
+        assert_eq!(number.as_ptr() as usize % 8, 0);
+        assert_eq!(arena.memory_usage(), block_usage);
+
+        // Big allocations get their own block.
+        let big = arena.alloc_bytes(&[7; 2048]);
+        assert!(arena.memory_usage() > block_usage + 2048);
+        unsafe {
+            assert_eq!(std::slice::from_raw_parts(first.as_ptr(), 5), b"hello");
+            assert_eq!(*number.as_ptr(), 42);
+            assert_eq!(std::slice::from_raw_parts(big.as_ptr(), 2048), &[7; 2048]);
+        }
+
+        // A full block is replaced by a new one.
+        for _ in 0..200 {
+            arena.alloc_value(0u64);
+        }
+        assert!(arena.memory_usage() > 2 * 1024 + 2048);
+    }
+}
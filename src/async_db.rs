@@ -0,0 +1,283 @@
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread::JoinHandle,
+};
+
+use crate::{
+    db::{DBError, Key, ReadOptions, Value, DB},
+    verify::IntegrityReport,
+    write_batch::WriteBatchWithIndex,
+};
+
+// An async front-end to a DB, for use inside async services.
+//
+// Every call runs on a pool of worker threads, since the database does blocking
+// file I/O, and resolves once it's done.  The futures don't depend on any
+// particular runtime, so they can be awaited from tokio, async-std or a simple
+// executor alike.  Calls are queued in order, but with more than one worker
+// thread, calls which weren't awaited one after the other may run in any order.
+pub struct AsyncDB {
+    db: Arc<DB>,
+    // Dropping the sender lets the workers exit once the queue is empty.
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+impl AsyncDB {
+    pub fn new(db: DB, num_threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..num_threads.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                std::thread::spawn(move || loop {
+                    // Only hold the lock while waiting for a job, not while running it.
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => return,
+                    }
+                })
+            })
+            .collect();
+        AsyncDB {
+            db: Arc::new(db),
+            jobs: Some(sender),
+            workers,
+        }
+    }
+
+    // The database, for calls which don't block (e.g. `stats()`).
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    pub async fn get(&self, key: impl Into<Key>) -> Result<Option<Value>, DBError> {
+        let key = key.into();
+        self.run(move |db| db.get(&key)).await
+    }
+
+    pub async fn contains_key(&self, key: impl Into<Key>) -> Result<bool, DBError> {
+        let key = key.into();
+        self.run(move |db| db.contains_key(&key)).await
+    }
+
+    pub async fn put(&self, key: impl Into<Key>, value: impl Into<Value>) -> Result<(), DBError> {
+        let (key, value) = (key.into(), value.into());
+        self.run(move |db| db.put(key, value)).await
+    }
+
+    // Applies all of the batch's writes or none; see `RawDB::write()`.
+    pub async fn write(&self, batch: WriteBatchWithIndex) -> Result<(), DBError> {
+        self.run(move |db| db.write(batch)).await
+    }
+
+    pub async fn delete(&self, key: impl Into<Key>) -> Result<(), DBError> {
+        let key = key.into();
+        self.run(move |db| db.delete(key)).await
+    }
+
+    pub async fn delete_range(
+        &self,
+        start: impl Into<Key>,
+        end: impl Into<Key>,
+    ) -> Result<(), DBError> {
+        let (start, end) = (start.into(), end.into());
+        self.run(move |db| db.delete_range(start, end)).await
+    }
+
+    pub async fn delete_prefix(&self, prefix: impl Into<Key>) -> Result<(), DBError> {
+        let prefix = prefix.into();
+        self.run(move |db| db.delete_prefix(&prefix)).await
+    }
+
+    pub async fn seek(&self, key_prefix: impl Into<Key>) -> Result<Vec<(Key, Value)>, DBError> {
+        let key_prefix = key_prefix.into();
+        self.run(move |db| db.seek(&key_prefix)).await
+    }
+
+    // Pages through the keys with `options.limit`.
+    pub async fn scan_prefix(
+        &self,
+        key_prefix: impl Into<Key>,
+        options: ReadOptions,
+    ) -> Result<Vec<(Key, Option<Value>)>, DBError> {
+        let key_prefix = key_prefix.into();
+        self.run(move |db| db.scan_prefix(&key_prefix, &options))
+            .await
+    }
+
+    pub async fn keys(
+        &self,
+        key_prefix: impl Into<Key>,
+        options: ReadOptions,
+    ) -> Result<Vec<Key>, DBError> {
+        let key_prefix = key_prefix.into();
+        self.run(move |db| db.keys(&key_prefix, &options)).await
+    }
+
+    pub async fn flush(&self) -> Result<(), DBError> {
+        self.run(|db| db.flush()).await
+    }
+
+    pub async fn compact(&self) -> Result<(), DBError> {
+        self.run(|db| db.compact()).await
+    }
+
+    pub async fn compact_range(
+        &self,
+        start: impl Into<Key>,
+        end: impl Into<Key>,
+    ) -> Result<(), DBError> {
+        let (start, end) = (start.into(), end.into());
+        self.run(move |db| db.compact_range(&start, &end)).await
+    }
+
+    pub async fn create_backup(&self, backup_path: impl Into<PathBuf>) -> Result<usize, DBError> {
+        let backup_path = backup_path.into();
+        self.run(move |db| db.create_backup(&backup_path)).await
+    }
+
+    pub async fn verify_integrity(&self) -> Result<IntegrityReport, DBError> {
+        self.run(|db| db.verify_integrity()).await
+    }
+
+    // Queues `f` to run on a worker thread, and returns a future of its result.
+    fn run<T: Send + 'static>(&self, f: impl FnOnce(&DB) -> T + Send + 'static) -> WorkerFuture<T> {
+        let state = Arc::new(Mutex::new(WorkerState {
+            result: None,
+            waker: None,
+        }));
+        let job_state = state.clone();
+        let db = self.db.clone();
+        let job: Job = Box::new(move || {
+            // A panic is passed on to whoever awaits the result.
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| f(&db)));
+            let mut state = job_state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        self.jobs
+            .as_ref()
+            .expect("workers are running")
+            .send(job)
+            .expect("workers are running");
+        WorkerFuture { state }
+    }
+}
+
+impl Drop for AsyncDB {
+    // Waits for the queued calls to finish.
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct WorkerState<T> {
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+// Resolves to the result of a job once a worker thread ran it.
+struct WorkerFuture<T> {
+    state: Arc<Mutex<WorkerState<T>>>,
+}
+
+impl<T> Future for WorkerFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::Arc,
+        task::Wake,
+        thread::{self, Thread},
+    };
+
+    // Runs a future to completion on the current thread.
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        struct ThreadWaker(Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(result) => return result,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn async_db() -> anyhow::Result<()> {
+        let db = AsyncDB::new(DB::open_in_memory()?, 2);
+        block_on(async {
+            for i in 0..10 {
+                db.put(format!("/key/{}", i), format!("{}", i)).await?;
+            }
+            db.delete("/key/3").await?;
+            db.flush().await?;
+            assert_eq!(db.get("/key/4").await?, Some(b"4".to_vec()));
+            assert_eq!(db.get("/key/3").await?, None);
+
+            let page = db
+                .scan_prefix(
+                    "/key/",
+                    ReadOptions {
+                        limit: Some(3),
+                        ..ReadOptions::default()
+                    },
+                )
+                .await?;
+            let keys: Vec<&str> = page.iter().map(|(key, _)| key.as_str()).collect();
+            assert_eq!(keys, vec!["/key/0", "/key/1", "/key/2"]);
+
+            let mut batch = WriteBatchWithIndex::new();
+            batch.put("/batch/a", "1");
+            batch.delete("/key/0");
+            db.write(batch).await?;
+            assert!(db.contains_key("/batch/a").await?);
+            assert!(!db.contains_key("/key/0").await?);
+            assert_eq!(
+                db.seek("/batch/").await?,
+                vec![("/batch/a".to_string(), b"1".to_vec())]
+            );
+            db.delete_prefix("/batch/").await?;
+            assert_eq!(db.keys("/batch/", ReadOptions::default()).await?.len(), 0);
+            Ok::<(), DBError>(())
+        })?;
+        assert_eq!(db.db().stats().num_flushes, 1);
+        Ok(())
+    }
+}
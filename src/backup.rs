@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    blob,
+    manifest::{self, Manifest},
+    storage::Storage,
+};
+
+// Backups
+// =======
+//
+// A backup directory holds a copy of a database's sstables and blob files along
+// with its MANIFEST, and can be opened like any other database.
+//
+// SSTables and blob files never change once written, and their file numbers are
+// never reused, so a file which is already in the backup directory is skipped.
+// This makes backing up into the same directory again incremental.
+
+// Copies the sstables and blob files listed in `manifest` from `root_path` into
+// `backup_path`, then writes the MANIFEST, and finally deletes the files the
+// backup no longer needs.  Returns the number of files copied.
+//
+// Files are copied with Storage::link_or_copy(), so they're hard linked where
+// possible.
+pub(crate) fn copy_database(
+    storage: &dyn Storage,
+    root_path: &Path,
+    manifest: &Manifest,
+    backup_path: &Path,
+) -> Result<usize, std::io::Error> {
+    storage.create_dir_all(backup_path)?;
+
+    let file_names: Vec<PathBuf> = manifest
+        .levels
+        .iter()
+        .flatten()
+        .map(|&file_number| manifest::sstable_path(Path::new(""), file_number))
+        .chain(
+            manifest
+                .blob_files
+                .iter()
+                .map(|&file_number| blob::blob_path(Path::new(""), file_number)),
+        )
+        .collect();
+    let mut num_copied = 0;
+    for file_name in &file_names {
+        let source = root_path.join(file_name);
+        let destination = backup_path.join(file_name);
+        if storage.exists(&destination)?
+            && storage.file_size(&destination)? == storage.file_size(&source)?
+        {
+            continue;
+        }
+        storage.link_or_copy(&source, &destination)?;
+        num_copied += 1;
+    }
+    manifest.write_to(storage, backup_path)?;
+
+    for path in storage.list_dir(backup_path)? {
+        let is_data_file = manifest::sstable_file_number(&path).is_some()
+            || blob::blob_file_number(&path).is_some();
+        if is_data_file
+            && !path
+                .file_name()
+                .is_some_and(|name| file_names.iter().any(|file_name| file_name == name))
+        {
+            storage.remove_file(&path)?;
+        }
+    }
+    Ok(num_copied)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::FileStorage;
+    use std::fs::DirBuilder;
+    use tempdir::TempDir;
+
+    #[test]
+    fn copy_database_incrementally() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let root_path = tempdir.path().join("db");
+        let backup_path = tempdir.path().join("backup");
+        DirBuilder::new()
+            .create(&root_path)
+            .expect("couldnt make db dir");
+        for file_number in 0..3 {
+            std::fs::write(manifest::sstable_path(&root_path, file_number), [0u8; 8])
+                .expect("couldnt write sstable");
+        }
+        std::fs::write(blob::blob_path(&root_path, 3), [0u8; 8]).expect("couldnt write blob");
+
+        let manifest = Manifest {
+            next_file_number: 3,
+            levels: vec![vec![0, 1]],
+            blob_files: vec![],
+            incarnation: 0,
+        };
+        assert_eq!(
+            copy_database(&FileStorage, &root_path, &manifest, &backup_path)
+                .expect("couldnt back up"),
+            2
+        );
+
+        let manifest = Manifest {
+            next_file_number: 3,
+            levels: vec![vec![1], vec![2]],
+            blob_files: vec![3],
+            incarnation: 0,
+        };
+        assert_eq!(
+            copy_database(&FileStorage, &root_path, &manifest, &backup_path)
+                .expect("couldnt back up"),
+            2
+        );
+        assert_eq!(
+            Manifest::read_from(&FileStorage, &backup_path).expect("couldnt read manifest"),
+            Some(manifest)
+        );
+        assert!(!manifest::sstable_path(&backup_path, 0).exists());
+        assert!(manifest::sstable_path(&backup_path, 1).exists());
+        assert!(manifest::sstable_path(&backup_path, 2).exists());
+        assert!(blob::blob_path(&backup_path, 3).exists());
+    }
+}
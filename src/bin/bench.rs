@@ -0,0 +1,165 @@
+// A simple benchmark driver.
+//
+// Usage: bench [--db <path>] [--num <keys>] [--value-size <bytes>]
+//              [--compression-ratio <ratio>] [--distribution sequential|uniform|zipfian]
+//              [--seed <seed>] [--scan-length <keys>]
+//              [--benchmarks <name>,..] [--workload a|b|c|d|e|f]
+//              [--save <file>] [--baseline <file>] [--max-regression <fraction>]
+//
+// Runs the benchmarks (see workload.rs) in order against one database, by default
+// fillseq,readrandom; or with `--workload`, loads `num` keys and runs `num`
+// operations of that YCSB workload.  Reports how many operations per second each
+// ran at.
+//
+// `--save` writes the results to a file, and `--baseline` compares them with a
+// file saved earlier: if any benchmark got slower by more than `--max-regression`
+// (0.1 by default), it fails, to catch performance regressions.
+use std::{collections::BTreeMap, error::Error, path::PathBuf, str::FromStr};
+
+use lsmdb::{
+    generators::KeyDistribution,
+    workload::{Benchmark, BenchmarkOptions, RunStats, Workload, WorkloadDriver},
+    RawDB,
+};
+
+struct Options {
+    db_path: Option<PathBuf>,
+    benchmark_options: BenchmarkOptions,
+    benchmarks: Vec<Benchmark>,
+    workload: Option<(String, Workload)>,
+    save_path: Option<PathBuf>,
+    baseline_path: Option<PathBuf>,
+    max_regression: f64,
+}
+
+fn parse<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        db_path: None,
+        benchmark_options: BenchmarkOptions::default(),
+        benchmarks: vec![Benchmark::FillSeq, Benchmark::ReadRandom],
+        workload: None,
+        save_path: None,
+        baseline_path: None,
+        max_regression: 0.1,
+    };
+    let benchmark_options = &mut options.benchmark_options;
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--db" => options.db_path = Some(PathBuf::from(&value)),
+            "--num" => benchmark_options.num = parse(&flag, &value)?,
+            "--value-size" => benchmark_options.value_size = parse(&flag, &value)?,
+            "--compression-ratio" => benchmark_options.compression_ratio = parse(&flag, &value)?,
+            "--seed" => benchmark_options.seed = parse(&flag, &value)?,
+            "--scan-length" => benchmark_options.scan_length = parse(&flag, &value)?,
+            "--distribution" => {
+                benchmark_options.distribution = match value.as_str() {
+                    "sequential" => KeyDistribution::Sequential,
+                    "uniform" => KeyDistribution::Uniform,
+                    "zipfian" => KeyDistribution::Zipfian { theta: 0.99 },
+                    _ => return Err(format!("invalid value for {}: {}", flag, value)),
+                }
+            }
+            "--benchmarks" => {
+                options.benchmarks = value.split(',').map(str::parse).collect::<Result<_, _>>()?
+            }
+            "--workload" => {
+                let workload = Workload::ycsb(&value)
+                    .ok_or_else(|| format!("invalid value for {}: {}", flag, value))?;
+                options.workload = Some((format!("ycsb-{}", value), workload));
+            }
+            "--save" => options.save_path = Some(PathBuf::from(&value)),
+            "--baseline" => options.baseline_path = Some(PathBuf::from(&value)),
+            "--max-regression" => options.max_regression = parse(&flag, &value)?,
+            _ => return Err(format!("unknown flag {}", flag)),
+        }
+    }
+    Ok(options)
+}
+
+fn report(name: &str, stats: &RunStats) {
+    println!(
+        "{:<12} {:>10} ops in {:>8.3}s: {:>12.0} ops/s",
+        name,
+        stats.num_ops(),
+        stats.elapsed.as_secs_f64(),
+        stats.ops_per_second()
+    );
+    if stats.reads > 0 {
+        println!(
+            "{:<12} {} of {} keys found",
+            "", stats.reads_found, stats.reads
+        );
+    }
+}
+
+// Results are saved one benchmark per line: "<name> <ops per second>".
+fn load_results(path: &PathBuf) -> Result<BTreeMap<String, f64>, Box<dyn Error>> {
+    let mut results = BTreeMap::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        if let Some((name, ops_per_second)) = line.split_once(' ') {
+            results.insert(name.to_string(), parse(name, ops_per_second)?);
+        }
+    }
+    Ok(results)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = parse_options()?;
+    let tempdir = tempdir::TempDir::new("lsmdb_bench")?;
+    let db_path = options
+        .db_path
+        .clone()
+        .unwrap_or_else(|| tempdir.path().to_path_buf());
+    let mut db = RawDB::open(&db_path)?;
+
+    let mut results = Vec::new();
+    if let Some((name, workload)) = &options.workload {
+        let mut driver = WorkloadDriver::new(workload.clone(), &options.benchmark_options)?;
+        let stats = driver.load(&mut db)?;
+        report("load", &stats);
+        let stats = driver.run(&mut db, options.benchmark_options.num)?;
+        report(name, &stats);
+        results.push((name.clone(), stats.ops_per_second()));
+    } else {
+        for benchmark in &options.benchmarks {
+            let stats = benchmark.run(&mut db, &options.benchmark_options)?;
+            report(benchmark.name(), &stats);
+            results.push((benchmark.name().to_string(), stats.ops_per_second()));
+        }
+    }
+
+    if let Some(save_path) = &options.save_path {
+        let lines: String = results
+            .iter()
+            .map(|(name, ops_per_second)| format!("{} {:.0}\n", name, ops_per_second))
+            .collect();
+        std::fs::write(save_path, lines)?;
+    }
+    if let Some(baseline_path) = &options.baseline_path {
+        let baseline = load_results(baseline_path)?;
+        let mut regressions = Vec::new();
+        for (name, ops_per_second) in &results {
+            if let Some(&baseline_ops_per_second) = baseline.get(name) {
+                let change = ops_per_second / baseline_ops_per_second - 1.0;
+                println!("{:<12} {:>+7.1}% from the baseline", name, change * 100.0);
+                if change < -options.max_regression {
+                    regressions.push(name.as_str());
+                }
+            }
+        }
+        if !regressions.is_empty() {
+            return Err(format!("regressed: {}", regressions.join(", ")).into());
+        }
+    }
+    Ok(())
+}
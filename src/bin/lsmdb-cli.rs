@@ -0,0 +1,126 @@
+// A command line tool for looking into and changing a database.
+//
+// Usage: lsmdb-cli <db path> get <key>
+//        lsmdb-cli <db path> put <key> <value>
+//        lsmdb-cli <db path> delete <key>
+//        lsmdb-cli <db path> scan <prefix> [--limit <n>]
+//        lsmdb-cli <db path> stats
+//        lsmdb-cli <db path> compact [<start key> <end key>]
+//        lsmdb-cli <db path> verify
+//        lsmdb-cli dump-sst <sstable path>
+//
+// Values are printed with non-printable bytes escaped.  The database must not be
+// open in another process, and must exist: a mistyped path isn't created.
+use std::{error::Error, path::Path};
+
+use lsmdb::{EntryValue, RawDB, ReadOptions, SstReader};
+
+const USAGE: &str = "usage: lsmdb-cli <db path> get <key>
+       lsmdb-cli <db path> put <key> <value>
+       lsmdb-cli <db path> delete <key>
+       lsmdb-cli <db path> scan <prefix> [--limit <n>]
+       lsmdb-cli <db path> stats
+       lsmdb-cli <db path> compact [<start key> <end key>]
+       lsmdb-cli <db path> verify
+       lsmdb-cli dump-sst <sstable path>";
+
+fn dump_sst(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut reader = SstReader::open(path)?;
+    println!("file size: {}", reader.file_size());
+    println!("entries: {}", reader.num_entries());
+    if let Some((smallest, largest)) = reader.key_range() {
+        println!("key range: {} .. {}", smallest, largest);
+    }
+    println!("prefix filter: {}", reader.has_prefix_filter());
+    for block in reader.blocks() {
+        println!(
+            "block at {}: {} bytes, {} entries, last key {}",
+            block.offset, block.size, block.num_entries, block.last_key
+        );
+    }
+    for (start, end) in reader.range_tombstones() {
+        println!("range tombstone: [{}, {})", start, end);
+    }
+    match reader.verify() {
+        Ok(()) => println!("verified"),
+        Err(err) => println!("verification failed: {}", err),
+    }
+    for entry in reader.entries() {
+        match entry? {
+            (key, EntryValue::Present(value)) => println!("{}\t{}", key, value.escape_ascii()),
+            (key, EntryValue::Deleted) => println!("{}\t(deleted)", key),
+            (key, EntryValue::Blob(blob_ref)) => println!("{}\t({:?})", key, blob_ref),
+            (key, entry) => println!("{}\t({:?})", key, entry),
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let ["dump-sst", path] = args[..] {
+        return dump_sst(Path::new(path));
+    }
+    let [db_path, command, ref command_args @ ..] = args[..] else {
+        return Err(USAGE.into());
+    };
+    let db_path = Path::new(db_path);
+    if !db_path.is_dir() {
+        return Err(format!("{} is not a database directory", db_path.display()).into());
+    }
+    let mut db = RawDB::open(db_path)?;
+    match (command, command_args) {
+        ("get", [key]) => match db.get(key)? {
+            Some(value) => println!("{}", value.escape_ascii()),
+            None => return Err(format!("{} not found", key).into()),
+        },
+        // There is no log yet, so writes are flushed before exiting.
+        ("put", [key, value]) => {
+            db.put(*key, value.as_bytes())?;
+            db.flush()?;
+        }
+        ("delete", [key]) => {
+            db.delete(*key)?;
+            db.flush()?;
+        }
+        ("scan", [prefix, scan_args @ ..]) => {
+            let options = match scan_args {
+                [] => ReadOptions::default(),
+                ["--limit", limit] => ReadOptions::default().with_limit(limit.parse()?),
+                _ => return Err(USAGE.into()),
+            };
+            for (key, value) in db.scan_prefix(prefix, &options)? {
+                println!("{}\t{}", key, value.unwrap_or_default().escape_ascii());
+            }
+        }
+        ("stats", []) => {
+            println!("{:#?}", db.stats());
+            println!("{:#?}", db.space_report()?);
+        }
+        ("compact", []) => db.compact()?,
+        ("compact", [start, end]) => db.compact_range(start, end)?,
+        ("verify", []) => {
+            let report = db.verify_integrity()?;
+            println!(
+                "checked {} sstables and {} blob files",
+                report.num_sstables_checked, report.num_blob_files_checked
+            );
+            for problem in &report.problems {
+                println!("{:?}", problem);
+            }
+            if !report.is_ok() {
+                return Err(format!("found {} problems", report.problems.len()).into());
+            }
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    db::{EntryValue, Value},
+    manifest,
+    storage::{RandomAccessReader, Storage, WritableFile},
+};
+
+// Blob files
+// ==========
+//
+// Values of at least DBConfig::min_blob_size bytes are kept out of the sstables:
+// when a memtable is flushed, they are appended to a blob file, and the sstable
+// stores a reference to the value instead.  Compactions only copy the references,
+// so a large value is written to disk once, however many times its key is
+// compacted.
+//
+// - Blob files are named `<file number>.blob`, and share file numbers with the
+//   sstables.
+// - A blob file is the values written one after another; the references say where
+//   each value starts and how long it is.
+// - Blob files never change once written.  The MANIFEST lists the database's
+//   blob files.
+//
+// Blob reference encoding (stored as the value of a blob entry in an sstable):
+// ---------------------------------------
+// - blob file number (u64; LE)
+// - offset of the value in the blob file (u64; LE)
+// - value size (u64; LE)
+pub(crate) const BLOB_REF_SIZE: usize = 3 * size_of::<u64>();
+
+// Where a value stored in a blob file is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlobRef {
+    pub(crate) file_number: u64,
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+impl BlobRef {
+    pub(crate) fn encode(&self) -> [u8; BLOB_REF_SIZE] {
+        let mut bytes = [0u8; BLOB_REF_SIZE];
+        bytes[0..8].copy_from_slice(&self.file_number.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Option<BlobRef> {
+        if bytes.len() != BLOB_REF_SIZE {
+            return None;
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        Some(BlobRef {
+            file_number: u64_at(0),
+            offset: u64_at(8),
+            size: u64_at(16),
+        })
+    }
+
+    // Returns a reader over the value, which is read from the blob file as it's
+    // consumed.
+    pub(crate) fn reader(
+        &self,
+        storage: &dyn Storage,
+        root_path: &Path,
+    ) -> Result<impl Read + Send, std::io::Error> {
+        let mut file = RandomAccessReader::new(
+            storage
+                .open(&blob_path(root_path, self.file_number))?
+                .into(),
+        )?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(file.take(self.size))
+    }
+
+    pub(crate) fn read(
+        &self,
+        storage: &dyn Storage,
+        root_path: &Path,
+    ) -> Result<Value, std::io::Error> {
+        let mut value = Vec::with_capacity(self.size as usize);
+        self.reader(storage, root_path)?.read_to_end(&mut value)?;
+        if value.len() as u64 != self.size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "blob file is truncated",
+            ));
+        }
+        Ok(value)
+    }
+}
+
+// Appends values to a new blob file.
+pub(crate) struct BlobFileWriter {
+    writer: BufWriter<Box<dyn WritableFile>>,
+    file_number: u64,
+    offset: u64,
+    min_blob_size: usize,
+}
+
+impl BlobFileWriter {
+    // `file` was created at `blob_path(root_path, file_number)`.
+    pub fn new(file: Box<dyn WritableFile>, file_number: u64, min_blob_size: usize) -> Self {
+        BlobFileWriter {
+            writer: BufWriter::new(file),
+            file_number,
+            offset: 0,
+            min_blob_size,
+        }
+    }
+
+    // Moves the entry's value into the blob file if it's big enough, and returns
+    // the entry to store in the sstable.
+    pub fn add(&mut self, entry: &EntryValue) -> Result<EntryValue, std::io::Error> {
+        match entry {
+            EntryValue::Present(value) if value.len() >= self.min_blob_size => {
+                self.writer.write_all(value)?;
+                let blob_ref = BlobRef {
+                    file_number: self.file_number,
+                    offset: self.offset,
+                    size: value.len() as u64,
+                };
+                self.offset += value.len() as u64;
+                Ok(EntryValue::Blob(blob_ref))
+            }
+            _ => Ok(entry.clone()),
+        }
+    }
+
+    pub fn file_number(&self) -> u64 {
+        self.file_number
+    }
+
+    // Syncs the blob file, and returns its size.
+    pub fn finish(self) -> Result<u64, std::io::Error> {
+        let mut file = self.writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync()?;
+        Ok(self.offset)
+    }
+}
+
+pub(crate) fn blob_path(root_path: &Path, file_number: u64) -> PathBuf {
+    root_path.join(format!("{}.blob", file_number))
+}
+
+// Returns the file number of a blob file path (`<file number>.blob`), or None if
+// `path` isn't a blob file.
+pub(crate) fn blob_file_number(path: &Path) -> Option<u64> {
+    if path.extension()? != "blob" {
+        return None;
+    }
+    manifest::file_number_from_stem(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::FileStorage;
+    use tempdir::TempDir;
+
+    #[test]
+    fn blob_file_round_trip() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let file = FileStorage
+            .create(&blob_path(tempdir.path(), 7))
+            .expect("couldnt create");
+        let mut writer = BlobFileWriter::new(file, 7, 4);
+        let small = EntryValue::Present(b"abc".to_vec());
+        assert_eq!(writer.add(&small).expect("couldnt add"), small);
+        assert_eq!(
+            writer.add(&EntryValue::Deleted).expect("couldnt add"),
+            EntryValue::Deleted
+        );
+        let mut blob_refs = Vec::new();
+        for value in [b"first value".to_vec(), b"second".to_vec()] {
+            match writer
+                .add(&EntryValue::Present(value))
+                .expect("couldnt add")
+            {
+                EntryValue::Blob(blob_ref) => blob_refs.push(blob_ref),
+                entry => panic!("expected a blob, got {:?}", entry),
+            }
+        }
+        assert_eq!(writer.finish().expect("couldnt finish"), 17);
+
+        assert_eq!(BlobRef::decode(&blob_refs[1].encode()), Some(blob_refs[1]));
+        assert_eq!(
+            blob_refs[1]
+                .read(&FileStorage, tempdir.path())
+                .expect("couldnt read"),
+            b"second"
+        );
+        let mut value = String::new();
+        blob_refs[0]
+            .reader(&FileStorage, tempdir.path())
+            .expect("couldnt open")
+            .read_to_string(&mut value)
+            .expect("couldnt read");
+        assert_eq!(value, "first value");
+        assert_eq!(blob_file_number(&blob_path(tempdir.path(), 7)), Some(7));
+        assert_eq!(
+            blob_file_number(&manifest::sstable_path(tempdir.path(), 7)),
+            None
+        );
+    }
+}
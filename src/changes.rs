@@ -0,0 +1,112 @@
+use std::{
+    collections::VecDeque,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use crate::db::{DBError, Key, Value};
+
+// A write to the database, as seen by `RawDB::subscribe()` and
+// `RawDB::get_updates_since()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+    // Grows with every write.  The top 24 bits are the number of times the
+    // database was opened before (its incarnation, kept in the MANIFEST), and the
+    // rest count the writes since it was opened, starting at 1.  There's no log
+    // yet, so writes which weren't flushed are lost on reopen, but their sequence
+    // numbers are never reused: a replica asking for the writes since one of them
+    // gets DBError::UpdatesUnavailable.
+    pub sequence: u64,
+    pub change: Change,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Put(Key, Value),
+    Delete(Key),
+    // Deletes the keys in the half-open range [start, end).
+    DeleteRange(Key, Key),
+}
+
+// Sequence numbers start at the incarnation shifted by this many bits.
+const INCARNATION_SHIFT: u32 = 40;
+
+// A subscriber can fall this many events behind before it's disconnected.
+pub(crate) const SUBSCRIBER_CAPACITY: usize = 1024;
+
+// Sends every write to the subscribers, in order, and keeps the recent ones for
+// `get_updates_since()`.
+pub(crate) struct ChangeFeed {
+    last_sequence: u64,
+    subscribers: Vec<mpsc::SyncSender<ChangeEvent>>,
+    // How long writes are kept in `retained`.
+    retention: Duration,
+    retained: VecDeque<(Instant, ChangeEvent)>,
+}
+
+impl ChangeFeed {
+    pub fn new(retention: Duration, incarnation: u64) -> Self {
+        ChangeFeed {
+            last_sequence: incarnation << INCARNATION_SHIFT,
+            subscribers: Vec::new(),
+            retention,
+            retained: VecDeque::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self) -> mpsc::Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn publish(&mut self, change: impl FnOnce() -> Change) {
+        self.last_sequence += 1;
+        let is_retained = !self.retention.is_zero();
+        if self.subscribers.is_empty() && !is_retained {
+            return;
+        }
+        let event = ChangeEvent {
+            sequence: self.last_sequence,
+            change: change(),
+        };
+        // Subscribers which dropped their receiver are forgotten, and so are those
+        // which fell too far behind, rather than blocking the write or buffering
+        // without bound: their receiver sees the end of the stream.
+        self.subscribers
+            .retain(|subscriber| subscriber.try_send(event.clone()).is_ok());
+        if is_retained {
+            let now = Instant::now();
+            self.retained.push_back((now, event));
+            self.expire(now);
+        }
+    }
+
+    // Returns the writes after `sequence`, or DBError::UpdatesUnavailable if some
+    // of them are no longer retained.
+    pub fn updates_since(&mut self, sequence: u64) -> Result<Vec<ChangeEvent>, DBError> {
+        self.expire(Instant::now());
+        if sequence >= self.last_sequence {
+            return Ok(Vec::new());
+        }
+        match self.retained.front() {
+            Some((_, oldest)) if oldest.sequence <= sequence + 1 => Ok(self
+                .retained
+                .iter()
+                .map(|(_, event)| event)
+                .filter(|event| event.sequence > sequence)
+                .cloned()
+                .collect()),
+            _ => Err(DBError::UpdatesUnavailable(sequence)),
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((time, _)) = self.retained.front() {
+            if now.duration_since(*time) <= self.retention {
+                break;
+            }
+            self.retained.pop_front();
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// CRC-32C (Castagnoli), the checksum used for values passed to
+// RawDB::put_with_checksum(), and for sstable blocks.
+//
+// Clients compute the same checksum on their side of the network boundary to
+// verify values end to end.
+pub fn crc32c(data: &[u8]) -> u32 {
+    !crc32c_extend(!0, data)
+}
+
+// Like `crc32c()` of `parts` one after the other, without concatenating them.
+pub(crate) fn crc32c_of_parts<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u32 {
+    !parts.into_iter().fold(!0, crc32c_extend)
+}
+
+const CRC32C_POLYNOMIAL: u32 = 0x82F6_3B78; // reversed
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c_extend(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_values() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(&[0u8; 32]), 0x8A91_36AA);
+    }
+}
@@ -0,0 +1,921 @@
+use std::{
+    io::Write,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    db::{DBConfig, EntryValue, Key, RangeTombstone, Value},
+    filter::PrefixExtractor,
+    merge::{MergeSource, MergingIterator},
+    properties::TablePropertiesCollectorFactory,
+    sstable::{SSTableError, SSTableReader, SSTableWriter},
+    timestamp::is_timestamped_key,
+};
+
+// How sstables are merged together over time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionStyle {
+    // Never compact; all sstables stay in level 0.
+    None,
+
+    // Level 0 is merged into level 1 once it has
+    // DBConfig::level0_file_num_compaction_trigger sstables.  Every other level N
+    // has a target size (see DBConfig::max_bytes_for_level_base), and once it grows
+    // past it, one of its sstables is merged into level N+1.
+    //
+    // Levels other than 0 never have overlapping sstables, so a read checks at most
+    // one sstable per level.
+    //
+    // When a level accumulates DBConfig::small_file_compaction_trigger small
+    // sstables, adjacent small sstables are merged together, without moving them to
+    // the next level.
+    #[default]
+    Leveled,
+
+    // All sstables stay in level 0, where runs of similarly-sized sstables are
+    // merged into one bigger sstable (see DBConfig::size_tiered_min_merge_width and
+    // DBConfig::size_tiered_size_ratio).
+    //
+    // Every entry is rewritten fewer times than with Leveled compaction, but reads
+    // may have to check more sstables.
+    SizeTiered,
+
+    // For logs and time series, where old data expires wholesale: all sstables
+    // stay in level 0 and are never merged, and the oldest ones are dropped once
+    // they add up to more than DBConfig::fifo_max_table_files_size, or once they're
+    // older than DBConfig::fifo_ttl.
+    //
+    // Set DBConfig::memtable_flush_interval too, so each sstable holds the writes
+    // of a bounded stretch of time, and expires close to when they do.
+    Fifo,
+}
+
+// Which sstable of a level leveled compaction merges into the next level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionPriority {
+    // The biggest sstable.
+    #[default]
+    ByFileSize,
+
+    // The sstable with the most deleted entries and range tombstones, so deleted
+    // data is dropped sooner.  Ties go to the biggest sstable.  Deletions are
+    // counted by reading each sstable once.
+    ByDeletions,
+}
+
+// What a CompactionFilter does with an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionDecision {
+    Keep,
+    // The key is deleted, as if by RawDB::delete(), so older values of the key in
+    // deeper levels stay hidden.
+    Remove,
+    // The value is replaced.  The new value is stored in the sstable, not a blob
+    // file; values bigger than a block (4 KB) get a block of their own.
+    ChangeValue(Value),
+}
+
+// Decides what happens to each value rewritten by a compaction, e.g. to drop
+// rows an application marked as deleted without a separate scan.
+//
+// Filters only see the values being compacted, so a key may keep its value until
+// its sstable is next compacted.  Like EventListeners, filters are called while
+// the database is locked, and must not call back into it.
+pub trait CompactionFilter: Send + Sync {
+    // `level` is the level the entry is being compacted into.  Values held in blob
+    // files are read to be passed in.
+    fn filter(&self, level: usize, key: &str, value: &[u8]) -> CompactionDecision;
+}
+
+// A compaction merges sstables from `level` with the overlapping sstables in
+// `output_level`, and replaces them all with new sstables in `output_level`.
+//
+// `output_level` is either `level + 1`, or `level` itself for compactions which
+// merge adjacent sstables of a level together (see pick_small_file_compaction and
+// pick_size_tiered_compaction).
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Compaction {
+    pub level: usize,
+    pub output_level: usize,
+
+    // Indexes of the input sstables in `level`.
+    pub inputs: Vec<usize>,
+
+    // Indexes of the input sstables in `output_level`, if it isn't `level`.
+    pub output_level_inputs: Vec<usize>,
+}
+
+// Returns the next compaction to run, if any level needs one.
+pub(crate) fn pick_compaction(
+    levels: &[Vec<SSTableReader>],
+    config: &DBConfig,
+) -> Option<Compaction> {
+    match config.compaction_style {
+        CompactionStyle::None | CompactionStyle::Fifo => None,
+        CompactionStyle::Leveled => pick_leveled_compaction(levels, config)
+            .or_else(|| pick_small_file_compaction(levels, config))
+            .or_else(|| pick_deletion_compaction(levels, config)),
+        CompactionStyle::SizeTiered => pick_size_tiered_compaction(levels, config),
+    }
+}
+
+// Returns how many of the oldest level-0 sstables FIFO compaction drops at `now`.
+// SSTables without a creation time only expire by size.
+pub(crate) fn pick_fifo_deletions(
+    levels: &[Vec<SSTableReader>],
+    config: &DBConfig,
+    now: SystemTime,
+) -> usize {
+    let Some(sstables) = levels.first() else {
+        return 0;
+    };
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    let mut total_size = level_size(sstables);
+    let mut num_expired = 0;
+    for sstable in sstables {
+        let too_big =
+            config.fifo_max_table_files_size > 0 && total_size > config.fifo_max_table_files_size;
+        let too_old = !config.fifo_ttl.is_zero()
+            && sstable.creation_time().is_some_and(|creation_time| {
+                Duration::from_secs(now.saturating_sub(creation_time)) >= config.fifo_ttl
+            });
+        if !too_big && !too_old {
+            break;
+        }
+        total_size -= sstable.file_size();
+        num_expired += 1;
+    }
+    num_expired
+}
+
+// Returns the target size of a level >= 1, in bytes.
+pub(crate) fn level_target_size(level: usize, config: &DBConfig) -> u64 {
+    let mut target_size = config.max_bytes_for_level_base;
+    for _ in 1..level {
+        target_size = target_size.saturating_mul(config.max_bytes_for_level_multiplier);
+    }
+    target_size
+}
+
+fn level_size(level: &[SSTableReader]) -> u64 {
+    level.iter().map(SSTableReader::file_size).sum()
+}
+
+fn pick_leveled_compaction(levels: &[Vec<SSTableReader>], config: &DBConfig) -> Option<Compaction> {
+    // Score each level by how far over its limit it is, and compact the worst one.
+    // The last level can't be compacted any further.
+    let mut best: Option<(f64, usize)> = None;
+    for (level, sstables) in levels
+        .iter()
+        .enumerate()
+        .take(config.num_levels.saturating_sub(1))
+    {
+        let score = if level == 0 {
+            sstables.len() as f64 / config.level0_file_num_compaction_trigger.max(1) as f64
+        } else {
+            level_size(sstables) as f64 / level_target_size(level, config).max(1) as f64
+        };
+        if score >= 1.0 && best.is_none_or(|(best_score, _)| score > best_score) {
+            best = Some((score, level));
+        }
+    }
+    let (_, level) = best?;
+
+    // Level 0 sstables overlap each other, so they are all compacted at once.
+    // For the other levels, pick one sstable by the CompactionPriority.
+    let inputs = if level == 0 {
+        (0..levels[0].len()).collect()
+    } else {
+        let sstables = levels[level].iter().enumerate();
+        let (picked, _) = match config.compaction_priority {
+            CompactionPriority::ByFileSize => {
+                sstables.max_by_key(|(_, sstable)| sstable.file_size())?
+            }
+            CompactionPriority::ByDeletions => sstables
+                .max_by_key(|(_, sstable)| (sstable.num_deletions(), sstable.file_size()))?,
+        };
+        vec![picked]
+    };
+
+    let output_level_inputs = match key_range(inputs.iter().map(|&i| &levels[level][i])) {
+        Some((smallest, largest)) => levels
+            .get(level + 1)
+            .map(|output_level| overlapping_sstables(output_level, &smallest, &largest))
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    Some(Compaction {
+        level,
+        output_level: level + 1,
+        inputs,
+        output_level_inputs,
+    })
+}
+
+// Merges runs of adjacent small sstables within a level >= 1 into sstables of
+// about DBConfig::target_file_size_base.  Frequent small flushes and compactions
+// otherwise leave behind many tiny sstables, which all need to be kept open.
+fn pick_small_file_compaction(
+    levels: &[Vec<SSTableReader>],
+    config: &DBConfig,
+) -> Option<Compaction> {
+    if config.small_file_compaction_trigger == 0 {
+        return None;
+    }
+    // Sstables under half the target size always merge into fewer sstables, so
+    // merging them again and again eventually stops.
+    let small_file_size = config.small_file_size.min(config.target_file_size_base / 2);
+    let is_small = |sstable: &SSTableReader| sstable.file_size() < small_file_size;
+    for (level, sstables) in levels.iter().enumerate().skip(1) {
+        if sstables.iter().filter(|sstable| is_small(sstable)).count()
+            < config.small_file_compaction_trigger
+        {
+            continue;
+        }
+
+        // Only adjacent sstables can be merged, otherwise the output would overlap
+        // the sstables between them.  Take the longest run of small sstables.
+        let mut longest_run = 0..0;
+        let mut run_start = 0;
+        for (i, sstable) in sstables.iter().enumerate() {
+            if !is_small(sstable) {
+                run_start = i + 1;
+            } else if i + 1 - run_start > longest_run.len() {
+                longest_run = run_start..i + 1;
+            }
+        }
+        if longest_run.len() >= 2 {
+            return Some(Compaction {
+                level,
+                output_level: level,
+                inputs: longest_run.collect(),
+                output_level_inputs: Vec::new(),
+            });
+        }
+    }
+    None
+}
+
+// Compacts an sstable of a level >= 1 whose deletion ratio reached
+// DBConfig::deletion_compaction_ratio.  If nothing deeper overlaps it, it's
+// rewritten in place without its deletions; otherwise it's merged into the next
+// level, closer to where they can be dropped.  SSTables without properties are
+// skipped, as their deletions would have to be counted by reading them.
+fn pick_deletion_compaction(
+    levels: &[Vec<SSTableReader>],
+    config: &DBConfig,
+) -> Option<Compaction> {
+    if config.deletion_compaction_ratio <= 0.0 {
+        return None;
+    }
+    for (level, sstables) in levels.iter().enumerate().skip(1) {
+        for (i, sstable) in sstables.iter().enumerate() {
+            let Some(ratio) = sstable.table_properties(level).deletion_ratio() else {
+                continue;
+            };
+            if ratio < config.deletion_compaction_ratio {
+                continue;
+            }
+            let Some((smallest, largest)) = sstable.key_range() else {
+                continue;
+            };
+            if is_bottommost(levels, level, smallest, largest) {
+                return Some(Compaction {
+                    level,
+                    output_level: level,
+                    inputs: vec![i],
+                    output_level_inputs: Vec::new(),
+                });
+            }
+            return Some(Compaction {
+                level,
+                output_level: level + 1,
+                inputs: vec![i],
+                output_level_inputs: overlapping_sstables(&levels[level + 1], smallest, largest),
+            });
+        }
+    }
+    None
+}
+
+fn pick_size_tiered_compaction(
+    levels: &[Vec<SSTableReader>],
+    config: &DBConfig,
+) -> Option<Compaction> {
+    // Merged sstables must be adjacent in level 0, which is ordered by age, so that
+    // the merged sstable can take their place.  Look for a long enough run of
+    // similarly-sized sstables, starting with the newest (and smallest) ones.
+    let sstables = levels.first()?;
+    let min_width = config.size_tiered_min_merge_width.max(2);
+    let max_width = config.size_tiered_max_merge_width.max(min_width);
+    for newest in (0..sstables.len()).rev() {
+        let mut smallest = sstables[newest].file_size();
+        let mut biggest = smallest;
+        let mut oldest = newest;
+        while oldest > 0 && newest - oldest + 1 < max_width {
+            let file_size = sstables[oldest - 1].file_size();
+            let (new_smallest, new_biggest) = (smallest.min(file_size), biggest.max(file_size));
+            if new_biggest as f64 > new_smallest.max(1) as f64 * config.size_tiered_size_ratio {
+                break;
+            }
+            (smallest, biggest) = (new_smallest, new_biggest);
+            oldest -= 1;
+        }
+        if newest - oldest + 1 >= min_width {
+            return Some(Compaction {
+                level: 0,
+                output_level: 0,
+                inputs: (oldest..=newest).collect(),
+                output_level_inputs: Vec::new(),
+            });
+        }
+    }
+    None
+}
+
+// Returns the smallest and largest key across all the given sstables.
+pub(crate) fn key_range<'a>(
+    sstables: impl Iterator<Item = &'a SSTableReader>,
+) -> Option<(Key, Key)> {
+    let mut range: Option<(Key, Key)> = None;
+    for (smallest, largest) in sstables.filter_map(SSTableReader::key_range) {
+        range = Some(match range {
+            None => (smallest.to_string(), largest.to_string()),
+            Some((range_smallest, range_largest)) => (
+                range_smallest.min(smallest.to_string()),
+                range_largest.max(largest.to_string()),
+            ),
+        });
+    }
+    range
+}
+
+// Returns the indexes of the sstables in `level` which overlap [smallest, largest].
+pub(crate) fn overlapping_sstables(
+    level: &[SSTableReader],
+    smallest: &str,
+    largest: &str,
+) -> Vec<usize> {
+    level
+        .iter()
+        .enumerate()
+        .filter(|(_, sstable)| sstable.overlaps(smallest, largest))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// Returns true if no level deeper than `level` holds keys in [smallest, largest];
+// deletions written to `level` then have nothing left to hide, so they can be dropped.
+pub(crate) fn is_bottommost(
+    levels: &[Vec<SSTableReader>],
+    level: usize,
+    smallest: &str,
+    largest: &str,
+) -> bool {
+    levels
+        .iter()
+        .skip(level + 1)
+        .all(|sstables| overlapping_sstables(sstables, smallest, largest).is_empty())
+}
+
+// Merges the `inputs` into new sstables, which are created by calling `new_output`.
+//
+// Each input comes with a rank; a smaller rank means newer data.  When a key is in
+// several inputs, only the newest entry is kept.  Inputs may share a rank as long as
+// they don't overlap, like the sstables of a level >= 1.  A range tombstone only
+// deletes entries from inputs with a bigger rank.
+//
+// If `drop_tombstones` is set, deleted entries and range tombstones are left out
+// of the output.
+//
+// `filter_entry` is called with every entry, and returns the entry to write in
+// its place.  With a `prefix_extractor`, the outputs get prefix filters, and
+// each output stores the properties of a collector from each of the
+// `collector_factories`.
+//
+// A new output sstable is started once the current one reaches
+// `target_file_size`.  Returns the finished outputs, in key order.
+pub(crate) fn merge_sstables<W: Write>(
+    inputs: &mut [(SSTableReader, u32)],
+    drop_tombstones: bool,
+    target_file_size: u64,
+    mut filter_entry: impl FnMut(&str, EntryValue) -> Result<Option<EntryValue>, SSTableError>,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    collector_factories: &[Arc<dyn TablePropertiesCollectorFactory>],
+    mut new_output: impl FnMut() -> Result<W, SSTableError>,
+) -> Result<Vec<W>, SSTableError> {
+    let mut new_writer = || -> Result<SSTableWriter<W>, SSTableError> {
+        Ok(SSTableWriter::new(new_output()?)
+            .with_prefix_extractor(prefix_extractor.clone())
+            .with_properties_collectors(collector_factories))
+    };
+    let merged = MergingIterator::new(
+        inputs
+            .iter_mut()
+            .map(|(sstable, rank)| {
+                let range_tombstones = sstable.range_tombstones().to_vec();
+                MergeSource::new(sstable.iter(), range_tombstones, *rank)
+            })
+            .collect(),
+    );
+    let range_tombstones = merged.range_tombstones().to_vec();
+
+    let mut outputs = Vec::new();
+    // The output being written, and the smallest key it may hold.
+    let mut output: Option<(SSTableWriter<W>, Option<Key>)> = None;
+    for entry in merged {
+        let (key, entry) = entry?;
+        // Entries the filter drops leave no tombstone behind.
+        let Some(entry) = filter_entry(&key, entry)? else {
+            continue;
+        };
+        // A deleted version of a timestamped key still hides its older versions.
+        if drop_tombstones && entry == EntryValue::Deleted && !is_timestamped_key(&key) {
+            continue;
+        }
+
+        // Start a new output once the current one is big enough.  Keys from here on
+        // go into the new output, so the previous one is bounded by `key`.
+        if let Some((writer, _)) = &output {
+            if writer.estimated_size() >= target_file_size {
+                let (writer, lower_bound) = output.take().unwrap();
+                outputs.push(finish_output(
+                    writer,
+                    &range_tombstones,
+                    drop_tombstones,
+                    lower_bound.as_deref(),
+                    Some(&key),
+                )?);
+                output = Some((new_writer()?, Some(key.clone())));
+            }
+        }
+        if output.is_none() {
+            output = Some((new_writer()?, None));
+        }
+        output.as_mut().unwrap().0.add(&key, &entry)?;
+    }
+
+    // Range tombstones are kept even when all the keys they cover are gone, since
+    // they may still hide keys in deeper levels.
+    if output.is_none() && !drop_tombstones && !range_tombstones.is_empty() {
+        output = Some((new_writer()?, None));
+    }
+    if let Some((writer, lower_bound)) = output {
+        outputs.push(finish_output(
+            writer,
+            &range_tombstones,
+            drop_tombstones,
+            lower_bound.as_deref(),
+            None,
+        )?);
+    }
+    Ok(outputs)
+}
+
+// Finishes an output sstable which holds the keys in [lower_bound, upper_bound).
+//
+// Range tombstones are clipped to the output's bounds, so that outputs never
+// overlap each other.
+fn finish_output<W: Write>(
+    mut writer: SSTableWriter<W>,
+    range_tombstones: &[(RangeTombstone, u32)],
+    drop_tombstones: bool,
+    lower_bound: Option<&str>,
+    upper_bound: Option<&str>,
+) -> Result<W, SSTableError> {
+    if !drop_tombstones {
+        for (tombstone, _) in range_tombstones {
+            let start = match lower_bound {
+                Some(lower_bound) if lower_bound > tombstone.start.as_str() => lower_bound,
+                _ => tombstone.start.as_str(),
+            };
+            let end = match upper_bound {
+                Some(upper_bound) if upper_bound < tombstone.end.as_str() => upper_bound,
+                _ => tombstone.end.as_str(),
+            };
+            if start < end {
+                writer.add_range_tombstone(RangeTombstone {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                });
+            }
+        }
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memtable::Memtable;
+    use crate::sstable::write_memtable_to_sstable;
+    use crate::storage::FileStorage;
+    use std::{fs::File, path::PathBuf};
+    use tempdir::TempDir;
+
+    // Writes an sstable holding `entries` and `range_tombstones`.
+    fn make_sstable(
+        path: PathBuf,
+        entries: &[(&str, Option<&str>)],
+        range_tombstones: &[(&str, &str)],
+    ) -> SSTableReader {
+        let mut memtable = Memtable::default();
+        for (key, value) in entries {
+            memtable.insert(
+                key.to_string(),
+                match value {
+                    Some(value) => EntryValue::Present(value.as_bytes().to_vec()),
+                    None => EntryValue::Deleted,
+                },
+            );
+        }
+        for (start, end) in range_tombstones {
+            memtable.add_range_tombstone(RangeTombstone {
+                start: start.to_string(),
+                end: end.to_string(),
+            });
+        }
+        let mut file = File::create(&path).expect("couldnt create sstable");
+        write_memtable_to_sstable(&memtable, &mut file, None, None, &[])
+            .expect("couldnt write sstable");
+        SSTableReader::from_path(&FileStorage, &path).expect("couldnt open sstable")
+    }
+
+    // Merges the inputs into in-memory outputs, and reads them back.
+    fn merge_for_test(
+        tempdir: &TempDir,
+        mut inputs: Vec<(SSTableReader, u32)>,
+        drop_tombstones: bool,
+        target_file_size: u64,
+    ) -> Vec<SSTableReader> {
+        let mut paths = Vec::new();
+        merge_sstables(
+            &mut inputs,
+            drop_tombstones,
+            target_file_size,
+            |_, entry| Ok(Some(entry)),
+            None,
+            &[],
+            || {
+                let path = tempdir.path().join(format!("out_{}.sst", paths.len()));
+                paths.push(path.clone());
+                Ok(File::create(path)?)
+            },
+        )
+        .expect("couldnt merge");
+        paths
+            .iter()
+            .map(|path| SSTableReader::from_path(&FileStorage, path).expect("couldnt open output"))
+            .collect()
+    }
+
+    fn read_all(sstable: &mut SSTableReader) -> Vec<(Key, EntryValue)> {
+        sstable
+            .iter()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("couldnt read sstable")
+    }
+
+    #[test]
+    fn merge_keeps_newest_entries() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let newer = make_sstable(
+            tempdir.path().join("0.sst"),
+            &[("/a", Some("new")), ("/c", None)],
+            &[],
+        );
+        let older = make_sstable(
+            tempdir.path().join("1.sst"),
+            &[
+                ("/a", Some("old")),
+                ("/b", Some("old")),
+                ("/c", Some("old")),
+            ],
+            &[],
+        );
+
+        let mut outputs = merge_for_test(&tempdir, vec![(older, 1), (newer, 0)], false, u64::MAX);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            read_all(&mut outputs[0]),
+            vec![
+                ("/a".to_string(), EntryValue::Present(b"new".to_vec())),
+                ("/b".to_string(), EntryValue::Present(b"old".to_vec())),
+                ("/c".to_string(), EntryValue::Deleted),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_drops_tombstones_at_the_bottom() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let newer = make_sstable(
+            tempdir.path().join("0.sst"),
+            &[("/b/2", Some("new")), ("/c", None)],
+            &[("/b/", "/b/9")],
+        );
+        let older = make_sstable(
+            tempdir.path().join("1.sst"),
+            &[
+                ("/a", Some("old")),
+                ("/b/1", Some("old")),
+                ("/b/3", Some("old")),
+                ("/c", Some("old")),
+            ],
+            &[],
+        );
+
+        let mut outputs = merge_for_test(&tempdir, vec![(newer, 0), (older, 1)], true, u64::MAX);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            read_all(&mut outputs[0]),
+            vec![
+                ("/a".to_string(), EntryValue::Present(b"old".to_vec())),
+                ("/b/2".to_string(), EntryValue::Present(b"new".to_vec())),
+            ]
+        );
+        assert!(outputs[0].range_tombstones().is_empty());
+    }
+
+    #[test]
+    fn merge_splits_outputs_and_clips_range_tombstones() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let entries: Vec<(String, String)> = (0..1000)
+            .map(|i| (format!("/key/{:04}", i), "v".repeat(100)))
+            .collect();
+        let entries: Vec<(&str, Option<&str>)> = entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), Some(value.as_str())))
+            .collect();
+        let older = make_sstable(tempdir.path().join("1.sst"), &entries, &[]);
+        let newer = make_sstable(
+            tempdir.path().join("0.sst"),
+            &[],
+            &[("/key/0500", "/key/0600"), ("/zzz", "/zzzz")],
+        );
+
+        let mut outputs = merge_for_test(&tempdir, vec![(newer, 0), (older, 1)], false, 16 * 1024);
+        assert!(outputs.len() > 1);
+
+        let mut all_entries = Vec::new();
+        for i in 0..outputs.len() {
+            let (smallest, largest) = outputs[i].key_range().expect("output is empty");
+            let (smallest, largest) = (smallest.to_string(), largest.to_string());
+            for tombstone in outputs[i].range_tombstones() {
+                assert!(smallest <= tombstone.start && tombstone.end <= largest);
+            }
+            all_entries.extend(read_all(&mut outputs[i]));
+            if i + 1 < outputs.len() {
+                // outputs never overlap (range tombstone ends are exclusive).
+                let (next_smallest, _) = outputs[i + 1].key_range().unwrap();
+                assert!(largest.as_str() <= next_smallest);
+            }
+        }
+        assert_eq!(all_entries.len(), 900);
+        assert!(all_entries
+            .iter()
+            .all(|(key, _)| !(key.as_str() >= "/key/0500" && key.as_str() < "/key/0600")));
+
+        // The range tombstones are kept, to hide keys in deeper levels.
+        let mut range_tombstones: Vec<RangeTombstone> = outputs
+            .iter()
+            .flat_map(|output| output.range_tombstones().to_vec())
+            .collect();
+        range_tombstones.sort_by(|a, b| a.start.cmp(&b.start));
+        assert_eq!(range_tombstones.first().unwrap().start, "/key/0500");
+        assert_eq!(range_tombstones.last().unwrap().end, "/zzzz");
+    }
+
+    #[test]
+    fn pick_leveled_compaction_by_score() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let config = DBConfig {
+            level0_file_num_compaction_trigger: 2,
+            max_bytes_for_level_base: 1,
+            ..DBConfig::default()
+        };
+        let mut levels: Vec<Vec<SSTableReader>> =
+            (0..config.num_levels).map(|_| Vec::new()).collect();
+        assert_eq!(pick_compaction(&levels, &config), None);
+
+        levels[0].push(make_sstable(
+            tempdir.path().join("0.sst"),
+            &[("/a", Some("0")), ("/c", Some("0"))],
+            &[],
+        ));
+        assert_eq!(pick_compaction(&levels, &config), None);
+
+        levels[0].push(make_sstable(
+            tempdir.path().join("1.sst"),
+            &[("/b", Some("1"))],
+            &[],
+        ));
+        levels[1].push(make_sstable(
+            tempdir.path().join("2.sst"),
+            &[("/a", Some("22222"))],
+            &[],
+        ));
+        levels[1].push(make_sstable(
+            tempdir.path().join("3.sst"),
+            &[("/d", Some("3"))],
+            &[],
+        ));
+        // Level 1 is way over its 1 byte target.
+        assert_eq!(
+            pick_compaction(&levels, &config),
+            Some(Compaction {
+                level: 1,
+                output_level: 2,
+                inputs: vec![0],
+                output_level_inputs: vec![],
+            })
+        );
+
+        let config = DBConfig {
+            level0_file_num_compaction_trigger: 2,
+            ..DBConfig::default()
+        };
+        assert_eq!(
+            pick_compaction(&levels, &config),
+            Some(Compaction {
+                level: 0,
+                output_level: 1,
+                inputs: vec![0, 1],
+                output_level_inputs: vec![0],
+            })
+        );
+
+        let config = DBConfig {
+            compaction_style: CompactionStyle::None,
+            ..config
+        };
+        assert_eq!(pick_compaction(&levels, &config), None);
+    }
+
+    #[test]
+    fn pick_leveled_compaction_by_priority() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let config = DBConfig {
+            max_bytes_for_level_base: 1,
+            ..DBConfig::default()
+        };
+        let mut levels: Vec<Vec<SSTableReader>> =
+            (0..config.num_levels).map(|_| Vec::new()).collect();
+        levels[1].push(make_sstable(
+            tempdir.path().join("0.sst"),
+            &[("/a", Some("a big value")), ("/b", Some("a big value"))],
+            &[],
+        ));
+        levels[1].push(make_sstable(
+            tempdir.path().join("1.sst"),
+            &[("/c", None), ("/d", Some("1"))],
+            &[("/e", "/f")],
+        ));
+        assert_eq!(levels[1][0].num_deletions(), 0);
+        assert_eq!(levels[1][1].num_deletions(), 2);
+
+        let picked = |config: &DBConfig| pick_compaction(&levels, config).map(|c| c.inputs);
+        assert_eq!(picked(&config), Some(vec![0]));
+        let config = DBConfig {
+            compaction_priority: CompactionPriority::ByDeletions,
+            ..config
+        };
+        assert_eq!(picked(&config), Some(vec![1]));
+    }
+
+    #[test]
+    fn pick_size_tiered_compaction_by_size() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let config = DBConfig {
+            compaction_style: CompactionStyle::SizeTiered,
+            size_tiered_min_merge_width: 3,
+            size_tiered_size_ratio: 2.0,
+            ..DBConfig::default()
+        };
+        let mut levels: Vec<Vec<SSTableReader>> =
+            (0..config.num_levels).map(|_| Vec::new()).collect();
+
+        // One big, old sstable, and then smaller ones.
+        let big_value = "x".repeat(2000);
+        levels[0].push(make_sstable(
+            tempdir.path().join("0.sst"),
+            &[("/a", Some(big_value.as_str()))],
+            &[],
+        ));
+        for i in 1..3 {
+            levels[0].push(make_sstable(
+                tempdir.path().join(format!("{}.sst", i)),
+                &[("/b", Some("1"))],
+                &[],
+            ));
+        }
+        assert_eq!(pick_compaction(&levels, &config), None);
+
+        levels[0].push(make_sstable(
+            tempdir.path().join("3.sst"),
+            &[("/c", Some("22"))],
+            &[],
+        ));
+        // The big sstable isn't merged with the small ones.
+        assert_eq!(
+            pick_compaction(&levels, &config),
+            Some(Compaction {
+                level: 0,
+                output_level: 0,
+                inputs: vec![1, 2, 3],
+                output_level_inputs: vec![],
+            })
+        );
+
+        let config = DBConfig {
+            size_tiered_size_ratio: 1000.0,
+            ..config
+        };
+        assert_eq!(
+            pick_compaction(&levels, &config),
+            Some(Compaction {
+                level: 0,
+                output_level: 0,
+                inputs: vec![0, 1, 2, 3],
+                output_level_inputs: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn pick_fifo_deletions_by_size_and_age() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let mut levels: Vec<Vec<SSTableReader>> = vec![Vec::new()];
+        for i in 0..4 {
+            levels[0].push(make_sstable(
+                tempdir.path().join(format!("{}.sst", i)),
+                &[("/key", Some("x".repeat(1000).as_str()))],
+                &[],
+            ));
+        }
+        let file_size = levels[0][0].file_size();
+        let now = SystemTime::now();
+        let config = DBConfig {
+            compaction_style: CompactionStyle::Fifo,
+            ..DBConfig::default()
+        };
+        assert_eq!(pick_compaction(&levels, &config), None);
+        assert_eq!(pick_fifo_deletions(&levels, &config, now), 0);
+
+        // The oldest sstables go until the rest fit.
+        let config = DBConfig {
+            fifo_max_table_files_size: file_size * 5 / 2,
+            ..config
+        };
+        assert_eq!(pick_fifo_deletions(&levels, &config, now), 2);
+
+        let config = DBConfig {
+            fifo_max_table_files_size: 0,
+            fifo_ttl: Duration::from_secs(60),
+            ..config
+        };
+        assert_eq!(pick_fifo_deletions(&levels, &config, now), 0);
+        let later = now + Duration::from_secs(61);
+        assert_eq!(pick_fifo_deletions(&levels, &config, later), 4);
+    }
+
+    #[test]
+    fn pick_small_file_compaction_of_adjacent_sstables() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let config = DBConfig {
+            small_file_size: 1024,
+            small_file_compaction_trigger: 3,
+            ..DBConfig::default()
+        };
+        let mut levels: Vec<Vec<SSTableReader>> =
+            (0..config.num_levels).map(|_| Vec::new()).collect();
+
+        let big_value = "x".repeat(2000);
+        for (i, value) in ["1", big_value.as_str(), "3", "4"].iter().enumerate() {
+            levels[1].push(make_sstable(
+                tempdir.path().join(format!("{}.sst", i)),
+                &[(format!("/{}", i).as_str(), Some(value))],
+                &[],
+            ));
+        }
+        assert_eq!(
+            pick_compaction(&levels, &config),
+            Some(Compaction {
+                level: 1,
+                output_level: 1,
+                inputs: vec![2, 3],
+                output_level_inputs: vec![],
+            })
+        );
+
+        let config = DBConfig {
+            small_file_compaction_trigger: 4,
+            ..config
+        };
+        assert_eq!(pick_compaction(&levels, &config), None);
+    }
+}
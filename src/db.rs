@@ -2,15 +2,47 @@ use sstable::SSTableError;
 use std::{
     cell::RefCell,
     cmp::{Ordering, Reverse},
-    collections::{btree_map::Range, BTreeMap, BinaryHeap, VecDeque},
-    fs::{DirBuilder, DirEntry, ReadDir},
-    iter::{Enumerate, Peekable},
+    collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+    io::{Read, Write},
+    iter::Peekable,
     ops::Bound,
     path::{Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, RwLock},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
 };
 use thiserror::Error;
 
-use crate::sstable::{self, write_memtable_to_sstable, SSTableReader};
+use crate::{
+    backup,
+    blob::{self, BlobFileWriter, BlobRef},
+    changes::{Change, ChangeEvent, ChangeFeed},
+    checksum::crc32c,
+    compaction::{self, CompactionDecision, CompactionFilter, CompactionPriority, CompactionStyle},
+    export::{ExportReader, ExportWriter},
+    filter::PrefixExtractor,
+    index::{self, IndexExtractor},
+    key_encoding::KeyEncoding,
+    listener::{
+        CompactionJobInfo, EventListener, FlushJobInfo, MemtableInfo, OpenInfo,
+        WriteStallCondition, WriteStallInfo,
+    },
+    manifest::{self, Manifest},
+    memtable::{self, EntryRef, Memtable, MemtableImpl},
+    merge::{MergeSource, MergingIterator},
+    options::MUTABLE_OPTIONS,
+    properties::{TableFilter, TableProperties, TablePropertiesCollectorFactory},
+    rate_limiter::{DeferredWait, RateLimitedFile, RateLimiter},
+    repair::{self, RepairReport},
+    sstable::{self, write_memtable_to_sstable, SSTableReader},
+    stats::{DBStats, Registration, SpaceReport, Statistics},
+    storage::{FileStorage, MemStorage, Storage, WritableFile},
+    timestamp,
+    verify::{self, IntegrityReport},
+    write_batch::WriteBatchWithIndex,
+    write_buffer::{WriteBufferHandle, WriteBufferManager},
+    write_handle::{FlushNotifier, WriteHandle},
+};
 
 #[derive(Error, Debug, Eq, PartialEq)]
 pub enum DBError {
@@ -25,49 +57,137 @@ pub enum DBError {
 
     #[error("IO Error: {0}")]
     Io(String),
+
+    #[error("MANIFEST error: {0}")]
+    Manifest(String),
+
+    #[error("Invalid export stream: {0}")]
+    InvalidExportStream(String),
+
+    // Writes are stopped until flushes and compactions catch up, and the write
+    // couldn't catch them up itself; the write can be retried later.
+    #[error("Writes are stalled: {0}")]
+    WriteStalled(String),
+
+    #[error("Checksum mismatch: expected {expected:#010x}, got {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+
+    // The writes after this sequence number are no longer retained; see
+    // DBConfig::change_retention.
+    #[error("Updates since sequence number {0} are no longer retained")]
+    UpdatesUnavailable(u64),
+
+    // A file was written by a newer version of lsmdb, in a format this version
+    // can't read; see format.rs.
+    #[error(
+        "{file} has format version {version}, which is newer than this version of lsmdb supports"
+    )]
+    UnsupportedVersion { file: String, version: u32 },
+
+    #[error("Database is read-only")]
+    ReadOnly,
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    // A value couldn't be decoded by a TypedDB's Codec.
+    #[error("Invalid value: {0}")]
+    InvalidValue(String),
+
+    // An option passed to `set_options()` is unknown, can't be changed while the
+    // database is open, or has an invalid value.
+    #[error("Invalid option: {0}")]
+    InvalidOption(String),
 }
 
 pub type Key = String;
 pub type Value = Vec<u8>;
 
+// More kinds of entries may be added, like Blob was, so matches outside the crate
+// need a wildcard arm.
 #[derive(Clone, PartialEq, Debug)]
+#[non_exhaustive]
 pub enum EntryValue {
     Present(Value),
     Deleted,
+    // The value is stored in a blob file; only found in sstables.
+    Blob(BlobRef),
 }
 
 impl EntryValue {
     /// Returns the entry's size on disk.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         match self {
             EntryValue::Present(value) => value.len(),
             EntryValue::Deleted => 0,
+            EntryValue::Blob(_) => blob::BLOB_REF_SIZE,
         }
     }
 }
 
-pub(crate) type Memtable = BTreeMap<Key, EntryValue>;
+// Marks every key in the half-open range [start, end) as deleted.
+//
+// A range tombstone only shadows entries that are older than it; i.e. entries
+// stored in older memtables or sstables. Entries in the same memtable (or sstable)
+// as the tombstone are always newer than it, because RawDB::delete_range() removes
+// any covered entries from the active memtable when the tombstone is added.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct RangeTombstone {
+    pub start: Key,
+    pub end: Key,
+}
+
+impl RangeTombstone {
+    pub fn covers(&self, key: &str) -> bool {
+        self.start.as_str() <= key && key < self.end.as_str()
+    }
 
-pub struct DB {
+    /// Returns the tombstone's size on disk.
+    pub fn len(&self) -> usize {
+        self.start.len() + self.end.len()
+    }
+}
+
+// The database, without any internal locking.
+//
+// Mutations take `&mut self`, so the borrow checker enforces the single-writer
+// contract; this is for embedders which already serialize access to the database.
+// Use `DB` to share a database between threads.
+pub struct RawDB {
     // SSTable files are stored under the root_path
     root_path: PathBuf,
 
-    // Opened SSTables readers.
-    // For now:
+    // Opened SSTables readers, for each level.
     // - All known sstables are opened.
-    // - All SSTables are level-0 (they have overlapping keys)
+    // - Level-0 SSTables have overlapping keys. The first element is the oldest
+    //   sstable, the last is the newest.
+    // - SSTables in every other level don't overlap, and are sorted by key.
+    //   A level holds newer data than the levels after it.
     //
-    // First element is the oldest sstable, the last is the newest.
-    //
-    // SSTables are stored
-    sstables: Vec<SSTableReader>,
+    // The levels are recorded in the MANIFEST.
+    levels: Vec<Vec<SSTableReader>>,
+
+    // File number to use for the next sstable (`<file number>.sst`) or blob file
+    // (`<file number>.blob`)
+    next_file_number: u64,
+
+    // File numbers of the blob files holding large values; see blob.rs.
+    blob_files: Vec<u64>,
+
+    // Active memtable, the latest source of data mutations.  Pinned iterators hold a
+    // reference to it, in which case the next write copies it, unless it's a
+    // skiplist (see DBConfig::memtable_impl).
+    active_memtable: Arc<Memtable>,
 
-    // Active memtable, the latest source of data mutations
-    active_memtable: Memtable,
+    // The memtables, newest first, for `DB::get()` to search without locking the
+    // database.  Only kept with MemtableImpl::SkipList, whose active memtable is
+    // written to in place; updated whenever the memtables are swapped.
+    published_memtables: Option<Arc<RwLock<Vec<Arc<Memtable>>>>>,
 
-    // Number of bytes that the active memtable has taken up so far.
-    // Accounts for key and value size.
-    active_memtable_size: usize,
+    // Where flushes and compactions leave the waits of DBConfig::rate_limiter
+    // while the database is locked by a DB, rather than sleeping under its lock.
+    deferred_wait: Option<Arc<DeferredWait>>,
 
     // Frozen memtables are former active memtables which got too big
     // (DBConfig::memtable_max_size_bytes) were snapshotted and saved. A frozen
@@ -75,631 +195,6190 @@ pub struct DB {
     // and removed from this list.
     //
     // The first element is the oldest memtable, the last is the newest.
-    frozen_memtables: VecDeque<Memtable>,
+    frozen_memtables: VecDeque<Arc<Memtable>>,
+
+    // Number of bytes that the frozen memtables take up; see `Memtable::size()`.
+    frozen_memtables_size: usize,
 
     config: DBConfig,
+
+    // Lists the database in the statistics registry, if it has a name.
+    _registration: Option<Registration>,
+
+    // Reports the size of the memtables to DBConfig::write_buffer_manager.
+    write_buffer: Option<WriteBufferHandle>,
+
+    // Sends every write to the subscribers; see `subscribe()`.
+    changes: ChangeFeed,
+    // Counts the times the database was opened for writing; see ChangeEvent::sequence.
+    incarnation: u64,
+
+    // Set for secondary instances, which read another process's database; see
+    // `open_as_secondary()`.
+    is_secondary: bool,
+
+    // Whether writes are currently delayed or stopped, as last reported to the
+    // event listeners.
+    write_stall_condition: WriteStallCondition,
+
+    // The number of memtables frozen so far, which is also the number of the
+    // active memtable; see write_handle.rs.
+    num_memtables_frozen: u64,
+
+    // Tells the handles of `put_async()` when their memtable is flushed.
+    flush_notifier: FlushNotifier,
 }
 
+// How opening the database handles the sstables its MANIFEST lists which are
+// missing or can't be opened.  There's no log yet, so these are the only files to
+// recover; a torn log tail will have its own mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecoveryMode {
+    // Refuse to open the database; `RawDB::repair()` salvages what it can.
+    #[default]
+    AbsoluteConsistency,
+    // Open the database without them, losing their keys: damaged sstables are
+    // moved into the `lost` directory, and both kinds are reported in
+    // OpenInfo::skipped_sstables.
+    SkipCorruptedSSTables,
+}
+
+#[derive(Clone)]
 pub struct DBConfig {
-    // Size threshold for a memtable (counts key & value)
+    // Size threshold for a memtable (see `Memtable::size()`; counts keys, values,
+    // range tombstones and per-entry overhead)
     pub memtable_max_size_bytes: usize,
     // Max number of frozen memtables before they are force-flushed to sstable
     pub max_frozen_memtables: usize,
+    // The data structure holding the memtables' entries
+    pub memtable_impl: MemtableImpl,
+    // Size of the blocks which skiplist memtables allocate their entries from
+    pub arena_block_size: usize,
+    // Collects counters about the database's activity; may be shared between databases.
+    pub statistics: Arc<Statistics>,
+
+    // How sstables are compacted
+    pub compaction_style: CompactionStyle,
+    // Which sstable leveled compaction picks from a level past 0
+    pub compaction_priority: CompactionPriority,
+    // Number of levels, including level 0
+    pub num_levels: usize,
+    // Number of level-0 sstables which triggers compacting them into level 1
+    pub level0_file_num_compaction_trigger: usize,
+    // Target size of level 1, in bytes
+    pub max_bytes_for_level_base: u64,
+    // Each level's target size is this many times the previous level's
+    pub max_bytes_for_level_multiplier: u64,
+    // Compactions split their output into sstables of about this size, in bytes
+    pub target_file_size_base: u64,
+    // Number of sstables smaller than `small_file_size` (capped at half of
+    // `target_file_size_base`) in a level which triggers merging them together;
+    // 0 disables it
+    pub small_file_compaction_trigger: usize,
+    pub small_file_size: u64,
+    // SSTables of a level past 0 whose share of deleted entries and range
+    // tombstones (see `TableProperties::deletion_ratio()`) reaches this are
+    // compacted, to drop the deletions sooner; 0.0 disables it
+    pub deletion_compaction_ratio: f64,
+    // Minimum and maximum number of sstables merged by a size-tiered compaction
+    pub size_tiered_min_merge_width: usize,
+    pub size_tiered_max_merge_width: usize,
+    // Size-tiered compactions merge sstables whose sizes are within this ratio
+    pub size_tiered_size_ratio: f64,
+    // With CompactionStyle::Fifo, the oldest sstables are dropped once all of them
+    // add up to more than this many bytes (0 for no limit), or once they're older
+    // than `fifo_ttl`, to the second (zero for no limit).  Checked after every
+    // flush, and by `compact()` and `flush_expired_memtables()`, so sstables only
+    // expire while the database is written to, unless `DB::start_flush_timer()`
+    // runs.
+    pub fifo_max_table_files_size: u64,
+    pub fifo_ttl: Duration,
+
+    // Number of level-0 sstables at which each write is delayed by
+    // `write_slowdown_delay`, and at which writes stop: the next write merges
+    // level 0 down, and fails with DBError::WriteStalled if it can't.  Only used
+    // with CompactionStyle::Leveled, since nothing else brings level 0 back under
+    // them.  Through a DB, the delay is waited out after releasing its lock.
+    pub level0_slowdown_writes_trigger: usize,
+    pub level0_stop_writes_trigger: usize,
+    // Number of frozen memtables (e.g. left behind by failed flushes) at which
+    // writes stop: the next write flushes them, and fails with
+    // DBError::WriteStalled if it can't.
+    pub frozen_memtables_stop_writes_trigger: usize,
+    pub write_slowdown_delay: Duration,
+
+    // Reads back every sstable written by flushes and compactions, to check that
+    // its keys are in order, before using it.
+    pub paranoid_checks: bool,
+
+    // Keeps the files written by flushes and compactions out of the page cache
+    // once they're synced, so they don't push out the application's data.  Rather
+    // than opening them with O_DIRECT, which needs aligned buffers, the storage is
+    // asked to drop them (see `Storage::drop_from_cache()`); storages which can't
+    // ignore it.
+    pub use_direct_io_for_flush_and_compaction: bool,
+    // Compactions read their inputs this many bytes at a time; 0 reads them block
+    // by block.
+    pub compaction_readahead_size: usize,
+
+    // Number of threads used to open the sstables when opening the database.
+    pub num_open_threads: usize,
+    // Opening an sstable reads its top-level index, prefix filter and properties;
+    // its block index partitions are read on first use.  Set this to read them
+    // all when opening the database too, so that first reads don't wait for them,
+    // at the cost of a slower open.
+    pub preload_index: bool,
+    pub recovery_mode: RecoveryMode,
+
+    // Names the database; while it's open, its statistics are listed under this
+    // name by `registered_statistics()`.
+    pub name: Option<String>,
+
+    // Values of at least this many bytes are moved into blob files when memtables
+    // are flushed, rather than being copied by every compaction; see blob.rs.
+    // Smaller values which don't fit in an sstable block (4 KB) get a block of
+    // their own.
+    pub min_blob_size: usize,
+
+    // Called on flushes, compactions and write stalls.
+    pub listeners: Vec<Arc<dyn EventListener>>,
+
+    // Where the database's files are stored; the local filesystem by default.
+    // Wrap it in an EncryptedStorage to encrypt them.
+    pub storage: Arc<dyn Storage>,
+
+    // Called with every value rewritten by a compaction, to keep, drop or change it.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    // Secondary indexes, which every write keeps up to date; see index.rs and
+    // `RawDB::seek_index()`.  Keys written before an index was added aren't in it.
+    pub indexes: Vec<Arc<dyn IndexExtractor>>,
+
+    // Groups keys by prefix, so that sstables record which prefixes they hold, and
+    // gets and `scan_prefix()` of a whole prefix skip the sstables without it.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+
+    // Orders keys other than byte by byte, e.g. ignoring case; see key_encoding.rs.
+    // TypedDB encodes the keys it's given with it, so that the memtables, merges
+    // and sstable indexes hold them in its order, and decodes the keys it scans.
+    // The byte API takes and returns encoded keys.  It can't change once keys were
+    // written.
+    pub key_encoding: Option<Arc<dyn KeyEncoding<Key = String>>>,
+
+    // Compute properties of each sstable as flushes and compactions write it; see
+    // `RawDB::table_properties()`.
+    pub table_properties_collectors: Vec<Arc<dyn TablePropertiesCollectorFactory>>,
+
+    // How many versions of each key written with a timestamp compactions keep: at
+    // most `max_versions` of them (0 for no limit), and none more than
+    // `max_version_age` older than the key's newest version, in the timestamps'
+    // units (0 for no limit).  The newest version is always kept.  Versions are
+    // only dropped by compactions into the bottommost level, which count those in
+    // their inputs, so more may be kept until they're compacted together.
+    pub max_versions: usize,
+    pub max_version_age: u64,
+
+    // How long writes are kept in memory for `get_updates_since()`; zero keeps none.
+    pub change_retention: Duration,
+
+    // Flushes the memtables once their oldest write is this old, even if they're
+    // small; zero never does.  Only checked on writes and by
+    // `flush_expired_memtables()`: a database which stops being written to keeps
+    // its memtables until then, unless `DB::start_flush_timer()` runs.
+    pub memtable_flush_interval: Duration,
+
+    // Caps the size of the memtables of every database sharing it.
+    pub write_buffer_manager: Option<Arc<WriteBufferManager>>,
+
+    // Limits how fast flushes and compactions write; may be shared between
+    // databases, and adjusted while they're open.  A DB's writes wait for it after
+    // releasing the lock, so other threads aren't held up.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl Default for DBConfig {
     fn default() -> Self {
         DBConfig {
-            memtable_max_size_bytes: 1024 * 1024 * 1, // 1 MB
+            memtable_max_size_bytes: 1024 * 1024, // 1 MB
             max_frozen_memtables: 1,
+            memtable_impl: MemtableImpl::default(),
+            arena_block_size: 64 * 1024, // 64 KB
+            statistics: Arc::new(Statistics::new()),
+            compaction_style: CompactionStyle::default(),
+            compaction_priority: CompactionPriority::default(),
+            num_levels: 7,
+            level0_file_num_compaction_trigger: 4,
+            max_bytes_for_level_base: 10 * 1024 * 1024, // 10 MB
+            max_bytes_for_level_multiplier: 10,
+            target_file_size_base: 2 * 1024 * 1024, // 2 MB
+            small_file_compaction_trigger: 8,
+            small_file_size: 512 * 1024, // 512 KB
+            deletion_compaction_ratio: 0.0,
+            size_tiered_min_merge_width: 4,
+            size_tiered_max_merge_width: 32,
+            size_tiered_size_ratio: 2.0,
+            fifo_max_table_files_size: 0,
+            fifo_ttl: Duration::ZERO,
+            level0_slowdown_writes_trigger: 20,
+            level0_stop_writes_trigger: 36,
+            frozen_memtables_stop_writes_trigger: 16,
+            write_slowdown_delay: Duration::from_millis(1),
+            paranoid_checks: false,
+            use_direct_io_for_flush_and_compaction: false,
+            compaction_readahead_size: 2 * 1024 * 1024, // 2 MB
+            num_open_threads: 4,
+            preload_index: false,
+            recovery_mode: RecoveryMode::default(),
+            name: None,
+            min_blob_size: 2 * 1024, // 2 KB
+            listeners: Vec::new(),
+            storage: Arc::new(FileStorage),
+            compaction_filter: None,
+            indexes: Vec::new(),
+            prefix_extractor: None,
+            key_encoding: None,
+            table_properties_collectors: Vec::new(),
+            max_versions: 0,
+            max_version_age: 0,
+            change_retention: Duration::ZERO,
+            memtable_flush_interval: Duration::ZERO,
+            write_buffer_manager: None,
+            rate_limiter: None,
         }
     }
 }
 
-impl DB {
+// Options for RawDB::scan_prefix().  More options may be added, so outside the
+// crate, start from `ReadOptions::default()` and set them with the `with_*()`
+// methods.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReadOptions {
+    // Return at most this many keys.
+    pub limit: Option<usize>,
+    // Return the keys in descending order, starting from the last one.  The scan
+    // still reads the whole prefix (after `start_after`), but only holds on to
+    // `limit` entries.
+    pub reverse: bool,
+    // Also return keys which were deleted, with no value.  Keys deleted by a range
+    // tombstone are never returned.
+    pub include_deleted_markers: bool,
+    // Only return the keys after this one (before it, with `reverse`), e.g. the
+    // last key of the previous page.
+    pub start_after: Option<Key>,
+    // Skips the entries of the sstables it returns false for, e.g. those whose
+    // user properties (see DBConfig::table_properties_collectors) show they hold
+    // nothing of interest.  Their range tombstones still apply, but keys they
+    // delete or overwrite may be returned with older values from other sstables.
+    // The memtables are always read.
+    pub table_filter: Option<TableFilter>,
+}
+
+impl ReadOptions {
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    pub fn with_include_deleted_markers(mut self, include_deleted_markers: bool) -> Self {
+        self.include_deleted_markers = include_deleted_markers;
+        self
+    }
+
+    pub fn with_start_after(mut self, start_after: impl Into<Key>) -> Self {
+        self.start_after = Some(start_after.into());
+        self
+    }
+
+    pub fn with_table_filter(mut self, table_filter: TableFilter) -> Self {
+        self.table_filter = Some(table_filter);
+        self
+    }
+}
+
+impl RawDB {
     // `root_path` is the directory where data files will live.
-    pub fn open(root_path: &Path) -> Result<DB, DBError> {
-        DB::open_with_config(root_path, DBConfig::default())
+    pub fn open(root_path: &Path) -> Result<RawDB, DBError> {
+        RawDB::open_with_config(root_path, DBConfig::default())
     }
 
     // `root_path` is the directory where data files will live.
-    pub fn open_with_config(root_path: &Path, config: DBConfig) -> Result<DB, DBError> {
-        Ok(DB {
+    pub fn open_with_config(root_path: &Path, config: DBConfig) -> Result<RawDB, DBError> {
+        let start = Instant::now();
+        let (mut levels, next_file_number, blob_files, incarnation, skipped_sstables) =
+            Self::open_all_sstables(&config, root_path)?;
+        while levels.len() < config.num_levels {
+            levels.push(Vec::new());
+        }
+        let info = OpenInfo {
+            num_sstables: levels.iter().map(Vec::len).sum(),
+            sstable_bytes: levels.iter().flatten().map(SSTableReader::file_size).sum(),
+            num_blob_files: blob_files.len(),
+            skipped_sstables,
+            duration: start.elapsed(),
+        };
+        for listener in &config.listeners {
+            listener.on_open_completed(&info);
+        }
+        let mut db = RawDB {
             root_path: root_path.into(),
-            sstables: Self::open_all_sstables(root_path)?,
-            active_memtable: BTreeMap::new(),
-            active_memtable_size: 0,
-            frozen_memtables: VecDeque::<Memtable>::new(),
+            levels,
+            next_file_number,
+            blob_files,
+            active_memtable: Arc::new(Memtable::new(&config)),
+            published_memtables: None,
+            deferred_wait: None,
+            frozen_memtables: VecDeque::new(),
+            frozen_memtables_size: 0,
+            _registration: config
+                .name
+                .clone()
+                .map(|name| Registration::new(name, config.statistics.clone())),
+            write_buffer: config
+                .write_buffer_manager
+                .clone()
+                .map(WriteBufferHandle::new),
+            changes: ChangeFeed::new(config.change_retention, incarnation),
+            incarnation,
             config,
-        })
+            is_secondary: false,
+            write_stall_condition: WriteStallCondition::Normal,
+            num_memtables_frozen: 0,
+            flush_notifier: FlushNotifier::new(),
+        };
+        if db.config.memtable_impl == MemtableImpl::SkipList {
+            db.published_memtables = Some(Arc::new(RwLock::new(db.memtables_newest_first())));
+        }
+        // Record the incarnation before handing out any sequence number in it.
+        db.write_manifest()
+            .map_err(|io_err| DBError::Manifest(io_err.to_string()))?;
+        Ok(db)
+    }
+
+    // Opens a database which only lives in memory, for tests and caches.  Nothing
+    // is written to disk, and the data is gone once the database is dropped.
+    pub fn open_in_memory() -> Result<RawDB, DBError> {
+        RawDB::open_in_memory_with_config(DBConfig::default())
+    }
+
+    // Like `open_in_memory()`; `config.storage` is replaced by a new MemStorage.
+    pub fn open_in_memory_with_config(config: DBConfig) -> Result<RawDB, DBError> {
+        let config = DBConfig {
+            storage: Arc::new(MemStorage::new()),
+            ..config
+        };
+        RawDB::open_with_config(Path::new("/"), config)
+    }
+
+    // Opens a read-only view of the database at `primary_path`, which another
+    // instance (possibly in another process) keeps writing to.
+    //
+    // The secondary instance only sees the primary's sstables, not its memtables.
+    // Call `try_catch_up()` to pick up the primary's latest flushes and compactions.
+    //
+    // Unlike RocksDB's, it takes no secondary path: the secondary never writes any
+    // files of its own (see the README's TODO).
+    pub fn open_as_secondary(primary_path: &Path, config: DBConfig) -> Result<RawDB, DBError> {
+        let mut db = RawDB {
+            root_path: primary_path.into(),
+            levels: (0..config.num_levels.max(1)).map(|_| Vec::new()).collect(),
+            next_file_number: 0,
+            blob_files: Vec::new(),
+            active_memtable: Arc::new(Memtable::new(&config)),
+            published_memtables: None,
+            deferred_wait: None,
+            frozen_memtables: VecDeque::new(),
+            frozen_memtables_size: 0,
+            _registration: config
+                .name
+                .clone()
+                .map(|name| Registration::new(name, config.statistics.clone())),
+            write_buffer: None,
+            changes: ChangeFeed::new(config.change_retention, 0),
+            incarnation: 0,
+            config,
+            is_secondary: true,
+            write_stall_condition: WriteStallCondition::Normal,
+            num_memtables_frozen: 0,
+            flush_notifier: FlushNotifier::new(),
+        };
+        db.try_catch_up()?;
+        Ok(db)
+    }
+
+    // Reloads the primary's MANIFEST, opening its new sstables and dropping the ones
+    // it deleted.  If this fails, e.g. because the primary deleted an sstable in the
+    // meantime, the secondary keeps its current sstables, and can try again later.
+    //
+    // Does nothing for a primary instance, which is always up to date.
+    pub fn try_catch_up(&mut self) -> Result<(), DBError> {
+        if !self.is_secondary {
+            return Ok(());
+        }
+        let storage = &*self.config.storage;
+        let Some(manifest) = Manifest::read_from(storage, &self.root_path)? else {
+            // Nothing was flushed yet.
+            return Ok(());
+        };
+        // A damaged MANIFEST may list an sstable twice; check before giving up the
+        // current sstables.
+        let mut listed = HashSet::new();
+        if let Some(file_number) = manifest
+            .levels
+            .iter()
+            .flatten()
+            .find(|&&file_number| !listed.insert(file_number))
+        {
+            return Err(DBError::Manifest(format!(
+                "sstable {} is listed more than once",
+                file_number
+            )));
+        }
+
+        // Only open the sstables which are new since the last catch up.
+        let current_file_numbers: HashSet<u64> = self
+            .levels
+            .iter()
+            .flatten()
+            .filter_map(|sstable| manifest::sstable_file_number(sstable.path()))
+            .collect();
+        let new_file_numbers: Vec<u64> = manifest
+            .levels
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|file_number| !current_file_numbers.contains(file_number))
+            .collect();
+        let new_paths: Vec<PathBuf> = new_file_numbers
+            .iter()
+            .map(|&file_number| manifest::sstable_path(&self.root_path, file_number))
+            .collect();
+        let new_sstables = Self::open_sstables(&self.config, &new_paths)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(SSTableError::into_db_error)?;
+
+        let mut sstables: HashMap<u64, SSTableReader> = self
+            .levels
+            .iter_mut()
+            .flat_map(std::mem::take)
+            .map(|sstable| {
+                let file_number = manifest::sstable_file_number(sstable.path())
+                    .expect("sstables are named by their file number");
+                (file_number, sstable)
+            })
+            .chain(new_file_numbers.into_iter().zip(new_sstables))
+            .collect();
+        self.levels = manifest
+            .levels
+            .iter()
+            .map(|level| {
+                level
+                    .iter()
+                    .map(|file_number| {
+                        sstables.remove(file_number).ok_or_else(|| {
+                            DBError::Manifest(format!("sstable {} isn't open", file_number))
+                        })
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+        while self.levels.len() < self.config.num_levels.max(1) {
+            self.levels.push(Vec::new());
+        }
+        self.next_file_number = manifest.next_file_number;
+        self.blob_files = manifest.blob_files;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), DBError> {
+        if self.is_secondary {
+            return Err(DBError::ReadOnly);
+        }
+        Ok(())
     }
 
-    // Opens all SSTable files stored under given the `root_path` directory.
+    // Opens all SSTable files stored under given the `root_path` directory, and
+    // returns them by level, along with the next file number to use, the blob
+    // files, the incarnation of this opening (one more than the MANIFEST's), and
+    // the sstables skipped by RecoveryMode::SkipCorruptedSSTables.
+    //
+    // SSTable filenames are formatted as <file number>.sst.  The MANIFEST records
+    // which level each sstable belongs to.
     //
-    // SSTable filenames are formatted as <age>.sstable, where <age> is a number used
-    // to signify the precedence order of the sstables.
+    // Databases written before the MANIFEST existed have level-0 sstables only,
+    // where the file number is the sstable's age:
     // - The oldest SSTable is `0.sst`, the 2nd oldest is `1.sst`, and so on.
     // - The newest SSTable has the highest number.
-    // - New SSTables are stored using the filename `<highest age so far + 1>.sst`.
-    fn open_all_sstables(root_path: &Path) -> Result<Vec<SSTableReader>, DBError> {
-        if !root_path
-            .try_exists()
+    #[allow(clippy::type_complexity)]
+    fn open_all_sstables(
+        config: &DBConfig,
+        root_path: &Path,
+    ) -> Result<(Vec<Vec<SSTableReader>>, u64, Vec<u64>, u64, Vec<PathBuf>), DBError> {
+        let storage = &*config.storage;
+        if !storage
+            .exists(root_path)
             .map_err(|io_err| DBError::InvalidRootPath(io_err.to_string()))?
         {
             // create dir and exit
-            DirBuilder::new()
-                .recursive(true)
-                .create(root_path)
+            storage
+                .create_dir_all(root_path)
                 .map_err(|io_err| DBError::Io(io_err.to_string()))?;
-            return Ok(Vec::new());
-        } else if !root_path.is_dir() {
+            return Ok((Vec::new(), 0, Vec::new(), 0, Vec::new()));
+        }
+        let Ok(dir_paths) = storage.list_dir(root_path) else {
             return Err(DBError::InvalidRootPath(
                 root_path.to_str().unwrap().to_string(),
             ));
-        }
+        };
 
-        // Grab all the .sst files, which are formatted as `<age>.sst`
-        // sort them by their age (ascending), and open/store them in this sorted order.
-        // 1. Grab all the paths
-        let mut path_bufs = Vec::new();
-        for dirent in root_path
-            .read_dir()
-            .map_err(|io_err: std::io::Error| DBError::Io(io_err.to_string()))?
-        {
-            let path_buf = dirent
-                .map_err(|io_err: std::io::Error| DBError::Io(io_err.to_string()))?
-                .path();
-            let sst_num = i32::from_str_radix(
-                path_buf
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .split(".")
-                    .next()
-                    .unwrap(),
-                10,
-            )
-            .map_err(|err| DBError::SSTableFilePath(err.to_string()))?;
-            path_bufs.push((sst_num, path_buf.clone()));
+        // Grab all the .sst files, which are formatted as `<file number>.sst`, and
+        // the blob files
+        let mut sst_nums = Vec::new();
+        let mut blob_nums = Vec::new();
+        for path_buf in dir_paths {
+            if path_buf
+                .extension()
+                .is_some_and(|extension| extension == "sst")
+            {
+                sst_nums.push(manifest::sstable_file_number(&path_buf).ok_or_else(|| {
+                    DBError::SSTableFilePath(path_buf.to_string_lossy().to_string())
+                })?);
+            } else if let Some(blob_num) = blob::blob_file_number(&path_buf) {
+                blob_nums.push(blob_num);
+            }
         }
-        path_bufs.sort_by_key(|(num, _)| *num);
 
-        let mut readers = Vec::new();
-        for (_, path_buf) in path_bufs {
-            readers.push(
-                SSTableReader::from_path(&path_buf)
-                    .map_err(|err| DBError::SSTable(err.to_string()))?,
-            );
-        }
-        Ok(readers)
-    }
+        let manifest = match Manifest::read_from(storage, root_path)? {
+            Some(mut manifest) => {
+                manifest.incarnation += 1;
+                // Delete sstables which aren't in the MANIFEST; they are left over from
+                // flushes or compactions which didn't finish.
+                for sst_num in sst_nums {
+                    if !manifest.levels.iter().any(|level| level.contains(&sst_num)) {
+                        storage
+                            .remove_file(&manifest::sstable_path(root_path, sst_num))
+                            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+                    }
+                }
+                for blob_num in blob_nums {
+                    if !manifest.blob_files.contains(&blob_num) {
+                        storage
+                            .remove_file(&blob::blob_path(root_path, blob_num))
+                            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+                    }
+                }
+                manifest
+            }
+            None => {
+                // No MANIFEST: sort the sstables by their age (ascending) into level 0.
+                sst_nums.sort();
+                blob_nums.sort();
+                Manifest {
+                    next_file_number: sst_nums
+                        .iter()
+                        .chain(&blob_nums)
+                        .max()
+                        .map_or(0, |num| num + 1),
+                    levels: vec![sst_nums],
+                    blob_files: blob_nums,
+                    incarnation: 0,
+                }
+            }
+        };
 
-    // Looks up the given `key`.
-    //
-    // Returns `Some(value)` if the given `key` is found.
-    pub fn get(&mut self, key: &str) -> Result<Option<Value>, DBError> {
-        // first check the active memtable
-        // if not in the active memtable, check the frozen memtables
-        // we have to check the most recently frozen memtable first (the last element)
-        for memtable in self
-            .frozen_memtables
+        let paths: Vec<PathBuf> = manifest
+            .levels
             .iter()
-            .chain([&self.active_memtable])
-            .rev()
-        {
-            match self.get_from_memtable(key, memtable)? {
-                Some(EntryValue::Present(value)) => return Ok(Some(value)),
-                Some(EntryValue::Deleted) => return Ok(None),
-                _ => continue,
-            };
+            .flatten()
+            .map(|&sst_num| manifest::sstable_path(root_path, sst_num))
+            .collect();
+        let mut readers = Self::open_sstables(config, &paths).into_iter().zip(&paths);
+        let mut skipped_sstables = Vec::new();
+        let mut levels = Vec::with_capacity(manifest.levels.len());
+        for level in &manifest.levels {
+            let mut sstables = Vec::with_capacity(level.len());
+            for (reader, path) in readers.by_ref().take(level.len()) {
+                match reader {
+                    Ok(sstable) => sstables.push(sstable),
+                    Err(err @ SSTableError::UnsupportedVersion { .. }) => {
+                        return Err(err.into_db_error())
+                    }
+                    Err(err) if config.recovery_mode == RecoveryMode::AbsoluteConsistency => {
+                        return Err(err.into_db_error())
+                    }
+                    Err(_) => {
+                        let exists = storage
+                            .exists(path)
+                            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+                        skipped_sstables.push(if exists {
+                            repair::move_to_lost(storage, root_path, path)?
+                        } else {
+                            path.clone()
+                        });
+                    }
+                }
+            }
+            levels.push(sstables);
         }
+        Ok((
+            levels,
+            manifest.next_file_number,
+            manifest.blob_files,
+            manifest.incarnation,
+            skipped_sstables,
+        ))
+    }
 
-        // Not in the memtables?  Lets try the sstables
-        // Newest one first
-        for sstable in self.sstables.iter_mut().rev() {
-            match sstable.get(key) {
-                Ok(Some(EntryValue::Present(value))) => return Ok(Some(value)),
-                Ok(Some(EntryValue::Deleted)) => return Ok(None),
-                _ => continue,
-            }
+    // Opens the sstables at `paths`, spread over up to `config.num_open_threads`
+    // threads, and returns them, or why they couldn't be opened, in the same order.
+    // Opening reads each sstable's index (all of it with `config.preload_index`),
+    // which adds up for databases with many sstables.
+    fn open_sstables(
+        config: &DBConfig,
+        paths: &[PathBuf],
+    ) -> Vec<Result<SSTableReader, SSTableError>> {
+        if paths.is_empty() {
+            return Vec::new();
         }
+        let storage = &*config.storage;
+        let preload_index = config.preload_index;
+        let chunk_size = paths.len().div_ceil(config.num_open_threads.max(1));
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = paths
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|path| {
+                                let mut sstable = SSTableReader::from_path(storage, path)?;
+                                if preload_index {
+                                    sstable.read_all_partitions()?;
+                                }
+                                Ok(sstable)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            let mut readers = Vec::with_capacity(paths.len());
+            for thread in threads {
+                readers.extend(thread.join().expect("sstable opening thread panicked"));
+            }
+            readers
+        })
+    }
 
-        Ok(None)
+    // Records the current sstables of each level in the MANIFEST.
+    fn write_manifest(&self) -> Result<(), std::io::Error> {
+        self.manifest()
+            .write_to(&*self.config.storage, &self.root_path)
     }
 
-    pub fn put(&mut self, key: impl Into<Key>, value: impl Into<Value>) -> Result<(), DBError> {
-        self.put_entry(key.into(), EntryValue::Present(value.into()))
+    fn manifest(&self) -> Manifest {
+        Manifest {
+            next_file_number: self.next_file_number,
+            levels: self
+                .levels
+                .iter()
+                .map(|level| {
+                    level
+                        .iter()
+                        .map(|sstable| {
+                            manifest::sstable_file_number(sstable.path())
+                                .expect("sstables are named by their file number")
+                        })
+                        .collect()
+                })
+                .collect(),
+            blob_files: self.blob_files.clone(),
+            incarnation: self.incarnation,
+        }
     }
 
-    pub fn delete(&mut self, key: impl Into<Key>) -> Result<(), DBError> {
-        self.put_entry(key.into(), EntryValue::Deleted)
+    // The directory the database's files live in.
+    pub fn path(&self) -> &Path {
+        &self.root_path
     }
 
-    pub fn seek(&self, key_prefix: &str) -> Result<DBIterator, DBError> {
-        // make a min-heap of peekable iterators, where the heap key is:
-        // (peekable iterator, precedent)
-        Ok(DBIterator {
-            // most recent memtable is first
-            memtables: {
-                let memtable_iters = self
-                    .frozen_memtables
+    // The files which currently make up the database: the MANIFEST, then the
+    // sstables of each level and the blob files.  Writes still in the memtables
+    // aren't in any of them; `flush()` first to include them.  Copying these files
+    // while nothing compacts (e.g. with compactions paused) gives a usable backup.
+    pub fn live_files(&self) -> Vec<PathBuf> {
+        let manifest = self.manifest();
+        std::iter::once(self.root_path.join(manifest::MANIFEST_FILENAME))
+            .chain(
+                manifest
+                    .levels
                     .iter()
-                    .chain([&self.active_memtable])
-                    .map(|memtable| {
-                        memtable
-                            .range((Bound::Included(key_prefix.to_string()), Bound::Unbounded))
-                            .peekable()
-                    })
-                    .rev();
-                let mut heap = BinaryHeap::new();
-                let mut memtable_order = 0; // 0 means newest
-                for memtable_iter in memtable_iters {
-                    heap.push(Reverse(DBIteratorItem(
-                        RefCell::new(memtable_iter),
-                        memtable_order,
-                    )));
-                    memtable_order += 1;
-                }
-                heap
-            },
-            prefix: key_prefix.to_string(),
-        })
+                    .flatten()
+                    .map(|&file_number| manifest::sstable_path(&self.root_path, file_number)),
+            )
+            .chain(
+                manifest
+                    .blob_files
+                    .iter()
+                    .map(|&file_number| blob::blob_path(&self.root_path, file_number)),
+            )
+            .collect()
     }
 
-    fn get_from_memtable(
-        &self,
-        key: &str,
-        memtable: &BTreeMap<Key, EntryValue>,
-    ) -> Result<Option<EntryValue>, DBError> {
-        Ok(memtable.get(key).cloned())
+    // The properties of every sstable, level by level; see properties.rs.
+    pub fn table_properties(&self) -> Vec<TableProperties> {
+        self.levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, sstables)| {
+                sstables
+                    .iter()
+                    .map(move |sstable| sstable.table_properties(level))
+            })
+            .collect()
     }
 
-    fn put_entry(&mut self, key: Key, entry: EntryValue) -> Result<(), DBError> {
-        let key_len = key.as_bytes().len();
-        let value_len = entry.len();
-        match self.active_memtable.insert(key, entry) {
-            Some(old_value) => {
-                self.active_memtable_size += value_len;
-                self.active_memtable_size -= old_value.len();
-            }
-            None => {
-                self.active_memtable_size += key_len;
-                self.active_memtable_size += value_len;
+    // Writes the memtables out to sstables, and compacts them as needed.  Without
+    // a log, writes which weren't flushed are lost if the process exits.
+    pub fn flush(&mut self) -> Result<(), DBError> {
+        self.check_writable()?;
+        self.flush_memtables()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        self.maybe_compact()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        self.update_write_stall_condition();
+        Ok(())
+    }
+
+    // Flushes the memtables if their oldest write is older than
+    // DBConfig::memtable_flush_interval, and returns whether it did.  With
+    // CompactionStyle::Fifo, also drops the sstables older than DBConfig::fifo_ttl.
+    // Writes check this themselves; call it periodically (e.g. through
+    // `DB::start_flush_timer()`) for a database which stopped being written to.
+    pub fn flush_expired_memtables(&mut self) -> Result<bool, DBError> {
+        if !self.memtables_expired() {
+            if self.config.compaction_style == CompactionStyle::Fifo {
+                self.drop_expired_sstables()
+                    .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+                self.update_write_stall_condition();
             }
+            return Ok(false);
         }
-        if self.active_memtable_size >= self.config.memtable_max_size_bytes {
-            self.freeze_active_memtable()
-                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        self.flush()?;
+        Ok(true)
+    }
+
+    // Flushes the memtables, then merges every sstable down into the deepest level
+    // holding data (at least level 1), which drops deleted keys and overwritten
+    // values.  With CompactionStyle::None or SizeTiered, level 0 is merged into a
+    // single sstable instead, and with Fifo, nothing's merged, but expired sstables
+    // are dropped.
+    pub fn compact(&mut self) -> Result<(), DBError> {
+        self.compact_levels(None)
+    }
+
+    // Like `compact()`, but past level 0 only the sstables holding keys in
+    // [`start`, `end`] (and those they overlap in the next level) are merged down,
+    // e.g. to drop the deletions piled up in a range.  Level 0 sstables overlap
+    // each other, so they're all merged.
+    pub fn compact_range(&mut self, start: &str, end: &str) -> Result<(), DBError> {
+        self.compact_levels(Some((start, end)))
+    }
+
+    fn compact_levels(&mut self, range: Option<(&str, &str)>) -> Result<(), DBError> {
+        self.check_writable()?;
+        self.flush_memtables()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        if self.config.compaction_style == CompactionStyle::Fifo {
+            return self
+                .maybe_compact()
+                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()));
         }
-        if self.frozen_memtables.len() > self.config.max_frozen_memtables {
-            self.flush_frozen_memtables()
+        let mut compactions = Vec::new();
+        if self.config.compaction_style == CompactionStyle::Leveled {
+            let last_level = self
+                .levels
+                .iter()
+                .rposition(|sstables| !sstables.is_empty())
+                .unwrap_or_default()
+                .max(1)
+                .min(self.config.num_levels.saturating_sub(1));
+            compactions.extend((0..last_level).map(|level| (level, level + 1)));
+        } else {
+            compactions.push((0, 0));
+        }
+        for (level, output_level) in compactions {
+            let inputs = match range {
+                Some((start, end)) if level > 0 => {
+                    compaction::overlapping_sstables(&self.levels[level], start, end)
+                }
+                _ => (0..self.levels[level].len()).collect(),
+            };
+            if inputs.is_empty() {
+                continue;
+            }
+            let compaction = self.compaction_of(level, output_level, inputs);
+            self.run_compaction(compaction)
                 .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
         }
+        self.update_write_stall_condition();
         Ok(())
     }
 
-    pub(crate) fn freeze_active_memtable(&mut self) -> Result<(), SSTableError> {
-        self.frozen_memtables
-            .push_back(std::mem::take(&mut self.active_memtable));
+    // The compaction of the `inputs` sstables of `level` into `output_level`, with
+    // the sstables they overlap there.
+    fn compaction_of(
+        &self,
+        level: usize,
+        output_level: usize,
+        inputs: Vec<usize>,
+    ) -> compaction::Compaction {
+        let output_level_inputs =
+            match compaction::key_range(inputs.iter().map(|&input| &self.levels[level][input])) {
+                Some((smallest, largest)) if output_level != level => self
+                    .levels
+                    .get(output_level)
+                    .map(|sstables| compaction::overlapping_sstables(sstables, &smallest, &largest))
+                    .unwrap_or_default(),
+                _ => Vec::new(),
+            };
+        compaction::Compaction {
+            level,
+            output_level,
+            inputs,
+            output_level_inputs,
+        }
+    }
+
+    // Backs up the database into `backup_path`, which can then be opened like any
+    // other database, or restored with `restore_from_backup()`.  Returns the number
+    // of sstables copied.
+    //
+    // The memtables are flushed first, so the backup holds every write so far.
+    // Backing up into the same directory again only copies the new sstables.
+    // Secondary instances can't flush, so they can't back up either.
+    pub fn create_backup(&mut self, backup_path: &Path) -> Result<usize, DBError> {
+        self.check_writable()?;
+        self.flush_memtables()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        backup::copy_database(
+            &*self.config.storage,
+            &self.root_path,
+            &self.manifest(),
+            backup_path,
+        )
+        .map_err(|io_err| DBError::Io(io_err.to_string()))
+    }
+
+    // Creates an openable copy of the database at `checkpoint_path`, which must not
+    // exist yet.  SSTables are hard linked where possible, so checkpoints are cheap
+    // and take little extra space until the database compacts its sstables away.
+    pub fn checkpoint(&mut self, checkpoint_path: &Path) -> Result<(), DBError> {
+        if self
+            .config
+            .storage
+            .exists(checkpoint_path)
+            .map_err(|io_err| DBError::Io(io_err.to_string()))?
+        {
+            return Err(DBError::InvalidRootPath(format!(
+                "{} already exists",
+                checkpoint_path.to_string_lossy()
+            )));
+        }
+        self.create_backup(checkpoint_path)?;
         Ok(())
     }
 
-    pub(crate) fn flush_frozen_memtables(&mut self) -> Result<(), SSTableError> {
-        for frozen_memtable in self.frozen_memtables.iter() {
-            let i = self.sstables.len();
-            let sstable_path = self.root_path.join(format!("{}.sst", i));
+    // Adds an sstable built with `SstWriter` to the database, without going through
+    // the memtables.  The sstable, a local file, is copied into the database's
+    // storage (hard linked for FileStorage), and its entries replace any older
+    // entries for the same keys.
+    pub fn ingest_sst(&mut self, path: &Path) -> Result<(), DBError> {
+        self.check_writable()?;
+        let mut sstable = SSTableReader::from_path(&FileStorage, path)
+            .map_err(|err| DBError::SSTable(err.to_string()))?;
+        sstable
+            .verify()
+            .map_err(|err| DBError::SSTable(err.to_string()))?;
+        let Some((smallest, largest)) = sstable
+            .key_range()
+            .map(|(smallest, largest)| (smallest.to_string(), largest.to_string()))
+        else {
+            return Ok(());
+        };
 
-            // flush the frozen memtable to sstable
-            let mut sstable_file =
-                std::fs::File::create(sstable_path.clone()).expect("could not create sstable file");
-            write_memtable_to_sstable(&frozen_memtable, &mut sstable_file)?;
-            sstable_file.sync_all()?;
-            std::mem::drop(sstable_file);
+        // The ingested entries must be newer than the memtables' entries.
+        let overlaps_memtables = [&self.active_memtable]
+            .into_iter()
+            .chain(self.frozen_memtables.iter())
+            .any(|memtable| {
+                memtable
+                    .entries
+                    .range(
+                        Bound::Included(smallest.as_str()),
+                        Bound::Included(largest.as_str()),
+                    )
+                    .next()
+                    .is_some()
+                    || memtable.range_tombstones.iter().any(|tombstone| {
+                        tombstone.start.as_str() <= largest.as_str()
+                            && smallest.as_str() < tombstone.end.as_str()
+                    })
+            });
+        if overlaps_memtables {
+            self.flush_memtables()
+                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        }
 
-            self.sstables.push(SSTableReader::from_path(&sstable_path)?);
+        // Put the sstable in the deepest level it can go to, while staying newer than
+        // everything it overlaps: above the first level holding overlapping keys.
+        let mut level = 0;
+        if self.config.compaction_style == CompactionStyle::Leveled {
+            for (i, sstables) in self.levels.iter().enumerate() {
+                if !compaction::overlapping_sstables(sstables, &smallest, &largest).is_empty() {
+                    break;
+                }
+                level = i;
+            }
         }
 
-        // remove all frozen memtables; From now on, DB::get() will query the sstable instead.
-        self.frozen_memtables.clear();
+        let sstable_path = manifest::sstable_path(&self.root_path, self.next_file_number);
+        self.next_file_number += 1;
+        self.config
+            .storage
+            .copy_from_local(path, &sstable_path)
+            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        let sstable = SSTableReader::from_path(&*self.config.storage, &sstable_path)
+            .map_err(|err| DBError::SSTable(err.to_string()))?;
+        if level == 0 {
+            self.levels[0].push(sstable);
+        } else {
+            self.levels[level].push(sstable);
+            self.levels[level].sort_by(|a, b| a.key_range().cmp(&b.key_range()));
+        }
+        self.write_manifest()
+            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        self.maybe_compact()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))
+    }
 
+    // Copies the backup in `backup_path` into `target_path`, which must not hold a
+    // database already.  Both are on the local filesystem.
+    pub fn restore_from_backup(backup_path: &Path, target_path: &Path) -> Result<(), DBError> {
+        let manifest = Manifest::read_from(&FileStorage, backup_path)?
+            .ok_or_else(|| DBError::Manifest("backup has no MANIFEST".to_string()))?;
+        let holds_database = target_path.join(manifest::MANIFEST_FILENAME).exists()
+            || target_path.read_dir().is_ok_and(|mut dirents| {
+                dirents.any(|dirent| {
+                    dirent
+                        .is_ok_and(|dirent| manifest::sstable_file_number(&dirent.path()).is_some())
+                })
+            });
+        if holds_database {
+            return Err(DBError::InvalidRootPath(format!(
+                "{} already holds a database",
+                target_path.to_string_lossy()
+            )));
+        }
+        backup::copy_database(&FileStorage, backup_path, &manifest, target_path)
+            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
         Ok(())
     }
-}
 
-type MemtablePeekableIter<'a> = Peekable<Range<'a, Key, EntryValue>>;
-type MemtableOrder = u32; // smaller is newer
+    // Rebuilds the database in `root_path` after its MANIFEST or sstables were
+    // damaged, keeping whatever can still be read; see repair.rs.  The database
+    // must not be open.
+    pub fn repair(root_path: &Path, config: &DBConfig) -> Result<RepairReport, DBError> {
+        repair::repair_database(&*config.storage, root_path)
+    }
 
-// TODO: Avoid using a RefCell<> by caching the saving the next key in DBIteratorItem.
-// e.g., struct DBIteratorItem(next_key, MemTableIter<>, MemtableOrder);
-// TODO: Instead of just iterating over Memtable, also iterate over SSTables.
-struct DBIteratorItem<'a>(RefCell<MemtablePeekableIter<'a>>, MemtableOrder);
+    // Reads every file of the database and checks the invariants between them,
+    // returning the problems found; see verify.rs.  Unlike `repair()`, nothing is
+    // changed.
+    pub fn verify_integrity(&self) -> Result<IntegrityReport, DBError> {
+        verify::verify_database(&*self.config.storage, &self.root_path, &self.manifest())
+    }
 
-impl<'a> Ord for DBIteratorItem<'a> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match (&self.0.borrow_mut().peek(), &other.0.borrow_mut().peek()) {
-            (Some(_), None) => Ordering::Less,
-            (None, Some(_)) => Ordering::Greater,
-            (Some((self_key, _)), Some((ref other_key, _))) => {
-                (self_key, self.1).cmp(&(&&other_key, other.1))
+    // Deletes the database in `root_path`, which must not be open.  Only the
+    // MANIFEST and the sstables and blob files it lists are removed; any other
+    // files, and the directory itself, are left alone.
+    pub fn destroy(root_path: &Path, config: &DBConfig) -> Result<(), DBError> {
+        let storage = &*config.storage;
+        let manifest = Manifest::read_from(storage, root_path)?.ok_or_else(|| {
+            DBError::InvalidRootPath(format!("{} holds no database", root_path.to_string_lossy()))
+        })?;
+        let data_files = manifest
+            .levels
+            .iter()
+            .flatten()
+            .map(|&file_number| manifest::sstable_path(root_path, file_number))
+            .chain(
+                manifest
+                    .blob_files
+                    .iter()
+                    .map(|&file_number| blob::blob_path(root_path, file_number)),
+            );
+        // The MANIFEST goes last, so an interrupted destroy can be run again.
+        for path in data_files.chain([
+            root_path.join(manifest::MANIFEST_TMP_FILENAME),
+            root_path.join(manifest::MANIFEST_FILENAME),
+        ]) {
+            if storage
+                .exists(&path)
+                .map_err(|io_err| DBError::Io(io_err.to_string()))?
+            {
+                storage
+                    .remove_file(&path)
+                    .map_err(|io_err| DBError::Io(io_err.to_string()))?;
             }
-            (None, None) => Ordering::Equal,
         }
+        Ok(())
     }
-}
-impl<'a> PartialOrd for DBIteratorItem<'a> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(Ord::cmp(self, other))
-    }
-}
-impl<'a> PartialEq for DBIteratorItem<'a> {
-    fn eq(&self, other: &Self) -> bool {
-        self.cmp(other) == Ordering::Equal
-    }
-}
-impl<'a> Eq for DBIteratorItem<'a> {}
 
-// An iterator used to scan over many memtables.
-pub struct DBIterator<'a> {
-    // BinaryHeap is a max-heap, so items (memtable iterators) are placed with
-    // Reverse() to make it a min-heap.
-    memtables: BinaryHeap<Reverse<DBIteratorItem<'a>>>,
+    // Looks up the given `key`.
+    //
+    // Returns `Some(value)` if the given `key` is found.
+    pub fn get(&mut self, key: &str) -> Result<Option<Value>, DBError> {
+        let value = match self.get_entry(key)? {
+            Some(EntryValue::Present(value)) => value,
+            Some(EntryValue::Blob(blob_ref)) => blob_ref
+                .read(&*self.config.storage, &self.root_path)
+                .map_err(|io_err| DBError::Io(io_err.to_string()))?,
+            _ => return Ok(None),
+        };
+        self.config.statistics.record_read(value.len());
+        Ok(Some(value))
+    }
 
-    // the prefix to scan
-    prefix: Key,
-}
+    // Like `get()`, but returns a reader over the value.  Values stored in blob
+    // files are read as the reader is consumed, rather than loaded into memory.
+    //
+    // The reader doesn't borrow the database, so it can outlive the lock of a `DB`.
+    pub fn get_reader(&mut self, key: &str) -> Result<Option<Box<dyn Read + Send>>, DBError> {
+        match self.get_entry(key)? {
+            Some(EntryValue::Present(value)) => {
+                self.config.statistics.record_read(value.len());
+                Ok(Some(Box::new(std::io::Cursor::new(value))))
+            }
+            Some(EntryValue::Blob(blob_ref)) => {
+                self.config.statistics.record_read(blob_ref.size as usize);
+                let reader = blob_ref
+                    .reader(&*self.config.storage, &self.root_path)
+                    .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+                Ok(Some(Box::new(reader)))
+            }
+            _ => Ok(None),
+        }
+    }
 
-impl<'a> Iterator for DBIterator<'a> {
-    type Item = (Key, Value);
+    // Returns true if `key` has a value, without reading it.
+    pub fn contains_key(&mut self, key: &str) -> Result<bool, DBError> {
+        Ok(self.value_size(key)?.is_some())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        'pop_key_val: loop {
-            if self.memtables.peek().is_none() {
-                return None;
+    // Returns the size of the value of `key`, without reading it: memtables hold
+    // values in memory, and sstables hold the sizes of the values they store in
+    // blob files, so only the sstable index and block holding the key are read.
+    pub fn value_size(&mut self, key: &str) -> Result<Option<u64>, DBError> {
+        self.find_entry(
+            key,
+            |entry| entry.len() as u64,
+            |sstable, key| {
+                let found = sstable.get_value_size(key)?;
+                Ok(found
+                    .map(|(entry, size)| (!matches!(entry, EntryValue::Deleted)).then_some(size)))
+            },
+        )
+    }
+
+    // Returns the newest entry for `key`, unless it was deleted.
+    fn get_entry(&mut self, key: &str) -> Result<Option<EntryValue>, DBError> {
+        self.find_entry(
+            key,
+            |entry| entry.to_entry_value(),
+            |sstable, key| {
+                Ok(sstable.get(key)?.map(|entry| match entry {
+                    EntryValue::Deleted => None,
+                    entry => Some(entry),
+                }))
+            },
+        )
+    }
+
+    // Looks up the newest entry for `key`, unless it was deleted, and returns what
+    // `from_memtable` or `from_sstable` make of it.  `from_sstable` returns
+    // Some(None) for a deletion, like `get_from_memtables()`.
+    fn find_entry<T>(
+        &mut self,
+        key: &str,
+        from_memtable: impl FnOnce(EntryRef<'_>) -> T,
+        mut from_sstable: impl FnMut(
+            &mut SSTableReader,
+            &str,
+        ) -> Result<Option<Option<T>>, SSTableError>,
+    ) -> Result<Option<T>, DBError> {
+        // first check the active memtable
+        // if not in the active memtable, check the frozen memtables
+        // we have to check the most recently frozen memtable first (the last element)
+        let memtables = self
+            .frozen_memtables
+            .iter()
+            .chain([&self.active_memtable])
+            .rev()
+            .map(Arc::as_ref);
+        if let Some(entry) = get_from_memtables(memtables, key, from_memtable) {
+            return Ok(entry);
+        }
+
+        // Sstables whose prefix filter rules out the key's prefix can't hold it.
+        let statistics = self.config.statistics.clone();
+        let prefix_extractor = self.config.prefix_extractor.clone();
+        let key_prefix = prefix_extractor
+            .as_ref()
+            .and_then(|extractor| extractor.prefix(key));
+        let may_hold_key = |sstable: &SSTableReader| match (&prefix_extractor, key_prefix) {
+            (Some(extractor), Some(key_prefix)) => {
+                let may_hold_key = sstable.may_contain_prefix(&**extractor, key_prefix);
+                if !may_hold_key {
+                    statistics.record_prefix_filter_skip();
+                }
+                may_hold_key
             }
-            // 1. Take out the smallest iterator (we have to put it back at the end)
-            let top_memtable = self.memtables.pop();
-            let top_kv = match &top_memtable {
-                None => return None, // There are no more memtables.
-                Some(Reverse(DBIteratorItem(kv_iter, _))) => kv_iter.borrow_mut().next(),
+            _ => true,
+        };
+
+        // Not in the memtables?  Lets try the sstables
+        // Newest one first
+        let (level0, other_levels) = self.levels.split_first_mut().expect("no levels");
+        for sstable in level0.iter_mut().rev() {
+            let entry = if may_hold_key(sstable) {
+                from_sstable(sstable, key)
+            } else {
+                Ok(None)
             };
+            // A damaged block mustn't let an older version of the key show through.
+            match entry {
+                Ok(Some(entry)) => return Ok(entry),
+                Err(err) => return Err(err.into_db_error()),
+                _ if sstable.is_range_deleted(key) => return Ok(None),
+                _ => continue,
+            }
+        }
 
-            match top_kv {
-                Some((key, entry_value)) => {
-                    if !key.starts_with(self.prefix.as_str()) {
-                        return None;
-                    }
-                    // 2. Skip any duplicates of this key -- we already have the newest one.
-                    self.skip_entries_with_key(key);
+        // SSTables in the other levels don't overlap, so only one can hold the key.
+        // A range tombstone in a level hides the key in the deeper levels.
+        for level in other_levels.iter_mut() {
+            for sstable in level.iter_mut() {
+                if !sstable.overlaps(key, key) || !may_hold_key(sstable) {
+                    continue;
+                }
+                match from_sstable(sstable, key) {
+                    Ok(Some(entry)) => return Ok(entry),
+                    Err(err) => return Err(err.into_db_error()),
+                    _ => continue,
+                }
+            }
+            if level.iter().any(|sstable| sstable.is_range_deleted(key)) {
+                return Ok(None);
+            }
+        }
 
-                    // 3. Put the memtable iterator back into the heap
-                    self.memtables.push(top_memtable.unwrap());
+        Ok(None)
+    }
 
-                    match entry_value {
-                        EntryValue::Present(value) => {
-                            return Some((key.to_string(), value.clone()))
-                        }
-                        EntryValue::Deleted => continue 'pop_key_val, // deleted -- try the next key value.
-                    }
-                }
-                // If we hit an memtable iterator that's empty, it implies that all iterators are empty,
-                // so we can early-exit this iterator.
-                None => return None,
+    pub fn put(&mut self, key: impl Into<Key>, value: impl Into<Value>) -> Result<(), DBError> {
+        self.put_entry(key.into(), EntryValue::Present(value.into()))
+    }
+
+    // Applies the writes of `batch`; see write_batch.rs.  Either all of them are
+    // applied or, if one is invalid, none; `DB::get()` sees them all at once.  An
+    // error from the flush which may follow leaves them applied, like for `put()`.
+    pub fn write(&mut self, batch: WriteBatchWithIndex) -> Result<(), DBError> {
+        self.check_writable()?;
+        let writes: Vec<(Key, EntryValue)> = batch
+            .into_entries()
+            .map(|(key, value)| (key, value.map_or(EntryValue::Deleted, EntryValue::Present)))
+            .collect();
+        for (key, _) in &writes {
+            index::check_key(key)?;
+        }
+        self.maybe_stall_write()?;
+        let mut index_updates = Vec::with_capacity(writes.len());
+        for (key, entry) in &writes {
+            index_updates.push(self.index_updates(key, entry)?);
+        }
+
+        // Lock-free readers wait for the whole batch.
+        let published_memtables = self.published_memtables.clone();
+        let published_memtables_guard = published_memtables.as_ref().map(|published_memtables| {
+            published_memtables
+                .write()
+                .expect("memtables lock is poisoned")
+        });
+        for ((key, entry), index_updates) in writes.into_iter().zip(index_updates) {
+            self.apply_write(key, entry, index_updates);
+        }
+        std::mem::drop(published_memtables_guard);
+        self.maybe_freeze_and_flush()
+    }
+
+    // Like `put()`, but returns a handle which tells when the write is synced, to
+    // only wait for durability at the end of a batch of writes; see write_handle.rs.
+    pub fn put_async(
+        &mut self,
+        key: impl Into<Key>,
+        value: impl Into<Value>,
+    ) -> Result<WriteHandle, DBError> {
+        let memtable_number = self.num_memtables_frozen;
+        self.put(key, value)?;
+        Ok(self.flush_notifier.handle(memtable_number))
+    }
+
+    pub fn delete(&mut self, key: impl Into<Key>) -> Result<(), DBError> {
+        self.put_entry(key.into(), EntryValue::Deleted)
+    }
+
+    // Like `put()`, but first checks the value against its CRC-32C checksum, which
+    // the caller computed before the value crossed the network.
+    pub fn put_with_checksum(
+        &mut self,
+        key: impl Into<Key>,
+        value: impl Into<Value>,
+        checksum: u32,
+    ) -> Result<(), DBError> {
+        let value = value.into();
+        let actual_checksum = crc32c(&value);
+        if actual_checksum != checksum {
+            return Err(DBError::ChecksumMismatch {
+                expected: checksum,
+                actual: actual_checksum,
+            });
+        }
+        self.put(key, value)
+    }
+
+    // Writes `value` as `key`'s version at `timestamp`; see timestamp.rs.  Reads at
+    // `timestamp` or later see it, until a newer version.  Keys written with a
+    // timestamp are only read with one, and must not contain '\0'.
+    pub fn put_with_ts(
+        &mut self,
+        key: &str,
+        timestamp: u64,
+        value: impl Into<Value>,
+    ) -> Result<(), DBError> {
+        timestamp::check_key(key)?;
+        self.put_entry(
+            timestamp::versioned_key(key, timestamp),
+            EntryValue::Present(value.into()),
+        )
+    }
+
+    // Deletes `key` as of `timestamp`; reads at earlier timestamps still see its
+    // older versions.
+    pub fn delete_with_ts(&mut self, key: &str, timestamp: u64) -> Result<(), DBError> {
+        timestamp::check_key(key)?;
+        self.put_entry(
+            timestamp::versioned_key(key, timestamp),
+            EntryValue::Deleted,
+        )
+    }
+
+    // Looks up `key` as it was at `timestamp`: the value of its newest version
+    // written at or before `timestamp`.
+    pub fn get_at_ts(&self, key: &str, timestamp: u64) -> Result<Option<Value>, DBError> {
+        timestamp::check_key(key)?;
+        let start = timestamp::versioned_key(key, timestamp);
+        let versions_prefix = timestamp::key_versions_prefix(key);
+        for entry in self.merged_entries(&start, false) {
+            let (versioned_key, entry) =
+                entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            if versioned_key < start {
+                continue;
+            }
+            if !versioned_key.starts_with(&versions_prefix) {
+                break;
+            }
+            let value = self.read_value(entry)?;
+            if let Some(value) = &value {
+                self.config.statistics.record_read(value.len());
+            }
+            return Ok(value);
+        }
+        Ok(None)
+    }
+
+    // Returns every version of `key` which compactions kept, newest first, as
+    // (timestamp, value) pairs; deleted versions have no value.
+    pub fn get_versions(&self, key: &str) -> Result<Vec<(u64, Option<Value>)>, DBError> {
+        timestamp::check_key(key)?;
+        let versions_prefix = timestamp::key_versions_prefix(key);
+        let mut versions = Vec::new();
+        for entry in self.merged_entries(&versions_prefix, false) {
+            let (versioned_key, entry) =
+                entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            if versioned_key < versions_prefix {
+                continue;
+            }
+            if !versioned_key.starts_with(&versions_prefix) {
+                break;
+            }
+            let Some((_, version_timestamp)) = timestamp::parse_versioned_key(&versioned_key)
+            else {
+                continue;
             };
+            let value = self.read_value(entry)?;
+            if let Some(value) = &value {
+                self.config.statistics.record_read(value.len());
+            }
+            versions.push((version_timestamp, value));
         }
+        Ok(versions)
     }
-}
 
-impl<'a> DBIterator<'a> {
-    fn peek_next_key(&mut self) -> Option<&Key> {
-        let next_memtable = self.memtables.peek();
-        if next_memtable.is_none() {
-            return None;
+    // Like `scan_prefix()`, but over the keys written with a timestamp, as they were
+    // at `timestamp`.
+    pub fn scan_at_ts(
+        &self,
+        key_prefix: &str,
+        timestamp: u64,
+        options: &ReadOptions,
+    ) -> Result<Vec<(Key, Option<Value>)>, DBError> {
+        timestamp::check_key(key_prefix)?;
+        let versions_prefix = timestamp::versions_prefix(key_prefix);
+        let limit = options.limit.unwrap_or(usize::MAX);
+        let mut entries: VecDeque<(Key, EntryValue)> = VecDeque::new();
+        // The key whose version was already taken; its older versions are skipped.
+        let mut current_key: Option<Key> = None;
+        let start = match &options.start_after {
+            Some(start_after) if !options.reverse => {
+                timestamp::key_versions_prefix(start_after).max(versions_prefix.clone())
+            }
+            _ => versions_prefix.clone(),
+        };
+        for entry in self.merged_entries_from(
+            &versions_prefix,
+            &start,
+            false,
+            options.table_filter.as_ref(),
+        ) {
+            let (versioned_key, entry) =
+                entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            if versioned_key < versions_prefix {
+                continue;
+            }
+            if !versioned_key.starts_with(&versions_prefix) {
+                break;
+            }
+            let Some((key, version_timestamp)) = timestamp::parse_versioned_key(&versioned_key)
+            else {
+                continue;
+            };
+            if version_timestamp > timestamp || current_key.as_deref() == Some(key) {
+                continue;
+            }
+            if let Some(start_after) = &options.start_after {
+                if options.reverse && key >= start_after.as_str() {
+                    break;
+                }
+                if !options.reverse && key <= start_after.as_str() {
+                    continue;
+                }
+            }
+            current_key = Some(key.to_string());
+            if entry == EntryValue::Deleted && !options.include_deleted_markers {
+                continue;
+            }
+            entries.push_back((key.to_string(), entry));
+            if !options.reverse && entries.len() == limit {
+                break;
+            }
+            if entries.len() > limit {
+                entries.pop_front();
+            }
         }
-        let DBIteratorItem(ref next_kv_iter_ref, _) = next_memtable.as_ref().unwrap().0;
-        let mut next_kv_iter = next_kv_iter_ref.borrow_mut();
-        let next_kv = next_kv_iter.peek();
-        match next_kv {
-            Some((key, _)) => Some(key),
-            _ => None,
+        let mut entries = Vec::from(entries);
+        if options.reverse {
+            entries.reverse();
         }
+        self.read_scanned_values(entries)
     }
-    // Must call peek_next_key() first -- panics if there is no next key.
-    fn skip_next_key(&mut self) {
-        let next_memtable = self.memtables.pop().unwrap();
-        let DBIteratorItem(next_kv_iter_ref, next_kv_order) = next_memtable.0;
-        next_kv_iter_ref.borrow_mut().next();
-        self.memtables
-            .push(Reverse(DBIteratorItem(next_kv_iter_ref, next_kv_order)));
+
+    // Returns a stream of the writes made from now on, e.g. to keep a search index
+    // up to date.  Events are sent once the write is in the memtable; like the
+    // write itself, they don't wait for a flush.  Drop the receiver to unsubscribe.
+    //
+    // A subscriber which falls more than 1024 events behind is disconnected: once
+    // it has received the events before, the stream ends.  It can subscribe
+    // again, and catch up with `get_updates_since()`.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<ChangeEvent> {
+        self.changes.subscribe()
     }
 
-    fn skip_entries_with_key(&mut self, key: &String) {
-        loop {
-            match self.peek_next_key() {
-                Some(next_key) if key == next_key => self.skip_next_key(),
-                _ => return,
+    // Returns the writes made after the write numbered `sequence` (see
+    // ChangeEvent::sequence), e.g. for a replica to catch up from the last write it
+    // applied.  Fails with DBError::UpdatesUnavailable if some of them are older
+    // than DBConfig::change_retention, in which case the replica must start over.
+    pub fn get_updates_since(&mut self, sequence: u64) -> Result<Vec<ChangeEvent>, DBError> {
+        self.changes.updates_since(sequence)
+    }
+
+    // Returns the value of `key`, first inserting `make_value()` if the key doesn't
+    // exist.  Writes are serialized, so only one caller inserts a value.
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &str,
+        make_value: impl FnOnce() -> Value,
+    ) -> Result<Value, DBError> {
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        let value = make_value();
+        self.put(key, value.clone())?;
+        Ok(value)
+    }
+
+    // Writes `new` to `key`, or deletes it if `new` is None, but only if its current
+    // value is `expected` (None if the key doesn't exist).  Returns whether it
+    // wrote.  Writes are serialized, so no other write can come in between.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, DBError> {
+        self.check_writable()?;
+        let current = match self.get_entry(key)? {
+            Some(entry) => self.read_value(entry)?,
+            None => None,
+        };
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => self.put(key, value)?,
+            None => self.delete(key)?,
+        }
+        Ok(true)
+    }
+
+    // Deletes every key in the half-open range [`start`, `end`).
+    //
+    // Rather than writing a tombstone for each key, a single range tombstone is
+    // added to the active memtable, so the cost does not depend on how many keys
+    // are stored in older memtables and sstables.  Does nothing if `start` >= `end`.
+    pub fn delete_range(
+        &mut self,
+        start: impl Into<Key>,
+        end: impl Into<Key>,
+    ) -> Result<(), DBError> {
+        let tombstone = RangeTombstone {
+            start: start.into(),
+            end: end.into(),
+        };
+        if tombstone.start >= tombstone.end {
+            return Ok(());
+        }
+        self.check_writable()?;
+        self.maybe_stall_write()?;
+        if !self.config.indexes.is_empty() {
+            self.delete_index_entries_in_range(&tombstone.start, &tombstone.end)?;
+        }
+
+        // Entries in the active memtable are older than the tombstone, so drop them.
+        // This keeps the invariant that entries are always newer than the range
+        // tombstones stored alongside them.
+        let covered_keys: Vec<Key> = self
+            .active_memtable
+            .entries
+            .range(
+                Bound::Included(tombstone.start.as_str()),
+                Bound::Excluded(tombstone.end.as_str()),
+            )
+            .map(|(key, _)| key.to_string())
+            .collect();
+        // The published memtables are let go of while the active memtable changes,
+        // so that it's only copied if an iterator holds on to it.
+        let mut published_memtables = self
+            .published_memtables
+            .as_ref()
+            .map(|published| published.write().expect("memtables lock is poisoned"));
+        if let Some(published_memtables) = published_memtables.as_mut() {
+            published_memtables.clear();
+        }
+        let active_memtable = Arc::make_mut(&mut self.active_memtable);
+        for key in covered_keys {
+            active_memtable.remove(&key);
+        }
+
+        self.changes
+            .publish(|| Change::DeleteRange(tombstone.start.clone(), tombstone.end.clone()));
+        self.config.statistics.record_write(tombstone.len());
+        active_memtable.add_range_tombstone(tombstone);
+        if let Some(published_memtables) = published_memtables.as_mut() {
+            **published_memtables = self.memtables_newest_first();
+        }
+        std::mem::drop(published_memtables);
+        self.maybe_freeze_and_flush()
+    }
+
+    // Deletes every key starting with `prefix`, e.g. everything stored for one
+    // tenant, and every version of those written with a timestamp.  Like
+    // `delete_range()` this writes range tombstones, and then the sstables holding
+    // nothing but keys under `prefix` are dropped straight away, rather than
+    // waiting for a compaction to find their keys deleted.
+    pub fn delete_prefix(&mut self, prefix: &str) -> Result<(), DBError> {
+        let end = prefix_end(prefix).ok_or_else(|| {
+            DBError::InvalidKey(format!("{:?} isn't a prefix of a range of keys", prefix))
+        })?;
+        let versions_prefix = timestamp::versions_prefix(prefix);
+        let versions_end = prefix_end(&versions_prefix).expect("versions_prefix isn't empty");
+        self.delete_range(prefix, end.as_str())?;
+        self.delete_range(versions_prefix.as_str(), versions_end.as_str())?;
+
+        // Sstables with range tombstones are kept, since those may delete keys in
+        // older sstables; that includes the one the tombstones above are flushed to.
+        let holds_only_prefix = |sstable: &SSTableReader| {
+            sstable.range_tombstones().is_empty()
+                && sstable.key_range().is_some_and(|(smallest, largest)| {
+                    (smallest >= prefix && largest < end.as_str())
+                        || (smallest >= versions_prefix.as_str() && largest < versions_end.as_str())
+                })
+        };
+        if !self.levels.iter().flatten().any(holds_only_prefix) {
+            return Ok(());
+        }
+        // The tombstone is flushed first, or a crash would bring back the keys in
+        // the sstables which are kept, but not in those dropped.
+        self.flush_memtables()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        let mut obsolete_paths = Vec::new();
+        for sstables in &mut self.levels {
+            sstables.retain(|sstable| {
+                if holds_only_prefix(sstable) {
+                    obsolete_paths.push(sstable.path().to_path_buf());
+                    return false;
+                }
+                true
+            });
+        }
+        self.write_manifest()
+            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        for path in obsolete_paths {
+            self.config
+                .storage
+                .remove_file(&path)
+                .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        }
+        self.update_write_stall_condition();
+        Ok(())
+    }
+
+    pub fn seek(&self, key_prefix: &str) -> Result<DBIterator<'_>, DBError> {
+        // make a min-heap of peekable iterators, where the heap key is:
+        // (peekable iterator, precedent)
+        //
+        // most recent memtable is first
+        let memtables: Vec<&Memtable> = self
+            .frozen_memtables
+            .iter()
+            .chain([&self.active_memtable])
+            .rev()
+            .map(|memtable| memtable.as_ref())
+            .collect();
+        Ok(DBIterator {
+            memtables: {
+                let mut heap = BinaryHeap::new();
+                // 0 means newest
+                for (memtable_order, memtable) in memtables.iter().enumerate() {
+                    let memtable_iter = memtable
+                        .entries
+                        .range(Bound::Included(key_prefix), Bound::Unbounded)
+                        .peekable();
+                    heap.push(Reverse(DBIteratorItem(
+                        RefCell::new(memtable_iter),
+                        memtable_order as MemtableOrder,
+                    )));
+                }
+                heap
+            },
+            range_tombstones: memtables
+                .iter()
+                .map(|memtable| memtable.range_tombstones.as_slice())
+                .collect(),
+            prefix: key_prefix.to_string(),
+            statistics: &self.config.statistics,
+        })
+    }
+
+    // Iterates over the keys starting with `key_prefix` and their values, across the
+    // memtables and the sstables.  Unlike `seek()`, the iterator doesn't borrow the
+    // database: it pins the memtables and sstables as they are now, so it doesn't
+    // see later writes, and keeps working through flushes and compactions.  While
+    // it's alive, the next write copies the active memtable.
+    pub fn iter_pinned(&self, key_prefix: &str) -> PinnedIterator {
+        PinnedIterator::new(self.raw_seek(key_prefix))
+    }
+
+    // Like `iter_pinned()`, but also returns the keys which were deleted, with no
+    // value, e.g. to pass deletions on to a replica.  Keys deleted by a range
+    // deletion aren't returned.
+    pub fn raw_seek(&self, key_prefix: &str) -> RawIterator {
+        RawIterator {
+            entries: self.merged_entries(key_prefix, false),
+            prefix: key_prefix.to_string(),
+            storage: self.config.storage.clone(),
+            root_path: self.root_path.clone(),
+            statistics: self.config.statistics.clone(),
+            buffered: VecDeque::new(),
+            buffered_error: None,
+        }
+    }
+
+    // Returns the keys starting with `key_prefix` and their values, across the
+    // memtables and the sstables.  Unlike `seek()`, the results are copied out, so
+    // `options.limit` saves reading values which would be thrown away.
+    pub fn scan_prefix(
+        &mut self,
+        key_prefix: &str,
+        options: &ReadOptions,
+    ) -> Result<Vec<(Key, Option<Value>)>, DBError> {
+        let entries = self.scan_entries(key_prefix, options, false)?;
+        self.read_scanned_values(entries)
+    }
+
+    // Reads the values of the entries returned by a scan.  Values are only read
+    // once the limit is applied, so large values which aren't returned are never
+    // read from their blob file.
+    fn read_scanned_values(
+        &self,
+        entries: Vec<(Key, EntryValue)>,
+    ) -> Result<Vec<(Key, Option<Value>)>, DBError> {
+        let mut results = Vec::with_capacity(entries.len());
+        for (key, entry) in entries {
+            let value = self.read_value(entry)?;
+            if let Some(value) = &value {
+                self.config.statistics.record_read(value.len());
             }
+            results.push((key, value));
         }
+        Ok(results)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::collections::HashMap;
+    // Returns the keys starting with `key_prefix`, like `scan_prefix()`, but without
+    // reading or copying their values; e.g. to check which keys exist.
+    pub fn keys(&self, key_prefix: &str, options: &ReadOptions) -> Result<Vec<Key>, DBError> {
+        Ok(self
+            .scan_entries(key_prefix, options, true)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    }
 
-    use super::*;
-    use anyhow;
-    use tempdir::TempDir;
+    // Looks up the keys whose index keys in `index_name` (see DBConfig::indexes)
+    // start with `index_key_prefix`.  Returns the keys and their values, ordered by
+    // index key, then by key.
+    pub fn seek_index(
+        &mut self,
+        index_name: &str,
+        index_key_prefix: &str,
+    ) -> Result<Vec<(Key, Value)>, DBError> {
+        index::check_name(index_name)?;
+        let index_prefix = index::index_entry_prefix(index_name, index_key_prefix);
+        let mut results = Vec::new();
+        for index_key in self.keys(&index_prefix, &ReadOptions::default())? {
+            let Some(key) = index::primary_key(&index_key) else {
+                continue;
+            };
+            if let Some(value) = self.get(key)? {
+                results.push((key.to_string(), value));
+            }
+        }
+        Ok(results)
+    }
 
-    fn make_db_for_test(config: DBConfig) -> DB {
-        let tmpdir = tempdir::TempDir::new("lsmdb").expect("tmpdir");
-        let mut db = DB::open_with_config(tmpdir.path(), config).expect("couldnt make db");
-        db
+    // The entries of `scan_prefix()` and `keys()`, before values are read from blob
+    // files.
+    fn scan_entries(
+        &self,
+        key_prefix: &str,
+        options: &ReadOptions,
+        keys_only: bool,
+    ) -> Result<Vec<(Key, EntryValue)>, DBError> {
+        let limit = options.limit.unwrap_or(usize::MAX);
+        let start = match &options.start_after {
+            Some(start_after) if !options.reverse => start_after.as_str().max(key_prefix),
+            _ => key_prefix,
+        };
+        let mut entries = VecDeque::new();
+        for entry in
+            self.merged_entries_from(key_prefix, start, keys_only, options.table_filter.as_ref())
+        {
+            let (key, entry) =
+                entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            if key.as_str() < key_prefix
+                || index::is_hidden(&key, key_prefix)
+                || timestamp::is_hidden(&key, key_prefix)
+            {
+                continue;
+            }
+            if !key.starts_with(key_prefix) {
+                break;
+            }
+            if let Some(start_after) = &options.start_after {
+                if options.reverse && key >= *start_after {
+                    break;
+                }
+                if !options.reverse && key <= *start_after {
+                    continue;
+                }
+            }
+            if entry == EntryValue::Deleted && !options.include_deleted_markers {
+                continue;
+            }
+            entries.push_back((key, entry));
+            if !options.reverse && entries.len() == limit {
+                break;
+            }
+            // Reverse scans read the prefix forwards, keeping only its last `limit`
+            // entries.
+            if entries.len() > limit {
+                entries.pop_front();
+            }
+        }
+        let mut entries = Vec::from(entries);
+        if options.reverse {
+            entries.reverse();
+        }
+        Ok(entries)
+    }
+
+    // Estimates the number of bytes taken up by keys in the half-open range [`start`, `end`).
+    //
+    // Memtables are measured exactly (key & value size), while sstables are
+    // estimated from their index, by counting every block which overlaps the range.
+    // Keys which were overwritten or deleted are counted in every memtable and
+    // sstable which still holds them.
+    pub fn approximate_size(&self, start: &str, end: &str) -> u64 {
+        if start >= end {
+            return 0;
+        }
+        let memtables_size: u64 = self
+            .frozen_memtables
+            .iter()
+            .chain([&self.active_memtable])
+            .flat_map(|memtable| {
+                memtable
+                    .entries
+                    .range(Bound::Included(start), Bound::Excluded(end))
+            })
+            .map(|(key, entry)| (key.len() + entry.len()) as u64)
+            .sum();
+        let sstables_size: u64 = self
+            .levels
+            .iter()
+            .flatten()
+            .map(|sstable| sstable.approximate_size(start, end))
+            .sum();
+        memtables_size + sstables_size
+    }
+
+    // Estimates the number of keys stored in the database.
+    //
+    // Counts every entry in the memtables and sstables, so a key which was
+    // overwritten or deleted is counted once for every memtable and sstable that
+    // holds it.
+    pub fn estimate_num_keys(&self) -> u64 {
+        let memtables_num_keys: u64 = self
+            .frozen_memtables
+            .iter()
+            .chain([&self.active_memtable])
+            .map(|memtable| memtable.entries.len() as u64)
+            .sum();
+        let sstables_num_keys: u64 = self
+            .levels
+            .iter()
+            .flatten()
+            .map(SSTableReader::num_entries)
+            .sum();
+        memtables_num_keys + sstables_num_keys
+    }
+
+    // Breaks down the database's space usage, to tell whether it would shrink by
+    // compacting it.  Reads every sstable, so this is slow for big databases.
+    pub fn space_report(&mut self) -> Result<SpaceReport, DBError> {
+        let mut report = SpaceReport {
+            sstable_file_bytes: self
+                .levels
+                .iter()
+                .flatten()
+                .map(SSTableReader::file_size)
+                .sum(),
+            memtable_bytes: (self.active_memtable.size() + self.frozen_memtables_size) as u64,
+            ..SpaceReport::default()
+        };
+
+        // Everything stored, live or not.
+        let mut present_bytes = 0u64;
+        let mut add_entry = |report: &mut SpaceReport, key: &str, entry: &EntryValue| match entry {
+            EntryValue::Present(value) => present_bytes += (key.len() + value.len()) as u64,
+            EntryValue::Deleted => report.tombstone_bytes += key.len() as u64,
+            EntryValue::Blob(blob_ref) => present_bytes += key.len() as u64 + blob_ref.size,
+        };
+        for memtable in [&self.active_memtable]
+            .into_iter()
+            .chain(self.frozen_memtables.iter())
+        {
+            for (key, entry) in memtable.entries.iter() {
+                add_entry(&mut report, key, &entry.to_entry_value());
+            }
+            report.tombstone_bytes += memtable
+                .range_tombstones
+                .iter()
+                .map(|tombstone| tombstone.len() as u64)
+                .sum::<u64>();
+        }
+        for sstable in self.levels.iter_mut().flatten() {
+            report.tombstone_bytes += sstable
+                .range_tombstones()
+                .iter()
+                .map(|tombstone| tombstone.len() as u64)
+                .sum::<u64>();
+            for entry in sstable.iter() {
+                let (key, entry) =
+                    entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+                add_entry(&mut report, &key, &entry);
+            }
+        }
+
+        // Only the newest entries are live.
+        for entry in self.merged_entries("", false) {
+            let (key, entry) =
+                entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            match entry {
+                EntryValue::Present(value) => {
+                    report.live_data_bytes += (key.len() + value.len()) as u64
+                }
+                EntryValue::Blob(blob_ref) => {
+                    report.live_data_bytes += key.len() as u64 + blob_ref.size
+                }
+                EntryValue::Deleted => {}
+            }
+        }
+        report.obsolete_data_bytes = present_bytes - report.live_data_bytes;
+
+        let live_file_numbers: HashSet<u64> = self
+            .levels
+            .iter()
+            .flatten()
+            .filter_map(|sstable| manifest::sstable_file_number(sstable.path()))
+            .collect();
+        let storage = &*self.config.storage;
+        for path in storage
+            .list_dir(&self.root_path)
+            .map_err(|io_err| DBError::Io(io_err.to_string()))?
+        {
+            let file_bytes = || {
+                storage
+                    .file_size(&path)
+                    .map_err(|io_err| DBError::Io(io_err.to_string()))
+            };
+            let is_trash = if let Some(file_number) = manifest::sstable_file_number(&path) {
+                !live_file_numbers.contains(&file_number)
+            } else if let Some(file_number) = blob::blob_file_number(&path) {
+                let is_live = self.blob_files.contains(&file_number);
+                if is_live {
+                    report.blob_file_bytes += file_bytes()?;
+                }
+                !is_live
+            } else {
+                path.file_name()
+                    .is_some_and(|name| name == manifest::MANIFEST_TMP_FILENAME)
+            };
+            if is_trash {
+                report.trash_bytes += file_bytes()?;
+            }
+        }
+        Ok(report)
+    }
+
+    // Changes options of the open database by name (see options.rs), e.g.
+    // `[("memtable_max_size_bytes", "4194304")]`.  Either every option is changed,
+    // or none is.  Lowered compaction triggers are acted on straight away.
+    pub fn set_options(&mut self, options: &[(&str, &str)]) -> Result<(), DBError> {
+        let mut previous_values = Vec::new();
+        for &(name, value) in options {
+            let result = match MUTABLE_OPTIONS.iter().find(|option| option.name == name) {
+                Some(option) => {
+                    let previous_value = (option.get)(&self.config);
+                    (option.set)(&mut self.config, value)
+                        .map(|()| previous_values.push((option, previous_value)))
+                        .map_err(|err| format!("{}: {}", name, err))
+                }
+                None => Err(format!("{} is unknown, or can't be changed", name)),
+            };
+            if let Err(err) = result {
+                for (option, previous_value) in previous_values.into_iter().rev() {
+                    (option.set)(&mut self.config, &previous_value).expect("was valid");
+                }
+                return Err(DBError::InvalidOption(err));
+            }
+        }
+        self.update_write_stall_condition();
+        if !self.is_secondary {
+            self.maybe_compact()
+                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    // Returns the current value of every option `set_options()` can change.
+    pub fn get_options(&self) -> Vec<(&'static str, String)> {
+        MUTABLE_OPTIONS
+            .iter()
+            .map(|option| (option.name, (option.get)(&self.config)))
+            .collect()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.config.name.as_deref()
+    }
+
+    pub(crate) fn key_encoding(&self) -> Option<Arc<dyn KeyEncoding<Key = String>>> {
+        self.config.key_encoding.clone()
+    }
+
+    // Reports the current state of the database, along with its activity counters.
+    pub fn stats(&self) -> DBStats {
+        let statistics = &self.config.statistics;
+        DBStats {
+            active_memtable_size_bytes: self.active_memtable.size(),
+            num_frozen_memtables: self.frozen_memtables.len(),
+            num_sstables_per_level: self.levels.iter().map(Vec::len).collect(),
+            bytes_written: statistics.bytes_written(),
+            bytes_read: statistics.bytes_read(),
+            flush_bytes_written: statistics.flush_bytes_written(),
+            num_flushes: statistics.num_flushes(),
+            num_compactions: statistics.num_compactions(),
+            compaction_bytes_read: statistics.compaction_bytes_read(),
+            compaction_bytes_written: statistics.compaction_bytes_written(),
+            num_write_slowdowns: statistics.num_write_slowdowns(),
+            num_write_stalls: statistics.num_write_stalls(),
+            num_prefix_filter_skips: statistics.num_prefix_filter_skips(),
+            num_write_buffer_flushes: statistics.num_write_buffer_flushes(),
+        }
+    }
+
+    // Writes every live key and value to `writer`, sorted by key, and returns the
+    // number of keys written.  See export.rs for the stream's format.
+    //
+    // Nothing can be written to the database during the export, so the dump is
+    // consistent.
+    pub fn export(&mut self, writer: impl Write) -> Result<u64, DBError> {
+        let statistics = self.config.statistics.clone();
+        let root_path = self.root_path.clone();
+        let storage = self.config.storage.clone();
+        let mut export_writer =
+            ExportWriter::new(writer).map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        for entry in self.merged_entries("", false) {
+            let (key, entry) =
+                entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            // Importing the keys rebuilds their index entries.
+            if index::is_index_key(&key) {
+                continue;
+            }
+            let value = match entry {
+                EntryValue::Present(value) => value,
+                EntryValue::Blob(blob_ref) => blob_ref
+                    .read(&*storage, &root_path)
+                    .map_err(|io_err| DBError::Io(io_err.to_string()))?,
+                EntryValue::Deleted => continue,
+            };
+            statistics.record_read(value.len());
+            export_writer
+                .add(&key, &value)
+                .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        }
+        export_writer
+            .finish()
+            .map_err(|io_err| DBError::Io(io_err.to_string()))
+    }
+
+    // Puts every key and value from a stream written by `export()`, and returns the
+    // number of keys imported.
+    //
+    // Keys are imported as they are read, so if the stream turns out to be
+    // invalid, the keys before the error are kept.  Each key is a new write, with
+    // this database's next sequence number; the exported ones aren't kept.
+    pub fn import_stream(&mut self, reader: impl Read) -> Result<u64, DBError> {
+        let mut export_reader = ExportReader::new(reader)
+            .map_err(|io_err| DBError::InvalidExportStream(io_err.to_string()))?;
+        let mut num_keys = 0;
+        while let Some((key, value)) = export_reader
+            .next_record()
+            .map_err(|io_err| DBError::InvalidExportStream(io_err.to_string()))?
+        {
+            self.put(key, value)?;
+            num_keys += 1;
+        }
+        Ok(num_keys)
+    }
+
+    // Merges every memtable and sstable into a single stream of entries, sorted by key.
+    //
+    // Only the keys starting with `key_prefix` are guaranteed to be there: when
+    // it's a whole prefix of the prefix extractor, the sstables whose filter rules
+    // it out only contribute their range tombstones.
+    // The merged entries may start with keys smaller than `key_prefix`.  They don't
+    // borrow the database: the memtables and sstables are pinned until they're dropped.
+    // With `keys_only`, values aren't read or copied, and come back empty.
+    fn merged_entries(&self, key_prefix: &str, keys_only: bool) -> MergingIterator<'static> {
+        self.merged_entries_from(key_prefix, key_prefix, keys_only, None)
+    }
+
+    // Like `merged_entries()`, but starts at `start`, a key past `key_prefix`, and
+    // skips the entries of the sstables `table_filter` rules out.
+    fn merged_entries_from(
+        &self,
+        key_prefix: &str,
+        start: &str,
+        keys_only: bool,
+        table_filter: Option<&TableFilter>,
+    ) -> MergingIterator<'static> {
+        let memtables = [&self.active_memtable]
+            .into_iter()
+            .chain(self.frozen_memtables.iter().rev())
+            // Later writes to a skiplist memtable aren't seen.
+            .map(|memtable| (memtable, memtable.entries.snapshot()))
+            .collect();
+        ReadSources {
+            memtables,
+            levels: &self.levels,
+            prefix_extractor: &self.config.prefix_extractor,
+            statistics: &self.config.statistics,
+        }
+        .merged_entries_from(key_prefix, start, keys_only, table_filter)
+    }
+
+    // Pins the memtables and sstables as they are now, to read them later without
+    // borrowing the database; see Snapshot.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memtables: [&self.active_memtable]
+                .into_iter()
+                .chain(self.frozen_memtables.iter().rev())
+                .map(|memtable| (memtable.clone(), memtable.entries.snapshot()))
+                .collect(),
+            levels: self.levels.clone(),
+            prefix_extractor: self.config.prefix_extractor.clone(),
+            storage: self.config.storage.clone(),
+            root_path: self.root_path.clone(),
+            statistics: self.config.statistics.clone(),
+        }
+    }
+
+    // The memtables, newest first.
+    fn memtables_newest_first(&self) -> Vec<Arc<Memtable>> {
+        self.frozen_memtables
+            .iter()
+            .chain([&self.active_memtable])
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    // Lets `DB::get()` see the current memtables; see `published_memtables`.
+    fn publish_memtables(&self) {
+        if let Some(published_memtables) = &self.published_memtables {
+            *published_memtables
+                .write()
+                .expect("memtables lock is poisoned") = self.memtables_newest_first();
+        }
+    }
+
+    // Delays or refuses a write if flushes and compactions fell behind.
+    fn maybe_stall_write(&mut self) -> Result<(), DBError> {
+        self.update_write_stall_condition();
+        if self.write_stall_condition == WriteStallCondition::Stopped {
+            if let Err(sstable_err) = self.relieve_write_stall() {
+                self.config.statistics.record_write_stall();
+                return Err(DBError::WriteStalled(format!(
+                    "couldn't flush or compact: {}",
+                    sstable_err
+                )));
+            }
+        }
+        if self.frozen_memtables.len() >= self.config.frozen_memtables_stop_writes_trigger {
+            self.config.statistics.record_write_stall();
+            return Err(DBError::WriteStalled(format!(
+                "{} frozen memtables",
+                self.frozen_memtables.len()
+            )));
+        }
+        if self.config.compaction_style == CompactionStyle::Leveled {
+            let level0_len = self.levels[0].len();
+            if level0_len >= self.config.level0_stop_writes_trigger {
+                self.config.statistics.record_write_stall();
+                return Err(DBError::WriteStalled(format!(
+                    "{} level-0 sstables",
+                    level0_len
+                )));
+            }
+            if level0_len >= self.config.level0_slowdown_writes_trigger {
+                self.config.statistics.record_write_slowdown();
+                self.wait(self.config.write_slowdown_delay);
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes the frozen memtables, and merges level 0 down, when there are enough
+    // of them to stop writes; nothing else would until the next flush.
+    fn relieve_write_stall(&mut self) -> Result<(), SSTableError> {
+        if self.frozen_memtables.len() >= self.config.frozen_memtables_stop_writes_trigger {
+            self.flush_frozen_memtables()?;
+        }
+        if self.config.compaction_style == CompactionStyle::Leveled
+            && self.levels[0].len() >= self.config.level0_stop_writes_trigger
+        {
+            let output_level = self.config.num_levels.saturating_sub(1).min(1);
+            let compaction =
+                self.compaction_of(0, output_level, (0..self.levels[0].len()).collect());
+            self.run_compaction(compaction)?;
+        }
+        self.update_write_stall_condition();
+        Ok(())
+    }
+
+    // Sleeps for `delay`, or, under a DB's lock, leaves it to the DB to sleep once
+    // it's released the lock.
+    fn wait(&self, delay: Duration) {
+        match &self.deferred_wait {
+            Some(deferred_wait) => deferred_wait.defer_until(Instant::now() + delay),
+            None => std::thread::sleep(delay),
+        }
+    }
+
+    // Tells the listeners when writes start or stop being delayed or refused.
+    fn update_write_stall_condition(&mut self) {
+        let level0_len = self.levels[0].len();
+        let is_leveled = self.config.compaction_style == CompactionStyle::Leveled;
+        let current = if self.frozen_memtables.len()
+            >= self.config.frozen_memtables_stop_writes_trigger
+            || (is_leveled && level0_len >= self.config.level0_stop_writes_trigger)
+        {
+            WriteStallCondition::Stopped
+        } else if is_leveled && level0_len >= self.config.level0_slowdown_writes_trigger {
+            WriteStallCondition::Delayed
+        } else {
+            WriteStallCondition::Normal
+        };
+        if current != self.write_stall_condition {
+            let info = WriteStallInfo {
+                previous: self.write_stall_condition,
+                current,
+            };
+            self.write_stall_condition = current;
+            for listener in &self.config.listeners {
+                listener.on_stall_conditions_changed(&info);
+            }
+        }
+    }
+
+    fn put_entry(&mut self, key: Key, entry: EntryValue) -> Result<(), DBError> {
+        self.check_writable()?;
+        index::check_key(&key)?;
+        self.maybe_stall_write()?;
+        let index_updates = self.index_updates(&key, &entry)?;
+        self.apply_write(key, entry, index_updates);
+        self.maybe_freeze_and_flush()
+    }
+
+    // Writes `entry` and its index updates into the active memtable; can't fail.
+    fn apply_write(&mut self, key: Key, entry: EntryValue, index_updates: Vec<(Key, EntryValue)>) {
+        for (index_key, index_entry) in index_updates {
+            self.insert_into_active_memtable(index_key, index_entry);
+        }
+        self.changes.publish(|| match &entry {
+            EntryValue::Deleted => Change::Delete(key.clone()),
+            EntryValue::Present(value) => Change::Put(key.clone(), value.clone()),
+            EntryValue::Blob(_) => unreachable!("writes don't hold blob references"),
+        });
+        self.insert_into_active_memtable(key, entry);
+    }
+
+    // Returns the index entries to write to replace those of `key`'s current value
+    // with those of `entry`.
+    fn index_updates(
+        &mut self,
+        key: &str,
+        entry: &EntryValue,
+    ) -> Result<Vec<(Key, EntryValue)>, DBError> {
+        if self.config.indexes.is_empty() || timestamp::is_timestamped_key(key) {
+            return Ok(Vec::new());
+        }
+        let old_value = match self.get_entry(key)? {
+            Some(entry) => self.read_value(entry)?,
+            None => None,
+        };
+        let new_value = match entry {
+            EntryValue::Present(value) => Some(value.as_slice()),
+            _ => None,
+        };
+        let old_entries = index::index_entries(&self.config.indexes, key, old_value.as_deref())?;
+        let new_entries = index::index_entries(&self.config.indexes, key, new_value)?;
+        Ok(old_entries
+            .difference(&new_entries)
+            .map(|index_key| (index_key.clone(), EntryValue::Deleted))
+            .chain(
+                new_entries
+                    .difference(&old_entries)
+                    .map(|index_key| (index_key.clone(), EntryValue::Present(Vec::new()))),
+            )
+            .collect())
+    }
+
+    // Deletes the index entries of the keys in [`start`, `end`), ahead of a range
+    // deletion.
+    fn delete_index_entries_in_range(&mut self, start: &str, end: &str) -> Result<(), DBError> {
+        let mut index_entries = BTreeSet::new();
+        for entry in self.merged_entries(start, false) {
+            let (key, entry) =
+                entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            if key.as_str() < start || is_reserved_key(&key) {
+                continue;
+            }
+            if key.as_str() >= end {
+                break;
+            }
+            if let Some(value) = self.read_value(entry)? {
+                index_entries.extend(index::index_entries(
+                    &self.config.indexes,
+                    &key,
+                    Some(&value),
+                )?);
+            }
+        }
+        for index_key in index_entries {
+            self.insert_into_active_memtable(index_key, EntryValue::Deleted);
+        }
+        Ok(())
+    }
+
+    // Returns the value of an entry, reading it from its blob file if needed.
+    fn read_value(&self, entry: EntryValue) -> Result<Option<Value>, DBError> {
+        match entry {
+            EntryValue::Present(value) => Ok(Some(value)),
+            EntryValue::Blob(blob_ref) => Ok(Some(
+                blob_ref
+                    .read(&*self.config.storage, &self.root_path)
+                    .map_err(|io_err| DBError::Io(io_err.to_string()))?,
+            )),
+            EntryValue::Deleted => Ok(None),
+        }
+    }
+
+    fn insert_into_active_memtable(&mut self, key: Key, entry: EntryValue) {
+        self.config.statistics.record_write(key.len() + entry.len());
+        // A skiplist is written to in place, even while it's shared.
+        if !self.active_memtable.insert_shared(&key, &entry) {
+            Arc::make_mut(&mut self.active_memtable).insert(key, entry);
+        }
+    }
+
+    // Freezes the active memtable if it got too big, and flushes the frozen
+    // memtables if there are too many of them, compacting the sstables as needed.
+    fn maybe_freeze_and_flush(&mut self) -> Result<(), DBError> {
+        if self.report_memtables_size() {
+            self.config.statistics.record_write_buffer_flush();
+            return self.flush();
+        }
+        if self.memtables_expired() {
+            return self.flush();
+        }
+        if self.active_memtable.size() >= self.config.memtable_max_size_bytes {
+            self.freeze_active_memtable()
+                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        }
+        if self.frozen_memtables.len() > self.config.max_frozen_memtables {
+            self.flush_frozen_memtables()
+                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            self.maybe_compact()
+                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+            self.update_write_stall_condition();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn freeze_active_memtable(&mut self) -> Result<(), SSTableError> {
+        let info = MemtableInfo {
+            num_entries: self.active_memtable.entries.len() as u64,
+            num_range_tombstones: self.active_memtable.range_tombstones.len() as u64,
+            size_bytes: self.active_memtable.size() as u64,
+        };
+        for listener in &self.config.listeners {
+            listener.on_memtable_frozen(&info);
+        }
+        self.frozen_memtables_size += self.active_memtable.size();
+        self.num_memtables_frozen += 1;
+        let active_memtable = Arc::new(Memtable::new(&self.config));
+        self.frozen_memtables.push_back(std::mem::replace(
+            &mut self.active_memtable,
+            active_memtable,
+        ));
+        self.publish_memtables();
+        Ok(())
+    }
+
+    // Returns true if the oldest write in the memtables is older than
+    // DBConfig::memtable_flush_interval.
+    fn memtables_expired(&self) -> bool {
+        let interval = self.config.memtable_flush_interval;
+        !interval.is_zero()
+            && self
+                .frozen_memtables
+                .iter()
+                .chain([&self.active_memtable])
+                .filter_map(|memtable| memtable.first_write())
+                .min()
+                .is_some_and(|first_write| first_write.elapsed() >= interval)
+    }
+
+    // Tells the DBConfig::write_buffer_manager how big the memtables are, and
+    // returns whether it asked to flush them.
+    fn report_memtables_size(&self) -> bool {
+        self.write_buffer.as_ref().is_some_and(|write_buffer| {
+            write_buffer.set_memory_usage(self.active_memtable.size() + self.frozen_memtables_size)
+        })
+    }
+
+    // Writes every memtable out to sstables.
+    fn flush_memtables(&mut self) -> Result<(), SSTableError> {
+        if !self.active_memtable.entries.is_empty()
+            || !self.active_memtable.range_tombstones.is_empty()
+        {
+            self.freeze_active_memtable()?;
+        }
+        if self.frozen_memtables.is_empty() {
+            return Ok(());
+        }
+        self.flush_frozen_memtables()
+    }
+
+    pub(crate) fn flush_frozen_memtables(&mut self) -> Result<(), SSTableError> {
+        for frozen_memtable in self.frozen_memtables.iter() {
+            // Large values are moved into a new blob file.
+            let min_blob_size = self.config.min_blob_size;
+            let has_blobs = frozen_memtable.entries.values().any(
+                |entry| matches!(entry, EntryRef::Present(value) if value.len() >= min_blob_size),
+            );
+            let mut blob_writer = None;
+            if has_blobs {
+                let blob_path = blob::blob_path(&self.root_path, self.next_file_number);
+                blob_writer = Some(BlobFileWriter::new(
+                    self.create_background_file(&blob_path)?,
+                    self.next_file_number,
+                    min_blob_size,
+                ));
+                self.next_file_number += 1;
+            }
+
+            let sstable_path = manifest::sstable_path(&self.root_path, self.next_file_number);
+            self.next_file_number += 1;
+            let start = Instant::now();
+            let mut info = FlushJobInfo {
+                path: sstable_path.clone(),
+                num_entries: frozen_memtable.entries.len() as u64,
+                ..FlushJobInfo::default()
+            };
+            for listener in &self.config.listeners {
+                listener.on_flush_begin(&info);
+            }
+
+            // flush the frozen memtable to sstable
+            let mut sstable_file = self.create_background_file(&sstable_path)?;
+            write_memtable_to_sstable(
+                frozen_memtable,
+                &mut sstable_file,
+                blob_writer.as_mut(),
+                self.config.prefix_extractor.clone(),
+                &self.config.table_properties_collectors,
+            )?;
+            sstable_file.sync()?;
+            self.drop_from_cache(&sstable_path);
+            let blob_bytes = match blob_writer {
+                Some(blob_writer) => {
+                    let file_number = blob_writer.file_number();
+                    let blob_bytes = blob_writer.finish()?;
+                    self.drop_from_cache(&blob::blob_path(&self.root_path, file_number));
+                    self.blob_files.push(file_number);
+                    blob_bytes
+                }
+                None => 0,
+            };
+            info.file_size = self.config.storage.file_size(&sstable_path)? + blob_bytes;
+            self.config.statistics.record_flush(info.file_size);
+            std::mem::drop(sstable_file);
+
+            let sstable = self.open_new_sstable(&sstable_path)?;
+            self.levels[0].push(sstable);
+            info.duration = start.elapsed();
+            for listener in &self.config.listeners {
+                listener.on_flush_completed(&info);
+            }
+        }
+        self.write_manifest()?;
+
+        // remove all frozen memtables; From now on, RawDB::get() will query the sstable instead.
+        self.frozen_memtables.clear();
+        self.frozen_memtables_size = 0;
+        self.publish_memtables();
+        self.report_memtables_size();
+        self.flush_notifier
+            .memtables_flushed(self.num_memtables_frozen);
+
+        Ok(())
+    }
+
+    // Creates a file for a flush or a compaction to write, which goes through
+    // DBConfig::rate_limiter.
+    fn create_background_file(&self, path: &Path) -> Result<Box<dyn WritableFile>, std::io::Error> {
+        let file = self.config.storage.create(path)?;
+        Ok(match &self.config.rate_limiter {
+            Some(rate_limiter) => Box::new(RateLimitedFile::new(
+                file,
+                rate_limiter.clone(),
+                self.deferred_wait.clone(),
+            )),
+            None => file,
+        })
+    }
+
+    // Opens a separate reader of an sstable for a compaction to read in full.
+    fn open_compaction_input(&self, path: &Path) -> Result<SSTableReader, SSTableError> {
+        Ok(SSTableReader::from_path(&*self.config.storage, path)?
+            .with_readahead(self.config.compaction_readahead_size))
+    }
+
+    // Drops a file written by a flush or a compaction from the page cache, with
+    // DBConfig::use_direct_io_for_flush_and_compaction.  It's only advice, so
+    // failures are ignored.
+    fn drop_from_cache(&self, path: &Path) {
+        if self.config.use_direct_io_for_flush_and_compaction {
+            let _ = self.config.storage.drop_from_cache(path);
+        }
+    }
+
+    // Opens an sstable which was just written by a flush or a compaction.
+    fn open_new_sstable(&self, path: &Path) -> Result<SSTableReader, SSTableError> {
+        let mut sstable = SSTableReader::from_path(&*self.config.storage, path)?;
+        if self.config.paranoid_checks {
+            sstable.verify()?;
+        }
+        Ok(sstable)
+    }
+
+    // Runs compactions until no level needs one.
+    pub(crate) fn maybe_compact(&mut self) -> Result<(), SSTableError> {
+        if self.config.compaction_style == CompactionStyle::Fifo {
+            return self.drop_expired_sstables();
+        }
+        while let Some(compaction) = compaction::pick_compaction(&self.levels, &self.config) {
+            self.run_compaction(compaction)?;
+        }
+        Ok(())
+    }
+
+    // Drops the level-0 sstables which CompactionStyle::Fifo expired, reporting it
+    // to the listeners as a compaction without outputs.
+    fn drop_expired_sstables(&mut self) -> Result<(), SSTableError> {
+        let num_expired =
+            compaction::pick_fifo_deletions(&self.levels, &self.config, SystemTime::now());
+        if num_expired == 0 {
+            return Ok(());
+        }
+        let start = Instant::now();
+        let expired: Vec<SSTableReader> = self.levels[0].drain(..num_expired).collect();
+        let mut info = CompactionJobInfo {
+            input_paths: expired
+                .iter()
+                .map(|sstable| sstable.path().to_path_buf())
+                .collect(),
+            input_bytes: expired.iter().map(SSTableReader::file_size).sum(),
+            ..CompactionJobInfo::default()
+        };
+        for listener in &self.config.listeners {
+            listener.on_compaction_begin(&info);
+        }
+        self.write_manifest()?;
+        for path in &info.input_paths {
+            self.config.storage.remove_file(path)?;
+        }
+        info.duration = start.elapsed();
+        for listener in &self.config.listeners {
+            listener.on_compaction_completed(&info);
+        }
+        Ok(())
+    }
+
+    fn run_compaction(&mut self, compaction: compaction::Compaction) -> Result<(), SSTableError> {
+        let level = compaction.level;
+        let output_level = compaction.output_level;
+        if self.levels.len() <= output_level {
+            self.levels.push(Vec::new());
+        }
+
+        // Open separate readers for the inputs, so the current sstables stay untouched
+        // if the compaction fails.
+        //
+        // Ranks order the inputs from newest to oldest. Level-0 sstables overlap, so
+        // each gets its own rank, newest first.  The sstables of other levels don't
+        // overlap, so they share their level's rank.
+        let mut inputs = Vec::new();
+        for (i, &input) in compaction.inputs.iter().enumerate() {
+            let rank = if level == 0 {
+                (compaction.inputs.len() - 1 - i) as u32
+            } else {
+                0
+            };
+            inputs.push((
+                self.open_compaction_input(self.levels[level][input].path())?,
+                rank,
+            ));
+        }
+        let output_level_rank = compaction.inputs.len() as u32;
+        for &input in &compaction.output_level_inputs {
+            inputs.push((
+                self.open_compaction_input(self.levels[output_level][input].path())?,
+                output_level_rank,
+            ));
+        }
+        let input_bytes: u64 = inputs.iter().map(|(sstable, _)| sstable.file_size()).sum();
+        let start = Instant::now();
+        let mut info = CompactionJobInfo {
+            level,
+            output_level,
+            input_paths: inputs
+                .iter()
+                .map(|(sstable, _)| sstable.path().to_path_buf())
+                .collect(),
+            input_bytes,
+            ..CompactionJobInfo::default()
+        };
+        for listener in &self.config.listeners {
+            listener.on_compaction_begin(&info);
+        }
+
+        // When merging within level 0, older level-0 sstables may also hold deleted keys.
+        let drop_tombstones = match compaction::key_range(inputs.iter().map(|(sstable, _)| sstable))
+        {
+            Some((smallest, largest)) => {
+                (level != 0 || output_level != 0 || compaction.inputs.first() == Some(&0))
+                    && compaction::is_bottommost(&self.levels, output_level, &smallest, &largest)
+            }
+            None => true,
+        };
+
+        // A merge within level 0 replaces its inputs with a single sstable.
+        let target_file_size = if output_level == 0 {
+            u64::MAX
+        } else {
+            self.config.target_file_size_base
+        };
+
+        let compaction_filter = self.config.compaction_filter.clone();
+        let collector_factories = self.config.table_properties_collectors.clone();
+        let storage = self.config.storage.clone();
+        let root_path = self.root_path.clone();
+        let mut version_retention =
+            timestamp::VersionRetention::new(self.config.max_versions, self.config.max_version_age);
+        let filter_entry = |key: &str, entry: EntryValue| {
+            // Dropped versions leave no tombstone behind, so only the bottommost level
+            // may drop them.
+            if drop_tombstones && !version_retention.keep(key) {
+                return Ok(None);
+            }
+            let Some(compaction_filter) = &compaction_filter else {
+                return Ok(Some(entry));
+            };
+            // Index entries and timestamped versions aren't the user's keys.
+            if is_reserved_key(key) {
+                return Ok(Some(entry));
+            }
+            let decision = match &entry {
+                EntryValue::Present(value) => compaction_filter.filter(output_level, key, value),
+                EntryValue::Blob(blob_ref) => compaction_filter.filter(
+                    output_level,
+                    key,
+                    &blob_ref.read(&*storage, &root_path)?,
+                ),
+                EntryValue::Deleted => return Ok(Some(entry)),
+            };
+            Ok(Some(match decision {
+                CompactionDecision::Keep => entry,
+                CompactionDecision::Remove => EntryValue::Deleted,
+                CompactionDecision::ChangeValue(value) => EntryValue::Present(value),
+            }))
+        };
+
+        let mut output_paths = Vec::new();
+        let output_files = compaction::merge_sstables(
+            &mut inputs,
+            drop_tombstones,
+            target_file_size,
+            filter_entry,
+            self.config.prefix_extractor.clone(),
+            &collector_factories,
+            || {
+                let path = manifest::sstable_path(&self.root_path, self.next_file_number);
+                self.next_file_number += 1;
+                output_paths.push(path.clone());
+                Ok(self.create_background_file(&path)?)
+            },
+        )?;
+        for (mut output_file, path) in output_files.into_iter().zip(&output_paths) {
+            output_file.sync()?;
+            self.drop_from_cache(path);
+        }
+        let mut outputs = Vec::new();
+        for path in &output_paths {
+            outputs.push(self.open_new_sstable(path)?);
+        }
+        let output_bytes: u64 = outputs.iter().map(SSTableReader::file_size).sum();
+
+        // Swap the inputs for the outputs, record that in the MANIFEST, and only then
+        // delete the inputs.
+        let mut obsolete_paths = Vec::new();
+        for (level, input_indexes) in [
+            (level, &compaction.inputs),
+            (output_level, &compaction.output_level_inputs),
+        ] {
+            for &i in input_indexes.iter().rev() {
+                obsolete_paths.push(self.levels[level].remove(i).path().to_path_buf());
+            }
+        }
+        if output_level == 0 {
+            // Level 0 is ordered by age, so the output takes its inputs' place.
+            let position = compaction.inputs.first().copied().unwrap_or_default();
+            self.levels[level].splice(position..position, outputs);
+        } else {
+            self.levels[output_level].extend(outputs);
+            self.levels[output_level].sort_by(|a, b| a.key_range().cmp(&b.key_range()));
+        }
+        self.write_manifest()?;
+        for path in obsolete_paths {
+            self.config.storage.remove_file(&path)?;
+        }
+
+        self.config
+            .statistics
+            .record_compaction(input_bytes, output_bytes);
+        info.output_paths = output_paths;
+        info.output_bytes = output_bytes;
+        info.duration = start.elapsed();
+        for listener in &self.config.listeners {
+            listener.on_compaction_completed(&info);
+        }
+        Ok(())
+    }
+}
+
+// A thread-safe database; every operation runs under an internal lock, except
+// for gets which find their key in skiplist memtables (see
+// DBConfig::memtable_impl).
+pub struct DB {
+    raw: Mutex<RawDB>,
+    // RawDB::published_memtables
+    published_memtables: Option<Arc<RwLock<Vec<Arc<Memtable>>>>>,
+    // RawDB::deferred_wait
+    deferred_wait: Arc<DeferredWait>,
+    statistics: Arc<Statistics>,
+}
+
+impl DB {
+    // `root_path` is the directory where data files will live.
+    pub fn open(root_path: &Path) -> Result<DB, DBError> {
+        Ok(DB::from_raw(RawDB::open(root_path)?))
+    }
+
+    // `root_path` is the directory where data files will live.
+    pub fn open_with_config(root_path: &Path, config: DBConfig) -> Result<DB, DBError> {
+        Ok(DB::from_raw(RawDB::open_with_config(root_path, config)?))
+    }
+
+    pub fn open_in_memory() -> Result<DB, DBError> {
+        Ok(DB::from_raw(RawDB::open_in_memory()?))
+    }
+
+    pub fn open_in_memory_with_config(config: DBConfig) -> Result<DB, DBError> {
+        Ok(DB::from_raw(RawDB::open_in_memory_with_config(config)?))
+    }
+
+    pub fn open_as_secondary(primary_path: &Path, config: DBConfig) -> Result<DB, DBError> {
+        Ok(DB::from_raw(RawDB::open_as_secondary(
+            primary_path,
+            config,
+        )?))
+    }
+
+    pub fn from_raw(mut raw: RawDB) -> DB {
+        let deferred_wait = Arc::new(DeferredWait::default());
+        raw.deferred_wait = Some(deferred_wait.clone());
+        DB {
+            published_memtables: raw.published_memtables.clone(),
+            deferred_wait,
+            statistics: raw.config.statistics.clone(),
+            raw: Mutex::new(raw),
+        }
+    }
+
+    pub fn into_raw(self) -> RawDB {
+        let mut raw = self.raw.into_inner().expect("DB lock is poisoned");
+        raw.deferred_wait = None;
+        raw
+    }
+
+    // Locks the database, giving exclusive access to the underlying RawDB until
+    // the guard is dropped.  Useful for running several operations atomically, or
+    // for iterating without copying the results.
+    //
+    // Flushes and compactions run through the guard don't wait for
+    // DBConfig::rate_limiter; the thread's next write through the DB waits for
+    // them.
+    pub fn lock(&self) -> MutexGuard<'_, RawDB> {
+        self.raw.lock().expect("DB lock is poisoned")
+    }
+
+    // Runs `f` on the locked database, then waits for the rate limiter once the
+    // lock is released, so that other threads can go on meanwhile.
+    fn with_lock<T>(&self, f: impl FnOnce(&mut RawDB) -> T) -> T {
+        let result = f(&mut self.lock());
+        self.deferred_wait.wait();
+        result
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Value>, DBError> {
+        if let Some(published_memtables) = &self.published_memtables {
+            let memtables = published_memtables
+                .read()
+                .expect("memtables lock is poisoned");
+            // Memtables never hold blob references.
+            match get_from_memtables(
+                memtables.iter().map(Arc::as_ref),
+                key,
+                EntryRef::to_entry_value,
+            ) {
+                Some(Some(EntryValue::Present(value))) => {
+                    self.statistics.record_read(value.len());
+                    return Ok(Some(value));
+                }
+                Some(_) => return Ok(None),
+                None => {}
+            }
+        }
+        self.lock().get(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> Result<bool, DBError> {
+        Ok(self.value_size(key)?.is_some())
+    }
+
+    pub fn value_size(&self, key: &str) -> Result<Option<u64>, DBError> {
+        if let Some(published_memtables) = &self.published_memtables {
+            let memtables = published_memtables
+                .read()
+                .expect("memtables lock is poisoned");
+            let entry_len = |entry: EntryRef<'_>| entry.len() as u64;
+            if let Some(size) =
+                get_from_memtables(memtables.iter().map(Arc::as_ref), key, entry_len)
+            {
+                return Ok(size);
+            }
+        }
+        self.lock().value_size(key)
+    }
+
+    // The reader is returned without holding the lock.
+    pub fn get_reader(&self, key: &str) -> Result<Option<Box<dyn Read + Send>>, DBError> {
+        self.lock().get_reader(key)
+    }
+
+    pub fn put_with_checksum(
+        &self,
+        key: impl Into<Key>,
+        value: impl Into<Value>,
+        checksum: u32,
+    ) -> Result<(), DBError> {
+        self.with_lock(|db| db.put_with_checksum(key, value, checksum))
+    }
+
+    pub fn put(&self, key: impl Into<Key>, value: impl Into<Value>) -> Result<(), DBError> {
+        self.with_lock(|db| db.put(key, value))
+    }
+
+    // Applies all of the batch's writes or none; see `RawDB::write()`.
+    pub fn write(&self, batch: WriteBatchWithIndex) -> Result<(), DBError> {
+        self.with_lock(|db| db.write(batch))
+    }
+
+    pub fn put_async(
+        &self,
+        key: impl Into<Key>,
+        value: impl Into<Value>,
+    ) -> Result<WriteHandle, DBError> {
+        self.with_lock(|db| db.put_async(key, value))
+    }
+
+    pub fn delete(&self, key: impl Into<Key>) -> Result<(), DBError> {
+        self.with_lock(|db| db.delete(key))
+    }
+
+    pub fn put_with_ts(
+        &self,
+        key: &str,
+        timestamp: u64,
+        value: impl Into<Value>,
+    ) -> Result<(), DBError> {
+        self.with_lock(|db| db.put_with_ts(key, timestamp, value))
+    }
+
+    pub fn delete_with_ts(&self, key: &str, timestamp: u64) -> Result<(), DBError> {
+        self.with_lock(|db| db.delete_with_ts(key, timestamp))
+    }
+
+    pub fn get_at_ts(&self, key: &str, timestamp: u64) -> Result<Option<Value>, DBError> {
+        self.lock().get_at_ts(key, timestamp)
+    }
+
+    pub fn get_versions(&self, key: &str) -> Result<Vec<(u64, Option<Value>)>, DBError> {
+        self.lock().get_versions(key)
+    }
+
+    pub fn scan_at_ts(
+        &self,
+        key_prefix: &str,
+        timestamp: u64,
+        options: &ReadOptions,
+    ) -> Result<Vec<(Key, Option<Value>)>, DBError> {
+        self.lock().scan_at_ts(key_prefix, timestamp, options)
+    }
+
+    pub fn subscribe(&self) -> mpsc::Receiver<ChangeEvent> {
+        self.lock().subscribe()
+    }
+
+    pub fn get_updates_since(&self, sequence: u64) -> Result<Vec<ChangeEvent>, DBError> {
+        self.lock().get_updates_since(sequence)
+    }
+
+    // `make_value` is called with the lock held.  If it panics, the panic is
+    // resumed once the lock is released, so the lock isn't poisoned.
+    pub fn get_or_insert_with(
+        &self,
+        key: &str,
+        make_value: impl FnOnce() -> Value,
+    ) -> Result<Value, DBError> {
+        let made = self.with_lock(|db| {
+            if let Some(value) = db.get(key)? {
+                return Ok(Ok(value));
+            }
+            let made = std::panic::catch_unwind(std::panic::AssertUnwindSafe(make_value));
+            if let Ok(value) = &made {
+                db.put(key, value.clone())?;
+            }
+            Ok(made)
+        })?;
+        Ok(made.unwrap_or_else(|payload| std::panic::resume_unwind(payload)))
+    }
+
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, DBError> {
+        self.with_lock(|db| db.compare_and_swap(key, expected, new))
+    }
+
+    pub fn delete_range(&self, start: impl Into<Key>, end: impl Into<Key>) -> Result<(), DBError> {
+        self.with_lock(|db| db.delete_range(start, end))
+    }
+
+    pub fn delete_prefix(&self, prefix: &str) -> Result<(), DBError> {
+        self.with_lock(|db| db.delete_prefix(prefix))
+    }
+
+    // Returns all the key value pairs starting with `key_prefix`.
+    //
+    // The lock can't be held by an iterator, so the results are copied out.  Use
+    // `iter_pinned()` to iterate without holding the lock.
+    pub fn seek(&self, key_prefix: &str) -> Result<Vec<(Key, Value)>, DBError> {
+        Ok(self.lock().seek(key_prefix)?.collect())
+    }
+
+    pub fn scan_prefix(
+        &self,
+        key_prefix: &str,
+        options: &ReadOptions,
+    ) -> Result<Vec<(Key, Option<Value>)>, DBError> {
+        self.lock().scan_prefix(key_prefix, options)
+    }
+
+    pub fn keys(&self, key_prefix: &str, options: &ReadOptions) -> Result<Vec<Key>, DBError> {
+        self.lock().keys(key_prefix, options)
+    }
+
+    pub fn seek_index(
+        &self,
+        index_name: &str,
+        index_key_prefix: &str,
+    ) -> Result<Vec<(Key, Value)>, DBError> {
+        self.lock().seek_index(index_name, index_key_prefix)
+    }
+
+    // The iterator doesn't hold the lock; see `RawDB::raw_seek()`.
+    pub fn raw_seek(&self, key_prefix: &str) -> RawIterator {
+        self.lock().raw_seek(key_prefix)
+    }
+
+    // The iterator doesn't hold the lock; see `RawDB::iter_pinned()`.
+    pub fn iter_pinned(&self, key_prefix: &str) -> PinnedIterator {
+        self.lock().iter_pinned(key_prefix)
+    }
+
+    // The snapshot doesn't hold the lock; see `RawDB::snapshot()`.
+    pub fn snapshot(&self) -> Snapshot {
+        self.lock().snapshot()
+    }
+
+    pub fn approximate_size(&self, start: &str, end: &str) -> u64 {
+        self.lock().approximate_size(start, end)
+    }
+
+    pub fn estimate_num_keys(&self) -> u64 {
+        self.lock().estimate_num_keys()
+    }
+
+    pub fn set_options(&self, options: &[(&str, &str)]) -> Result<(), DBError> {
+        self.with_lock(|db| db.set_options(options))
+    }
+
+    pub fn get_options(&self) -> Vec<(&'static str, String)> {
+        self.lock().get_options()
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.lock().name().map(str::to_string)
+    }
+
+    pub fn stats(&self) -> DBStats {
+        self.lock().stats()
+    }
+
+    pub fn space_report(&self) -> Result<SpaceReport, DBError> {
+        self.lock().space_report()
+    }
+
+    pub fn flush(&self) -> Result<(), DBError> {
+        self.with_lock(|db| db.flush())
+    }
+
+    pub fn flush_expired_memtables(&self) -> Result<bool, DBError> {
+        self.with_lock(|db| db.flush_expired_memtables())
+    }
+
+    // Calls `flush_expired_memtables()` every `period` on a background thread, so
+    // DBConfig::memtable_flush_interval and fifo_ttl are kept to without writes.
+    // The thread stops once the timer or the database is dropped.  Errors are
+    // dropped, and the flush is retried on the next tick.
+    pub fn start_flush_timer(self: &Arc<Self>, period: Duration) -> FlushTimer {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = std::thread::spawn({
+            let db = Arc::downgrade(self);
+            let stopped = stopped.clone();
+            move || {
+                let (lock, condvar) = &*stopped;
+                let mut guard = lock.lock().expect("flush timer lock is poisoned");
+                loop {
+                    guard = condvar
+                        .wait_timeout(guard, period)
+                        .expect("flush timer lock is poisoned")
+                        .0;
+                    if *guard {
+                        return;
+                    }
+                    let Some(db) = db.upgrade() else {
+                        return;
+                    };
+                    let _ = db.flush_expired_memtables();
+                }
+            }
+        });
+        FlushTimer {
+            stopped,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn compact(&self) -> Result<(), DBError> {
+        self.with_lock(|db| db.compact())
+    }
+
+    pub fn compact_range(&self, start: &str, end: &str) -> Result<(), DBError> {
+        self.with_lock(|db| db.compact_range(start, end))
+    }
+
+    pub fn create_backup(&self, backup_path: &Path) -> Result<usize, DBError> {
+        self.with_lock(|db| db.create_backup(backup_path))
+    }
+
+    pub fn restore_from_backup(backup_path: &Path, target_path: &Path) -> Result<(), DBError> {
+        RawDB::restore_from_backup(backup_path, target_path)
+    }
+
+    pub fn repair(root_path: &Path, config: &DBConfig) -> Result<RepairReport, DBError> {
+        RawDB::repair(root_path, config)
+    }
+
+    pub fn verify_integrity(&self) -> Result<IntegrityReport, DBError> {
+        self.lock().verify_integrity()
+    }
+
+    pub fn destroy(root_path: &Path, config: &DBConfig) -> Result<(), DBError> {
+        RawDB::destroy(root_path, config)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.lock().path().to_path_buf()
+    }
+
+    pub fn live_files(&self) -> Vec<PathBuf> {
+        self.lock().live_files()
+    }
+
+    pub fn table_properties(&self) -> Vec<TableProperties> {
+        self.lock().table_properties()
+    }
+
+    pub fn checkpoint(&self, checkpoint_path: &Path) -> Result<(), DBError> {
+        self.with_lock(|db| db.checkpoint(checkpoint_path))
+    }
+
+    pub fn ingest_sst(&self, path: &Path) -> Result<(), DBError> {
+        self.with_lock(|db| db.ingest_sst(path))
+    }
+
+    pub fn try_catch_up(&self) -> Result<(), DBError> {
+        self.lock().try_catch_up()
+    }
+
+    // Holds the lock for the whole export, so the dump is consistent.
+    pub fn export(&self, writer: impl Write) -> Result<u64, DBError> {
+        self.lock().export(writer)
+    }
+
+    pub fn import_stream(&self, reader: impl Read) -> Result<u64, DBError> {
+        self.with_lock(|db| db.import_stream(reader))
+    }
+}
+
+// Returned by `DB::start_flush_timer()`; stops the timer's thread when dropped.
+pub struct FlushTimer {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for FlushTimer {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.stopped;
+        *lock.lock().expect("flush timer lock is poisoned") = true;
+        condvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Returns true if `key` is an index entry or a timestamped version, rather than a
+// key as the user wrote it.
+pub(crate) fn is_reserved_key(key: &str) -> bool {
+    index::is_index_key(key) || timestamp::is_timestamped_key(key)
+}
+
+// Returns the smallest key after every key starting with `prefix`, or None if
+// there's none, e.g. for the empty prefix.
+fn prefix_end(prefix: &str) -> Option<Key> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        // Keys are compared byte by byte, which for UTF-8 is by char; surrogates
+        // aren't chars, so they're skipped.
+        if let Some(next) = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+// Looks up `key` in `memtables`, newest first.  Returns the newest entry, or None
+// inside if the key was deleted, unless the memtables don't know about the key.
+fn get_from_memtables<'a, T>(
+    memtables: impl IntoIterator<Item = &'a Memtable>,
+    key: &str,
+    from_entry: impl FnOnce(EntryRef<'a>) -> T,
+) -> Option<Option<T>> {
+    for memtable in memtables {
+        match memtable.entries.get(key) {
+            Some(EntryRef::Deleted) => return Some(None),
+            Some(entry) => return Some(Some(from_entry(entry))),
+            // A range deletion in this memtable hides the key in all older data.
+            None if memtable.is_range_deleted(key) => return Some(None),
+            None => continue,
+        }
+    }
+    None
+}
+
+// The memtables and sstables which reads merge, either the database's own or a
+// Snapshot's.
+struct ReadSources<'a> {
+    // Newest first, with the skiplist snapshot to read each one at.
+    memtables: Vec<(&'a Arc<Memtable>, u64)>,
+    levels: &'a [Vec<SSTableReader>],
+    prefix_extractor: &'a Option<Arc<dyn PrefixExtractor>>,
+    statistics: &'a Statistics,
+}
+
+impl ReadSources<'_> {
+    // See `RawDB::merged_entries_from()`.
+    fn merged_entries_from(
+        &self,
+        key_prefix: &str,
+        start: &str,
+        keys_only: bool,
+        table_filter: Option<&TableFilter>,
+    ) -> MergingIterator<'static> {
+        let statistics = self.statistics;
+        let prefix_extractor = self.prefix_extractor;
+        let skip_entries = |level: usize, sstable: &SSTableReader| {
+            if let Some(table_filter) = table_filter {
+                if !table_filter.matches(&sstable.table_properties(level)) {
+                    return true;
+                }
+            }
+            let skip = match &prefix_extractor {
+                Some(extractor) if extractor.prefix(key_prefix) == Some(key_prefix) => {
+                    !sstable.may_contain_prefix(&**extractor, key_prefix)
+                }
+                _ => false,
+            };
+            if skip {
+                statistics.record_prefix_filter_skip();
+            }
+            skip
+        };
+
+        let mut sources = Vec::new();
+        let mut rank = 0;
+        for &(memtable, snapshot) in &self.memtables {
+            sources.push(memtable_merge_source(
+                memtable.clone(),
+                snapshot,
+                start,
+                keys_only,
+                rank,
+            ));
+            rank += 1;
+        }
+        let (level0, other_levels) = self.levels.split_first().expect("no levels");
+        for sstable in level0.iter().rev() {
+            let skip = skip_entries(0, sstable);
+            sources.push(sstable_merge_source(sstable, start, keys_only, skip, rank));
+            rank += 1;
+        }
+        // The sstables of a level don't overlap, so they share a rank.
+        for (level, sstables) in other_levels.iter().enumerate() {
+            for sstable in sstables {
+                let skip = skip_entries(level + 1, sstable);
+                sources.push(sstable_merge_source(sstable, start, keys_only, skip, rank));
+            }
+            rank += 1;
+        }
+        MergingIterator::new(sources)
+    }
+}
+
+// Reads `memtable`'s entries as of `snapshot` from `start` on, looking up each one
+// after the previous key, so that the source owns the memtable rather than
+// borrowing it.
+fn memtable_merge_source(
+    memtable: Arc<Memtable>,
+    snapshot: u64,
+    start: &str,
+    keys_only: bool,
+    rank: u32,
+) -> MergeSource<'static> {
+    let range_tombstones = memtable.range_tombstones.clone();
+    let mut next_bound = Bound::Included(start.to_string());
+    let entries = std::iter::from_fn(move || {
+        let (key, entry) = memtable
+            .entries
+            .range_at(
+                next_bound.as_ref().map(String::as_str),
+                Bound::Unbounded,
+                snapshot,
+            )
+            .next()?;
+        next_bound = Bound::Excluded(key.to_string());
+        let entry = match entry {
+            EntryRef::Present(_) if keys_only => EntryValue::Present(Vec::new()),
+            entry => entry.to_entry_value(),
+        };
+        Some(Ok((key.to_string(), entry)))
+    });
+    MergeSource::new(entries, range_tombstones, rank)
+}
+
+// Merges `sstable`'s entries, or with `skip_entries`, only its range tombstones.
+fn sstable_merge_source(
+    sstable: &SSTableReader,
+    start: &str,
+    keys_only: bool,
+    skip_entries: bool,
+    rank: u32,
+) -> MergeSource<'static> {
+    let range_tombstones = sstable.range_tombstones().to_vec();
+    if skip_entries {
+        MergeSource::new(std::iter::empty(), range_tombstones, rank)
+    } else {
+        MergeSource::new(
+            sstable.clone().into_iter_from(start, keys_only),
+            range_tombstones,
+            rank,
+        )
+    }
+}
+
+type MemtablePeekableIter<'a> = Peekable<memtable::Range<'a>>;
+type MemtableOrder = u32; // smaller is newer
+
+// TODO: Avoid using a RefCell<> by caching the saving the next key in DBIteratorItem.
+// e.g., struct DBIteratorItem(next_key, MemTableIter<>, MemtableOrder);
+// TODO: Instead of just iterating over Memtable, also iterate over SSTables.
+struct DBIteratorItem<'a>(RefCell<MemtablePeekableIter<'a>>, MemtableOrder);
+
+impl<'a> Ord for DBIteratorItem<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.0.borrow_mut().peek(), &other.0.borrow_mut().peek()) {
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((self_key, _)), Some((other_key, _))) => {
+                (self_key, self.1).cmp(&(other_key, other.1))
+            }
+            (None, None) => Ordering::Equal,
+        }
+    }
+}
+impl<'a> PartialOrd for DBIteratorItem<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(Ord::cmp(self, other))
+    }
+}
+impl<'a> PartialEq for DBIteratorItem<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<'a> Eq for DBIteratorItem<'a> {}
+
+// `next_batch()` doesn't reserve room for more entries than this up front, since
+// the caller may ask for far more than there are.
+const BATCH_CAPACITY_MAX: usize = 1024;
+
+// An iterator used to scan over many memtables.
+pub struct DBIterator<'a> {
+    // BinaryHeap is a max-heap, so items (memtable iterators) are placed with
+    // Reverse() to make it a min-heap.
+    memtables: BinaryHeap<Reverse<DBIteratorItem<'a>>>,
+
+    // Range tombstones of each memtable, indexed by MemtableOrder.
+    range_tombstones: Vec<&'a [RangeTombstone]>,
+
+    // the prefix to scan
+    prefix: Key,
+
+    statistics: &'a Statistics,
+}
+
+impl<'a> Iterator for DBIterator<'a> {
+    type Item = (Key, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'pop_key_val: loop {
+            self.memtables.peek()?;
+            // 1. Take out the smallest iterator (we have to put it back at the end)
+            let top_memtable = self.memtables.pop();
+            let (top_kv, top_order) = match &top_memtable {
+                None => return None, // There are no more memtables.
+                Some(Reverse(DBIteratorItem(kv_iter, order))) => {
+                    (kv_iter.borrow_mut().next(), *order)
+                }
+            };
+
+            match top_kv {
+                Some((key, entry_value)) => {
+                    if !key.starts_with(self.prefix.as_str()) {
+                        return None;
+                    }
+                    // 2. Skip any duplicates of this key -- we already have the newest one.
+                    self.skip_entries_with_key(key);
+
+                    // 3. Put the memtable iterator back into the heap
+                    self.memtables.push(top_memtable.unwrap());
+
+                    if self.is_range_deleted(key, top_order)
+                        || index::is_hidden(key, &self.prefix)
+                        || timestamp::is_hidden(key, &self.prefix)
+                    {
+                        continue 'pop_key_val;
+                    }
+                    match entry_value {
+                        EntryRef::Present(value) => {
+                            self.statistics.record_read(value.len());
+                            return Some((key.to_string(), value.to_vec()));
+                        }
+                        EntryRef::Deleted => continue 'pop_key_val, // deleted -- try the next key value.
+                    }
+                }
+                // If we hit an memtable iterator that's empty, it implies that all iterators are empty,
+                // so we can early-exit this iterator.
+                None => return None,
+            };
+        }
+    }
+}
+
+// The database as it was when `RawDB::snapshot()` was called.  Like a
+// PinnedIterator, it holds on to the memtables and sstables of the time rather
+// than borrowing the database, so writes, flushes and compactions can go on while
+// it's read, and it never sees them.  While it's alive, the next write copies the
+// active memtable, and the sstables it holds stay open after they're compacted.
+#[derive(Clone)]
+pub struct Snapshot {
+    // Newest first, with the skiplist snapshot to read each one at.
+    memtables: Vec<(Arc<Memtable>, u64)>,
+    levels: Vec<Vec<SSTableReader>>,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    storage: Arc<dyn Storage>,
+    root_path: PathBuf,
+    statistics: Arc<Statistics>,
+}
+
+impl Snapshot {
+    pub fn get(&self, key: &str) -> Result<Option<Value>, DBError> {
+        match self.raw_seek(key).next() {
+            Some(Ok((found_key, value))) if found_key == key => Ok(value),
+            Some(Err(err)) => Err(err),
+            _ => Ok(None),
+        }
+    }
+
+    // See `RawDB::iter_pinned()`.
+    pub fn iter_pinned(&self, key_prefix: &str) -> PinnedIterator {
+        PinnedIterator::new(self.raw_seek(key_prefix))
+    }
+
+    // See `RawDB::raw_seek()`.
+    pub fn raw_seek(&self, key_prefix: &str) -> RawIterator {
+        let sources = ReadSources {
+            memtables: self
+                .memtables
+                .iter()
+                .map(|(memtable, snapshot)| (memtable, *snapshot))
+                .collect(),
+            levels: &self.levels,
+            prefix_extractor: &self.prefix_extractor,
+            statistics: &self.statistics,
+        };
+        RawIterator {
+            entries: sources.merged_entries_from(key_prefix, key_prefix, false, None),
+            prefix: key_prefix.to_string(),
+            storage: self.storage.clone(),
+            root_path: self.root_path.clone(),
+            statistics: self.statistics.clone(),
+            buffered: VecDeque::new(),
+            buffered_error: None,
+        }
+    }
+}
+
+// Iterates over the keys with a prefix, as they were when the iterator was made;
+// see `RawDB::iter_pinned()`.
+pub struct PinnedIterator {
+    raw: RawIterator,
+    // An error hit by `next_batch()` after some entries, returned by the next call.
+    error: Option<DBError>,
+}
+
+impl PinnedIterator {
+    fn new(raw: RawIterator) -> Self {
+        PinnedIterator { raw, error: None }
+    }
+
+    // Returns up to `n` entries at once, e.g. to fill a page of a response.  Fewer
+    // than `n` means the iterator is done.  Entries are merged a run at a time (see
+    // MergingIterator::next_batch()), rather than one by one.
+    //
+    // An error after some entries is returned by the next call, so the entries
+    // before it aren't lost.
+    pub fn next_batch(&mut self, n: usize) -> Result<Vec<(Key, Value)>, DBError> {
+        let mut batch = Vec::with_capacity(n.min(BATCH_CAPACITY_MAX));
+        while batch.len() < n {
+            self.raw.buffer(n - batch.len());
+            match self.next() {
+                Some(Ok(entry)) => batch.push(entry),
+                Some(Err(err)) if batch.is_empty() => return Err(err),
+                Some(Err(err)) => {
+                    self.error = Some(err);
+                    break;
+                }
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+}
+
+impl Iterator for PinnedIterator {
+    type Item = Result<(Key, Value), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+        loop {
+            match self.raw.next()? {
+                Ok((key, Some(value))) => return Some(Ok((key, value))),
+                Ok((_, None)) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+// Like PinnedIterator, but deleted keys come with no value; see `RawDB::raw_seek()`.
+pub struct RawIterator {
+    entries: MergingIterator<'static>,
+    prefix: Key,
+    storage: Arc<dyn Storage>,
+    root_path: PathBuf,
+    statistics: Arc<Statistics>,
+    // Merged entries read ahead by `buffer()`, and the error which stopped it.
+    buffered: VecDeque<(Key, EntryValue)>,
+    buffered_error: Option<SSTableError>,
+}
+
+impl RawIterator {
+    // Reads up to `n` merged entries ahead, if none are buffered yet.
+    fn buffer(&mut self, n: usize) {
+        if !self.buffered.is_empty() || self.buffered_error.is_some() {
+            return;
+        }
+        let mut entries = Vec::with_capacity(n.min(BATCH_CAPACITY_MAX));
+        self.buffered_error = self.entries.next_batch(n, &mut entries).err();
+        self.buffered.extend(entries);
+    }
+
+    fn next_merged(&mut self) -> Option<Result<(Key, EntryValue), SSTableError>> {
+        if let Some(entry) = self.buffered.pop_front() {
+            return Some(Ok(entry));
+        }
+        if let Some(err) = self.buffered_error.take() {
+            return Some(Err(err));
+        }
+        self.entries.next()
+    }
+}
+
+impl Iterator for RawIterator {
+    type Item = Result<(Key, Option<Value>), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, entry) = match self.next_merged()? {
+                Ok(entry) => entry,
+                Err(sstable_err) => return Some(Err(DBError::SSTable(sstable_err.to_string()))),
+            };
+            if key < self.prefix
+                || index::is_hidden(&key, &self.prefix)
+                || timestamp::is_hidden(&key, &self.prefix)
+            {
+                continue;
+            }
+            if !key.starts_with(self.prefix.as_str()) {
+                return None;
+            }
+            let value = match entry {
+                EntryValue::Present(value) => value,
+                EntryValue::Blob(blob_ref) => {
+                    match blob_ref.read(&*self.storage, &self.root_path) {
+                        Ok(value) => value,
+                        Err(io_err) => return Some(Err(DBError::Io(io_err.to_string()))),
+                    }
+                }
+                EntryValue::Deleted => return Some(Ok((key, None))),
+            };
+            self.statistics.record_read(value.len());
+            return Some(Ok((key, Some(value))));
+        }
+    }
+}
+
+impl<'a> DBIterator<'a> {
+    fn peek_next_key(&mut self) -> Option<&'a str> {
+        let next_memtable = self.memtables.peek()?;
+        let DBIteratorItem(ref next_kv_iter_ref, _) = next_memtable.0;
+        let mut next_kv_iter = next_kv_iter_ref.borrow_mut();
+        let next_kv = next_kv_iter.peek();
+        match next_kv {
+            Some((key, _)) => Some(key),
+            _ => None,
+        }
+    }
+    // Must call peek_next_key() first -- panics if there is no next key.
+    fn skip_next_key(&mut self) {
+        let next_memtable = self.memtables.pop().unwrap();
+        let DBIteratorItem(next_kv_iter_ref, next_kv_order) = next_memtable.0;
+        next_kv_iter_ref.borrow_mut().next();
+        self.memtables
+            .push(Reverse(DBIteratorItem(next_kv_iter_ref, next_kv_order)));
+    }
+
+    fn skip_entries_with_key(&mut self, key: &str) {
+        loop {
+            match self.peek_next_key() {
+                Some(next_key) if key == next_key => self.skip_next_key(),
+                _ => return,
+            }
+        }
+    }
+
+    // Returns true if `key`, found in the memtable at `order`, is covered by a
+    // range tombstone from a newer memtable.
+    fn is_range_deleted(&self, key: &str, order: MemtableOrder) -> bool {
+        self.range_tombstones[..order as usize]
+            .iter()
+            .flat_map(|tombstones| tombstones.iter())
+            .any(|tombstone| tombstone.covers(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{memtable::ENTRY_OVERHEAD, sstable::SstWriter};
+    use std::mem::size_of;
+    use tempdir::TempDir;
+
+    #[allow(dead_code, unused_mut, clippy::let_and_return)]
+    fn make_db_for_test(config: DBConfig) -> RawDB {
+        let tmpdir = tempdir::TempDir::new("lsmdb").expect("tmpdir");
+        let mut db = RawDB::open_with_config(tmpdir.path(), config).expect("couldnt make db");
+        db
+    }
+
+    // The returned TempDir must outlive the RawDB, otherwise its files are deleted.
+    fn make_db_in_tempdir(config: DBConfig) -> (TempDir, RawDB) {
+        let tmpdir = TempDir::new("lsmdb").expect("tmpdir");
+        let db = RawDB::open_with_config(tmpdir.path(), config).expect("couldnt make db");
+        (tmpdir, db)
+    }
+
+    #[test]
+    fn basic_put_get() {
+        let mut db = RawDB::open(Path::new("/tmp/hello")).expect("failed to open");
+
+        db.put("1", "hello").expect("cant put 1");
+        db.put("2", "world").expect("cant put 2");
+
+        assert_eq!(db.get("1"), Ok(Some("hello".as_bytes().to_vec())));
+        assert_eq!(db.get("2"), Ok(Some("world".as_bytes().to_vec())));
+        assert_eq!(db.get("3"), Ok(None));
+    }
+
+    #[test]
+    fn basic_delete() {
+        let mut db = RawDB::open(Path::new("/tmp/hello")).expect("failed to open");
+
+        db.put("1", "hello").expect("cant put 1");
+        db.put("2", "world").expect("cant put 2");
+
+        assert_eq!(db.get("2"), Ok(Some(b"world".to_vec())));
+
+        db.delete("2").expect("couldnt delete 2");
+        assert_eq!(db.get("2").expect("cant put 2"), None);
+    }
+
+    #[test]
+    fn basic_seek() {
+        let mut db = RawDB::open(Path::new("/tmp/hello")).expect("failed to open");
+
+        db.put("/user/name/adam", "adam")
+            .expect("cant put /user/adam");
+        db.put("/user/name/vardhan", "vardhan")
+            .expect("cant put /user/vardhan");
+        db.put("/abc", "abc").expect("cant put /abc");
+        db.put("/xyz", "xyz").expect("cant put /xyz");
+
+        assert_eq!(db.get("/user/name/vardhan"), Ok(Some(b"vardhan".to_vec())));
+
+        assert_eq!(
+            db.seek("/user/")
+                .expect("couldnt seek /user")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![
+                ("/user/name/adam".to_string(), b"adam".to_vec()),
+                ("/user/name/vardhan".to_string(), b"vardhan".to_vec())
+            ]
+        );
+
+        assert_eq!(
+            db.seek("/user/vardhan_")
+                .expect("couldn't seen /user/vardhan_")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![]
+        );
+
+        assert_eq!(
+            db.seek("/items/")
+                .expect("couldnt seek /items")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn seek_with_active_and_frozen_memtable() {
+        let tmpdir = tempdir::TempDir::new("lsmdb").expect("tmpdir");
+        let mut db = RawDB::open_with_config(
+            tmpdir.path(),
+            DBConfig {
+                // don't trigger writing to sstables
+                max_frozen_memtables: 100,
+                ..DBConfig::default()
+            },
+        )
+        .expect("couldnt make db");
+
+        db.put("/user/name/adam", "adam")
+            .expect("cant put /user/adam");
+        db.put("/user/name/vardhan", "vardhan")
+            .expect("cant put /user/vardhan");
+        db.put("/user/name/catherine", "catherine")
+            .expect("cant put /user/catherine");
+        db.put("/abc", "abc").expect("cant put /abc");
+        db.put("/xyz", "xyz").expect("cant put /xyz");
+
+        db.freeze_active_memtable()
+            .expect("could not freeze active memtable");
+
+        // There should be two memtables now; active and 1 frozen.
+
+        assert_eq!(
+            db.seek("/user/")
+                .expect("couldnt seek /user")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![
+                ("/user/name/adam".to_string(), b"adam".to_vec()),
+                ("/user/name/catherine".to_string(), b"catherine".to_vec()),
+                ("/user/name/vardhan".to_string(), b"vardhan".to_vec())
+            ]
+        );
+
+        // put() and delete() below go in the active memtable, replacing the frozen entries.
+        db.delete("/user/name/catherine")
+            .expect("couldnt delete /user/catherine");
+
+        db.put("/user/name/adam", "adam2")
+            .expect("cant put /user/name/adam");
+
+        assert_eq!(db.get("/user/name/adam"), Ok(Some(b"adam2".to_vec())));
+        assert_eq!(db.get("/user/name/catherine"), Ok(None));
+        assert_eq!(db.get("/user/name/vardhan"), Ok(Some(b"vardhan".to_vec())));
+
+        assert_eq!(
+            db.seek("/user/")
+                .expect("couldnt seek /user")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![
+                ("/user/name/adam".to_string(), b"adam2".to_vec()),
+                ("/user/name/vardhan".to_string(), b"vardhan".to_vec())
+            ]
+        );
+
+        db.freeze_active_memtable()
+            .expect("could not freeze active memtable");
+
+        // Now we have 3 memtables:  1 active and 2 frozen.
+        db.put("/user/name/adam", "adam3")
+            .expect("cant put /user/name/adam");
+        db.put("/user/name/catherine", "catherine3")
+            .expect("cant put /user/name/catherine");
+        db.delete("/user/name/vardhan")
+            .expect("cant put /user/name/vardhan");
+        assert_eq!(
+            db.seek("/user/")
+                .expect("couldnt seek /user")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![
+                ("/user/name/adam".to_string(), b"adam3".to_vec()),
+                ("/user/name/catherine".to_string(), b"catherine3".to_vec())
+            ]
+        );
+
+        assert_eq!(
+            db.seek("/user/vardhan_")
+                .expect("couldn't see /user/vardhan_")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![]
+        );
+
+        assert_eq!(
+            db.seek("/items/")
+                .expect("couldnt seek /items")
+                .collect::<Vec<(Key, Value)>>(),
+            vec![]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::expect_fun_call)]
+    fn flush_memtable_to_sstable() {
+        let tmpdir = tempdir::TempDir::new("lsmdb").expect("tmpdir");
+        let mut db = RawDB::open_with_config(
+            tmpdir.path(),
+            DBConfig {
+                // No automatic flushing; all manual for now
+                max_frozen_memtables: 100,
+                ..DBConfig::default()
+            },
+        )
+        .expect("couldnt make db");
+
+        let keys = vec!["a", "b", "c", "d", "e", "f"];
+        for key in &keys {
+            db.put(format!("/key/{}", key), format!("val {}", key))
+                .expect("couldnt put");
+            db.freeze_active_memtable()
+                .expect("couldnt freeze active memtables");
+        }
+        assert_eq!(db.frozen_memtables.len(), keys.len());
+
+        db.flush_frozen_memtables()
+            .expect("couldnt flush frozen memtables");
+
+        assert_eq!(db.levels[0].len(), keys.len());
+
+        // sstables should now be persisted -- test that they are accessible when db is re-opened
+        std::mem::drop(db);
+        let mut db: RawDB = RawDB::open_with_config(
+            tmpdir.path(),
+            DBConfig {
+                // No automatic flushing; all manual for now
+                max_frozen_memtables: 100,
+                ..DBConfig::default()
+            },
+        )
+        .expect("couldnt reopen db");
+        assert_eq!(db.levels[0].len(), keys.len());
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                db.levels[0][i]
+                    .get(format!("/key/{}", key).as_str())
+                    .expect(format!("couldnt get /key/{}", key).as_str()),
+                Some(EntryValue::Present(format!("val {}", key).into_bytes()))
+            );
+            for non_present_key in keys.iter() {
+                if non_present_key == key {
+                    continue;
+                }
+                assert_eq!(
+                    db.levels[0][i]
+                        .get(format!("/key/{}", non_present_key).as_str())
+                        .expect(format!("couldnt get /key/{}", non_present_key).as_str()),
+                    None
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn basic_across_memtables_and_sstables() -> anyhow::Result<()> {
+        // zig-zag keys across active, frozen, and an sstable.
+        let tmpdir = tempdir::TempDir::new("lsmdb")?;
+        let mut db = RawDB::open_with_config(
+            tmpdir.path(),
+            DBConfig {
+                // No automatic flushing; all manual for now
+                max_frozen_memtables: 100,
+                ..DBConfig::default()
+            },
+        )?;
+
+        db.put("/key/1", "sstable0".as_bytes())?; // should be replaced by sstable1 and then frozen memtable
+        db.put("/key/3", "sstable0".as_bytes())?;
+        db.put("/key/4", "sstable0".as_bytes())?; // should be replaced by sstable1
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+
+        db.delete("/key/1")?; // should replace sstable0, and be replaced by frozen memtable
+        db.put("/key/2", "sstable1".as_bytes())?;
+        db.put("/key/4", "sstable1".as_bytes())?; // should replace sstable0
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+
+        db.put("/key/1", "frozen".as_bytes())?;
+        db.put("/key/5", "frozen".as_bytes())?;
+        db.freeze_active_memtable()?;
+
+        db.put("/key/0", "active".as_bytes())?;
+        db.put("/key/6", "active".as_bytes())?;
+
+        assert_eq!(db.active_memtable.entries.len(), 2);
+        assert_eq!(db.frozen_memtables.len(), 1);
+        assert_eq!(db.frozen_memtables[0].entries.len(), 2);
+        assert_eq!(db.levels[0].len(), 2);
+
+        assert_eq!(db.get("/key/3")?, Some("sstable0".into()));
+        assert_eq!(db.get("/key/2")?, Some("sstable1".into()));
+        assert_eq!(db.get("/key/4")?, Some("sstable1".into()));
+        assert_eq!(db.get("/key/1")?, Some("frozen".into()));
+        assert_eq!(db.get("/key/5")?, Some("frozen".into()));
+        assert_eq!(db.get("/key/0")?, Some("active".into()));
+        assert_eq!(db.get("/key/6")?, Some("active".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_range_across_memtables_and_sstables() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing; all manual for now
+            max_frozen_memtables: 100,
+            ..DBConfig::default()
+        });
+
+        db.put("/log/1", "sstable")?;
+        db.put("/log/2", "sstable")?;
+        db.put("/meta", "sstable")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+
+        db.put("/log/3", "frozen")?;
+        db.freeze_active_memtable()?;
+
+        db.put("/log/4", "active")?;
+        db.delete_range("/log/", "/log/4")?;
+        db.put("/log/2", "active")?; // newer than the range tombstone
+
+        assert_eq!(db.get("/log/1")?, None);
+        assert_eq!(db.get("/log/2")?, Some("active".into()));
+        assert_eq!(db.get("/log/3")?, None);
+        assert_eq!(db.get("/log/4")?, Some("active".into()));
+        assert_eq!(db.get("/meta")?, Some("sstable".into()));
+        assert_eq!(
+            db.seek("/log/")?.collect::<Vec<(Key, Value)>>(),
+            vec![
+                ("/log/2".to_string(), b"active".to_vec()),
+                ("/log/4".to_string(), b"active".to_vec())
+            ]
+        );
+
+        // The range tombstone is persisted along with the sstable.
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        std::mem::drop(db);
+        let mut db = RawDB::open(tmpdir.path())?;
+        assert_eq!(db.get("/log/1")?, None);
+        assert_eq!(db.get("/log/2")?, Some("active".into()));
+        assert_eq!(db.get("/log/3")?, None);
+        assert_eq!(db.get("/log/4")?, Some("active".into()));
+        assert_eq!(db.get("/meta")?, Some("sstable".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn value_size() -> anyhow::Result<()> {
+        let (tmpdir, db) = make_db_in_tempdir(DBConfig {
+            min_blob_size: 1024,
+            ..DBConfig::default()
+        });
+        let db = DB::from_raw(db);
+        db.put("/blob", vec![7u8; 4096])?;
+        db.put("/inline", "12345")?;
+        db.put("/deleted", "value")?;
+        db.put("/range/a", "value")?;
+        db.lock().flush()?;
+        db.delete("/deleted")?;
+        db.delete_range("/range/", "/range0")?;
+        db.put("/memtable", "123")?;
+
+        // The blob file isn't read.
+        for path in std::fs::read_dir(tmpdir.path())? {
+            let path = path?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "blob")
+            {
+                std::fs::remove_file(path)?;
+            }
+        }
+        assert_eq!(db.value_size("/blob")?, Some(4096));
+        assert_eq!(db.value_size("/inline")?, Some(5));
+        assert_eq!(db.value_size("/memtable")?, Some(3));
+        assert_eq!(db.value_size("/deleted")?, None);
+        assert_eq!(db.value_size("/range/a")?, None);
+        assert_eq!(db.value_size("/unknown")?, None);
+        assert!(db.contains_key("/blob")?);
+        assert!(!db.contains_key("/deleted")?);
+
+        db.lock().flush()?;
+        assert_eq!(db.lock().value_size("/memtable")?, Some(3));
+        assert!(!db.lock().contains_key("/deleted")?);
+        Ok(())
+    }
+
+    #[test]
+    fn delete_prefix() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            max_frozen_memtables: 100,
+            ..DBConfig::default()
+        });
+
+        db.put("/tenant/1/a", "1a")?;
+        db.put("/tenant/1/b", "1b")?;
+        db.flush()?;
+        db.put("/tenant/1/c", "1c")?;
+        db.put("/tenant/2/a", "2a")?;
+        db.flush()?;
+        db.put("/tenant/1/d", "1d")?;
+        db.put_with_ts("/tenant/1/e", 1, "1e")?;
+        db.put_with_ts("/tenant/2/e", 1, "2e")?;
+        let sstable_paths = db.live_files()[1..].to_vec();
+
+        db.delete_prefix("/tenant/1/")?;
+        // The first sstable only held the tenant's keys, so it's gone; the second
+        // also holds another tenant's key, so it's kept.
+        assert!(!tmpdir.path().join(&sstable_paths[0]).exists());
+        assert!(tmpdir.path().join(&sstable_paths[1]).exists());
+        assert!(db.keys("/tenant/1/", &ReadOptions::default())?.is_empty());
+        assert_eq!(db.get("/tenant/2/a")?, Some("2a".into()));
+        // The versions written with a timestamp are deleted too.
+        assert_eq!(db.get_at_ts("/tenant/1/e", 1)?, None);
+        assert_eq!(db.get_at_ts("/tenant/2/e", 1)?, Some("2e".into()));
+        db.put("/tenant/1/a", "new")?;
+        db.flush()?;
+
+        std::mem::drop(db);
+        let mut db = RawDB::open(tmpdir.path())?;
+        assert_eq!(
+            db.scan_prefix("/tenant/", &ReadOptions::default())?,
+            vec![
+                ("/tenant/1/a".to_string(), Some(b"new".to_vec())),
+                ("/tenant/2/a".to_string(), Some(b"2a".to_vec()))
+            ]
+        );
+
+        assert!(db.delete_prefix("").is_err());
+        assert_eq!(prefix_end("/a/").as_deref(), Some("/a0"));
+        assert_eq!(prefix_end("a\u{10FFFF}").as_deref(), Some("b"));
+        assert_eq!(prefix_end("\u{D7FF}").as_deref(), Some("\u{E000}"));
+        Ok(())
+    }
+
+    #[test]
+    fn delete_range_empty_range() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+
+        db.put("/b", "b")?;
+        db.delete_range("/c", "/a")?;
+        db.delete_range("/b", "/b")?;
+        assert_eq!(db.get("/b")?, Some("b".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn approximate_size_and_num_keys() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing; all manual for now
+            max_frozen_memtables: 100,
+            ..DBConfig::default()
+        });
+
+        // ~40KB per prefix, which spans many sstable blocks.
+        for i in 0..1000 {
+            db.put(format!("/a/{:04}", i), [0u8; 32])?;
+            db.put(format!("/b/{:04}", i), [0u8; 32])?;
+        }
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.put("/c/1", "active")?;
+
+        assert_eq!(db.estimate_num_keys(), 2001);
+
+        let a_size = db.approximate_size("/a/", "/b/");
+        let b_size = db.approximate_size("/b/", "/c/");
+        assert!(a_size >= 1000 * (6 + 32), "a_size = {}", a_size);
+        assert!(a_size < 2 * 1000 * (6 + 32), "a_size = {}", a_size);
+        assert!(b_size >= 1000 * (6 + 32), "b_size = {}", b_size);
+        assert!(b_size < 2 * 1000 * (6 + 32), "b_size = {}", b_size);
+        assert_eq!(db.approximate_size("/c/", "/d/"), 4 + 6);
+        assert_eq!(db.approximate_size("/d/", "/e/"), 0);
+        assert_eq!(db.approximate_size("/b/", "/a/"), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn shared_between_threads() -> anyhow::Result<()> {
+        let tmpdir = tempdir::TempDir::new("lsmdb")?;
+        let db = DB::open(tmpdir.path())?;
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let db = &db;
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        db.put(format!("/{}/{:03}", thread, i), format!("{}", i))
+                            .expect("cant put");
+                    }
+                });
+            }
+        });
+
+        for thread in 0..4 {
+            assert_eq!(db.seek(&format!("/{}/", thread))?.len(), 100);
+            assert_eq!(db.get(&format!("/{}/099", thread))?, Some("99".into()));
+        }
+
+        let mut raw = db.into_raw();
+        assert_eq!(raw.get("/3/042")?, Some("42".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stats() -> anyhow::Result<()> {
+        let statistics = Arc::new(Statistics::new());
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing; all manual for now
+            max_frozen_memtables: 100,
+            statistics: statistics.clone(),
+            ..DBConfig::default()
+        });
+
+        db.put("/a", "1234")?;
+        db.put("/b", "5678")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.put("/c", "90")?;
+        db.freeze_active_memtable()?;
+        db.delete("/a")?;
+
+        assert_eq!(db.get("/b")?, Some("5678".into()));
+        assert_eq!(db.get("/c")?, Some("90".into()));
+        // Only scans the memtables, so only finds /c.
+        assert_eq!(db.seek("/")?.count(), 1);
+
+        let stats = db.stats();
+        // A deletion's key and its overhead.
+        assert_eq!(stats.active_memtable_size_bytes, 2 + ENTRY_OVERHEAD);
+        assert_eq!(stats.num_frozen_memtables, 1);
+        assert_eq!(stats.num_sstables_per_level, vec![1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(stats.bytes_written, (2 + 4) + (2 + 4) + (2 + 2) + 2);
+        assert_eq!(stats.bytes_read, 4 + 2 + 2);
+        assert_eq!(stats.num_flushes, 1);
+        assert!(stats.flush_bytes_written > 0);
+        assert_eq!(statistics.bytes_written(), stats.bytes_written);
+
+        Ok(())
+    }
+
+    #[test]
+    fn leveled_compaction() -> anyhow::Result<()> {
+        let config = || DBConfig {
+            memtable_max_size_bytes: 512,
+            max_frozen_memtables: 0,
+            level0_file_num_compaction_trigger: 2,
+            max_bytes_for_level_base: 4 * 1024,
+            max_bytes_for_level_multiplier: 4,
+            target_file_size_base: 2 * 1024,
+            paranoid_checks: true,
+            ..DBConfig::default()
+        };
+        let (tmpdir, mut db) = make_db_in_tempdir(config());
+
+        for round in 0..3 {
+            for i in 0..500 {
+                db.put(format!("/key/{:04}", i), format!("{}_{}", round, i))?;
+            }
+        }
+        for i in (0..500).step_by(7) {
+            db.delete(format!("/key/{:04}", i))?;
+        }
+        db.delete_range("/key/0100", "/key/0200")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.maybe_compact()?;
+
+        let stats = db.stats();
+        assert!(stats.num_compactions > 0);
+        assert!(stats.num_sstables_per_level[0] < 2);
+        assert!(stats.num_sstables_per_level[1..].iter().any(|&n| n > 0));
+
+        let expected = |i: usize| {
+            if i.is_multiple_of(7) || (100..200).contains(&i) {
+                None
+            } else {
+                Some(format!("2_{}", i).into_bytes())
+            }
+        };
+        for i in 0..500 {
+            assert_eq!(db.get(&format!("/key/{:04}", i))?, expected(i));
+        }
+
+        // The levels are recovered from the MANIFEST.
+        std::mem::drop(db);
+        let mut db = RawDB::open_with_config(tmpdir.path(), config())?;
+        assert_eq!(
+            db.stats().num_sstables_per_level,
+            stats.num_sstables_per_level
+        );
+        for i in 0..500 {
+            assert_eq!(db.get(&format!("/key/{:04}", i))?, expected(i));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn size_tiered_compaction() -> anyhow::Result<()> {
+        let config = || DBConfig {
+            memtable_max_size_bytes: 512,
+            max_frozen_memtables: 0,
+            compaction_style: CompactionStyle::SizeTiered,
+            size_tiered_min_merge_width: 2,
+            ..DBConfig::default()
+        };
+        let (tmpdir, mut db) = make_db_in_tempdir(config());
+
+        for round in 0..3 {
+            for i in 0..300 {
+                db.put(format!("/key/{:04}", i), format!("{}_{}", round, i))?;
+            }
+        }
+        db.delete_range("/key/0100", "/key/0200")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.maybe_compact()?;
+
+        let stats = db.stats();
+        assert!(stats.num_compactions > 0);
+        // Everything stays in level 0.
+        assert!(stats.num_sstables_per_level[1..].iter().all(|&n| n == 0));
+
+        let expected = |i: usize| {
+            if (100..200).contains(&i) {
+                None
+            } else {
+                Some(format!("2_{}", i).into_bytes())
+            }
+        };
+        for i in 0..300 {
+            assert_eq!(db.get(&format!("/key/{:04}", i))?, expected(i));
+        }
+
+        std::mem::drop(db);
+        let mut db = RawDB::open_with_config(tmpdir.path(), config())?;
+        for i in 0..300 {
+            assert_eq!(db.get(&format!("/key/{:04}", i))?, expected(i));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn fifo_compaction() -> anyhow::Result<()> {
+        let config = || DBConfig {
+            compaction_style: CompactionStyle::Fifo,
+            fifo_max_table_files_size: 16 * 1024,
+            ..DBConfig::default()
+        };
+        let (tmpdir, mut db) = make_db_in_tempdir(config());
+        let value = "x".repeat(1000);
+        for batch in 0..10 {
+            for i in 0..4 {
+                db.put(format!("/log/{:02}/{}", batch, i), value.as_str())?;
+            }
+            db.flush()?;
+        }
+
+        // Only the newest sstables are left, and nothing was rewritten.
+        let stats = db.stats();
+        assert_eq!(stats.num_compactions, 0);
+        assert!(stats.num_sstables_per_level[0] < 10);
+        assert!(stats.num_sstables_per_level[1..].iter().all(|&n| n == 0));
+        let properties = db.table_properties();
+        assert!(properties.iter().map(|p| p.file_size).sum::<u64>() <= 16 * 1024);
+        assert!(properties.iter().all(|p| p.creation_time.is_some()));
+        assert_eq!(db.get("/log/00/0")?, None);
+        assert_eq!(db.get("/log/09/3")?, Some(value.clone().into_bytes()));
+
+        std::mem::drop(db);
+        let mut db = RawDB::open_with_config(tmpdir.path(), config())?;
+        assert_eq!(db.get("/log/00/0")?, None);
+        assert_eq!(db.get("/log/09/3")?, Some(value.into_bytes()));
+        db.compact()?;
+        assert_eq!(db.stats().num_sstables_per_level[0], properties.len());
+        Ok(())
+    }
+
+    #[test]
+    fn export_and_import() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing; all manual for now
+            max_frozen_memtables: 100,
+            ..DBConfig::default()
+        });
+
+        db.put("/a", "old")?;
+        db.put("/b", "old")?;
+        db.put("/c", "old")?;
+        db.put("/d", "old")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.put("/a", "new")?;
+        db.delete("/b")?;
+        db.delete_range("/c", "/d")?;
+        db.freeze_active_memtable()?;
+        db.put("/e", "new")?;
+
+        let mut stream = Vec::new();
+        assert_eq!(db.export(&mut stream)?, 3);
+
+        let (_tmpdir, other_db) = make_db_in_tempdir(DBConfig::default());
+        let other_db = DB::from_raw(other_db);
+        let changes = other_db.subscribe();
+        other_db.put("before", "import")?;
+        assert_eq!(other_db.import_stream(stream.as_slice())?, 3);
+        // The imported keys are new writes, numbered after the importer's own.
+        let events: Vec<ChangeEvent> = changes.try_iter().collect();
+        let first_sequence = events[0].sequence;
+        assert_eq!(
+            events[1..],
+            [
+                ChangeEvent {
+                    sequence: first_sequence + 1,
+                    change: Change::Put("/a".to_string(), b"new".to_vec())
+                },
+                ChangeEvent {
+                    sequence: first_sequence + 2,
+                    change: Change::Put("/d".to_string(), b"old".to_vec())
+                },
+                ChangeEvent {
+                    sequence: first_sequence + 3,
+                    change: Change::Put("/e".to_string(), b"new".to_vec())
+                },
+            ]
+        );
+        assert_eq!(
+            other_db.seek("/")?,
+            vec![
+                ("/a".to_string(), b"new".to_vec()),
+                ("/d".to_string(), b"old".to_vec()),
+                ("/e".to_string(), b"new".to_vec()),
+            ]
+        );
+
+        assert!(matches!(
+            other_db.import_stream(&stream[..stream.len() - 1]),
+            Err(DBError::InvalidExportStream(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_stalls() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing or compaction; all manual for now
+            max_frozen_memtables: 100,
+            level0_file_num_compaction_trigger: 100,
+            level0_slowdown_writes_trigger: 2,
+            level0_stop_writes_trigger: 3,
+            frozen_memtables_stop_writes_trigger: 2,
+            ..DBConfig::default()
+        });
+
+        db.put("/a", "1")?;
+        db.freeze_active_memtable()?;
+        db.put("/b", "2")?;
+        db.freeze_active_memtable()?;
+        // Writes stop at two frozen memtables, so the next write flushes them, but
+        // level 0 is then over its slowdown trigger.
+        db.put("/c", "3")?;
+        assert_eq!(db.frozen_memtables.len(), 0);
+        assert_eq!(db.levels[0].len(), 2);
+        assert_eq!(db.stats().num_write_slowdowns, 1);
+
+        // At three level-0 sstables, the next write merges them down.
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.delete("/a")?;
+        assert_eq!(db.levels[0].len(), 0);
+        assert_eq!(db.get("/a")?, None);
+        assert_eq!(db.get("/c")?, Some(b"3".to_vec()));
+        assert_eq!(db.stats().num_write_stalls, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn backup_and_restore() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+        let backup_path = tmpdir.path().join("backup");
+
+        db.put("/a", "1")?;
+        assert_eq!(db.create_backup(&backup_path)?, 1);
+        db.put("/b", "2")?;
+        db.delete("/a")?;
+        // Only the new sstable is copied.
+        assert_eq!(db.create_backup(&backup_path)?, 1);
+        db.put("/c", "3")?;
+
+        let restore_path = tmpdir.path().join("restored");
+        RawDB::restore_from_backup(&backup_path, &restore_path)?;
+        assert!(matches!(
+            RawDB::restore_from_backup(&backup_path, &restore_path),
+            Err(DBError::InvalidRootPath(_))
+        ));
+        let mut restored_db = RawDB::open(&restore_path)?;
+        assert_eq!(restored_db.get("/a")?, None);
+        assert_eq!(restored_db.get("/b")?, Some(b"2".to_vec()));
+        assert_eq!(restored_db.get("/c")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_timestamps() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+        db.put_with_ts("/a", 10, "a10")?;
+        db.put_with_ts("/b", 10, "b10")?;
+        db.flush()?;
+        db.put_with_ts("/a", 20, "a20")?;
+        db.delete_with_ts("/b", 30)?;
+        db.put("/a", "untimestamped")?;
+
+        assert_eq!(db.get_at_ts("/a", 5)?, None);
+        assert_eq!(db.get_at_ts("/a", 10)?, Some(b"a10".to_vec()));
+        assert_eq!(db.get_at_ts("/a", 19)?, Some(b"a10".to_vec()));
+        assert_eq!(db.get_at_ts("/a", u64::MAX)?, Some(b"a20".to_vec()));
+        assert_eq!(db.get_at_ts("/b", 29)?, Some(b"b10".to_vec()));
+        assert_eq!(db.get_at_ts("/b", 30)?, None);
+        assert_eq!(db.get("/a")?, Some(b"untimestamped".to_vec()));
+
+        let options = ReadOptions::default();
+        assert_eq!(
+            db.scan_at_ts("/", 25, &options)?,
+            vec![
+                ("/a".to_string(), Some(b"a20".to_vec())),
+                ("/b".to_string(), Some(b"b10".to_vec())),
+            ]
+        );
+        assert_eq!(
+            db.scan_at_ts("/", 30, &options)?,
+            vec![("/a".to_string(), Some(b"a20".to_vec()))]
+        );
+        let options = ReadOptions {
+            include_deleted_markers: true,
+            reverse: true,
+            limit: Some(1),
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            db.scan_at_ts("/", 30, &options)?,
+            vec![("/b".to_string(), None)]
+        );
+        let options = ReadOptions {
+            start_after: Some("/a".to_string()),
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            db.scan_at_ts("/", 25, &options)?,
+            vec![("/b".to_string(), Some(b"b10".to_vec()))]
+        );
+
+        // Versions don't show up in other scans.
+        assert_eq!(db.keys("", &ReadOptions::default())?, vec!["/a"]);
+        assert!(matches!(
+            db.put_with_ts("/a\0b", 10, "x"),
+            Err(DBError::InvalidKey(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn version_retention() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            max_versions: 3,
+            max_version_age: 100,
+            ..DBConfig::default()
+        });
+        for timestamp in [10, 20, 30] {
+            db.put_with_ts("/a", timestamp, format!("a{}", timestamp))?;
+            db.put_with_ts("/b", timestamp * 10, format!("b{}", timestamp * 10))?;
+        }
+        db.flush()?;
+        db.put_with_ts("/a", 40, "a40")?;
+        db.delete_with_ts("/b", 400)?;
+
+        // Nothing's dropped before a compaction.
+        assert_eq!(db.get_versions("/a")?.len(), 4);
+        assert_eq!(
+            db.get_versions("/b")?,
+            vec![
+                (400, None),
+                (300, Some(b"b300".to_vec())),
+                (200, Some(b"b200".to_vec())),
+                (100, Some(b"b100".to_vec())),
+            ]
+        );
+
+        db.compact()?;
+        // "/a" keeps its 3 newest versions, "/b" those at most 100 older than 400.
+        assert_eq!(
+            db.get_versions("/a")?,
+            vec![
+                (40, Some(b"a40".to_vec())),
+                (30, Some(b"a30".to_vec())),
+                (20, Some(b"a20".to_vec())),
+            ]
+        );
+        assert_eq!(
+            db.get_versions("/b")?,
+            vec![(400, None), (300, Some(b"b300".to_vec()))]
+        );
+        assert_eq!(db.get_at_ts("/a", 15)?, None);
+        assert_eq!(db.get_at_ts("/b", 400)?, None);
+        assert_eq!(db.get_versions("/c")?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn destroy_and_live_files() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            min_blob_size: 16,
+            ..DBConfig::default()
+        });
+        assert_eq!(db.path(), tmpdir.path());
+        db.put("/a", "1")?;
+        db.put("/b", "a value stored in a blob file")?;
+        db.flush()?;
+        db.put("/c", "3")?;
+        db.flush()?;
+
+        let live_files = db.live_files();
+        assert_eq!(live_files[0], tmpdir.path().join("MANIFEST"));
+        assert_eq!(
+            live_files
+                .iter()
+                .filter(|path| manifest::sstable_file_number(path).is_some())
+                .count(),
+            2
+        );
+        assert!(live_files
+            .iter()
+            .any(|path| blob::blob_file_number(path).is_some()));
+        assert!(live_files.iter().all(|path| path.exists()));
+        std::mem::drop(db);
+
+        let unrelated = tmpdir.path().join("notes.txt");
+        std::fs::write(&unrelated, "keep me")?;
+        RawDB::destroy(tmpdir.path(), &DBConfig::default())?;
+        assert!(live_files.iter().all(|path| !path.exists()));
+        assert!(unrelated.exists());
+        assert!(matches!(
+            RawDB::destroy(tmpdir.path(), &DBConfig::default()),
+            Err(DBError::InvalidRootPath(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+        let checkpoint_path = tmpdir.path().join("checkpoint");
+
+        db.put("/a", "1")?;
+        db.checkpoint(&checkpoint_path)?;
+        db.put("/a", "2")?;
+        assert!(matches!(
+            db.checkpoint(&checkpoint_path),
+            Err(DBError::InvalidRootPath(_))
+        ));
+
+        let mut checkpoint_db = RawDB::open(&checkpoint_path)?;
+        assert_eq!(checkpoint_db.get("/a")?, Some(b"1".to_vec()));
+        assert_eq!(db.get("/a")?, Some(b"2".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksums() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+
+        db.put_with_checksum("/a", "hello", crc32c(b"hello"))?;
+        assert_eq!(
+            db.put_with_checksum("/b", "hello", crc32c(b"hellO")),
+            Err(DBError::ChecksumMismatch {
+                expected: crc32c(b"hellO"),
+                actual: crc32c(b"hello"),
+            })
+        );
+        assert_eq!(db.get("/b")?, None);
+        assert_eq!(db.get("/a")?, Some(b"hello".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ingest_sst() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+
+        let path = tmpdir.path().join("bulk.sst");
+        let mut writer = SstWriter::create(&path)?;
+        for i in 0..100 {
+            writer.put(&format!("/bulk/{:03}", i), format!("{}", i))?;
+        }
+        assert!(writer.put("/bulk/000", "0").is_err());
+        let path = writer.finish()?;
+
+        // Nothing overlaps, so the sstable goes to the last level.
+        db.ingest_sst(&path)?;
+        assert_eq!(db.stats().num_sstables_per_level, vec![0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(db.get("/bulk/042")?, Some(b"42".to_vec()));
+
+        db.put("/bulk/001", "old")?;
+        db.put("/bulk/500", "old")?;
+        let path = tmpdir.path().join("update.sst");
+        let mut writer = SstWriter::create(&path)?;
+        writer.put("/bulk/001", "new")?;
+        writer.delete("/bulk/002")?;
+        writer.delete_range("/bulk/050", "/bulk/060");
+        let path = writer.finish()?;
+
+        // Overlaps the memtable, which is flushed first, and the last level.
+        db.ingest_sst(&path)?;
+        assert_eq!(db.stats().num_sstables_per_level, vec![2, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(db.get("/bulk/001")?, Some(b"new".to_vec()));
+        assert_eq!(db.get("/bulk/002")?, None);
+        assert_eq!(db.get("/bulk/003")?, Some(b"3".to_vec()));
+        assert_eq!(db.get("/bulk/055")?, None);
+        assert_eq!(db.get("/bulk/500")?, Some(b"old".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn compact() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+        for i in 0..100 {
+            db.put(format!("/key/{:03}", i), "old")?;
+        }
+        db.flush()?;
+        db.put("/key/001", "new")?;
+        db.delete("/key/002")?;
+        db.delete_range("/key/050", "/key/100")?;
+
+        // Everything is merged into level 1, without the deleted keys.
+        db.compact()?;
+        assert_eq!(db.stats().num_sstables_per_level, vec![0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(db.estimate_num_keys(), 49);
+        assert_eq!(db.get("/key/001")?, Some(b"new".to_vec()));
+        assert_eq!(db.get("/key/002")?, None);
+        assert_eq!(db.get("/key/003")?, Some(b"old".to_vec()));
+        assert_eq!(db.get("/key/050")?, None);
+
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            compaction_style: CompactionStyle::None,
+            ..DBConfig::default()
+        })?;
+        for i in 0..3 {
+            db.put("/key", format!("{}", i))?;
+            db.flush()?;
+        }
+        db.compact()?;
+        assert_eq!(db.stats().num_sstables_per_level[0], 1);
+        assert_eq!(db.get("/key")?, Some(b"2".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn compact_range() -> anyhow::Result<()> {
+        // Every key ends up in an sstable of its own.
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            target_file_size_base: 1,
+            small_file_compaction_trigger: 0,
+            ..DBConfig::default()
+        });
+        db.put("/a", "1")?;
+        db.put("/b", "1")?;
+        db.put("/c", "1")?;
+        db.compact()?;
+        assert_eq!(db.stats().num_sstables_per_level[1], 3);
+        let paths = |db: &RawDB| -> Vec<PathBuf> {
+            db.levels[1]
+                .iter()
+                .map(|sstable| sstable.path().to_path_buf())
+                .collect()
+        };
+        let before = paths(&db);
+
+        // Only the sstable holding "/b" is rewritten, which drops it.
+        db.delete("/b")?;
+        db.compact_range("/b", "/b")?;
+        assert_eq!(paths(&db), vec![before[0].clone(), before[2].clone()]);
+        assert_eq!(db.get("/b")?, None);
+        assert_eq!(db.get("/a")?, Some(b"1".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn deletion_compaction() -> anyhow::Result<()> {
+        // Every key ends up in an sstable of its own, and level 1 is pushed into
+        // level 2 as soon as it has anything.
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            num_levels: 3,
+            level0_file_num_compaction_trigger: 1,
+            max_bytes_for_level_base: 1,
+            max_bytes_for_level_multiplier: 1_000_000,
+            target_file_size_base: 1,
+            small_file_compaction_trigger: 0,
+            ..DBConfig::default()
+        });
+        db.put("/a", "1")?;
+        db.put("/b", "1")?;
+        db.put("/c", "1")?;
+        db.flush()?;
+        assert_eq!(db.stats().num_sstables_per_level, vec![0, 0, 3]);
+
+        // The deletions stay in level 1, where they hide the values in level 2.
+        db.config.max_bytes_for_level_base = u64::MAX;
+        db.delete("/a")?;
+        db.delete("/b")?;
+        db.put("/c", "2")?;
+        db.flush()?;
+        let deletions = |db: &RawDB| -> Vec<(usize, Option<u64>)> {
+            db.table_properties()
+                .into_iter()
+                .map(|properties| (properties.level, properties.num_deletions))
+                .collect()
+        };
+        assert_eq!(
+            deletions(&db),
+            vec![
+                (1, Some(1)),
+                (1, Some(1)),
+                (1, Some(0)),
+                (2, Some(0)),
+                (2, Some(0)),
+                (2, Some(0))
+            ]
+        );
+        assert_eq!(db.table_properties()[0].deletion_ratio(), Some(1.0));
+
+        // Past the ratio, the deleted sstables are merged into level 2, which is the
+        // last one, so the deletions are dropped with the values they hid.
+        db.config.deletion_compaction_ratio = 0.5;
+        db.flush()?;
+        assert_eq!(deletions(&db), vec![(1, Some(0)), (2, Some(0))]);
+        assert_eq!(db.get("/a")?, None);
+        assert_eq!(db.get("/c")?, Some(b"2".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn direct_io_for_flush_and_compaction() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            use_direct_io_for_flush_and_compaction: true,
+            // Smaller than a block, so blocks span several reads.
+            compaction_readahead_size: 1000,
+            ..DBConfig::default()
+        });
+        for i in 0..500 {
+            db.put(format!("/a/{:03}", i), vec![b'a'; 20])?;
+        }
+        db.flush()?;
+        db.put("/a/100", "1")?;
+        db.compact()?;
+        assert_eq!(db.get("/a/100")?, Some(b"1".to_vec()));
+        assert_eq!(db.keys("/a/", &ReadOptions::default())?.len(), 500);
+        FileStorage.drop_from_cache(&tmpdir.path().join(manifest::MANIFEST_FILENAME))?;
+        Ok(())
+    }
+
+    #[test]
+    fn secondary_catches_up() -> anyhow::Result<()> {
+        let config = || DBConfig {
+            max_frozen_memtables: 0,
+            level0_file_num_compaction_trigger: 2,
+            ..DBConfig::default()
+        };
+        let (tmpdir, mut primary) = make_db_in_tempdir(config());
+        let mut secondary = RawDB::open_as_secondary(tmpdir.path(), config())?;
+        assert_eq!(secondary.get("/a")?, None);
+        assert_eq!(secondary.put("/a", "1"), Err(DBError::ReadOnly));
+        assert_eq!(secondary.delete_range("/a", "/b"), Err(DBError::ReadOnly));
+
+        primary.put("/a", "1")?;
+        primary.freeze_active_memtable()?;
+        primary.flush_frozen_memtables()?;
+        // Not flushed, so the secondary never sees it.
+        primary.put("/b", "2")?;
+        assert_eq!(secondary.get("/a")?, None);
+        secondary.try_catch_up()?;
+        assert_eq!(secondary.get("/a")?, Some(b"1".to_vec()));
+        assert_eq!(secondary.get("/b")?, None);
+
+        // The primary compacts away the sstable the secondary has open.
+        primary.put("/a", "3")?;
+        primary.freeze_active_memtable()?;
+        primary.flush_frozen_memtables()?;
+        primary.maybe_compact()?;
+        assert_eq!(primary.stats().num_sstables_per_level[..2], [0, 1]);
+        assert_eq!(secondary.get("/a")?, Some(b"1".to_vec()));
+        secondary.try_catch_up()?;
+        assert_eq!(secondary.get("/a")?, Some(b"3".to_vec()));
+        assert_eq!(secondary.get("/b")?, Some(b"2".to_vec()));
+        assert_eq!(
+            secondary.stats().num_sstables_per_level,
+            primary.stats().num_sstables_per_level
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn secondary_rejects_damaged_manifest() -> anyhow::Result<()> {
+        let (tmpdir, mut primary) = make_db_in_tempdir(DBConfig::default());
+        primary.put("/a", "1")?;
+        primary.flush()?;
+        let mut secondary = RawDB::open_as_secondary(tmpdir.path(), DBConfig::default())?;
+
+        let mut manifest = Manifest::read_from(&FileStorage, tmpdir.path())?.unwrap();
+        let file_number = manifest.levels[0][0];
+        manifest.levels[0].push(file_number);
+        manifest.write_to(&FileStorage, tmpdir.path())?;
+        assert!(matches!(
+            secondary.try_catch_up(),
+            Err(DBError::Manifest(_))
+        ));
+        // The secondary keeps its sstables.
+        assert_eq!(secondary.get("/a")?, Some(b"1".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn secondary_cant_back_up() -> anyhow::Result<()> {
+        let (tmpdir, mut primary) = make_db_in_tempdir(DBConfig::default());
+        primary.put("/a", "1")?;
+        primary.flush()?;
+        let secondary = DB::open_as_secondary(tmpdir.path(), DBConfig::default())?;
+        let backup_dir = TempDir::new("lsmdb_backup")?;
+        assert_eq!(
+            secondary.create_backup(backup_dir.path()),
+            Err(DBError::ReadOnly)
+        );
+        assert_eq!(
+            secondary.checkpoint(&backup_dir.path().join("checkpoint")),
+            Err(DBError::ReadOnly)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn space_report() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing; all manual for now
+            max_frozen_memtables: 100,
+            ..DBConfig::default()
+        });
+
+        db.put("/a", "1234")?;
+        db.put("/b", "5678")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.put("/a", "12")?;
+        db.delete("/b")?;
+        db.delete_range("/c", "/d")?;
+        std::fs::write(tmpdir.path().join("99.sst"), [0u8; 10])?;
+
+        let report = db.space_report()?;
+        assert!(report.sstable_file_bytes > 0);
+        assert_eq!(
+            report.memtable_bytes as usize,
+            (2 + 2 + ENTRY_OVERHEAD) + (2 + ENTRY_OVERHEAD) + (2 + 2 + size_of::<RangeTombstone>())
+        );
+        assert_eq!(report.live_data_bytes, 2 + 2);
+        assert_eq!(report.obsolete_data_bytes, (2 + 4) + (2 + 4));
+        assert_eq!(report.tombstone_bytes, 2 + (2 + 2));
+        assert_eq!(report.trash_bytes, 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blob_values() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing; all manual for now
+            max_frozen_memtables: 100,
+            min_blob_size: 100,
+            ..DBConfig::default()
+        });
+
+        // Bigger than an sstable block.
+        let big_value: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        db.put("/big", big_value.clone())?;
+        db.put("/small", "1")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        assert_eq!(db.blob_files, vec![0]);
+        assert!(blob::blob_path(tmpdir.path(), 0).exists());
+
+        assert_eq!(db.get("/big")?, Some(big_value.clone()));
+        assert_eq!(db.get("/small")?, Some(b"1".to_vec()));
+        let mut value = Vec::new();
+        db.get_reader("/big")?.unwrap().read_to_end(&mut value)?;
+        assert_eq!(value, big_value);
+        assert!(db.get_reader("/missing")?.is_none());
+
+        // Compactions copy the blob references, not the values.
+        db.put("/big2", big_value.clone())?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        db.run_compaction(compaction::Compaction {
+            level: 0,
+            output_level: 1,
+            inputs: vec![0, 1],
+            output_level_inputs: vec![],
+        })?;
+        assert!(db.stats().compaction_bytes_written < 1000);
+        assert_eq!(db.get("/big2")?, Some(big_value.clone()));
+
+        let report = db.space_report()?;
+        assert_eq!(report.blob_file_bytes, 2 * big_value.len() as u64);
+        assert_eq!(
+            report.live_data_bytes,
+            (4 + 10_000) + (6 + 1) + (5 + 10_000)
+        );
+
+        // Blob files are listed in the MANIFEST, and copied by backups.
+        let backup_path = tmpdir.path().join("backup");
+        db.create_backup(&backup_path)?;
+        std::mem::drop(db);
+        let mut db = RawDB::open(tmpdir.path())?;
+        assert_eq!(db.get("/big")?, Some(big_value.clone()));
+        let mut backup = RawDB::open(&backup_path)?;
+        assert_eq!(backup.get("/big2")?, Some(big_value));
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_and_freeze_events() -> anyhow::Result<()> {
+        #[derive(Default)]
+        struct RecordingListener {
+            opens: Mutex<Vec<OpenInfo>>,
+            freezes: Mutex<Vec<MemtableInfo>>,
+        }
+
+        impl EventListener for RecordingListener {
+            fn on_open_completed(&self, info: &OpenInfo) {
+                self.opens.lock().unwrap().push(info.clone());
+            }
+
+            fn on_memtable_frozen(&self, info: &MemtableInfo) {
+                self.freezes.lock().unwrap().push(info.clone());
+            }
+        }
+
+        let listener = Arc::new(RecordingListener::default());
+        let config = || DBConfig {
+            min_blob_size: 16,
+            listeners: vec![listener.clone()],
+            ..DBConfig::default()
+        };
+        let (tmpdir, mut db) = make_db_in_tempdir(config());
+        db.put("/a", "1")?;
+        db.put("/b", "a value stored in a blob file")?;
+        db.delete_range("/c", "/d")?;
+        let memtable_size = db.active_memtable.size() as u64;
+        db.flush()?;
+        assert_eq!(
+            *listener.freezes.lock().unwrap(),
+            vec![MemtableInfo {
+                num_entries: 2,
+                num_range_tombstones: 1,
+                size_bytes: memtable_size,
+            }]
+        );
+
+        std::mem::drop(db);
+        RawDB::open_with_config(tmpdir.path(), config())?;
+        let opens = listener.opens.lock().unwrap();
+        assert_eq!(opens.len(), 2);
+        assert_eq!((opens[0].num_sstables, opens[0].sstable_bytes), (0, 0));
+        let mut sstable_bytes = 0;
+        for dirent in std::fs::read_dir(tmpdir.path())? {
+            let path = dirent?.path();
+            if path.extension().is_some_and(|extension| extension == "sst") {
+                sstable_bytes += std::fs::metadata(path)?.len();
+            }
+        }
+        assert_eq!(
+            (
+                opens[1].num_sstables,
+                opens[1].sstable_bytes,
+                opens[1].num_blob_files
+            ),
+            (1, sstable_bytes, 1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn event_listeners() -> anyhow::Result<()> {
+        #[derive(Default)]
+        struct RecordingListener {
+            events: Mutex<Vec<String>>,
+        }
+
+        impl EventListener for RecordingListener {
+            fn on_open_completed(&self, info: &OpenInfo) {
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push(format!("open {}", info.num_sstables));
+            }
+
+            fn on_memtable_frozen(&self, info: &MemtableInfo) {
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push(format!("frozen {}", info.size_bytes));
+            }
+
+            fn on_flush_completed(&self, info: &FlushJobInfo) {
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push(format!("flush {}", info.num_entries));
+            }
+
+            fn on_compaction_begin(&self, info: &CompactionJobInfo) {
+                self.events.lock().unwrap().push(format!(
+                    "compaction {} -> {} of {}",
+                    info.level,
+                    info.output_level,
+                    info.input_paths.len()
+                ));
+            }
+
+            fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push(format!("compacted into {}", info.output_paths.len()));
+            }
+
+            fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push(format!("{:?} -> {:?}", info.previous, info.current));
+            }
+        }
+
+        let listener = Arc::new(RecordingListener::default());
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig {
+            // No automatic flushing; all manual for now
+            max_frozen_memtables: 100,
+            level0_file_num_compaction_trigger: 3,
+            frozen_memtables_stop_writes_trigger: 2,
+            listeners: vec![listener.clone()],
+            ..DBConfig::default()
+        });
+
+        db.put("/a", "1")?;
+        db.freeze_active_memtable()?;
+        db.put("/b", "2")?;
+        db.freeze_active_memtable()?;
+        // Writes stop until the frozen memtables are flushed, which the next write
+        // does.
+        db.put("/c", "3")?;
+        db.freeze_active_memtable()?;
+        db.flush_frozen_memtables()?;
+        assert_eq!(
+            *listener.events.lock().unwrap(),
+            vec![
+                "open 0".to_string(),
+                format!("frozen {}", 2 + 1 + ENTRY_OVERHEAD),
+                format!("frozen {}", 2 + 1 + ENTRY_OVERHEAD),
+                "Normal -> Stopped".to_string(),
+                "flush 1".to_string(),
+                "flush 1".to_string(),
+                "Stopped -> Normal".to_string(),
+                format!("frozen {}", 2 + 1 + ENTRY_OVERHEAD),
+                "flush 1".to_string(),
+            ]
+        );
+
+        listener.events.lock().unwrap().clear();
+        db.maybe_compact()?;
+        assert_eq!(
+            *listener.events.lock().unwrap(),
+            vec!["compaction 0 -> 1 of 3", "compacted into 1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mem_storage() -> anyhow::Result<()> {
+        let storage = MemStorage::new();
+        let config = || DBConfig {
+            storage: Arc::new(storage.clone()),
+            min_blob_size: 100,
+            ..DBConfig::default()
+        };
+        let root_path = Path::new("/lsmdb_mem_storage_test/db");
+        let backup_path = Path::new("/lsmdb_mem_storage_test/backup");
+
+        let mut db = RawDB::open_with_config(root_path, config())?;
+        db.put("/a", "1")?;
+        db.put("/big", vec![7u8; 1000])?;
+        db.flush()?;
+        db.delete("/a")?;
+        db.flush()?;
+        db.create_backup(backup_path)?;
+        assert!(!root_path.exists());
+        std::mem::drop(db);
+
+        for path in [root_path, backup_path] {
+            let mut db = RawDB::open_with_config(path, config())?;
+            assert_eq!(db.get("/a")?, None);
+            assert_eq!(db.get("/big")?, Some(vec![7u8; 1000]));
+            assert_eq!(db.stats().num_sstables_per_level.iter().sum::<usize>(), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn in_memory() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            memtable_max_size_bytes: 64,
+            max_frozen_memtables: 0,
+            ..DBConfig::default()
+        })?;
+        for i in 0..100 {
+            db.put(format!("/key/{:03}", i), format!("{}", i))?;
+        }
+        assert!(db.stats().num_flushes > 0);
+        assert_eq!(db.get("/key/042")?, Some(b"42".to_vec()));
+
+        // Every in-memory database is separate.
+        assert_eq!(DB::open_in_memory()?.get("/key/042")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn scan_prefix() -> anyhow::Result<()> {
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            min_blob_size: 8,
+            ..DBConfig::default()
+        })?;
+        db.put("/a/1", "one")?;
+        db.put("/a/2", "a large value")?;
+        db.put("/a/3", "three")?;
+        db.put("/b/1", "other")?;
+        db.flush()?;
+        db.delete("/a/2")?;
+        db.put("/a/4", "four")?;
+        db.put("/a", "prefix")?;
+
+        let scan = |db: &mut RawDB, options: ReadOptions| -> anyhow::Result<Vec<String>> {
+            Ok(db
+                .scan_prefix("/a/", &options)?
+                .into_iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}={}",
+                        key,
+                        String::from_utf8(value.unwrap_or_default()).unwrap()
+                    )
+                })
+                .collect())
+        };
+        assert_eq!(
+            scan(&mut db, ReadOptions::default())?,
+            vec!["/a/1=one", "/a/3=three", "/a/4=four"]
+        );
+        assert_eq!(
+            scan(
+                &mut db,
+                ReadOptions {
+                    limit: Some(2),
+                    reverse: true,
+                    ..ReadOptions::default()
+                }
+            )?,
+            vec!["/a/4=four", "/a/3=three"]
+        );
+        assert_eq!(
+            scan(
+                &mut db,
+                ReadOptions {
+                    limit: Some(2),
+                    include_deleted_markers: true,
+                    ..ReadOptions::default()
+                }
+            )?,
+            vec!["/a/1=one", "/a/2="]
+        );
+
+        // Paging with the last key of the previous page.
+        let page = |db: &mut RawDB, start_after: &str, reverse: bool| {
+            scan(
+                db,
+                ReadOptions {
+                    limit: Some(1),
+                    reverse,
+                    start_after: Some(start_after.to_string()),
+                    ..ReadOptions::default()
+                },
+            )
+        };
+        assert_eq!(page(&mut db, "/a/1", false)?, vec!["/a/3=three"]);
+        assert_eq!(page(&mut db, "/a/3", false)?, vec!["/a/4=four"]);
+        assert!(page(&mut db, "/a/4", false)?.is_empty());
+        assert_eq!(page(&mut db, "/", false)?, vec!["/a/1=one"]);
+        assert_eq!(page(&mut db, "/a/4", true)?, vec!["/a/3=three"]);
+        assert_eq!(page(&mut db, "/a/2", true)?, vec!["/a/1=one"]);
+
+        db.flush()?;
+        db.put("/a/2", "a new large value")?;
+        assert_eq!(
+            db.scan_prefix("/a/2", &ReadOptions::default())?,
+            vec![("/a/2".to_string(), Some(b"a new large value".to_vec()))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_scan_with_limit() -> anyhow::Result<()> {
+        let (_tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+        for i in 0..100 {
+            db.put(format!("/a/{:03}", i), format!("{}", i))?;
+            if i == 50 {
+                db.flush()?;
+            }
+        }
+        db.put("/b", "other")?;
+
+        let options = ReadOptions {
+            limit: Some(3),
+            reverse: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            db.keys("/a/", &options)?,
+            vec!["/a/099", "/a/098", "/a/097"]
+        );
+        let options = ReadOptions {
+            start_after: Some("/a/051".to_string()),
+            ..options
+        };
+        assert_eq!(
+            db.scan_prefix("/a/", &options)?,
+            vec![
+                ("/a/050".to_string(), Some(b"50".to_vec())),
+                ("/a/049".to_string(), Some(b"49".to_vec())),
+                ("/a/048".to_string(), Some(b"48".to_vec())),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn keys() -> anyhow::Result<()> {
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            min_blob_size: 8,
+            ..DBConfig::default()
+        })?;
+        db.put("/a/1", "one")?;
+        db.put("/a/2", "a large value")?;
+        db.put("/a/3", "three")?;
+        db.flush()?;
+        db.delete("/a/3")?;
+        db.put("/a/4", "four")?;
+        db.put("/b/1", "other")?;
+
+        assert_eq!(
+            db.keys("/a/", &ReadOptions::default())?,
+            vec!["/a/1", "/a/2", "/a/4"]
+        );
+        assert_eq!(
+            db.keys(
+                "/a/",
+                &ReadOptions {
+                    include_deleted_markers: true,
+                    reverse: true,
+                    limit: Some(2),
+                    ..ReadOptions::default()
+                }
+            )?,
+            vec!["/a/4", "/a/3"]
+        );
+        // No values were read.
+        assert_eq!(db.stats().bytes_read, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn compare_and_swap() -> anyhow::Result<()> {
+        let db = DB::open_in_memory()?;
+        assert!(db.compare_and_swap("/lock", None, Some(b"a"))?);
+        assert!(!db.compare_and_swap("/lock", None, Some(b"b"))?);
+        db.flush()?;
+        assert!(!db.compare_and_swap("/lock", Some(b"b"), None)?);
+        assert!(db.compare_and_swap("/lock", Some(b"a"), None)?);
+        assert_eq!(db.get("/lock")?, None);
+
+        // Concurrent increments don't lose updates.
+        db.put("/counter", "0")?;
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        loop {
+                            let current = db.get("/counter").unwrap().unwrap();
+                            let count: u64 =
+                                String::from_utf8(current.clone()).unwrap().parse().unwrap();
+                            let new = (count + 1).to_string();
+                            if db
+                                .compare_and_swap("/counter", Some(&current), Some(new.as_bytes()))
+                                .unwrap()
+                            {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(db.get("/counter")?, Some(b"400".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn subscriber_falls_behind() -> anyhow::Result<()> {
+        let db = DB::open_in_memory()?;
+        let capacity = crate::changes::SUBSCRIBER_CAPACITY;
+        let changes = db.subscribe();
+        for i in 0..capacity + 10 {
+            db.put(format!("/key/{:04}", i), "value")?;
+        }
+        // The events which didn't fit were dropped, and the stream ends after the
+        // others, even though the database is still open.
+        assert_eq!(changes.iter().count(), capacity);
+
+        let changes = db.subscribe();
+        db.put("/after", "value")?;
+        assert!(changes.try_recv().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn subscribe() -> anyhow::Result<()> {
+        let db = DB::open_in_memory()?;
+        db.put("/before", "not sent")?;
+        let changes = db.subscribe();
+        db.put("/a", "1")?;
+        db.delete("/b")?;
+        db.delete_range("/c", "/d")?;
+        std::mem::drop(db);
+
+        let events: Vec<ChangeEvent> = changes.iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                ChangeEvent {
+                    sequence: 2,
+                    change: Change::Put("/a".to_string(), b"1".to_vec())
+                },
+                ChangeEvent {
+                    sequence: 3,
+                    change: Change::Delete("/b".to_string())
+                },
+                ChangeEvent {
+                    sequence: 4,
+                    change: Change::DeleteRange("/c".to_string(), "/d".to_string())
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn get_updates_since() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            change_retention: Duration::from_millis(200),
+            ..DBConfig::default()
+        })?;
+        db.put("/a", "1")?;
+        std::thread::sleep(Duration::from_millis(300));
+        db.put("/b", "2")?;
+        db.delete("/a")?;
+
+        let sequences = |events: Vec<ChangeEvent>| -> Vec<u64> {
+            events.iter().map(|event| event.sequence).collect()
+        };
+        assert_eq!(sequences(db.get_updates_since(1)?), vec![2, 3]);
+        assert_eq!(
+            db.get_updates_since(2)?,
+            vec![ChangeEvent {
+                sequence: 3,
+                change: Change::Delete("/a".to_string())
+            }]
+        );
+        assert_eq!(db.get_updates_since(3)?, vec![]);
+        // The first write has expired.
+        assert_eq!(db.get_updates_since(0), Err(DBError::UpdatesUnavailable(0)));
+        Ok(())
+    }
+
+    #[test]
+    fn get_updates_since_reopen() -> anyhow::Result<()> {
+        let tempdir = tempdir::TempDir::new("lsmdb")?;
+        let config = || DBConfig {
+            change_retention: Duration::from_secs(60),
+            ..DBConfig::default()
+        };
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        db.put("/a", "1")?;
+        db.put("/b", "2")?;
+        let last_sequence = db.get_updates_since(0)?.last().unwrap().sequence;
+        std::mem::drop(db);
+
+        // The writes made after reopening aren't silently skipped.
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        db.put("/c", "3")?;
+        assert_eq!(
+            db.get_updates_since(last_sequence),
+            Err(DBError::UpdatesUnavailable(last_sequence))
+        );
+        let changes = db.subscribe();
+        db.put("/d", "4")?;
+        assert!(changes.try_recv()?.sequence > last_sequence);
+        Ok(())
+    }
+
+    #[test]
+    fn open_newer_format_version() -> anyhow::Result<()> {
+        let tempdir = tempdir::TempDir::new("lsmdb")?;
+        let mut db = RawDB::open(tempdir.path())?;
+        db.put("/a", "1")?;
+        db.flush()?;
+        std::mem::drop(db);
+
+        // Pretend a newer version wrote the sstable; its version is the second to
+        // last u32 of the footer.
+        let sstable_path = manifest::sstable_path(tempdir.path(), 0);
+        let mut bytes = std::fs::read(&sstable_path)?;
+        let version_offset = bytes.len() - 8;
+        bytes[version_offset..][..4].copy_from_slice(&99u32.to_le_bytes());
+        std::fs::write(&sstable_path, bytes)?;
+
+        let expected_err = || DBError::UnsupportedVersion {
+            file: sstable_path.to_string_lossy().to_string(),
+            version: 99,
+        };
+        assert_eq!(RawDB::open(tempdir.path()).err(), Some(expected_err()));
+        // Repairing doesn't mistake the sstable for a damaged one.
+        assert_eq!(
+            RawDB::repair(tempdir.path(), &DBConfig::default()).err(),
+            Some(expected_err())
+        );
+        assert!(sstable_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn raw_seek() -> anyhow::Result<()> {
+        let db = DB::open_in_memory()?;
+        db.put("/a", "1")?;
+        db.put("/b", "2")?;
+        db.put("/c", "3")?;
+        db.flush()?;
+        db.delete("/b")?;
+        db.delete("/d")?;
+        db.delete_range("/c", "/cc")?;
+        assert_eq!(
+            db.raw_seek("/").collect::<Result<Vec<_>, _>>()?,
+            vec![
+                ("/a".to_string(), Some(b"1".to_vec())),
+                ("/b".to_string(), None),
+                ("/d".to_string(), None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn get_or_insert_with() -> anyhow::Result<()> {
+        let db = DB::open_in_memory()?;
+        let num_calls = std::sync::atomic::AtomicUsize::new(0);
+        let values: Vec<Value> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|thread| {
+                    let (db, num_calls) = (&db, &num_calls);
+                    scope.spawn(move || {
+                        db.get_or_insert_with("/config", || {
+                            num_calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            format!("from thread {}", thread).into_bytes()
+                        })
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Result<_, _>>()
+        })?;
+        assert_eq!(num_calls.into_inner(), 1);
+        assert!(values.iter().all(|value| *value == values[0]));
+        assert_eq!(db.get("/config")?, Some(values[0].clone()));
+
+        // A panic in `make_value` reaches the caller, but doesn't poison the lock.
+        let panicked = std::panic::catch_unwind(|| {
+            db.get_or_insert_with("/panics", || panic!("couldnt make value"))
+        });
+        assert!(panicked.is_err());
+        assert_eq!(db.get("/panics")?, None);
+        assert_eq!(
+            db.get_or_insert_with("/panics", || b"made".to_vec())?,
+            b"made"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn secondary_index() -> anyhow::Result<()> {
+        // Indexes users by city.
+        struct City;
+        impl IndexExtractor for City {
+            fn name(&self) -> String {
+                "city".to_string()
+            }
+
+            fn index_keys(&self, _key: &str, value: &[u8]) -> Vec<String> {
+                let value = String::from_utf8_lossy(value);
+                value
+                    .split_once(',')
+                    .map(|(_, city)| city.to_string())
+                    .into_iter()
+                    .collect()
+            }
+        }
+
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            indexes: vec![Arc::new(City)],
+            ..DBConfig::default()
+        })?;
+        db.put("/user/1", "alice,paris")?;
+        db.put("/user/2", "bob,berlin")?;
+        db.put("/user/3", "carol,paris")?;
+        db.flush()?;
+        db.put("/user/4", "dave,perth")?;
+
+        let keys = |results: Vec<(Key, Value)>| -> Vec<Key> {
+            results.into_iter().map(|(key, _)| key).collect()
+        };
+        assert_eq!(
+            keys(db.seek_index("city", "paris")?),
+            vec!["/user/1", "/user/3"]
+        );
+        assert_eq!(
+            keys(db.seek_index("city", "p")?),
+            vec!["/user/1", "/user/3", "/user/4"]
+        );
+
+        // Overwrites and deletes replace the old index entries.
+        db.put("/user/1", "alice,berlin")?;
+        db.delete("/user/3")?;
+        assert_eq!(keys(db.seek_index("city", "paris")?), Vec::<Key>::new());
+        assert_eq!(
+            db.seek_index("city", "berlin")?,
+            vec![
+                ("/user/1".to_string(), b"alice,berlin".to_vec()),
+                ("/user/2".to_string(), b"bob,berlin".to_vec())
+            ]
+        );
+        db.flush()?;
+        db.delete_range("/user/2", "/user/9")?;
+        assert_eq!(keys(db.seek_index("city", "")?), vec!["/user/1"]);
+
+        // The index entries are hidden from other scans.
+        assert_eq!(db.keys("", &ReadOptions::default())?, vec!["/user/1"]);
+        assert_eq!(db.seek("")?.count(), 0);
+
+        // Users can't write index entries, nor have values with invalid index keys.
+        assert!(matches!(
+            db.put("\0index/city/paris\0/user/5", ""),
+            Err(DBError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            db.put("/user/5", "eve,pa\0ris"),
+            Err(DBError::InvalidKey(_))
+        ));
+        assert_eq!(db.get("/user/5")?, None);
+        assert!(matches!(
+            db.seek_index("ci/ty", ""),
+            Err(DBError::InvalidKey(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn compaction_filter() -> anyhow::Result<()> {
+        // Drops soft-deleted rows, and upgrades old ones.
+        struct SoftDeletes;
+        impl CompactionFilter for SoftDeletes {
+            fn filter(&self, _level: usize, _key: &str, value: &[u8]) -> CompactionDecision {
+                if value.starts_with(b"deleted") {
+                    CompactionDecision::Remove
+                } else if let Some(rest) = value.strip_prefix(b"v1:") {
+                    CompactionDecision::ChangeValue([b"v2:", rest].concat())
+                } else {
+                    CompactionDecision::Keep
+                }
+            }
+        }
+
+        let statistics = Arc::new(Statistics::new());
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            statistics: statistics.clone(),
+            level0_file_num_compaction_trigger: 2,
+            min_blob_size: 16,
+            compaction_filter: Some(Arc::new(SoftDeletes)),
+            ..DBConfig::default()
+        })?;
+        db.put("/a", "v1:a")?;
+        db.put("/b", "v1:b")?;
+        db.put("/c", "v2:c")?;
+        db.flush()?;
+        db.put("/b", "deleted")?;
+        db.put("/d", "deleted, stored in a blob file")?;
+        db.flush()?;
+        assert_eq!(statistics.num_compactions(), 1);
+
+        assert_eq!(db.get("/a")?, Some(b"v2:a".to_vec()));
+        assert_eq!(db.get("/b")?, None);
+        assert_eq!(db.get("/c")?, Some(b"v2:c".to_vec()));
+        assert_eq!(db.get("/d")?, None);
+        // Removed keys are gone from the bottommost level.
+        assert_eq!(db.estimate_num_keys(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn compaction_filter_skips_reserved_keys() -> anyhow::Result<()> {
+        struct Everything;
+        impl IndexExtractor for Everything {
+            fn name(&self) -> String {
+                "everything".to_string()
+            }
+
+            fn index_keys(&self, _key: &str, _value: &[u8]) -> Vec<String> {
+                vec!["all".to_string()]
+            }
+        }
+        // Removes every key it's given.
+        #[derive(Default)]
+        struct RemoveAll {
+            keys: Mutex<Vec<Key>>,
+        }
+        impl CompactionFilter for RemoveAll {
+            fn filter(&self, _level: usize, key: &str, _value: &[u8]) -> CompactionDecision {
+                self.keys.lock().unwrap().push(key.to_string());
+                CompactionDecision::Remove
+            }
+        }
+
+        let filter = Arc::new(RemoveAll::default());
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            indexes: vec![Arc::new(Everything)],
+            compaction_filter: Some(filter.clone()),
+            ..DBConfig::default()
+        })?;
+        db.put("/a", "value")?;
+        db.put_with_ts("/b", 1, "value")?;
+        db.flush()?;
+        db.compact()?;
+        assert_eq!(*filter.keys.lock().unwrap(), vec!["/a"]);
+        assert_eq!(db.get("/a")?, None);
+        assert_eq!(db.get_at_ts("/b", 1)?, Some(b"value".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_filters() -> anyhow::Result<()> {
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            compaction_style: CompactionStyle::None,
+            prefix_extractor: Some(Arc::new(crate::SeparatorPrefix {
+                separator: '/',
+                count: 2,
+            })),
+            ..DBConfig::default()
+        })?;
+        db.put("/user/1", "alice")?;
+        db.put("/user/2", "bob")?;
+        db.flush()?;
+        db.put("/group/1", "admins")?;
+        db.flush()?;
+
+        assert_eq!(db.get("/order/1")?, None);
+        assert_eq!(db.stats().num_prefix_filter_skips, 2);
+        assert_eq!(db.get("/user/2")?, Some(b"bob".to_vec()));
+        assert_eq!(db.stats().num_prefix_filter_skips, 3);
+        let users = db.scan_prefix("/user/", &ReadOptions::default())?;
+        assert_eq!(users.len(), 2);
+        assert_eq!(db.stats().num_prefix_filter_skips, 4);
+
+        // The range tombstones of skipped sstables still apply.
+        db.delete_range("/user/", "/user/2")?;
+        db.put("/group/2", "users")?;
+        db.flush()?;
+        assert_eq!(
+            db.scan_prefix("/user/", &ReadOptions::default())?,
+            vec![("/user/2".to_string(), Some(b"bob".to_vec()))]
+        );
+        assert_eq!(db.get("/user/1")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn write_buffer_manager() -> anyhow::Result<()> {
+        let manager = Arc::new(WriteBufferManager::new(100 + 2 * ENTRY_OVERHEAD));
+        let config = || DBConfig {
+            write_buffer_manager: Some(manager.clone()),
+            ..DBConfig::default()
+        };
+        let mut a = RawDB::open_in_memory_with_config(config())?;
+        let mut b = RawDB::open_in_memory_with_config(config())?;
+        a.put("/a/1", vec![b'a'; 46])?;
+        b.put("/b/1", vec![b'b'; 26])?;
+        assert_eq!(manager.memory_usage(), 80 + 2 * ENTRY_OVERHEAD);
+
+        // Going over the budget flushes the largest memtables, which are a's.
+        a.put("/a/2", vec![b'a'; 26])?;
+        assert_eq!(a.stats().num_write_buffer_flushes, 1);
+        assert_eq!(a.stats().num_flushes, 1);
+        assert_eq!(b.stats().num_flushes, 0);
+        assert_eq!(manager.memory_usage(), 30 + ENTRY_OVERHEAD);
+        assert_eq!(a.get("/a/2")?, Some(vec![b'a'; 26]));
+
+        std::mem::drop(b);
+        assert_eq!(manager.memory_usage(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn write_buffer_manager_idle_database() -> anyhow::Result<()> {
+        let manager = Arc::new(WriteBufferManager::new(12 * 1024));
+        let config = || DBConfig {
+            write_buffer_manager: Some(manager.clone()),
+            ..DBConfig::default()
+        };
+        let mut big = RawDB::open_in_memory_with_config(config())?;
+        let mut small = RawDB::open_in_memory_with_config(config())?;
+        big.put("/big", vec![b'b'; 10 * 1024])?;
+
+        // The big database is asked to flush, but it's idle, so the small one keeps
+        // its memtables rather than flushing on every write.
+        for i in 0..50 {
+            small.put(format!("/small/{:02}", i), "value")?;
+        }
+        assert_eq!(small.stats().num_flushes, 0);
+        assert_eq!(big.stats().num_flushes, 0);
+
+        big.put("/big/2", "value")?;
+        assert_eq!(big.stats().num_write_buffer_flushes, 1);
+        assert_eq!(small.stats().num_flushes, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn rate_limiter() -> anyhow::Result<()> {
+        let rate_limiter = Arc::new(RateLimiter::new(100 * 1024));
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            rate_limiter: Some(rate_limiter.clone()),
+            ..DBConfig::default()
+        })?;
+        for i in 0..20 {
+            db.put(format!("/key/{:02}", i), vec![b'v'; 1024])?;
+        }
+        let start = Instant::now();
+        db.flush()?;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // Without a limit, the writes aren't delayed.
+        rate_limiter.set_bytes_per_sec(0);
+        for i in 0..20 {
+            db.put(format!("/key/{:02}", i), vec![b'w'; 1024])?;
+        }
+        let start = Instant::now();
+        db.flush()?;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(db.get("/key/07")?, Some(vec![b'w'; 1024]));
+        Ok(())
     }
 
     #[test]
-    fn basic_put_get() {
-        let mut db = DB::open(Path::new("/tmp/hello")).expect("failed to open");
-
-        db.put("1", "hello").expect("cant put 1");
-        db.put("2", "world").expect("cant put 2");
-
-        assert_eq!(db.get("1"), Ok(Some("hello".as_bytes().to_vec())));
-        assert_eq!(db.get("2"), Ok(Some("world".as_bytes().to_vec())));
-        assert_eq!(db.get("3"), Ok(None));
+    fn rate_limiter_waits_without_lock() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            rate_limiter: Some(Arc::new(RateLimiter::new(20 * 1024))),
+            ..DBConfig::default()
+        })?;
+        for i in 0..40 {
+            db.put(format!("/key/{:02}", i), vec![b'v'; 1024])?;
+        }
+        let start = Instant::now();
+        let flushed = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|scope| -> anyhow::Result<()> {
+            let flusher = scope.spawn(|| {
+                let result = db.flush();
+                flushed.store(true, std::sync::atomic::Ordering::SeqCst);
+                result
+            });
+            // Once the sstable is written, the lock is free while the flusher
+            // waits out the rest of its two seconds.
+            while db.stats().num_flushes == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            db.put("/other", "value")?;
+            assert!(!flushed.load(std::sync::atomic::Ordering::SeqCst));
+            flusher.join().unwrap()?;
+            Ok(())
+        })?;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        Ok(())
     }
 
     #[test]
-    fn basic_delete() {
-        let mut db = DB::open(Path::new("/tmp/hello")).expect("failed to open");
-
-        db.put("1", "hello").expect("cant put 1");
-        db.put("2", "world").expect("cant put 2");
+    fn snapshot() -> anyhow::Result<()> {
+        for memtable_impl in [MemtableImpl::BTree, MemtableImpl::SkipList] {
+            let db = DB::open_in_memory_with_config(DBConfig {
+                memtable_impl,
+                ..DBConfig::default()
+            })?;
+            db.put("/a", "1")?;
+            db.flush()?;
+            db.put("/b", "1")?;
+            let snapshot = db.snapshot();
 
-        assert_eq!(db.get("2"), Ok(Some(b"world".to_vec())));
+            // Writes go on while the snapshot is held, even while it's iterated.
+            let mut iter = snapshot.iter_pinned("/");
+            assert_eq!(
+                iter.next().transpose()?,
+                Some(("/a".to_string(), b"1".to_vec()))
+            );
+            db.put("/a", "2")?;
+            db.delete("/b")?;
+            db.put("/c", "2")?;
+            db.delete_range("/a", "/b")?;
+            db.flush()?;
+            db.compact()?;
+            assert_eq!(
+                iter.next().transpose()?,
+                Some(("/b".to_string(), b"1".to_vec()))
+            );
+            assert!(iter.next().is_none());
 
-        db.delete("2").expect("couldnt delete 2");
-        assert_eq!(db.get("2").expect("cant put 2"), None);
+            assert_eq!(snapshot.get("/a")?, Some(b"1".to_vec()));
+            assert_eq!(snapshot.get("/c")?, None);
+            assert_eq!(snapshot.get("/")?, None);
+            assert_eq!(db.get("/a")?, None);
+            assert_eq!(db.snapshot().get("/c")?, Some(b"2".to_vec()));
+        }
+        Ok(())
     }
 
     #[test]
-    fn basic_seek() {
-        let mut db = DB::open(Path::new("/tmp/hello")).expect("failed to open");
-
-        db.put("/user/name/adam", "adam")
-            .expect("cant put /user/adam");
-        db.put("/user/name/vardhan", "vardhan")
-            .expect("cant put /user/vardhan");
-        db.put("/abc", "abc").expect("cant put /abc");
-        db.put("/xyz", "xyz").expect("cant put /xyz");
+    fn pinned_iterator() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            level0_file_num_compaction_trigger: 2,
+            min_blob_size: 16,
+            ..DBConfig::default()
+        })?;
+        db.put("/a", "sstable")?;
+        db.put("/b", "sstable, stored in a blob file")?;
+        db.flush()?;
+        db.put("/c", "memtable")?;
+        db.delete("/a")?;
+        db.put("/z", "outside the prefix")?;
 
-        assert_eq!(db.get("/user/name/vardhan"), Ok(Some(b"vardhan".to_vec())));
+        let mut iter = db.iter_pinned("/");
+        assert_eq!(
+            iter.next().transpose()?,
+            Some(("/b".to_string(), b"sstable, stored in a blob file".to_vec()))
+        );
 
+        // Writes, flushes and compactions don't change what the iterator sees, and
+        // don't wait for it.
+        db.put("/c", "new")?;
+        db.put("/d", "new")?;
+        db.flush()?;
+        db.compact()?;
+        assert_eq!(db.stats().num_sstables_per_level[0], 0);
         assert_eq!(
-            db.seek("/user/")
-                .expect("couldnt seek /user")
-                .collect::<Vec<(Key, Value)>>(),
+            iter.collect::<Result<Vec<_>, _>>()?,
             vec![
-                ("/user/name/adam".to_string(), b"adam".to_vec()),
-                ("/user/name/vardhan".to_string(), b"vardhan".to_vec())
+                ("/c".to_string(), b"memtable".to_vec()),
+                ("/z".to_string(), b"outside the prefix".to_vec()),
             ]
         );
 
-        assert_eq!(
-            db.seek("/user/vardhan_")
-                .expect("couldn't seen /user/vardhan_")
-                .collect::<Vec<(Key, Value)>>(),
-            vec![]
-        );
+        let keys: Vec<Key> = db
+            .iter_pinned("/c")
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(keys, vec!["/c".to_string()]);
+        Ok(())
+    }
 
-        assert_eq!(
-            db.seek("/items/")
-                .expect("couldnt seek /items")
-                .collect::<Vec<(Key, Value)>>(),
-            vec![]
-        );
+    #[test]
+    fn next_batch() -> anyhow::Result<()> {
+        let mut db = RawDB::open_in_memory()?;
+        for i in 0..5 {
+            db.put(format!("/{}", i), "memtable")?;
+        }
+        db.flush()?;
+        db.put("/5", "memtable")?;
+
+        let keys = |batch: Vec<(Key, Value)>| -> Vec<Key> {
+            batch.into_iter().map(|(key, _)| key).collect()
+        };
+        let mut iter = db.iter_pinned("/");
+        assert_eq!(keys(iter.next_batch(4)?), vec!["/0", "/1", "/2", "/3"]);
+        assert_eq!(keys(iter.next_batch(4)?), vec!["/4", "/5"]);
+        assert!(iter.next_batch(4)?.is_empty());
+        let mut iter = db.iter_pinned("/");
+        assert_eq!(iter.next_batch(usize::MAX)?.len(), 6);
+        Ok(())
     }
 
     #[test]
-    fn seek_with_active_and_frozen_memtable() {
-        let tmpdir = tempdir::TempDir::new("lsmdb").expect("tmpdir");
-        let mut db = DB::open_with_config(
-            tmpdir.path(),
-            DBConfig {
-                // don't trigger writing to sstables
-                max_frozen_memtables: 100,
-                ..DBConfig::default()
-            },
-        )
-        .expect("couldnt make db");
+    fn next_batch_error() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+        for i in 0..1000 {
+            let value = if i == 500 { "needle" } else { "haystack" };
+            db.put(format!("/key/{:03}", i), value)?;
+        }
+        db.flush()?;
+        std::mem::drop(db);
+        let path = manifest::sstable_path(tmpdir.path(), 0);
+        let mut bytes = std::fs::read(&path)?;
+        let offset = bytes
+            .windows(6)
+            .position(|window| window == b"needle")
+            .expect("value not found");
+        bytes[offset] ^= 0xff;
+        std::fs::write(&path, bytes)?;
 
-        db.put("/user/name/adam", "adam")
-            .expect("cant put /user/adam");
-        db.put("/user/name/vardhan", "vardhan")
-            .expect("cant put /user/vardhan");
-        db.put("/user/name/catherine", "catherine")
-            .expect("cant put /user/catherine");
-        db.put("/abc", "abc").expect("cant put /abc");
-        db.put("/xyz", "xyz").expect("cant put /xyz");
+        // The entries before the damaged block come first, then the error.
+        let db = RawDB::open(tmpdir.path())?;
+        let mut iter = db.iter_pinned("/key/");
+        let batch = iter.next_batch(1000)?;
+        assert!(!batch.is_empty() && batch.len() <= 500);
+        assert_eq!(batch[0].0, "/key/000");
+        assert!(matches!(iter.next_batch(1000), Err(DBError::SSTable(_))));
+        Ok(())
+    }
 
-        db.freeze_active_memtable()
-            .expect("could not freeze active memtable");
+    #[test]
+    fn skiplist_memtable() -> anyhow::Result<()> {
+        let db = Arc::new(DB::open_in_memory_with_config(DBConfig {
+            memtable_impl: MemtableImpl::SkipList,
+            memtable_max_size_bytes: 4096,
+            arena_block_size: 1024,
+            ..DBConfig::default()
+        })?);
+        db.put("/a", "1")?;
+        db.put("/b", "1")?;
+        // The memtable's size is the size of its arena's blocks.
+        assert!(db.space_report()?.memtable_bytes > 1024);
 
-        // There should be two memtables now; active and 1 frozen.
+        // Gets find memtable keys without waiting for the lock.
+        let guard = db.lock();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn({
+            let db = db.clone();
+            move || sender.send(db.get("/a"))
+        });
+        let value = receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("get waited for the lock")?;
+        std::mem::drop(guard);
+        assert_eq!(value, Some(b"1".to_vec()));
 
+        // Later writes go into the same memtable, but the iterator doesn't see them.
+        let iter = db.iter_pinned("/");
+        db.put("/a", "2")?;
+        db.delete("/b")?;
+        db.put("/c", "2")?;
         assert_eq!(
-            db.seek("/user/")
-                .expect("couldnt seek /user")
-                .collect::<Vec<(Key, Value)>>(),
+            iter.collect::<Result<Vec<_>, _>>()?,
             vec![
-                ("/user/name/adam".to_string(), b"adam".to_vec()),
-                ("/user/name/catherine".to_string(), b"catherine".to_vec()),
-                ("/user/name/vardhan".to_string(), b"vardhan".to_vec())
+                ("/a".to_string(), b"1".to_vec()),
+                ("/b".to_string(), b"1".to_vec()),
             ]
         );
+        assert_eq!(db.get("/a")?, Some(b"2".to_vec()));
+        assert_eq!(db.get("/b")?, None);
 
-        // put() and delete() below go in the active memtable, replacing the frozen entries.
-        db.delete("/user/name/catherine")
-            .expect("couldnt delete /user/catherine");
+        // Readers keep finding every key while the memtables are written, frozen
+        // and flushed.
+        std::thread::scope(|scope| -> anyhow::Result<()> {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), DBError> {
+                        for _ in 0..500 {
+                            assert_eq!(db.get("/a")?, Some(b"2".to_vec()));
+                            assert_eq!(db.get("/b")?, None);
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            for i in 0..500 {
+                db.put(format!("/key/{i:03}"), format!("{i}"))?;
+            }
+            for reader in readers {
+                reader.join().expect("reader panicked")?;
+            }
+            Ok(())
+        })?;
+        for i in 0..500 {
+            assert_eq!(
+                db.get(&format!("/key/{i:03}"))?,
+                Some(format!("{i}").into_bytes())
+            );
+        }
+        Ok(())
+    }
 
-        db.put("/user/name/adam", "adam2")
-            .expect("cant put /user/name/adam");
+    #[test]
+    fn memtable_flush_interval() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            memtable_flush_interval: Duration::from_millis(100),
+            ..DBConfig::default()
+        })?;
+        db.put("/a", "1")?;
+        assert!(!db.flush_expired_memtables()?);
+        assert_eq!(db.stats().num_flushes, 0);
 
-        assert_eq!(db.get("/user/name/adam"), Ok(Some(b"adam2".to_vec())));
-        assert_eq!(db.get("/user/name/catherine"), Ok(None));
-        assert_eq!(db.get("/user/name/vardhan"), Ok(Some(b"vardhan".to_vec())));
+        // The next write flushes the old memtable.
+        std::thread::sleep(Duration::from_millis(150));
+        db.put("/b", "2")?;
+        assert_eq!(db.stats().num_flushes, 1);
+        assert_eq!(db.stats().active_memtable_size_bytes, 0);
 
-        assert_eq!(
-            db.seek("/user/")
-                .expect("couldnt seek /user")
-                .collect::<Vec<(Key, Value)>>(),
-            vec![
-                ("/user/name/adam".to_string(), b"adam2".to_vec()),
-                ("/user/name/vardhan".to_string(), b"vardhan".to_vec())
-            ]
-        );
+        // Without writes, the memtables are flushed when asked to.
+        db.put("/c", "3")?;
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(db.flush_expired_memtables()?);
+        assert_eq!(db.stats().num_flushes, 2);
+        assert!(!db.flush_expired_memtables()?);
+        assert_eq!(db.get("/c")?, Some(b"3".to_vec()));
 
-        db.freeze_active_memtable()
-            .expect("could not freeze active memtable");
+        // Or by the timer.
+        let db = Arc::new(db);
+        let timer = db.start_flush_timer(Duration::from_millis(20));
+        db.put("/d", "4")?;
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(db.stats().num_flushes, 3);
+        std::mem::drop(timer);
+        Ok(())
+    }
 
-        // Now we have 3 memtables:  1 active and 2 frozen.
-        db.put("/user/name/adam", "adam3")
-            .expect("cant put /user/name/adam");
-        db.put("/user/name/catherine", "catherine3")
-            .expect("cant put /user/name/catherine");
-        db.delete("/user/name/vardhan")
-            .expect("cant put /user/name/vardhan");
-        assert_eq!(
-            db.seek("/user/")
-                .expect("couldnt seek /user")
-                .collect::<Vec<(Key, Value)>>(),
-            vec![
-                ("/user/name/adam".to_string(), b"adam3".to_vec()),
-                ("/user/name/catherine".to_string(), b"catherine3".to_vec())
-            ]
-        );
+    #[test]
+    fn get_from_damaged_block() -> anyhow::Result<()> {
+        let (tmpdir, mut db) = make_db_in_tempdir(DBConfig::default());
+        for i in 0..1000 {
+            let value = if i == 500 { "needle" } else { "haystack" };
+            db.put(format!("/key/{:03}", i), value)?;
+        }
+        db.flush()?;
+        std::mem::drop(db);
 
-        assert_eq!(
-            db.seek("/user/vardhan_")
-                .expect("couldn't see /user/vardhan_")
-                .collect::<Vec<(Key, Value)>>(),
-            vec![]
-        );
+        // Damage the value in the middle of the sstable, away from the first and
+        // last blocks read when opening it.
+        let path = manifest::sstable_path(tmpdir.path(), 0);
+        let mut bytes = std::fs::read(&path)?;
+        let offset = bytes
+            .windows(6)
+            .position(|window| window == b"needle")
+            .expect("value not found");
+        bytes[offset] ^= 0xff;
+        std::fs::write(&path, bytes)?;
 
-        assert_eq!(
-            db.seek("/items/")
-                .expect("couldnt seek /items")
-                .collect::<Vec<(Key, Value)>>(),
-            vec![]
-        );
+        let mut db = RawDB::open(tmpdir.path())?;
+        assert!(matches!(db.get("/key/500"), Err(DBError::SSTable(_))));
+        assert_eq!(db.get("/key/000")?, Some(b"haystack".to_vec()));
+        Ok(())
     }
 
     #[test]
-    fn flush_memtable_to_sstable() {
-        let tmpdir = tempdir::TempDir::new("lsmdb").expect("tmpdir");
-        let mut db = DB::open_with_config(
-            tmpdir.path(),
-            DBConfig {
-                // No automatic flushing; all manual for now
-                max_frozen_memtables: 100,
-                ..DBConfig::default()
-            },
-        )
-        .expect("couldnt make db");
-
-        let keys = vec!["a", "b", "c", "d", "e", "f"];
-        for key in &keys {
-            db.put(format!("/key/{}", key), format!("val {}", key))
-                .expect("couldnt put");
-            db.freeze_active_memtable()
-                .expect("couldnt freeze active memtables");
+    fn preload_index() -> anyhow::Result<()> {
+        let storage: Arc<dyn Storage> = Arc::new(MemStorage::new());
+        let config = |preload_index| DBConfig {
+            storage: storage.clone(),
+            preload_index,
+            ..DBConfig::default()
+        };
+        let mut db = RawDB::open_with_config(Path::new("/db"), config(false))?;
+        for i in 0..20000 {
+            db.put(format!("/user/{:05}", i), vec![0u8; 64])?;
         }
-        assert_eq!(db.frozen_memtables.len(), keys.len());
-
-        db.flush_frozen_memtables()
-            .expect("couldnt flush frozen memtables");
+        db.flush()?;
+        std::mem::drop(db);
 
-        assert_eq!(db.sstables.len(), keys.len());
+        let partitions_read = |db: &RawDB| -> Vec<(usize, usize)> {
+            db.levels
+                .iter()
+                .flatten()
+                .map(SSTableReader::num_partitions_read)
+                .collect()
+        };
+        let db = RawDB::open_with_config(Path::new("/db"), config(false))?;
+        assert!(partitions_read(&db)
+            .iter()
+            .any(|(num_read, num_partitions)| num_read < num_partitions));
+        std::mem::drop(db);
+        let db = RawDB::open_with_config(Path::new("/db"), config(true))?;
+        assert!(partitions_read(&db)
+            .iter()
+            .all(|(num_read, num_partitions)| num_read == num_partitions));
+        Ok(())
+    }
 
-        // sstables should now be persisted -- test that they are accessible when db is re-opened
+    #[test]
+    fn recovery_modes() -> anyhow::Result<()> {
+        let config = |recovery_mode| DBConfig {
+            compaction_style: CompactionStyle::None,
+            recovery_mode,
+            ..DBConfig::default()
+        };
+        let (tmpdir, mut db) = make_db_in_tempdir(config(RecoveryMode::AbsoluteConsistency));
+        for key in ["/a", "/b", "/c"] {
+            db.put(key, key)?;
+            db.flush()?;
+        }
         std::mem::drop(db);
-        let mut db: DB = DB::open_with_config(
-            tmpdir.path(),
-            DBConfig {
-                // No automatic flushing; all manual for now
-                max_frozen_memtables: 100,
-                ..DBConfig::default()
-            },
-        )
-        .expect("couldnt reopen db");
-        assert_eq!(db.sstables.len(), keys.len());
+        let damaged_path = manifest::sstable_path(tmpdir.path(), 0);
+        std::fs::write(&damaged_path, [0xff; 8])?;
+        let missing_path = manifest::sstable_path(tmpdir.path(), 1);
+        std::fs::remove_file(&missing_path)?;
 
-        for (i, key) in keys.iter().enumerate() {
-            assert_eq!(
-                db.sstables[i]
-                    .get(format!("/key/{}", key).as_str())
-                    .expect(format!("couldnt get /key/{}", key).as_str()),
-                Some(EntryValue::Present(format!("val {}", key).into_bytes()))
-            );
-            for non_present_key in keys.iter() {
-                if non_present_key == key {
-                    continue;
-                }
-                assert_eq!(
-                    db.sstables[i]
-                        .get(format!("/key/{}", non_present_key).as_str())
-                        .expect(format!("couldnt get /key/{}", non_present_key).as_str()),
-                    None
-                );
+        assert!(
+            RawDB::open_with_config(tmpdir.path(), config(RecoveryMode::AbsoluteConsistency))
+                .is_err()
+        );
+
+        #[derive(Default)]
+        struct SkippedListener(Mutex<Vec<PathBuf>>);
+        impl EventListener for SkippedListener {
+            fn on_open_completed(&self, info: &OpenInfo) {
+                *self.0.lock().unwrap() = info.skipped_sstables.clone();
             }
         }
-    }
-
-    #[test]
-    fn basic_across_memtables_and_sstables() -> anyhow::Result<()> {
-        // zig-zag keys across active, frozen, and an sstable.
-        let tmpdir = tempdir::TempDir::new("lsmdb")?;
-        let mut db = DB::open_with_config(
+        let listener = Arc::new(SkippedListener::default());
+        let mut db = RawDB::open_with_config(
             tmpdir.path(),
             DBConfig {
-                // No automatic flushing; all manual for now
-                max_frozen_memtables: 100,
-                ..DBConfig::default()
+                listeners: vec![listener.clone()],
+                ..config(RecoveryMode::SkipCorruptedSSTables)
             },
         )?;
+        let lost_path = tmpdir.path().join(repair::LOST_DIRNAME).join("0.sst");
+        assert_eq!(
+            *listener.0.lock().unwrap(),
+            vec![lost_path.clone(), missing_path]
+        );
+        assert!(lost_path.exists() && !damaged_path.exists());
+        assert_eq!(db.get("/a")?, None);
+        assert_eq!(db.get("/b")?, None);
+        assert_eq!(db.get("/c")?, Some(b"/c".to_vec()));
 
-        db.put("/key/1", "sstable0".as_bytes())?; // should be replaced by sstable1 and then frozen memtable
-        db.put("/key/3", "sstable0".as_bytes())?;
-        db.put("/key/4", "sstable0".as_bytes())?; // should be replaced by sstable1
-        db.freeze_active_memtable()?;
-        db.flush_frozen_memtables()?;
-
-        db.delete("/key/1")?; // should replace sstable0, and be replaced by frozen memtable
-        db.put("/key/2", "sstable1".as_bytes())?;
-        db.put("/key/4", "sstable1".as_bytes())?; // should replace sstable0
-        db.freeze_active_memtable()?;
-        db.flush_frozen_memtables()?;
-
-        db.put("/key/1", "frozen".as_bytes())?;
-        db.put("/key/5", "frozen".as_bytes())?;
-        db.freeze_active_memtable()?;
-
-        db.put("/key/0", "active".as_bytes())?;
-        db.put("/key/6", "active".as_bytes())?;
+        // They're gone from the MANIFEST too.
+        std::mem::drop(db);
+        RawDB::open_with_config(tmpdir.path(), config(RecoveryMode::AbsoluteConsistency))?;
+        Ok(())
+    }
 
-        assert_eq!(db.active_memtable.len(), 2);
-        assert_eq!(db.frozen_memtables.len(), 1);
-        assert_eq!(db.frozen_memtables[0].len(), 2);
-        assert_eq!(db.sstables.len(), 2);
+    #[test]
+    fn parallel_open() -> anyhow::Result<()> {
+        let config = |num_open_threads| DBConfig {
+            compaction_style: CompactionStyle::None,
+            num_open_threads,
+            ..DBConfig::default()
+        };
+        let (tmpdir, mut db) = make_db_in_tempdir(config(1));
+        for i in 0..20 {
+            db.put(format!("/key/{:02}", i), format!("{}", i))?;
+            db.put("/newest", format!("{}", i))?;
+            db.flush()?;
+        }
+        std::mem::drop(db);
 
-        assert_eq!(db.get("/key/3")?, Some("sstable0".into()));
-        assert_eq!(db.get("/key/2")?, Some("sstable1".into()));
-        assert_eq!(db.get("/key/4")?, Some("sstable1".into()));
-        assert_eq!(db.get("/key/1")?, Some("frozen".into()));
-        assert_eq!(db.get("/key/5")?, Some("frozen".into()));
-        assert_eq!(db.get("/key/0")?, Some("active".into()));
-        assert_eq!(db.get("/key/6")?, Some("active".into()));
+        // However many threads open them, the sstables keep their order.
+        for num_open_threads in [1, 4, 32] {
+            let mut db = RawDB::open_with_config(tmpdir.path(), config(num_open_threads))?;
+            assert_eq!(db.levels[0].len(), 20);
+            assert_eq!(db.get("/newest")?, Some(b"19".to_vec()));
+            let keys = db.keys("/key/", &ReadOptions::default())?;
+            assert_eq!(
+                keys,
+                (0..20)
+                    .map(|i| format!("/key/{:02}", i))
+                    .collect::<Vec<_>>()
+            );
+        }
 
+        // A damaged sstable in another thread's share fails the open.
+        std::fs::write(manifest::sstable_path(tmpdir.path(), 13), [0xff; 8])?;
+        assert!(matches!(
+            RawDB::open_with_config(tmpdir.path(), config(4)),
+            Err(DBError::SSTable(_))
+        ));
         Ok(())
     }
 }
@@ -0,0 +1,156 @@
+use std::io::{Read, Write};
+
+use crate::sstable::ReaderExt;
+
+// Export stream format
+// ====================
+//
+// A logical dump of the database's live keys and values, as written by
+// RawDB::export() and read by RawDB::import_stream().  It doesn't depend on the
+// sstable format, so it can be read back by a database with different options.
+//
+// Records only hold keys and values, not the sequence numbers of the writes
+// which made them (see ChangeEvent::sequence): importing a stream writes every
+// key again, so the keys get new sequence numbers from the importing database.
+//
+// - All numbers are encoded in little-endian (LE)
+// - Records are sorted by key
+//
+// Export stream encoding:
+// ---------------------------------------
+// - magic (8 bytes; "LSMDBEXP")
+// - <record #1>
+//   - key length (u32; LE); never END_OF_STREAM
+//   - key (utf-8 string)
+//   - value length (u32; LE)
+//   - value
+// - <record #2>
+//   ..
+// - end of stream marker (u32; LE; END_OF_STREAM)
+// - number of records (u64; LE)
+const MAGIC: &[u8; 8] = b"LSMDBEXP";
+const END_OF_STREAM: u32 = u32::MAX;
+
+pub(crate) struct ExportWriter<W: Write> {
+    writer: W,
+    num_records: u64,
+}
+
+impl<W: Write> ExportWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, std::io::Error> {
+        writer.write_all(MAGIC)?;
+        Ok(ExportWriter {
+            writer,
+            num_records: 0,
+        })
+    }
+
+    pub fn add(&mut self, key: &str, value: &[u8]) -> Result<(), std::io::Error> {
+        self.writer
+            .write_all(&length_to_u32(key.len())?.to_le_bytes())?;
+        self.writer.write_all(key.as_bytes())?;
+        self.writer
+            .write_all(&length_to_u32(value.len())?.to_le_bytes())?;
+        self.writer.write_all(value)?;
+        self.num_records += 1;
+        Ok(())
+    }
+
+    // Ends the stream, and returns the number of records written.
+    pub fn finish(mut self) -> Result<u64, std::io::Error> {
+        self.writer.write_all(&END_OF_STREAM.to_le_bytes())?;
+        self.writer.write_all(&self.num_records.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.num_records)
+    }
+}
+
+fn length_to_u32(len: usize) -> Result<u32, std::io::Error> {
+    match u32::try_from(len) {
+        Ok(len) if len != END_OF_STREAM => Ok(len),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "key or value is too big to export",
+        )),
+    }
+}
+
+pub(crate) struct ExportReader<R: Read> {
+    reader: R,
+    num_records: u64,
+    finished: bool,
+}
+
+impl<R: Read> ExportReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, std::io::Error> {
+        let mut magic = [0u8; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not an export stream"));
+        }
+        Ok(ExportReader {
+            reader,
+            num_records: 0,
+            finished: false,
+        })
+    }
+
+    // Returns the next record, or None at the end of the stream.
+    pub fn next_record(&mut self) -> Result<Option<(String, Vec<u8>)>, std::io::Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        let key_len = self.reader.read_u32_le()?;
+        if key_len == END_OF_STREAM {
+            self.finished = true;
+            if self.reader.read_u64_le()? != self.num_records {
+                return Err(invalid_data("wrong number of records"));
+            }
+            return Ok(None);
+        }
+        let key = String::from_utf8(self.reader.read_u8s(key_len as usize)?)
+            .map_err(|_| invalid_data("key isn't utf-8"))?;
+        let value_len = self.reader.read_u32_le()?;
+        let value = self.reader.read_u8s(value_len as usize)?;
+        self.num_records += 1;
+        Ok(Some((key, value)))
+    }
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn export_stream_round_trip() {
+        let mut buffer = Vec::new();
+        let mut writer = ExportWriter::new(&mut buffer).expect("couldnt start stream");
+        writer.add("/a", b"1").expect("couldnt add");
+        writer.add("/b", b"").expect("couldnt add");
+        writer.finish().expect("couldnt finish");
+
+        let mut reader = ExportReader::new(Cursor::new(&buffer)).expect("couldnt read stream");
+        assert_eq!(
+            reader.next_record().expect("couldnt read"),
+            Some(("/a".to_string(), b"1".to_vec()))
+        );
+        assert_eq!(
+            reader.next_record().expect("couldnt read"),
+            Some(("/b".to_string(), vec![]))
+        );
+        assert_eq!(reader.next_record().expect("couldnt read"), None);
+
+        // A truncated stream is an error, rather than a shorter dump.
+        let mut reader = ExportReader::new(Cursor::new(&buffer[..buffer.len() - 13]))
+            .expect("couldnt read stream");
+        reader.next_record().expect("couldnt read");
+        assert!(reader.next_record().is_err());
+
+        assert!(ExportReader::new(Cursor::new(b"NOTANEXPORT")).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::{io::Write, mem::size_of};
+
+// Prefix filters
+// ==============
+//
+// With a DBConfig::prefix_extractor, every sstable holds a bloom filter of the
+// prefixes of its keys (see format.rs for where it's stored).  Gets and
+// `RawDB::scan_prefix()` skip the sstables whose filter rules out the prefix
+// they're looking for, without reading any of their blocks.
+//
+// Prefix filter encoding:
+// ---------------------------------------
+// - extractor name length (u32; LE)
+// - extractor name (name length bytes)
+// - number of probes (u32; LE)
+// - filter bits (the rest of the section)
+
+// Bits per prefix in a filter, for about 1% false positives.
+const BITS_PER_PREFIX: usize = 10;
+
+// Maps keys to the prefix they're grouped under, e.g. "/user/42/name" to "/user/".
+//
+// The prefix of a key must be a prefix of the key, and every key starting with a
+// prefix must map to that same prefix.
+pub trait PrefixExtractor: Send + Sync {
+    // Names the extractor in the sstables' filters.  Filters built by an extractor
+    // with another name are ignored, so change the name along with the prefixes.
+    fn name(&self) -> String;
+
+    // Returns the prefix of `key`, or None if the key doesn't have one.
+    fn prefix<'a>(&self, key: &'a str) -> Option<&'a str>;
+}
+
+// The first N bytes of the key; shorter keys don't have a prefix.
+pub struct FixedLengthPrefix(pub usize);
+
+impl PrefixExtractor for FixedLengthPrefix {
+    fn name(&self) -> String {
+        format!("fixed-length:{}", self.0)
+    }
+
+    fn prefix<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.get(..self.0)
+    }
+}
+
+// The key up to and including its `count`th separator, e.g. "/user/" for
+// "/user/42/name" with a '/' separator and a count of 2.  Keys with fewer
+// separators don't have a prefix.
+pub struct SeparatorPrefix {
+    pub separator: char,
+    pub count: usize,
+}
+
+impl PrefixExtractor for SeparatorPrefix {
+    fn name(&self) -> String {
+        format!("separator:{}:{}", self.separator, self.count)
+    }
+
+    fn prefix<'a>(&self, key: &'a str) -> Option<&'a str> {
+        if self.count == 0 {
+            return None;
+        }
+        let (position, separator) = key.match_indices(self.separator).nth(self.count - 1)?;
+        Some(&key[..position + separator.len()])
+    }
+}
+
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    // FNV-1a, with the SplitMix64 finalizer to spread the bits.
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in bytes {
+        hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+// A bloom filter of the prefixes in an sstable, along with the name of the
+// extractor which made them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PrefixFilter {
+    extractor_name: String,
+    num_probes: u32,
+    bits: Vec<u8>,
+}
+
+impl PrefixFilter {
+    // `hashes` are the hashes of the prefixes (see `hash()`).
+    pub fn build(extractor_name: String, hashes: &[u64]) -> Self {
+        let num_bits = (hashes.len() * BITS_PER_PREFIX).max(64);
+        let mut filter = PrefixFilter {
+            extractor_name,
+            // ln(2) * bits per prefix minimizes the false positives.
+            num_probes: ((BITS_PER_PREFIX as f64 * 0.69) as u32).clamp(1, 30),
+            bits: vec![0u8; num_bits.div_ceil(8)],
+        };
+        for &hash in hashes {
+            for bit in filter.probes(hash) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    // Returns false if the sstable definitely holds no keys with `prefix`, which
+    // `extractor` extracted.
+    pub fn may_contain(&self, extractor: &dyn PrefixExtractor, prefix: &str) -> bool {
+        if extractor.name() != self.extractor_name {
+            return true;
+        }
+        self.probes(hash(prefix.as_bytes()))
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    // Double hashing: the probes are h1, h1 + h2, h1 + 2 * h2, ..
+    fn probes(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 8;
+        let (h1, h2) = (hash & 0xffff_ffff, hash >> 32);
+        (0..self.num_probes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    // Writes the filter out, and returns its size in bytes.
+    pub fn encode(&self, writer: &mut impl Write) -> Result<u32, std::io::Error> {
+        let name = self.extractor_name.as_bytes();
+        writer.write_all(&(name.len() as u32).to_le_bytes())?;
+        writer.write_all(name)?;
+        writer.write_all(&self.num_probes.to_le_bytes())?;
+        writer.write_all(&self.bits)?;
+        Ok((2 * size_of::<u32>() + name.len() + self.bits.len()) as u32)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let u32_at = |i: usize| {
+            bytes
+                .get(i..i + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        };
+        let name_len = u32_at(0)? as usize;
+        let extractor_name = String::from_utf8(bytes.get(4..4 + name_len)?.to_vec()).ok()?;
+        let num_probes = u32_at(4 + name_len)?;
+        let bits = bytes.get(8 + name_len..)?.to_vec();
+        if bits.is_empty() {
+            return None;
+        }
+        Some(PrefixFilter {
+            extractor_name,
+            num_probes,
+            bits,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefix_filter() {
+        let extractor = SeparatorPrefix {
+            separator: '/',
+            count: 2,
+        };
+        assert_eq!(extractor.prefix("/user/42/name"), Some("/user/"));
+        assert_eq!(extractor.prefix("/user"), None);
+        assert_eq!(FixedLengthPrefix(3).prefix("abcd"), Some("abc"));
+        assert_eq!(FixedLengthPrefix(3).prefix("ab"), None);
+
+        let prefixes: Vec<String> = (0..1000).map(|i| format!("/user{}/", i)).collect();
+        let hashes: Vec<u64> = prefixes.iter().map(|p| hash(p.as_bytes())).collect();
+        let filter = PrefixFilter::build(extractor.name(), &hashes);
+        let mut encoded = Vec::new();
+        let size = filter.encode(&mut encoded).expect("couldnt encode");
+        assert_eq!(size as usize, encoded.len());
+        let filter = PrefixFilter::decode(&encoded).expect("couldnt decode");
+
+        assert!(prefixes.iter().all(|p| filter.may_contain(&extractor, p)));
+        let false_positives = (0..1000)
+            .filter(|i| filter.may_contain(&extractor, &format!("/group{}/", i)))
+            .count();
+        assert!(false_positives < 50, "{} false positives", false_positives);
+        // A filter made by another extractor can't rule anything out.
+        assert!(filter.may_contain(&FixedLengthPrefix(6), "/group"));
+    }
+}
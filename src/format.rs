@@ -0,0 +1,172 @@
+use std::mem::size_of;
+
+// On-disk formats
+// ===============
+//
+// Every format which can change carries a version number, so that a newer
+// version of the code can still read what an older one wrote.  A new version only
+// changes how things are encoded, never what they mean, and the readers keep
+// decoding every version listed below.
+//
+// Some additional context:
+// - All numbers are encoded in little-endian (LE)
+// - Strings (keys) are encoded as # of bytes (LE), followed by bytes.
+// - A database whose files have a newer version than the code supports is
+//   refused with DBError::UnsupportedVersion, rather than misread.
+//
+// MANIFEST format versions (see manifest.rs for the encoding):
+// ---------------------------------------
+// - 0: no header; the MANIFEST starts with the next file number.  The oldest
+//      ones end after the levels, without the blob files.
+// - 1: starts with MANIFEST_MAGIC and the format version (u32; LE).
+// - 2: ends with the incarnation (u64; LE).
+//
+// SSTable format versions:
+// ---------------------------------------
+// - 0: full keys in every block entry, the offset of every entry in the block
+//      footer, and a single-level index.  These sstables have no version in
+//      their footer.  The first ones, from before range tombstones, have a
+//      smaller index and footer (see below).
+// - 1: keys are prefix-compressed between restart points, and the index is
+//      split into partitions which are read on demand.
+// - 2: adds the properties block, and its size to the footer.
+// - 3: adds a checksum to every block's footer.
+//
+// SSTable encoding (version 3):
+// ---------------------------------------
+//
+// - <Block> #1
+// - <Block> #2
+// - ..
+// - <Index partition> #1
+// - <Index partition> #2
+// - ..
+// - <Top-level index>
+// - <Range tombstones>
+// - <Prefix filter> (only with a prefix extractor; see filter.rs)
+// - <Properties> (see properties.rs)
+// - <Footer>
+//
+// Blocks are sorted by their keyspan, and the entries in a block are sorted in
+// ascending order, by key.
+//
+// Block format:
+// ---------------------------------------
+//   - entry #1
+//     - shared key length (u32; LE): bytes shared with the previous entry's key,
+//       0 at restart points
+//     - unshared key length (u32; LE)
+//     - value length (u32; LE)
+//     - the rest of the key (unshared key length bytes)
+//     - indicator for isPresent (1), deleted (0), or stored in a blob file (2).  (u8)
+//     - value (val_len bytes); a blob reference (see blob.rs) for blob entries
+//   - entry #2
+//     ...
+//   - block footer:
+//     * byte offset inside block of restart point #1 (u32; LE)
+//     * byte offset inside block of restart point #2 (u32; LE)
+//       ...
+//     * number of restart points (u32; LE)
+//     * number of entries (u32; LE)
+//     * CRC-32C of the block up to here (u32; LE)
+//
+// Blocks of version 1 and 2 sstables don't have the checksum.
+// Every BLOCK_RESTART_INTERVAL-th entry is a restart point, which holds its whole
+// key.  A lookup binary searches the restart points' keys, then reads at most one
+// interval of entries.
+//
+// Index partition format:
+// ---------------------------------------
+// - <block #1>
+//   byte offset in the file (u32; LE),
+//   byte size (u32; LE),
+//   number of entries (u32; LE),
+//   last_key_length (u32; LE),
+//   last key in the block (last_key_length bytes)
+// - <block #2>
+//   ..
+//
+// Partitions hold up to INDEX_PARTITION_SIZE_MAX bytes of block entries.
+//
+// Top-level index format:
+// ---------------------------------------
+// - <partition #1>
+//   byte offset in the file (u32; LE),
+//   byte size (u32; LE),
+//   number of entries in its blocks (u64; LE),
+//   byte size of its blocks (u64; LE),
+//   last_key_length (u32; LE),
+//   last key in its last block (last_key_length bytes)
+// - <partition #2>
+//   ..
+//
+// Only the top-level index is read when an sstable is opened, so a point lookup
+// reads one index partition and one block, each found by binary search.
+//
+// Range tombstones format:
+// ---------------------------------------
+// - <range tombstone #1>
+//   start key length (u32; LE),
+//   start key (start_key_length bytes),
+//   end key length (u32; LE),
+//   end key (end_key_length bytes)
+// - <range tombstone #2>
+//   ..
+//
+// SSTable footer:
+// ---------------------------------------
+// - size of the top-level index in bytes (u32; LE)
+// - size of range tombstones in bytes (u32; LE)
+// - size of the prefix filter in bytes, 0 without one (u32; LE)
+// - size of the properties in bytes (u32; LE)
+// - format version (u32; LE)
+// - SSTABLE_MAGIC (u32; LE)
+//
+// Version 2 sstables are encoded the same way, but without the blocks'
+// checksums.  Version 1 sstables don't have the properties and their size in the
+// footer either.
+//
+// SSTable encoding (version 0):
+// ---------------------------------------
+// Blocks, then the index, range tombstones and prefix filter.  Block entries are
+// encoded as:
+//   - key length (u32; LE)
+//   - value length (u32; LE)
+//   - key, the indicator and the value, as above
+// and the block footer holds the byte offset of every entry, then the number of
+// entries (u32; LE).  The index is a single list of:
+//   byte size (u32; LE), number of entries (u32; LE), last_key_length (u32; LE),
+//   last key
+// for every block, which start one after the other from the beginning of the
+// file.  The footer is:
+// - size of the index in bytes (u32; LE)
+// - size of range tombstones in bytes (u32; LE)
+// With a prefix filter, the footer goes on with:
+// - size of the prefix filter in bytes (u32; LE)
+// - PREFIX_FILTER_MAGIC (u32; LE)
+// The last 4 bytes of a version 0 sstable without a prefix filter are the range
+// tombstones' size, which is never PREFIX_FILTER_MAGIC or SSTABLE_MAGIC.
+//
+// The version 0 sstables written before range tombstones don't have the number
+// of entries in their index, which is read from the blocks' footers instead, and
+// their footer is only:
+// - size of the index in bytes (u32; LE)
+// They're told apart from the other version 0 sstables by their index: read with
+// the other footer's sizes, its blocks wouldn't fill the file up to it.
+
+pub(crate) const MANIFEST_FORMAT_VERSION: u32 = 2;
+// "LSMDBMAN"; never a version 0 MANIFEST's next file number in practice.
+pub(crate) const MANIFEST_MAGIC: u64 = u64::from_le_bytes(*b"LSMDBMAN");
+
+pub(crate) const SSTABLE_FORMAT_VERSION: u32 = 3;
+pub(crate) const SSTABLE_MAGIC: u32 = 0x4c53_4d54;
+
+pub(crate) const SSTABLE_FOOTER_SIZE: usize = 6 * size_of::<u32>();
+pub(crate) const V1_FOOTER_SIZE: usize = 5 * size_of::<u32>();
+pub(crate) const LEGACY_FOOTER_SIZE: usize = 2 * size_of::<u32>();
+pub(crate) const BASELINE_FOOTER_SIZE: usize = size_of::<u32>();
+pub(crate) const LEGACY_PREFIX_FILTER_FOOTER_SIZE: usize = 4 * size_of::<u32>();
+pub(crate) const PREFIX_FILTER_MAGIC: u32 = 0x5046_4c54;
+
+pub(crate) const BLOCK_RESTART_INTERVAL: u32 = 16;
+pub(crate) const INDEX_PARTITION_SIZE_MAX: usize = 4 * 1024;
@@ -0,0 +1,233 @@
+// Key and value generators for benchmarks.
+//
+// Everything is driven by a seeded `Random`, so two runs with the same seed and
+// options produce the same keys and values, and comparisons between database
+// configurations are reproducible.
+
+use crate::db::DBError;
+
+// A small, fast pseudo-random number generator (SplitMix64).  Not suitable for
+// anything but generating test data.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Returns a number in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Returns a number in [0, n).
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        self.next_u64() % n
+    }
+}
+
+// How keys are picked from the key space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyDistribution {
+    // 0, 1, 2, .. wrapping around at the number of keys.
+    Sequential,
+    // Every key is equally likely.
+    Uniform,
+    // A few keys are much more likely than the rest.  Key i is picked with a
+    // probability proportional to 1 / (i + 1)^theta, where theta is in (0, 1);
+    // YCSB uses a theta of 0.99.  Other thetas are rejected as invalid options.
+    Zipfian { theta: f64 },
+}
+
+// Generates keys formatted as `/key/<16 digit number>`, so they sort numerically.
+pub struct KeyGenerator {
+    num_keys: u64,
+    distribution: KeyDistribution,
+    random: Random,
+    next_sequential: u64,
+    zipfian: Option<Zipfian>,
+}
+
+impl KeyGenerator {
+    pub fn new(num_keys: u64, distribution: KeyDistribution, seed: u64) -> Result<Self, DBError> {
+        let num_keys = num_keys.max(1);
+        Ok(KeyGenerator {
+            num_keys,
+            distribution,
+            random: Random::new(seed),
+            next_sequential: 0,
+            zipfian: match distribution {
+                KeyDistribution::Zipfian { theta } => Some(Zipfian::new(num_keys, theta)?),
+                _ => None,
+            },
+        })
+    }
+
+    // Returns the index of the next key, in [0, num_keys).
+    pub fn next_index(&mut self) -> u64 {
+        match self.distribution {
+            KeyDistribution::Sequential => {
+                let index = self.next_sequential;
+                self.next_sequential = (self.next_sequential + 1) % self.num_keys;
+                index
+            }
+            KeyDistribution::Uniform => self.random.below(self.num_keys),
+            KeyDistribution::Zipfian { .. } => {
+                let u = self.random.next_f64();
+                self.zipfian.as_ref().unwrap().index(u)
+            }
+        }
+    }
+
+    pub fn next_key(&mut self) -> String {
+        Self::key(self.next_index())
+    }
+
+    pub fn key(index: u64) -> String {
+        format!("/key/{:016}", index)
+    }
+}
+
+// Zipfian sampling from "Quickly Generating Billion-Record Synthetic Databases"
+// (Gray et al.), as used by YCSB.
+struct Zipfian {
+    num_items: u64,
+    theta: f64,
+    alpha: f64,
+    zeta_n: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    // A theta of 1 or more makes `alpha` infinite or negative, and the indexes
+    // NaN or out of range.
+    fn new(num_items: u64, theta: f64) -> Result<Self, DBError> {
+        if !(theta > 0.0 && theta < 1.0) {
+            return Err(DBError::InvalidOption(format!(
+                "zipfian theta must be in (0, 1), not {}",
+                theta
+            )));
+        }
+        let zeta = |n: u64| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zeta_n = zeta(num_items);
+        let zeta_2 = zeta(2.min(num_items));
+        Ok(Zipfian {
+            num_items,
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zeta_n,
+            eta: (1.0 - (2.0 / num_items as f64).powf(1.0 - theta)) / (1.0 - zeta_2 / zeta_n),
+        })
+    }
+
+    // Maps `u`, uniform in [0, 1), to an index in [0, num_items).
+    fn index(&self, u: f64) -> u64 {
+        let uz = u * self.zeta_n;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.num_items - 1);
+        }
+        let index =
+            (self.num_items as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as u64;
+        index.min(self.num_items - 1)
+    }
+}
+
+// Generates values of a fixed size which compress to about `compression_ratio`
+// of their size: each value is a random run of bytes, repeated to fill it.
+pub struct ValueGenerator {
+    value_size: usize,
+    compression_ratio: f64,
+    random: Random,
+}
+
+impl ValueGenerator {
+    // `compression_ratio` is clamped to (0, 1]; 1 means incompressible values.
+    pub fn new(value_size: usize, compression_ratio: f64, seed: u64) -> Self {
+        ValueGenerator {
+            value_size,
+            compression_ratio: compression_ratio.clamp(f64::MIN_POSITIVE, 1.0),
+            random: Random::new(seed),
+        }
+    }
+
+    pub fn next_value(&mut self) -> Vec<u8> {
+        let random_len = ((self.value_size as f64 * self.compression_ratio).ceil() as usize)
+            .clamp(1, self.value_size.max(1));
+        let random_bytes: Vec<u8> = (0..random_len)
+            .map(|_| b' ' + self.random.below(95) as u8)
+            .collect();
+        random_bytes
+            .iter()
+            .copied()
+            .cycle()
+            .take(self.value_size)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generators_are_reproducible() {
+        let keys = |seed| {
+            let mut generator = KeyGenerator::new(1000, KeyDistribution::Uniform, seed).unwrap();
+            (0..10).map(|_| generator.next_key()).collect::<Vec<_>>()
+        };
+        assert_eq!(keys(1), keys(1));
+        assert_ne!(keys(1), keys(2));
+
+        let mut generator = KeyGenerator::new(3, KeyDistribution::Sequential, 0).unwrap();
+        let indexes: Vec<u64> = (0..4).map(|_| generator.next_index()).collect();
+        assert_eq!(indexes, vec![0, 1, 2, 0]);
+        assert_eq!(KeyGenerator::key(42), "/key/0000000000000042");
+    }
+
+    #[test]
+    fn zipfian_keys_are_skewed() {
+        let zipfian = |theta| KeyGenerator::new(1000, KeyDistribution::Zipfian { theta }, 7);
+        let mut generator = zipfian(0.99).unwrap();
+        let mut counts = vec![0u32; 1000];
+        for _ in 0..100_000 {
+            counts[generator.next_index() as usize] += 1;
+        }
+        // The hottest key is picked far more often than an average one (100 times).
+        assert!(counts[0] > 5_000);
+        assert!(counts[0] > counts[1] && counts[1] > counts[100]);
+        assert!(counts[500..].iter().all(|&count| count < 100));
+
+        for theta in [0.0, 1.0, 1.5, -0.5, f64::NAN] {
+            assert!(matches!(zipfian(theta), Err(DBError::InvalidOption(_))));
+        }
+    }
+
+    #[test]
+    fn value_entropy() {
+        let mut generator = ValueGenerator::new(100, 0.25, 3);
+        let value = generator.next_value();
+        assert_eq!(value.len(), 100);
+        assert_eq!(value[..25], value[25..50]);
+        assert_ne!(value, generator.next_value());
+
+        let mut generator = ValueGenerator::new(0, 0.5, 3);
+        assert!(generator.next_value().is_empty());
+    }
+}
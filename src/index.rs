@@ -0,0 +1,149 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use crate::db::{DBError, Key};
+
+// Secondary indexes
+// =================
+//
+// A secondary index maps keys derived from values (e.g. a user's email address)
+// back to the keys holding them.  Its entries are stored with the data, with an
+// empty value, under reserved keys:
+//
+//     "\0index/<index name>/<index key>\0<primary key>"
+//
+// A write adds and removes its index entries in the same memtable as the primary
+// entry, so they're flushed to the same sstable, and readers never see one
+// without the other.  Scans of other prefixes skip the reserved keys, and users
+// can't write them.
+
+pub(crate) const INDEX_KEY_PREFIX: &str = "\0index/";
+
+// Derives the index keys of a value; see DBConfig::indexes.
+pub trait IndexExtractor: Send + Sync {
+    // Names the index, for `RawDB::seek_index()`.  Must not contain '/'.
+    fn name(&self) -> String;
+
+    // Returns the index keys of `key`'s `value`, if any.  Index keys must not
+    // contain '\0'.
+    fn index_keys(&self, key: &str, value: &[u8]) -> Vec<String>;
+}
+
+pub(crate) fn is_index_key(key: &str) -> bool {
+    key.starts_with(INDEX_KEY_PREFIX)
+}
+
+// Keys written by users can't be index entries.
+pub(crate) fn check_key(key: &str) -> Result<(), DBError> {
+    if is_index_key(key) {
+        return Err(DBError::InvalidKey(format!(
+            "{:?} starts with {:?}, which is reserved for index entries",
+            key, INDEX_KEY_PREFIX
+        )));
+    }
+    Ok(())
+}
+
+// Index names can't contain '/', which ends them in the index entries' keys.
+pub(crate) fn check_name(index_name: &str) -> Result<(), DBError> {
+    if index_name.contains('/') {
+        return Err(DBError::InvalidKey(format!(
+            "index name {:?} contains '/'",
+            index_name
+        )));
+    }
+    Ok(())
+}
+
+// Returns true if a scan of `prefix` should skip `key`, which only index lookups
+// may return.
+pub(crate) fn is_hidden(key: &str, prefix: &str) -> bool {
+    is_index_key(key) && !is_index_key(prefix)
+}
+
+// The prefix of the entries of `index_name` whose index key starts with
+// `index_key_prefix`.
+pub(crate) fn index_entry_prefix(index_name: &str, index_key_prefix: &str) -> Key {
+    format!("{}{}/{}", INDEX_KEY_PREFIX, index_name, index_key_prefix)
+}
+
+// Returns the primary key of an index entry.
+pub(crate) fn primary_key(index_entry_key: &str) -> Option<&str> {
+    let (_, primary_key) = index_entry_key
+        .strip_prefix(INDEX_KEY_PREFIX)?
+        .split_once('\0')?;
+    Some(primary_key)
+}
+
+// Returns the keys of the index entries of `key`'s `value` (None if it's deleted),
+// or an error if an extractor's index name or keys are invalid.
+pub(crate) fn index_entries(
+    indexes: &[Arc<dyn IndexExtractor>],
+    key: &str,
+    value: Option<&[u8]>,
+) -> Result<BTreeSet<Key>, DBError> {
+    let mut entries = BTreeSet::new();
+    let Some(value) = value else {
+        return Ok(entries);
+    };
+    for index in indexes {
+        let index_name = index.name();
+        check_name(&index_name)?;
+        for index_key in index.index_keys(key, value) {
+            if index_key.contains('\0') {
+                return Err(DBError::InvalidKey(format!(
+                    "index key {:?} of {:?} in {:?} contains '\\0'",
+                    index_key, key, index_name
+                )));
+            }
+            entries.insert(format!(
+                "{}\0{}",
+                index_entry_prefix(&index_name, &index_key),
+                key
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct FirstWord;
+
+    impl IndexExtractor for FirstWord {
+        fn name(&self) -> String {
+            "first-word".to_string()
+        }
+
+        fn index_keys(&self, _key: &str, value: &[u8]) -> Vec<String> {
+            String::from_utf8_lossy(value)
+                .split(' ')
+                .next()
+                .map(str::to_string)
+                .into_iter()
+                .collect()
+        }
+    }
+
+    #[test]
+    fn index_entry_keys() {
+        let indexes: Vec<Arc<dyn IndexExtractor>> = vec![Arc::new(FirstWord)];
+        let entries = index_entries(&indexes, "/user/1", Some(b"alice smith")).unwrap();
+        assert_eq!(
+            entries.iter().collect::<Vec<_>>(),
+            vec!["\0index/first-word/alice\0/user/1"]
+        );
+        let entry = entries.first().unwrap();
+        assert!(entry.starts_with(&index_entry_prefix("first-word", "ali")));
+        assert_eq!(primary_key(entry), Some("/user/1"));
+        assert!(is_hidden(entry, "") && !is_hidden(entry, INDEX_KEY_PREFIX));
+        assert!(index_entries(&indexes, "/user/1", None).unwrap().is_empty());
+        assert!(matches!(
+            index_entries(&indexes, "/user/1", Some(b"bad\0key")),
+            Err(DBError::InvalidKey(_))
+        ));
+        assert!(check_key("/user/1").is_ok() && check_key(entry).is_err());
+        assert!(check_name("first-word").is_ok() && check_name("first/word").is_err());
+    }
+}
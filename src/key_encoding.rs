@@ -0,0 +1,138 @@
+use crate::db::Key;
+
+// Key encodings
+// =============
+//
+// Keys are compared byte by byte everywhere (memtables, merges, sstable indexes,
+// compactions and range tombstones), so there is no custom comparator.  Instead,
+// a KeyEncoding turns typed keys into strings whose byte order is the order
+// wanted, e.g. case-insensitive keys, or composite keys with numeric parts, and
+// scans return them in that order.
+//
+// A KeyEncoding of string keys can be set as DBConfig::key_encoding, which
+// TypedDB applies to the keys it's given; others are applied by hand.
+
+pub trait KeyEncoding: Send + Sync {
+    type Key;
+
+    // Encodes `key` so that encoded keys compare like the keys they encode.
+    fn encode_key(&self, key: &Self::Key) -> Key;
+
+    // Returns a description of the problem if `key` isn't a valid encoding.
+    fn decode_key(&self, key: &str) -> Result<Self::Key, String>;
+}
+
+// Compares strings ignoring case, by storing them lowercased; so decoding gives
+// back the lowercased key.
+pub struct CaseInsensitiveKey;
+
+impl KeyEncoding for CaseInsensitiveKey {
+    type Key = String;
+
+    fn encode_key(&self, key: &String) -> Key {
+        key.to_lowercase()
+    }
+
+    fn decode_key(&self, key: &str) -> Result<String, String> {
+        Ok(key.to_string())
+    }
+}
+
+// Stores numbers as 16 hex digits, so they sort numerically.
+pub struct U64Key;
+
+impl KeyEncoding for U64Key {
+    type Key = u64;
+
+    fn encode_key(&self, key: &u64) -> Key {
+        format!("{:016x}", key)
+    }
+
+    fn decode_key(&self, key: &str) -> Result<u64, String> {
+        if key.len() != 16 {
+            return Err(format!("expected 16 hex digits, got {:?}", key));
+        }
+        u64::from_str_radix(key, 16).map_err(|err| err.to_string())
+    }
+}
+
+// Like U64Key, with the sign bit flipped so negative numbers sort first.
+pub struct I64Key;
+
+impl KeyEncoding for I64Key {
+    type Key = i64;
+
+    fn encode_key(&self, key: &i64) -> Key {
+        U64Key.encode_key(&(*key as u64 ^ (1 << 63)))
+    }
+
+    fn decode_key(&self, key: &str) -> Result<i64, String> {
+        Ok((U64Key.decode_key(key)? ^ (1 << 63)) as i64)
+    }
+}
+
+// Separates the parts of a PairKey; it sorts before every other character but
+// '\0', so a shorter first part sorts before the longer ones it's a prefix of.
+const PAIR_SEPARATOR: char = '\u{1}';
+
+// A composite key, ordered by its first part and then by its second.  The first
+// part's encoding mustn't contain '\0' or '\u{1}'.
+pub struct PairKey<A, B>(pub A, pub B);
+
+impl<A: KeyEncoding, B: KeyEncoding> KeyEncoding for PairKey<A, B> {
+    type Key = (A::Key, B::Key);
+
+    fn encode_key(&self, (a, b): &(A::Key, B::Key)) -> Key {
+        format!(
+            "{}{}{}",
+            self.0.encode_key(a),
+            PAIR_SEPARATOR,
+            self.1.encode_key(b)
+        )
+    }
+
+    fn decode_key(&self, key: &str) -> Result<(A::Key, B::Key), String> {
+        let (a, b) = key
+            .split_once(PAIR_SEPARATOR)
+            .ok_or_else(|| format!("{:?} isn't a pair", key))?;
+        Ok((self.0.decode_key(a)?, self.1.decode_key(b)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::DB;
+
+    #[test]
+    fn key_encodings() -> anyhow::Result<()> {
+        // Users by case-insensitive name and then by signed score.
+        let encoding = PairKey(CaseInsensitiveKey, I64Key);
+        let db = DB::open_in_memory()?;
+        for (name, score) in [("bob", 10), ("Al", 2), ("al", -5), ("alice", 1), ("BOB", 9)] {
+            db.put(encoding.encode_key(&(name.to_string(), score)), "")?;
+        }
+        let keys = db
+            .seek("")?
+            .into_iter()
+            .map(|(key, _)| encoding.decode_key(&key).map_err(anyhow::Error::msg))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            keys,
+            vec![
+                ("al".to_string(), -5),
+                ("al".to_string(), 2),
+                ("alice".to_string(), 1),
+                ("bob".to_string(), 9),
+                ("bob".to_string(), 10),
+            ]
+        );
+        assert_eq!(
+            U64Key.decode_key(&U64Key.encode_key(&u64::MAX)),
+            Ok(u64::MAX)
+        );
+        assert!(U64Key.decode_key("12").is_err());
+        assert!(encoding.decode_key("bob").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::db::{DBError, Key, Snapshot, Value, DB};
+
+// The basic reads and writes, as traits, so code using the database can be
+// written against them and tested with a fake, e.g. a BTreeMap behind a Mutex.
+// `DB::open_in_memory()` works as well, without a fake.
+//
+// DB implements both, and Snapshot implements KvRead.
+
+// An iterator over keys and their values, in key order.
+pub type KvIterator<'a> = Box<dyn Iterator<Item = Result<(Key, Value), DBError>> + 'a>;
+
+pub trait KvRead {
+    fn get(&self, key: &str) -> Result<Option<Value>, DBError>;
+
+    // Iterates over the keys starting with `key_prefix`, and their values.
+    fn seek(&self, key_prefix: &str) -> KvIterator<'_>;
+}
+
+pub trait KvWrite {
+    fn put(&self, key: Key, value: Value) -> Result<(), DBError>;
+
+    fn delete(&self, key: Key) -> Result<(), DBError>;
+
+    // Deletes every key in [`start`, `end`).
+    fn delete_range(&self, start: Key, end: Key) -> Result<(), DBError>;
+}
+
+impl KvRead for DB {
+    fn get(&self, key: &str) -> Result<Option<Value>, DBError> {
+        DB::get(self, key)
+    }
+
+    // Unlike `DB::seek()`, reads the sstables too, and doesn't hold the lock.
+    fn seek(&self, key_prefix: &str) -> KvIterator<'_> {
+        Box::new(self.lock().iter_pinned(key_prefix))
+    }
+}
+
+impl KvWrite for DB {
+    fn put(&self, key: Key, value: Value) -> Result<(), DBError> {
+        DB::put(self, key, value)
+    }
+
+    fn delete(&self, key: Key) -> Result<(), DBError> {
+        DB::delete(self, key)
+    }
+
+    fn delete_range(&self, start: Key, end: Key) -> Result<(), DBError> {
+        DB::delete_range(self, start, end)
+    }
+}
+
+impl KvRead for Snapshot {
+    fn get(&self, key: &str) -> Result<Option<Value>, DBError> {
+        Snapshot::get(self, key)
+    }
+
+    fn seek(&self, key_prefix: &str) -> KvIterator<'_> {
+        Box::new(self.iter_pinned(key_prefix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    // A fake, as application tests would write.
+    #[derive(Default)]
+    struct FakeKv(Mutex<BTreeMap<Key, Value>>);
+
+    impl KvRead for FakeKv {
+        fn get(&self, key: &str) -> Result<Option<Value>, DBError> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        fn seek(&self, key_prefix: &str) -> KvIterator<'_> {
+            let entries: Vec<(Key, Value)> = self
+                .0
+                .lock()
+                .unwrap()
+                .range(key_prefix.to_string()..)
+                .take_while(|(key, _)| key.starts_with(key_prefix))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            Box::new(entries.into_iter().map(Ok))
+        }
+    }
+
+    impl KvWrite for FakeKv {
+        fn put(&self, key: Key, value: Value) -> Result<(), DBError> {
+            self.0.lock().unwrap().insert(key, value);
+            Ok(())
+        }
+
+        fn delete(&self, key: Key) -> Result<(), DBError> {
+            self.0.lock().unwrap().remove(&key);
+            Ok(())
+        }
+
+        fn delete_range(&self, start: Key, end: Key) -> Result<(), DBError> {
+            self.0
+                .lock()
+                .unwrap()
+                .retain(|key, _| *key < start || *key >= end);
+            Ok(())
+        }
+    }
+
+    // Application code, written against the traits.
+    fn move_user(kv: &(impl KvRead + KvWrite), from: &str, to: &str) -> Result<usize, DBError> {
+        let entries: Vec<(Key, Value)> = kv.seek(from).collect::<Result<_, _>>()?;
+        for (key, value) in &entries {
+            kv.put(key.replacen(from, to, 1), value.clone())?;
+            kv.delete(key.clone())?;
+        }
+        Ok(entries.len())
+    }
+
+    fn check_move_user(kv: &(impl KvRead + KvWrite)) -> anyhow::Result<()> {
+        kv.put("/user/a/name".into(), b"a".to_vec())?;
+        kv.put("/user/a/mail".into(), b"a@".to_vec())?;
+        kv.put("/user/ab/name".into(), b"ab".to_vec())?;
+        assert_eq!(move_user(kv, "/user/a/", "/user/b/")?, 2);
+        assert_eq!(kv.get("/user/b/name")?, Some(b"a".to_vec()));
+        assert_eq!(kv.get("/user/a/name")?, None);
+        assert_eq!(kv.seek("/user/").count(), 3);
+        kv.delete_range("/user/".into(), "/user0".into())?;
+        assert_eq!(kv.seek("/user/").count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn kv_traits() -> anyhow::Result<()> {
+        check_move_user(&FakeKv::default())?;
+
+        let db = DB::open_in_memory()?;
+        check_move_user(&db)?;
+        db.put("/key", "value")?;
+        let snapshot = db.snapshot();
+        db.put("/key", "new value")?;
+        db.flush()?;
+        assert_eq!(KvRead::get(&snapshot, "/key")?, Some(b"value".to_vec()));
+        assert_eq!(KvRead::seek(&db, "/").count(), 1);
+        Ok(())
+    }
+}
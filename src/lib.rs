@@ -1,2 +1,71 @@
+mod arena;
+mod async_db;
+mod backup;
+mod blob;
+mod changes;
+mod checksum;
+mod compaction;
 mod db;
+mod encryption;
+mod export;
+mod filter;
+mod format;
+pub mod generators;
+mod index;
+mod key_encoding;
+mod kv;
+mod listener;
+mod manifest;
+mod memtable;
+mod merge;
+mod options;
+mod properties;
+mod rate_limiter;
+mod repair;
+mod sharded_db;
 mod sstable;
+mod stats;
+mod storage;
+mod timestamp;
+mod typed_db;
+mod verify;
+pub mod workload;
+mod write_batch;
+mod write_buffer;
+mod write_handle;
+
+pub use async_db::AsyncDB;
+pub use blob::BlobRef;
+pub use changes::{Change, ChangeEvent};
+pub use checksum::crc32c;
+pub use compaction::{CompactionDecision, CompactionFilter, CompactionPriority, CompactionStyle};
+pub use db::{
+    DBConfig, DBError, DBIterator, EntryValue, FlushTimer, Key, PinnedIterator, RawDB, RawIterator,
+    ReadOptions, RecoveryMode, Snapshot, Value, DB,
+};
+pub use encryption::{BlockCipher, EncryptedStorage, CIPHER_BLOCK_SIZE};
+pub use filter::{FixedLengthPrefix, PrefixExtractor, SeparatorPrefix};
+pub use index::IndexExtractor;
+pub use key_encoding::{CaseInsensitiveKey, I64Key, KeyEncoding, PairKey, U64Key};
+pub use kv::{KvIterator, KvRead, KvWrite};
+pub use listener::{
+    CompactionJobInfo, EventListener, FlushJobInfo, LogListener, MemtableInfo, OpenInfo,
+    WriteStallCondition, WriteStallInfo,
+};
+pub use memtable::MemtableImpl;
+pub use properties::{
+    TableFilter, TableProperties, TablePropertiesCollector, TablePropertiesCollectorFactory,
+};
+pub use rate_limiter::RateLimiter;
+pub use repair::RepairReport;
+pub use sharded_db::{ShardedDB, ShardedIterator};
+pub use sstable::{BlockInfo, SstReader, SstWriter};
+pub use stats::{registered_statistics, DBStats, SpaceReport, Statistics};
+pub use storage::{
+    FaultInjectionStorage, FileStorage, MemStorage, RandomAccessFile, Storage, WritableFile,
+};
+pub use typed_db::{Codec, TypedDB, TypedEntry, Utf8Codec};
+pub use verify::{IntegrityProblem, IntegrityReport};
+pub use write_batch::WriteBatchWithIndex;
+pub use write_buffer::WriteBufferManager;
+pub use write_handle::{AckLevel, WriteHandle};
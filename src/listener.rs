@@ -0,0 +1,212 @@
+use std::{io::Write, path::PathBuf, sync::Mutex, time::Duration};
+
+// Callbacks for the database's internal events, e.g. to trace or alert on them.
+//
+// Listeners are registered through DBConfig::listeners, and are called on the
+// thread doing the work, while the database is locked; they must not call back
+// into the database, and should return quickly.
+pub trait EventListener: Send + Sync {
+    // Called once the database is opened, with how long loading it took.
+    fn on_open_completed(&self, _info: &OpenInfo) {}
+
+    // Called when the active memtable is frozen, to be flushed later.
+    fn on_memtable_frozen(&self, _info: &MemtableInfo) {}
+
+    // Called before a memtable is flushed; only `path` and `num_entries` are set.
+    fn on_flush_begin(&self, _info: &FlushJobInfo) {}
+
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    // Called before a compaction runs; only the levels and the inputs are set.
+    fn on_compaction_begin(&self, _info: &CompactionJobInfo) {}
+
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    // Called when writes start or stop being delayed or refused, because flushes
+    // and compactions fell behind.
+    fn on_stall_conditions_changed(&self, _info: &WriteStallInfo) {}
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpenInfo {
+    pub num_sstables: usize,
+    pub sstable_bytes: u64,
+    pub num_blob_files: usize,
+    // The sstables left out by RecoveryMode::SkipCorruptedSSTables; those which
+    // weren't missing are now in the `lost` directory.
+    pub skipped_sstables: Vec<PathBuf>,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemtableInfo {
+    pub num_entries: u64,
+    pub num_range_tombstones: u64,
+    pub size_bytes: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlushJobInfo {
+    // The sstable the memtable is flushed to.
+    pub path: PathBuf,
+    pub num_entries: u64,
+    // Bytes written to the sstable and to its blob file, if any.
+    pub file_size: u64,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompactionJobInfo {
+    pub level: usize,
+    pub output_level: usize,
+    pub input_paths: Vec<PathBuf>,
+    pub output_paths: Vec<PathBuf>,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    #[default]
+    Normal,
+    // Each write is delayed by DBConfig::write_slowdown_delay.
+    Delayed,
+    // Writes fail with DBError::WriteStalled.
+    Stopped,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteStallInfo {
+    pub previous: WriteStallCondition,
+    pub current: WriteStallCondition,
+}
+
+// Writes a line for each event to a sink, e.g. `std::io::stderr()`, with its byte
+// counts and duration, to see why an open, flush or compaction is slow without
+// writing a listener.  Failed writes to the sink are ignored.
+pub struct LogListener<W: Write + Send> {
+    sink: Mutex<W>,
+}
+
+impl<W: Write + Send> LogListener<W> {
+    pub fn new(sink: W) -> Self {
+        LogListener {
+            sink: Mutex::new(sink),
+        }
+    }
+
+    fn log(&self, line: std::fmt::Arguments) {
+        let mut sink = self.sink.lock().expect("log sink lock is poisoned");
+        let _ = writeln!(sink, "lsmdb: {}", line);
+    }
+}
+
+impl<W: Write + Send> EventListener for LogListener<W> {
+    fn on_open_completed(&self, info: &OpenInfo) {
+        self.log(format_args!(
+            "opened {} sstables ({} bytes) and {} blob files in {:?}",
+            info.num_sstables, info.sstable_bytes, info.num_blob_files, info.duration
+        ));
+        for path in &info.skipped_sstables {
+            self.log(format_args!("skipped sstable {}", path.display()));
+        }
+    }
+
+    fn on_memtable_frozen(&self, info: &MemtableInfo) {
+        self.log(format_args!(
+            "froze memtable of {} entries and {} range tombstones ({} bytes)",
+            info.num_entries, info.num_range_tombstones, info.size_bytes
+        ));
+    }
+
+    fn on_flush_begin(&self, info: &FlushJobInfo) {
+        self.log(format_args!(
+            "flushing {} entries to {}",
+            info.num_entries,
+            info.path.display()
+        ));
+    }
+
+    fn on_flush_completed(&self, info: &FlushJobInfo) {
+        self.log(format_args!(
+            "flushed {} entries to {} ({} bytes) in {:?}",
+            info.num_entries,
+            info.path.display(),
+            info.file_size,
+            info.duration
+        ));
+    }
+
+    fn on_compaction_begin(&self, info: &CompactionJobInfo) {
+        self.log(format_args!(
+            "compacting {} sstables ({} bytes) from level {} to {}",
+            info.input_paths.len(),
+            info.input_bytes,
+            info.level,
+            info.output_level
+        ));
+    }
+
+    fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+        self.log(format_args!(
+            "compacted {} sstables ({} bytes) from level {} into {} ({} bytes) in {:?}",
+            info.input_paths.len(),
+            info.input_bytes,
+            info.level,
+            info.output_paths.len(),
+            info.output_bytes,
+            info.duration
+        ));
+    }
+
+    fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+        self.log(format_args!(
+            "write stall condition went from {:?} to {:?}",
+            info.previous, info.current
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{DBConfig, RawDB};
+    use std::sync::Arc;
+
+    #[test]
+    fn log_listener() -> anyhow::Result<()> {
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = SharedBuffer::default();
+        let mut db = RawDB::open_in_memory_with_config(DBConfig {
+            listeners: vec![Arc::new(LogListener::new(buffer.clone()))],
+            ..DBConfig::default()
+        })?;
+        db.put("/a", "1")?;
+        db.flush()?;
+        db.compact()?;
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(lines[0].starts_with("lsmdb: opened 0 sstables (0 bytes)"));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("lsmdb: flushed 1 entries")));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("lsmdb: compacted 1 sstables")));
+        Ok(())
+    }
+}
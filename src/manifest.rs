@@ -0,0 +1,257 @@
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    db::DBError,
+    format::{MANIFEST_FORMAT_VERSION, MANIFEST_MAGIC},
+    sstable::ReaderExt,
+    storage::{RandomAccessReader, Storage},
+};
+
+// MANIFEST file format
+// ====================
+//
+// The MANIFEST records which sstable files make up each level of the database.
+// It is rewritten after every flush and compaction: the new contents are written
+// to MANIFEST.tmp, which is then renamed over MANIFEST.
+//
+// - All numbers are encoded in little-endian (LE)
+// - SSTable files are named `<file number>.sst`, and blob files (see blob.rs)
+//   `<file number>.blob`
+//
+// MANIFEST Encoding:
+// ---------------------------------------
+// - MANIFEST_MAGIC (u64; LE)
+// - format version (u32; LE); see format.rs
+// - next file number (u64; LE)
+// - number of levels (u32; LE)
+// - <level #0>
+//   - number of sstables (u32; LE)
+//   - file number of sstable #1 (u64; LE)
+//   - file number of sstable #2 (u64; LE)
+//   - ..
+// - <level #1>
+//   ..
+// - ..
+// - number of blob files (u32; LE)
+// - file number of blob file #1 (u64; LE)
+// - ..
+// - incarnation (u64; LE)
+//
+// MANIFESTs written before the header existed start at the next file number, the
+// ones written before blob files existed end after the levels, and the ones
+// written before incarnations existed end after the blob files (incarnation 0).
+pub(crate) const MANIFEST_FILENAME: &str = "MANIFEST";
+pub(crate) const MANIFEST_TMP_FILENAME: &str = "MANIFEST.tmp";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    // File number to use for the next new sstable.
+    pub next_file_number: u64,
+
+    // File numbers of the sstables in each level.
+    // - Level 0 is ordered from oldest to newest.
+    // - All other levels are ordered by key.
+    pub levels: Vec<Vec<u64>>,
+
+    // File numbers of the blob files, in the order they were written.
+    pub blob_files: Vec<u64>,
+
+    // Counts the times the database was opened for writing; see ChangeEvent::sequence.
+    pub incarnation: u64,
+}
+
+impl Manifest {
+    // Reads the MANIFEST stored under `root_path`.  Returns None if there isn't one.
+    pub fn read_from(storage: &dyn Storage, root_path: &Path) -> Result<Option<Manifest>, DBError> {
+        let manifest_error = |io_err: std::io::Error| DBError::Manifest(io_err.to_string());
+        let file = match storage.open(&root_path.join(MANIFEST_FILENAME)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(manifest_error(err)),
+        };
+        let mut reader =
+            BufReader::new(RandomAccessReader::new(file.into()).map_err(manifest_error)?);
+
+        let first = reader.read_u64_le().map_err(manifest_error)?;
+        let next_file_number = if first == MANIFEST_MAGIC {
+            let version = reader.read_u32_le().map_err(manifest_error)?;
+            if version > MANIFEST_FORMAT_VERSION {
+                return Err(DBError::UnsupportedVersion {
+                    file: MANIFEST_FILENAME.to_string(),
+                    version,
+                });
+            }
+            reader.read_u64_le().map_err(manifest_error)?
+        } else {
+            first
+        };
+        Self::read_levels(&mut reader, next_file_number)
+            .map(Some)
+            .map_err(manifest_error)
+    }
+
+    // Reads the rest of the MANIFEST, after the next file number.
+    fn read_levels(
+        reader: &mut impl Read,
+        next_file_number: u64,
+    ) -> Result<Manifest, std::io::Error> {
+        let num_levels = reader.read_u32_le()?;
+        let mut levels = Vec::with_capacity(num_levels as usize);
+        for _ in 0..num_levels {
+            let num_sstables = reader.read_u32_le()?;
+            let mut level = Vec::with_capacity(num_sstables as usize);
+            for _ in 0..num_sstables {
+                level.push(reader.read_u64_le()?);
+            }
+            levels.push(level);
+        }
+
+        let num_blob_files = match reader.read_u32_le() {
+            Ok(num_blob_files) => num_blob_files,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+            Err(err) => return Err(err),
+        };
+        let mut blob_files = Vec::with_capacity(num_blob_files as usize);
+        for _ in 0..num_blob_files {
+            blob_files.push(reader.read_u64_le()?);
+        }
+
+        let incarnation = match reader.read_u64_le() {
+            Ok(incarnation) => incarnation,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+            Err(err) => return Err(err),
+        };
+
+        Ok(Manifest {
+            next_file_number,
+            levels,
+            blob_files,
+            incarnation,
+        })
+    }
+
+    // Atomically replaces the MANIFEST stored under `root_path`.
+    pub fn write_to(&self, storage: &dyn Storage, root_path: &Path) -> Result<(), std::io::Error> {
+        let tmp_path = root_path.join(MANIFEST_TMP_FILENAME);
+        let mut writer = BufWriter::new(storage.create(&tmp_path)?);
+
+        writer.write_all(&MANIFEST_MAGIC.to_le_bytes())?;
+        writer.write_all(&MANIFEST_FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&self.next_file_number.to_le_bytes())?;
+        writer.write_all(&(self.levels.len() as u32).to_le_bytes())?;
+        for level in &self.levels {
+            writer.write_all(&(level.len() as u32).to_le_bytes())?;
+            for file_number in level {
+                writer.write_all(&file_number.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&(self.blob_files.len() as u32).to_le_bytes())?;
+        for file_number in &self.blob_files {
+            writer.write_all(&file_number.to_le_bytes())?;
+        }
+        writer.write_all(&self.incarnation.to_le_bytes())?;
+
+        let mut file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync()?;
+        std::mem::drop(file);
+
+        storage.rename(&tmp_path, &root_path.join(MANIFEST_FILENAME))?;
+        // The rename, and the files the MANIFEST lists, only survive a crash once
+        // the directory is synced.
+        storage.sync_dir(root_path)
+    }
+}
+
+pub(crate) fn sstable_path(root_path: &Path, file_number: u64) -> PathBuf {
+    root_path.join(format!("{}.sst", file_number))
+}
+
+// Returns the file number of an sstable path (`<file number>.sst`), or None if
+// `path` isn't an sstable.
+pub(crate) fn sstable_file_number(path: &Path) -> Option<u64> {
+    if path.extension()? != "sst" {
+        return None;
+    }
+    file_number_from_stem(path)
+}
+
+pub(crate) fn file_number_from_stem(path: &Path) -> Option<u64> {
+    path.file_stem()?.to_str()?.parse::<u64>().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::FileStorage;
+    use tempdir::TempDir;
+
+    #[test]
+    fn manifest_read_write() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        assert_eq!(
+            Manifest::read_from(&FileStorage, tempdir.path()).expect("couldnt read manifest"),
+            None
+        );
+
+        let manifest = Manifest {
+            next_file_number: 12,
+            levels: vec![vec![9, 11], vec![], vec![3, 1, 10]],
+            blob_files: vec![2, 8],
+            incarnation: 5,
+        };
+        manifest
+            .write_to(&FileStorage, tempdir.path())
+            .expect("couldnt write manifest");
+        assert_eq!(
+            Manifest::read_from(&FileStorage, tempdir.path()).expect("couldnt read manifest"),
+            Some(manifest)
+        );
+
+        // MANIFESTs from before blob files have none.
+        let mut old_manifest = Vec::new();
+        old_manifest.extend_from_slice(&4u64.to_le_bytes());
+        old_manifest.extend_from_slice(&1u32.to_le_bytes());
+        old_manifest.extend_from_slice(&1u32.to_le_bytes());
+        old_manifest.extend_from_slice(&3u64.to_le_bytes());
+        std::fs::write(tempdir.path().join(MANIFEST_FILENAME), old_manifest)
+            .expect("couldnt write manifest");
+        assert_eq!(
+            Manifest::read_from(&FileStorage, tempdir.path()).expect("couldnt read manifest"),
+            Some(Manifest {
+                next_file_number: 4,
+                levels: vec![vec![3]],
+                blob_files: vec![],
+                incarnation: 0,
+            })
+        );
+
+        // MANIFESTs from a newer version are refused.
+        let mut new_manifest = Vec::new();
+        new_manifest.extend_from_slice(&MANIFEST_MAGIC.to_le_bytes());
+        new_manifest.extend_from_slice(&(MANIFEST_FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(tempdir.path().join(MANIFEST_FILENAME), new_manifest)
+            .expect("couldnt write manifest");
+        assert_eq!(
+            Manifest::read_from(&FileStorage, tempdir.path()),
+            Err(DBError::UnsupportedVersion {
+                file: MANIFEST_FILENAME.to_string(),
+                version: MANIFEST_FORMAT_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn sstable_file_numbers() {
+        let root_path = Path::new("/db");
+        assert_eq!(sstable_file_number(&sstable_path(root_path, 42)), Some(42));
+        assert_eq!(
+            sstable_file_number(&root_path.join(MANIFEST_FILENAME)),
+            None
+        );
+        assert_eq!(sstable_file_number(&root_path.join("x.sst")), None);
+        assert_eq!(sstable_file_number(&root_path.join("3.tmp")), None);
+    }
+}
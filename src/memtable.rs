@@ -0,0 +1,816 @@
+use std::{
+    alloc::Layout,
+    collections::{btree_map, BTreeMap},
+    marker::PhantomData,
+    mem::size_of,
+    ops::Bound,
+    ptr::{self, NonNull},
+    slice, str,
+    sync::{
+        atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use crate::{
+    arena::Arena,
+    db::{DBConfig, EntryValue, Key, RangeTombstone},
+};
+
+// Which data structure holds a memtable's entries; see DBConfig::memtable_impl.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemtableImpl {
+    // A BTreeMap.  Writes copy the memtable while an iterator holds on to it.
+    #[default]
+    BTree,
+    // A skiplist which can be read while it's written to.  `DB::get()` searches
+    // the memtables without holding the database's lock, and writes other than
+    // range deletions don't copy the memtable while iterators hold on to it.
+    // Overwritten values are kept until the memtable is flushed, for the iterators.
+    //
+    // Keys and values are stored in blocks of DBConfig::arena_block_size bytes,
+    // and the memtable's size is the size of its blocks.
+    SkipList,
+}
+
+// Bytes of bookkeeping for each entry of a BTree memtable, on top of its key and
+// value.  Skiplist memtables count their arena's blocks instead.
+pub(crate) const ENTRY_OVERHEAD: usize = size_of::<Key>() + size_of::<EntryValue>();
+
+// The writes made since the last freeze, sorted by key, and how much memory they
+// take up.
+#[derive(Default)]
+pub(crate) struct Memtable {
+    pub entries: MemtableEntries,
+
+    // Range deletions which apply to older memtables and sstables.
+    pub range_tombstones: Vec<RangeTombstone>,
+
+    // See `size()`.
+    size: AtomicUsize,
+
+    // When the first entry or range tombstone was added.
+    first_write: OnceLock<Instant>,
+}
+
+impl Clone for Memtable {
+    fn clone(&self) -> Self {
+        let entries = self.entries.clone();
+        // A skiplist's copy has a different arena.
+        let entries_size = match &entries {
+            MemtableEntries::BTree(_) => self.size() - self.range_tombstones_size(),
+            MemtableEntries::SkipList(skiplist) => skiplist.memory_usage(),
+        };
+        Memtable {
+            entries,
+            range_tombstones: self.range_tombstones.clone(),
+            size: AtomicUsize::new(entries_size + self.range_tombstones_size()),
+            first_write: self.first_write.clone(),
+        }
+    }
+}
+
+impl Memtable {
+    pub fn new(config: &DBConfig) -> Self {
+        Memtable {
+            entries: MemtableEntries::new(config.memtable_impl, config.arena_block_size),
+            ..Memtable::default()
+        }
+    }
+
+    // Bytes taken up by the entries and range tombstones, including their
+    // bookkeeping; this is what DBConfig::memtable_max_size_bytes limits.
+    //
+    // - A new key counts its key and value, and ENTRY_OVERHEAD.
+    // - Replacing an entry swaps the old value's bytes for the new one's; the key
+    //   is only counted once.  Deletions are entries without a value.
+    // - Removing an entry gives back its key, value and overhead.
+    // - Skiplist memtables count the blocks of their arena, which only grows.
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    // When the oldest write in the memtable was made; None if it's empty.
+    pub fn first_write(&self) -> Option<Instant> {
+        self.first_write.get().copied()
+    }
+
+    // Inserts or replaces the entry of `key`.
+    pub fn insert(&mut self, key: Key, entry: EntryValue) {
+        let grown = self.entries.insert(key, entry);
+        self.grow(grown);
+    }
+
+    // Like `insert()`, while the memtable may be shared (e.g. with an iterator).
+    // Only skiplists can be written to in place; returns false for other
+    // memtables, which must be copied first.
+    pub fn insert_shared(&self, key: &str, entry: &EntryValue) -> bool {
+        let MemtableEntries::SkipList(entries) = &self.entries else {
+            return false;
+        };
+        self.grow(entries.insert(key, entry.into()));
+        true
+    }
+
+    // Removes the entry of `key`, if any.
+    pub fn remove(&mut self, key: &str) {
+        let grown = self.entries.remove(key);
+        self.grow(grown);
+    }
+
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.grow(Self::range_tombstone_size(&tombstone) as isize);
+        self.range_tombstones.push(tombstone);
+    }
+
+    // Returns true if a range tombstone in this memtable covers `key`.
+    pub fn is_range_deleted(&self, key: &str) -> bool {
+        self.range_tombstones
+            .iter()
+            .any(|tombstone| tombstone.covers(key))
+    }
+
+    fn range_tombstone_size(tombstone: &RangeTombstone) -> usize {
+        tombstone.len() + size_of::<RangeTombstone>()
+    }
+
+    fn range_tombstones_size(&self) -> usize {
+        self.range_tombstones
+            .iter()
+            .map(Self::range_tombstone_size)
+            .sum()
+    }
+
+    // Writes are serialized, so the size is only read by other threads.
+    fn grow(&self, grown: isize) {
+        self.first_write.get_or_init(Instant::now);
+        self.size
+            .store(self.size().saturating_add_signed(grown), Ordering::Relaxed);
+    }
+}
+
+// A memtable entry's value, borrowed from the memtable.  Memtables never hold
+// blob references.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EntryRef<'a> {
+    Present(&'a [u8]),
+    Deleted,
+}
+
+impl EntryRef<'_> {
+    // See `EntryValue::len()`.
+    pub fn len(&self) -> usize {
+        match self {
+            EntryRef::Present(value) => value.len(),
+            EntryRef::Deleted => 0,
+        }
+    }
+
+    pub fn to_entry_value(self) -> EntryValue {
+        match self {
+            EntryRef::Present(value) => EntryValue::Present(value.to_vec()),
+            EntryRef::Deleted => EntryValue::Deleted,
+        }
+    }
+}
+
+impl<'a> From<&'a EntryValue> for EntryRef<'a> {
+    fn from(entry: &'a EntryValue) -> Self {
+        match entry {
+            EntryValue::Present(value) => EntryRef::Present(value),
+            EntryValue::Deleted => EntryRef::Deleted,
+            EntryValue::Blob(_) => unreachable!("memtables don't hold blob references"),
+        }
+    }
+}
+
+// A memtable's entries, sorted by key.
+pub(crate) enum MemtableEntries {
+    BTree(BTreeMap<Key, EntryValue>),
+    SkipList(SkipList),
+}
+
+impl Default for MemtableEntries {
+    fn default() -> Self {
+        MemtableEntries::BTree(BTreeMap::new())
+    }
+}
+
+impl Clone for MemtableEntries {
+    fn clone(&self) -> Self {
+        match self {
+            MemtableEntries::BTree(entries) => MemtableEntries::BTree(entries.clone()),
+            MemtableEntries::SkipList(entries) => {
+                // Only the latest values are copied.
+                let copy = SkipList::new(entries.arena_block_size);
+                for (key, entry) in
+                    entries.range_at(Bound::Unbounded, Bound::Unbounded, entries.snapshot())
+                {
+                    copy.insert(key, entry);
+                }
+                MemtableEntries::SkipList(copy)
+            }
+        }
+    }
+}
+
+impl MemtableEntries {
+    pub fn new(memtable_impl: MemtableImpl, arena_block_size: usize) -> Self {
+        match memtable_impl {
+            MemtableImpl::BTree => MemtableEntries::BTree(BTreeMap::new()),
+            MemtableImpl::SkipList => MemtableEntries::SkipList(SkipList::new(arena_block_size)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<EntryRef<'_>> {
+        match self {
+            MemtableEntries::BTree(entries) => entries.get(key).map(EntryRef::from),
+            MemtableEntries::SkipList(entries) => entries.get(key, entries.snapshot()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            MemtableEntries::BTree(entries) => entries.len(),
+            MemtableEntries::SkipList(entries) => entries.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Inserts or replaces the entry of `key`.  Returns how many bytes the memtable
+    // grew by (see `Memtable::size()`), which is negative if a bigger value was
+    // replaced.
+    pub fn insert(&mut self, key: Key, entry: EntryValue) -> isize {
+        match self {
+            MemtableEntries::BTree(entries) => {
+                let (key_len, value_len) = (key.len() as isize, entry.len() as isize);
+                match entries.insert(key, entry) {
+                    Some(old_value) => value_len - old_value.len() as isize,
+                    None => key_len + value_len + ENTRY_OVERHEAD as isize,
+                }
+            }
+            MemtableEntries::SkipList(entries) => entries.insert(&key, (&entry).into()),
+        }
+    }
+
+    // Removes the entry of `key`, if any.  Returns how many bytes the memtable grew
+    // by, like `insert()`.
+    pub fn remove(&mut self, key: &str) -> isize {
+        match self {
+            MemtableEntries::BTree(entries) => entries.remove(key).map_or(0, |old_value| {
+                -((key.len() + old_value.len() + ENTRY_OVERHEAD) as isize)
+            }),
+            MemtableEntries::SkipList(entries) => entries.remove(key),
+        }
+    }
+
+    // Returns the entries in key order.
+    pub fn iter(&self) -> Range<'_> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = EntryRef<'_>> {
+        self.iter().map(|(_, entry)| entry)
+    }
+
+    pub fn range(&self, start: Bound<&str>, end: Bound<&str>) -> Range<'_> {
+        self.range_at(start, end, self.snapshot())
+    }
+
+    // Identifies the entries as they are now; a skiplist's later writes aren't
+    // visible through `range_at()` with this snapshot.  Other memtables aren't
+    // written to while they're shared, so their snapshots are all the same.
+    pub fn snapshot(&self) -> u64 {
+        match self {
+            MemtableEntries::BTree(_) => u64::MAX,
+            MemtableEntries::SkipList(entries) => entries.snapshot(),
+        }
+    }
+
+    pub fn range_at(&self, start: Bound<&str>, end: Bound<&str>, snapshot: u64) -> Range<'_> {
+        match self {
+            MemtableEntries::BTree(entries) => Range::BTree(entries.range::<str, _>((start, end))),
+            MemtableEntries::SkipList(entries) => {
+                Range::SkipList(entries.range_at(start, end, snapshot))
+            }
+        }
+    }
+}
+
+pub(crate) enum Range<'a> {
+    BTree(btree_map::Range<'a, Key, EntryValue>),
+    SkipList(SkipListRange<'a>),
+}
+
+impl<'a> Iterator for Range<'a> {
+    type Item = (&'a str, EntryRef<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Range::BTree(range) => range
+                .next()
+                .map(|(key, entry)| (key.as_str(), entry.into())),
+            Range::SkipList(range) => range.next(),
+        }
+    }
+}
+
+// Skiplist
+// ========
+//
+// A sorted map with a single writer at a time and any number of lock-free
+// readers, like LevelDB's memtable.  Nodes are only added, never unlinked, and
+// live in the skiplist's arena until it's dropped, so readers can follow any
+// pointer they load.  A writer links a new node in from the bottom level up,
+// publishing it with a release store once its key and tower are set.
+//
+// A node holds the versions of its key's entry, newest first, each with the
+// sequence number of the write which made it.  Readers see the versions up to a
+// snapshot (the sequence number of the last write when it was taken), so an
+// iterator isn't affected by the writes made after it was created.  Removing a
+// key adds a version without an entry.
+
+const MAX_HEIGHT: usize = 12;
+// Each level holds about 1 in BRANCHING of the nodes of the level below.
+const BRANCHING: u64 = 4;
+
+pub(crate) struct SkipList {
+    // The head's tower; the first node of each level.
+    head: [AtomicPtr<Node>; MAX_HEIGHT],
+    height: AtomicUsize,
+    // The sequence number of the last write, which readers may see.
+    last_sequence: AtomicU64,
+    len: AtomicUsize,
+    // The arena's memory usage, for readers.
+    memory_usage: AtomicUsize,
+    arena_block_size: usize,
+    // Serializes writers.
+    writer: Mutex<Writer>,
+}
+
+struct Writer {
+    // Holds the nodes, their towers, keys and versions.
+    arena: Arena,
+    // State of the random number generator picking node heights.
+    rng: u64,
+}
+
+struct Node {
+    key: NonNull<u8>,
+    key_len: usize,
+    versions: AtomicPtr<Version>,
+    // The next node on each level, up to the node's height.
+    tower: NonNull<AtomicPtr<Node>>,
+    height: usize,
+}
+
+impl Node {
+    fn key(&self) -> &str {
+        // SAFETY: the key was copied from a str into the arena.
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(self.key.as_ptr(), self.key_len)) }
+    }
+
+    fn next(&self, level: usize) -> &AtomicPtr<Node> {
+        assert!(level < self.height);
+        // SAFETY: the tower has `height` pointers.
+        unsafe { &*self.tower.as_ptr().add(level) }
+    }
+}
+
+struct Version {
+    sequence: u64,
+    // None if the key was removed.
+    entry: Option<VersionEntry>,
+    older: *mut Version,
+}
+
+enum VersionEntry {
+    Present(NonNull<u8>, usize),
+    Deleted,
+}
+
+impl Version {
+    fn entry(&self) -> Option<EntryRef<'_>> {
+        match self.entry.as_ref()? {
+            // SAFETY: the value was copied into the arena.
+            VersionEntry::Present(value, len) => Some(EntryRef::Present(unsafe {
+                slice::from_raw_parts(value.as_ptr(), *len)
+            })),
+            VersionEntry::Deleted => Some(EntryRef::Deleted),
+        }
+    }
+}
+
+// The nodes and versions live in the arena, which only the writer changes.
+unsafe impl Send for SkipList {}
+unsafe impl Sync for SkipList {}
+
+impl SkipList {
+    pub fn new(arena_block_size: usize) -> Self {
+        SkipList {
+            head: Default::default(),
+            height: AtomicUsize::new(1),
+            last_sequence: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+            memory_usage: AtomicUsize::new(0),
+            arena_block_size,
+            writer: Mutex::new(Writer {
+                arena: Arena::new(arena_block_size),
+                rng: 0x2545_f491_4f6c_dd1d,
+            }),
+        }
+    }
+
+    pub fn snapshot(&self) -> u64 {
+        self.last_sequence.load(Ordering::Acquire)
+    }
+
+    // Number of keys with an entry.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    // Bytes taken from the allocator by the arena.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    // Returns the entry of `key` as of `snapshot`.
+    pub fn get(&self, key: &str, snapshot: u64) -> Option<EntryRef<'_>> {
+        let node = self.find_greater_or_equal(key, None);
+        // SAFETY: nodes live as long as the skiplist.
+        let node = unsafe { node.as_ref() }?;
+        if node.key() != key {
+            return None;
+        }
+        Self::visible_entry(node, snapshot)
+    }
+
+    // Inserts or replaces the entry of `key`.  Replaced values are kept for the
+    // snapshots, so the skiplist never shrinks; returns how many bytes it grew by.
+    pub fn insert(&self, key: &str, entry: EntryRef<'_>) -> isize {
+        self.write(key, Some(entry))
+    }
+
+    // Removes the entry of `key`, if any; returns how many bytes the skiplist grew
+    // by.
+    pub fn remove(&self, key: &str) -> isize {
+        self.write(key, None)
+    }
+
+    pub fn range_at(
+        &self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+        snapshot: u64,
+    ) -> SkipListRange<'_> {
+        let mut node = match start {
+            Bound::Included(start) | Bound::Excluded(start) => {
+                self.find_greater_or_equal(start, None)
+            }
+            Bound::Unbounded => self.head[0].load(Ordering::Acquire),
+        };
+        if let Bound::Excluded(start) = start {
+            // SAFETY: nodes live as long as the skiplist.
+            if unsafe { node.as_ref() }.is_some_and(|first| first.key() == start) {
+                node = self.next(node, 0).load(Ordering::Acquire);
+            }
+        }
+        SkipListRange {
+            skiplist: PhantomData,
+            node,
+            end: match end {
+                Bound::Included(end) => Bound::Included(end.to_string()),
+                Bound::Excluded(end) => Bound::Excluded(end.to_string()),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+            snapshot,
+        }
+    }
+
+    // Returns the newest version of the node's entry up to `snapshot`.
+    fn visible_entry(node: &Node, snapshot: u64) -> Option<EntryRef<'_>> {
+        let mut version = node.versions.load(Ordering::Acquire);
+        // SAFETY: versions live as long as the skiplist, and aren't changed once
+        // they're published.
+        while let Some(current) = unsafe { version.as_ref() } {
+            if current.sequence <= snapshot {
+                return current.entry();
+            }
+            version = current.older;
+        }
+        None
+    }
+
+    // The pointer to the node after `node` (the head if it's null) on `level`.
+    fn next(&self, node: *const Node, level: usize) -> &AtomicPtr<Node> {
+        // SAFETY: nodes live as long as the skiplist.
+        match unsafe { node.as_ref() } {
+            Some(node) => node.next(level),
+            None => &self.head[level],
+        }
+    }
+
+    // Returns the first node whose key isn't smaller than `key`, or null.  Fills
+    // in `prev` with the last node before it on each level (null for the head).
+    fn find_greater_or_equal(
+        &self,
+        key: &str,
+        mut prev: Option<&mut [*mut Node; MAX_HEIGHT]>,
+    ) -> *mut Node {
+        let mut node: *mut Node = ptr::null_mut();
+        let mut level = self.height.load(Ordering::Acquire) - 1;
+        loop {
+            let next = self.next(node, level).load(Ordering::Acquire);
+            // SAFETY: nodes live as long as the skiplist.
+            if unsafe { next.as_ref() }.is_some_and(|next| next.key() < key) {
+                node = next;
+                continue;
+            }
+            if let Some(prev) = prev.as_deref_mut() {
+                prev[level] = node;
+            }
+            if level == 0 {
+                return next;
+            }
+            level -= 1;
+        }
+    }
+
+    fn write(&self, key: &str, entry: Option<EntryRef<'_>>) -> isize {
+        let mut writer = self
+            .writer
+            .lock()
+            .expect("skiplist writer lock is poisoned");
+        let Writer { arena, rng } = &mut *writer;
+        let sequence = self.last_sequence.load(Ordering::Relaxed) + 1;
+        let mut prev = [ptr::null_mut(); MAX_HEIGHT];
+        let node = self.find_greater_or_equal(key, Some(&mut prev));
+
+        // SAFETY: nodes live as long as the skiplist.
+        let had_entry = match unsafe { node.as_ref() }.filter(|node| node.key() == key) {
+            Some(node) => {
+                let older = node.versions.load(Ordering::Acquire);
+                // SAFETY: see `visible_entry()`.
+                let had_entry = unsafe { (*older).entry.is_some() };
+                if !had_entry && entry.is_none() {
+                    return 0;
+                }
+                let version = Self::alloc_version(arena, sequence, entry, older);
+                node.versions.store(version, Ordering::Release);
+                had_entry
+            }
+            None if entry.is_none() => return 0,
+            None => {
+                let height = Self::random_height(rng);
+                if height > self.height.load(Ordering::Relaxed) {
+                    // The head is the previous node on the new levels.
+                    self.height.store(height, Ordering::Release);
+                }
+                let version = Self::alloc_version(arena, sequence, entry, ptr::null_mut());
+                let tower = arena
+                    .alloc(Layout::array::<AtomicPtr<Node>>(height).expect("tower is too big"))
+                    .cast::<AtomicPtr<Node>>();
+                for (level, prev) in prev.iter().enumerate().take(height) {
+                    let next = self.next(*prev, level).load(Ordering::Acquire);
+                    // SAFETY: the tower has room for `height` pointers.
+                    unsafe { tower.as_ptr().add(level).write(AtomicPtr::new(next)) };
+                }
+                let key_bytes = arena.alloc_bytes(key.as_bytes());
+                let node = arena
+                    .alloc_value(Node {
+                        key: key_bytes,
+                        key_len: key.len(),
+                        versions: AtomicPtr::new(version),
+                        tower,
+                        height,
+                    })
+                    .as_ptr();
+                for (level, prev) in prev.iter().enumerate().take(height) {
+                    self.next(*prev, level).store(node, Ordering::Release);
+                }
+                false
+            }
+        };
+        match (had_entry, entry.is_some()) {
+            (false, true) => self.len.fetch_add(1, Ordering::Relaxed),
+            (true, false) => self.len.fetch_sub(1, Ordering::Relaxed),
+            _ => 0,
+        };
+        self.last_sequence.store(sequence, Ordering::Release);
+        let memory_usage = arena.memory_usage();
+        (memory_usage - self.memory_usage.swap(memory_usage, Ordering::Relaxed)) as isize
+    }
+
+    fn alloc_version(
+        arena: &mut Arena,
+        sequence: u64,
+        entry: Option<EntryRef<'_>>,
+        older: *mut Version,
+    ) -> *mut Version {
+        let entry = entry.map(|entry| match entry {
+            EntryRef::Present(value) => {
+                VersionEntry::Present(arena.alloc_bytes(value), value.len())
+            }
+            EntryRef::Deleted => VersionEntry::Deleted,
+        });
+        arena
+            .alloc_value(Version {
+                sequence,
+                entry,
+                older,
+            })
+            .as_ptr()
+    }
+
+    // Picks a node height: 1, or more with probability 1/BRANCHING per level.
+    fn random_height(rng: &mut u64) -> usize {
+        let mut height = 1;
+        while height < MAX_HEIGHT {
+            // xorshift64
+            *rng ^= *rng << 13;
+            *rng ^= *rng >> 7;
+            *rng ^= *rng << 17;
+            if !rng.is_multiple_of(BRANCHING) {
+                break;
+            }
+            height += 1;
+        }
+        height
+    }
+}
+
+pub(crate) struct SkipListRange<'a> {
+    // The nodes are borrowed from the skiplist.
+    skiplist: PhantomData<&'a SkipList>,
+    node: *mut Node,
+    end: Bound<Key>,
+    snapshot: u64,
+}
+
+impl<'a> Iterator for SkipListRange<'a> {
+    type Item = (&'a str, EntryRef<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // SAFETY: nodes live as long as the skiplist.
+            let node: &'a Node = unsafe { self.node.as_ref() }?;
+            let is_past_end = match &self.end {
+                Bound::Included(end) => node.key() > end.as_str(),
+                Bound::Excluded(end) => node.key() >= end.as_str(),
+                Bound::Unbounded => false,
+            };
+            if is_past_end {
+                self.node = ptr::null_mut();
+                return None;
+            }
+            self.node = node.next(0).load(Ordering::Acquire);
+            if let Some(entry) = SkipList::visible_entry(node, self.snapshot) {
+                return Some((node.key(), entry));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn present(value: &str) -> EntryValue {
+        EntryValue::Present(value.as_bytes().to_vec())
+    }
+
+    fn keys(range: Range<'_>) -> Vec<&str> {
+        range.map(|(key, _)| key).collect()
+    }
+
+    #[test]
+    fn memtable_size() {
+        let mut memtable = Memtable::default();
+        memtable.insert("/a".to_string(), present("12345"));
+        assert_eq!(memtable.size(), 2 + 5 + ENTRY_OVERHEAD);
+
+        // Replacing an entry only counts the difference between the values.
+        memtable.insert("/a".to_string(), present("12"));
+        assert_eq!(memtable.size(), 2 + 2 + ENTRY_OVERHEAD);
+        memtable.insert("/a".to_string(), present("1234"));
+        assert_eq!(memtable.size(), 2 + 4 + ENTRY_OVERHEAD);
+
+        // Deletions keep their key.
+        memtable.insert("/a".to_string(), EntryValue::Deleted);
+        memtable.insert("/b".to_string(), EntryValue::Deleted);
+        assert_eq!(memtable.size(), 2 * (2 + ENTRY_OVERHEAD));
+
+        memtable.add_range_tombstone(RangeTombstone {
+            start: "/c".to_string(),
+            end: "/d".to_string(),
+        });
+        let tombstone_size = 2 + 2 + size_of::<RangeTombstone>();
+        assert_eq!(memtable.size(), 2 * (2 + ENTRY_OVERHEAD) + tombstone_size);
+
+        // Removing an entry gives back all of it.
+        memtable.remove("/a");
+        memtable.remove("/nothing");
+        assert_eq!(memtable.size(), 2 + ENTRY_OVERHEAD + tombstone_size);
+        assert_eq!(memtable.clone().size(), memtable.size());
+        // Only skiplists are written to while shared.
+        assert!(!memtable.insert_shared("/e", &present("1")));
+    }
+
+    #[test]
+    fn skiplist_memtable_size() {
+        let memtable = Memtable::new(&DBConfig {
+            memtable_impl: MemtableImpl::SkipList,
+            arena_block_size: 1024,
+            ..DBConfig::default()
+        });
+        assert!(memtable.insert_shared("/a", &present("1")));
+        assert!(memtable.size() > 1024);
+
+        // Replaced values stay in the arena.
+        let size = memtable.size();
+        for _ in 0..100 {
+            memtable.insert_shared("/a", &present("1234567890"));
+        }
+        assert!(memtable.size() > size + 1000);
+        // Copies only hold the latest value.
+        assert!(memtable.clone().size() < memtable.size());
+    }
+
+    #[test]
+    fn skiplist() {
+        let mut entries = MemtableEntries::new(MemtableImpl::SkipList, 4096);
+        let mut size = 0;
+        for i in (0..200).rev() {
+            size += entries.insert(format!("/{i:03}"), present("old"));
+        }
+        // The arena's blocks are counted.
+        assert!(size >= 4096);
+        assert_eq!(entries.len(), 200);
+        assert_eq!(entries.get("/007"), Some(EntryRef::Present(b"old")));
+        assert_eq!(entries.get("/7"), None);
+        assert_eq!(
+            keys(entries.range(Bound::Excluded("/010"), Bound::Included("/013"))),
+            vec!["/011", "/012", "/013"]
+        );
+        assert_eq!(keys(entries.iter()).len(), 200);
+        assert!(keys(entries.iter()).is_sorted());
+
+        // Older snapshots keep seeing the replaced and removed entries.
+        let snapshot = entries.snapshot();
+        assert!(entries.insert("/007".to_string(), present("new")) >= 0);
+        entries.remove("/008");
+        assert_eq!(entries.remove("/008"), 0);
+        assert_eq!(entries.remove("/nothing"), 0);
+        assert_eq!(entries.len(), 199);
+        assert_eq!(entries.get("/007"), Some(EntryRef::Present(b"new")));
+        assert_eq!(entries.get("/008"), None);
+        let at_snapshot: Vec<_> = entries
+            .range_at(Bound::Included("/007"), Bound::Excluded("/009"), snapshot)
+            .collect();
+        assert_eq!(
+            at_snapshot,
+            vec![
+                ("/007", EntryRef::Present(b"old")),
+                ("/008", EntryRef::Present(b"old")),
+            ]
+        );
+
+        // Copies only hold the latest entries.
+        let copy = entries.clone();
+        assert_eq!(copy.len(), 199);
+        assert_eq!(copy.get("/007"), Some(EntryRef::Present(b"new")));
+        entries.insert("/008".to_string(), present("again"));
+        assert_eq!(copy.get("/008"), None);
+    }
+
+    #[test]
+    fn skiplist_concurrent_reads() {
+        let skiplist = SkipList::new(4096);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        let snapshot = skiplist.snapshot();
+                        let keys: Vec<&str> = skiplist
+                            .range_at(Bound::Unbounded, Bound::Unbounded, snapshot)
+                            .map(|(key, _)| key)
+                            .collect();
+                        // Every write up to the snapshot is seen, in order.
+                        assert_eq!(keys.len() as u64, snapshot);
+                        assert!(keys.is_sorted());
+                    }
+                });
+            }
+            for i in 0..1000 {
+                let key = format!("/{}", (i * 7919) % 1000);
+                skiplist.insert(&key, EntryRef::Present(b"value"));
+            }
+        });
+        assert_eq!(skiplist.len(), 1000);
+    }
+}
@@ -0,0 +1,305 @@
+use std::iter::Peekable;
+
+use crate::{
+    db::{EntryValue, Key, RangeTombstone},
+    sstable::SSTableError,
+};
+
+pub(crate) type EntryResult = Result<(Key, EntryValue), SSTableError>;
+
+// A sorted stream of entries to merge, like a memtable or an sstable.
+pub(crate) struct MergeSource<'a> {
+    entries: Peekable<Box<dyn Iterator<Item = EntryResult> + 'a>>,
+    range_tombstones: Vec<RangeTombstone>,
+    // A smaller rank means newer data.
+    rank: u32,
+}
+
+impl<'a> MergeSource<'a> {
+    // `entries` must be sorted by key, without duplicates.
+    pub fn new(
+        entries: impl Iterator<Item = EntryResult> + 'a,
+        range_tombstones: Vec<RangeTombstone>,
+        rank: u32,
+    ) -> Self {
+        let entries: Box<dyn Iterator<Item = EntryResult> + 'a> = Box::new(entries);
+        MergeSource {
+            entries: entries.peekable(),
+            range_tombstones,
+            rank,
+        }
+    }
+}
+
+// Merges several sources into a single stream of entries, sorted by key.
+//
+// When a key is in several sources, only the entry from the newest source (the
+// smallest rank) is returned.  Sources may share a rank as long as they don't
+// overlap, like the sstables of a level >= 1.  A range tombstone only deletes
+// entries from sources with a bigger rank; those entries are skipped.
+//
+// Deleted entries are returned, since they may still hide keys which aren't part
+// of the merge.
+pub(crate) struct MergingIterator<'a> {
+    sources: Vec<MergeSource<'a>>,
+    range_tombstones: Vec<(RangeTombstone, u32)>,
+}
+
+impl<'a> MergingIterator<'a> {
+    pub fn new(mut sources: Vec<MergeSource<'a>>) -> Self {
+        // Newest sources first, so the first entry found for a key is the newest one.
+        sources.sort_by_key(|source| source.rank);
+        let range_tombstones = sources
+            .iter()
+            .flat_map(|source| {
+                source
+                    .range_tombstones
+                    .iter()
+                    .map(|tombstone| (tombstone.clone(), source.rank))
+            })
+            .collect();
+        MergingIterator {
+            sources,
+            range_tombstones,
+        }
+    }
+
+    // The range tombstones of all the sources, along with their source's rank.
+    pub fn range_tombstones(&self) -> &[(RangeTombstone, u32)] {
+        &self.range_tombstones
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(Key, EntryValue)>, SSTableError> {
+        loop {
+            // Find the smallest key across all the sources.
+            let mut next_key: Option<Key> = None;
+            for source in self.sources.iter_mut() {
+                match source.entries.peek() {
+                    Some(Ok((key, _)))
+                        if next_key.as_ref().is_none_or(|next_key| key < next_key) =>
+                    {
+                        next_key = Some(key.clone());
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) => return Err(source.entries.next().unwrap().unwrap_err()),
+                    None => {}
+                }
+            }
+            let Some(key) = next_key else {
+                return Ok(None);
+            };
+
+            // Take the newest entry for the key, and skip the older ones.
+            let mut newest: Option<(EntryValue, u32)> = None;
+            for source in self.sources.iter_mut() {
+                if matches!(source.entries.peek(), Some(Ok((source_key, _))) if *source_key == key)
+                {
+                    let (_, entry) = source.entries.next().unwrap()?;
+                    newest.get_or_insert((entry, source.rank));
+                }
+            }
+            let (entry, rank) = newest.expect("the smallest key comes from a source");
+
+            let is_range_deleted =
+                self.range_tombstones
+                    .iter()
+                    .any(|(tombstone, tombstone_rank)| {
+                        *tombstone_rank < rank && tombstone.covers(&key)
+                    });
+            if !is_range_deleted {
+                return Ok(Some((key, entry)));
+            }
+        }
+    }
+
+    // Appends up to `n` entries to `batch`, the same ones `n` calls to `next()`
+    // would return.  Rather than comparing every entry against every source, it
+    // takes the run of entries from the source with the smallest key which come
+    // before any other source's next key.  Fewer than `n` entries means the
+    // merge is done; on an error, the entries before it stay in `batch`.
+    pub fn next_batch(
+        &mut self,
+        n: usize,
+        batch: &mut Vec<(Key, EntryValue)>,
+    ) -> Result<(), SSTableError> {
+        let mut num_added = 0;
+        while num_added < n {
+            // The source with the smallest key, and the smallest key of the others.
+            let mut smallest: Option<(usize, &Key)> = None;
+            let mut bound: Option<&Key> = None;
+            let mut failed = None;
+            for (i, source) in self.sources.iter_mut().enumerate() {
+                match source.entries.peek() {
+                    Some(Ok((key, _))) => match smallest {
+                        // Sources are sorted by rank, so on a tie the newest wins.
+                        Some((_, smallest_key)) if key >= smallest_key => {
+                            if bound.is_none_or(|bound| key < bound) {
+                                bound = Some(key);
+                            }
+                        }
+                        _ => {
+                            bound = smallest.map(|(_, smallest_key)| smallest_key);
+                            smallest = Some((i, key));
+                        }
+                    },
+                    Some(Err(_)) => {
+                        failed = Some(i);
+                        break;
+                    }
+                    None => {}
+                }
+            }
+            if let Some(i) = failed {
+                return Err(self.sources[i].entries.next().unwrap().unwrap_err());
+            }
+            let Some((source_index, _)) = smallest else {
+                return Ok(());
+            };
+            let bound = bound.cloned();
+
+            let source = &mut self.sources[source_index];
+            let mut num_taken = 0;
+            while num_added < n {
+                match source.entries.peek() {
+                    Some(Ok((key, _))) if bound.as_ref().is_none_or(|bound| key < bound) => {}
+                    _ => break,
+                }
+                let (key, entry) = source.entries.next().unwrap()?;
+                num_taken += 1;
+                let is_range_deleted =
+                    self.range_tombstones
+                        .iter()
+                        .any(|(tombstone, tombstone_rank)| {
+                            *tombstone_rank < source.rank && tombstone.covers(&key)
+                        });
+                if !is_range_deleted {
+                    batch.push((key, entry));
+                    num_added += 1;
+                }
+            }
+            // Another source has the same key: merge it the slow way.
+            if num_taken == 0 {
+                match self.next_entry()? {
+                    Some(entry) => {
+                        batch.push(entry);
+                        num_added += 1;
+                    }
+                    None => return Ok(()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for MergingIterator<'a> {
+    type Item = EntryResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn source(
+        entries: &[(&str, Option<&str>)],
+        range_tombstones: &[(&str, &str)],
+        rank: u32,
+    ) -> MergeSource<'static> {
+        let entries: Vec<EntryResult> = entries
+            .iter()
+            .map(|(key, value)| {
+                Ok((
+                    key.to_string(),
+                    match value {
+                        Some(value) => EntryValue::Present(value.as_bytes().to_vec()),
+                        None => EntryValue::Deleted,
+                    },
+                ))
+            })
+            .collect();
+        let range_tombstones = range_tombstones
+            .iter()
+            .map(|(start, end)| RangeTombstone {
+                start: start.to_string(),
+                end: end.to_string(),
+            })
+            .collect();
+        MergeSource::new(entries.into_iter(), range_tombstones, rank)
+    }
+
+    #[test]
+    fn merge_in_batches() {
+        let sources = || {
+            vec![
+                source(
+                    &[
+                        ("/a", Some("old")),
+                        ("/b", Some("old")),
+                        ("/c", Some("old")),
+                    ],
+                    &[],
+                    2,
+                ),
+                source(&[("/c", Some("new")), ("/d", None)], &[("/a", "/b")], 0),
+                source(
+                    &[
+                        ("/e", Some("mid")),
+                        ("/f", Some("mid")),
+                        ("/g", Some("mid")),
+                    ],
+                    &[],
+                    1,
+                ),
+            ]
+        };
+        let expected: Vec<(Key, EntryValue)> = MergingIterator::new(sources())
+            .collect::<Result<_, _>>()
+            .expect("couldnt merge");
+        for n in 1..=expected.len() + 1 {
+            let mut merge = MergingIterator::new(sources());
+            let mut merged = Vec::new();
+            loop {
+                let len = merged.len();
+                merge.next_batch(n, &mut merged).expect("couldnt merge");
+                assert!(merged.len() - len <= n);
+                if merged.len() - len < n {
+                    break;
+                }
+            }
+            assert_eq!(merged, expected);
+        }
+    }
+
+    #[test]
+    fn merge_sources() {
+        let merged: Vec<(Key, EntryValue)> = MergingIterator::new(vec![
+            source(
+                &[
+                    ("/a", Some("old")),
+                    ("/c", Some("old")),
+                    ("/e", Some("old")),
+                ],
+                &[],
+                2,
+            ),
+            source(&[("/a", Some("new")), ("/b", None)], &[("/c", "/d")], 0),
+            source(&[("/b", Some("mid")), ("/d", Some("mid"))], &[], 1),
+        ])
+        .collect::<Result<_, _>>()
+        .expect("couldnt merge");
+
+        assert_eq!(
+            merged,
+            vec![
+                ("/a".to_string(), EntryValue::Present(b"new".to_vec())),
+                ("/b".to_string(), EntryValue::Deleted),
+                ("/d".to_string(), EntryValue::Present(b"mid".to_vec())),
+                ("/e".to_string(), EntryValue::Present(b"old".to_vec())),
+            ]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::{fmt::Display, str::FromStr, sync::Arc, time::Duration};
+
+use crate::{db::DBConfig, rate_limiter::RateLimiter};
+
+// Options which can be changed while the database is open, by name; see
+// `RawDB::set_options()`.  Names are those of the DBConfig fields, with durations
+// in milliseconds (e.g. `write_slowdown_delay_ms`), plus
+// `rate_limiter_bytes_per_sec`, the limit of DBConfig::rate_limiter (0 for none).
+pub(crate) struct MutableOption {
+    pub name: &'static str,
+    pub get: fn(&DBConfig) -> String,
+    pub set: fn(&mut DBConfig, &str) -> Result<(), String>,
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {:?}", value))
+}
+
+fn assign<T: FromStr>(field: &mut T, value: &str) -> Result<(), String> {
+    *field = parse(value)?;
+    Ok(())
+}
+
+fn assign_millis(field: &mut Duration, value: &str) -> Result<(), String> {
+    *field = Duration::from_millis(parse(value)?);
+    Ok(())
+}
+
+fn to_string(value: impl Display) -> String {
+    value.to_string()
+}
+
+pub(crate) const MUTABLE_OPTIONS: &[MutableOption] = &[
+    MutableOption {
+        name: "memtable_max_size_bytes",
+        get: |config| to_string(config.memtable_max_size_bytes),
+        set: |config, value| assign(&mut config.memtable_max_size_bytes, value),
+    },
+    MutableOption {
+        name: "max_frozen_memtables",
+        get: |config| to_string(config.max_frozen_memtables),
+        set: |config, value| assign(&mut config.max_frozen_memtables, value),
+    },
+    MutableOption {
+        name: "level0_file_num_compaction_trigger",
+        get: |config| to_string(config.level0_file_num_compaction_trigger),
+        set: |config, value| assign(&mut config.level0_file_num_compaction_trigger, value),
+    },
+    MutableOption {
+        name: "max_bytes_for_level_base",
+        get: |config| to_string(config.max_bytes_for_level_base),
+        set: |config, value| assign(&mut config.max_bytes_for_level_base, value),
+    },
+    MutableOption {
+        name: "max_bytes_for_level_multiplier",
+        get: |config| to_string(config.max_bytes_for_level_multiplier),
+        set: |config, value| assign(&mut config.max_bytes_for_level_multiplier, value),
+    },
+    MutableOption {
+        name: "target_file_size_base",
+        get: |config| to_string(config.target_file_size_base),
+        set: |config, value| assign(&mut config.target_file_size_base, value),
+    },
+    MutableOption {
+        name: "small_file_compaction_trigger",
+        get: |config| to_string(config.small_file_compaction_trigger),
+        set: |config, value| assign(&mut config.small_file_compaction_trigger, value),
+    },
+    MutableOption {
+        name: "small_file_size",
+        get: |config| to_string(config.small_file_size),
+        set: |config, value| assign(&mut config.small_file_size, value),
+    },
+    MutableOption {
+        name: "deletion_compaction_ratio",
+        get: |config| to_string(config.deletion_compaction_ratio),
+        set: |config, value| assign(&mut config.deletion_compaction_ratio, value),
+    },
+    MutableOption {
+        name: "size_tiered_min_merge_width",
+        get: |config| to_string(config.size_tiered_min_merge_width),
+        set: |config, value| assign(&mut config.size_tiered_min_merge_width, value),
+    },
+    MutableOption {
+        name: "size_tiered_max_merge_width",
+        get: |config| to_string(config.size_tiered_max_merge_width),
+        set: |config, value| assign(&mut config.size_tiered_max_merge_width, value),
+    },
+    MutableOption {
+        name: "size_tiered_size_ratio",
+        get: |config| to_string(config.size_tiered_size_ratio),
+        set: |config, value| assign(&mut config.size_tiered_size_ratio, value),
+    },
+    MutableOption {
+        name: "fifo_max_table_files_size",
+        get: |config| to_string(config.fifo_max_table_files_size),
+        set: |config, value| assign(&mut config.fifo_max_table_files_size, value),
+    },
+    MutableOption {
+        name: "fifo_ttl_ms",
+        get: |config| to_string(config.fifo_ttl.as_millis()),
+        set: |config, value| assign_millis(&mut config.fifo_ttl, value),
+    },
+    MutableOption {
+        name: "level0_slowdown_writes_trigger",
+        get: |config| to_string(config.level0_slowdown_writes_trigger),
+        set: |config, value| assign(&mut config.level0_slowdown_writes_trigger, value),
+    },
+    MutableOption {
+        name: "level0_stop_writes_trigger",
+        get: |config| to_string(config.level0_stop_writes_trigger),
+        set: |config, value| assign(&mut config.level0_stop_writes_trigger, value),
+    },
+    MutableOption {
+        name: "frozen_memtables_stop_writes_trigger",
+        get: |config| to_string(config.frozen_memtables_stop_writes_trigger),
+        set: |config, value| assign(&mut config.frozen_memtables_stop_writes_trigger, value),
+    },
+    MutableOption {
+        name: "write_slowdown_delay_ms",
+        get: |config| to_string(config.write_slowdown_delay.as_millis()),
+        set: |config, value| assign_millis(&mut config.write_slowdown_delay, value),
+    },
+    MutableOption {
+        name: "paranoid_checks",
+        get: |config| to_string(config.paranoid_checks),
+        set: |config, value| assign(&mut config.paranoid_checks, value),
+    },
+    MutableOption {
+        name: "use_direct_io_for_flush_and_compaction",
+        get: |config| to_string(config.use_direct_io_for_flush_and_compaction),
+        set: |config, value| assign(&mut config.use_direct_io_for_flush_and_compaction, value),
+    },
+    MutableOption {
+        name: "compaction_readahead_size",
+        get: |config| to_string(config.compaction_readahead_size),
+        set: |config, value| assign(&mut config.compaction_readahead_size, value),
+    },
+    MutableOption {
+        name: "min_blob_size",
+        get: |config| to_string(config.min_blob_size),
+        set: |config, value| assign(&mut config.min_blob_size, value),
+    },
+    MutableOption {
+        name: "memtable_flush_interval_ms",
+        get: |config| to_string(config.memtable_flush_interval.as_millis()),
+        set: |config, value| assign_millis(&mut config.memtable_flush_interval, value),
+    },
+    // A rate limiter shared with other databases is changed for all of them.
+    MutableOption {
+        name: "rate_limiter_bytes_per_sec",
+        get: |config| {
+            to_string(
+                config
+                    .rate_limiter
+                    .as_ref()
+                    .map_or(0, |rate_limiter| rate_limiter.bytes_per_sec()),
+            )
+        },
+        set: |config, value| {
+            let bytes_per_sec = parse(value)?;
+            match &config.rate_limiter {
+                Some(rate_limiter) => rate_limiter.set_bytes_per_sec(bytes_per_sec),
+                None if bytes_per_sec > 0 => {
+                    config.rate_limiter = Some(Arc::new(RateLimiter::new(bytes_per_sec)))
+                }
+                None => {}
+            }
+            Ok(())
+        },
+    },
+];
+
+#[cfg(test)]
+mod test {
+    use crate::db::{DBConfig, DBError, DB};
+
+    #[test]
+    fn set_options() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            level0_file_num_compaction_trigger: 4,
+            ..DBConfig::default()
+        })?;
+        let option = |name: &str| {
+            db.get_options()
+                .into_iter()
+                .find(|(option_name, _)| *option_name == name)
+                .map(|(_, value)| value)
+        };
+        assert_eq!(
+            option("memtable_max_size_bytes").as_deref(),
+            Some("1048576")
+        );
+        assert_eq!(option("num_levels"), None);
+
+        db.set_options(&[
+            ("memtable_max_size_bytes", "4096"),
+            ("write_slowdown_delay_ms", "5"),
+            ("rate_limiter_bytes_per_sec", "1000000"),
+        ])?;
+        assert_eq!(option("memtable_max_size_bytes").as_deref(), Some("4096"));
+        assert_eq!(option("write_slowdown_delay_ms").as_deref(), Some("5"));
+        assert_eq!(
+            option("rate_limiter_bytes_per_sec").as_deref(),
+            Some("1000000")
+        );
+
+        // Nothing changes if any option is invalid.
+        let err = db.set_options(&[("max_frozen_memtables", "3"), ("num_levels", "3")]);
+        assert!(matches!(err, Err(DBError::InvalidOption(_))));
+        let err = db.set_options(&[("max_frozen_memtables", "3"), ("paranoid_checks", "yes")]);
+        assert!(matches!(err, Err(DBError::InvalidOption(_))));
+        assert_eq!(option("max_frozen_memtables").as_deref(), Some("1"));
+
+        // Lowering a compaction trigger compacts straight away.
+        for i in 0..3 {
+            db.put(format!("/key/{}", i), "value")?;
+            db.flush()?;
+        }
+        let level0_len = || {
+            db.table_properties()
+                .iter()
+                .filter(|properties| properties.level == 0)
+                .count()
+        };
+        assert_eq!(level0_len(), 3);
+        db.set_options(&[("level0_file_num_compaction_trigger", "2")])?;
+        assert_eq!(level0_len(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    mem::size_of,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    db::{self, EntryValue},
+    sstable::ReaderExt,
+};
+
+// Table properties
+// ================
+//
+// Stats about an sstable's entries are gathered as it's written, and stored in
+// its properties block (see format.rs), so they're known without reading the
+// sstable again.  Each property is a name and a value:
+//
+// - "lsmdb.num_deletions" (u64; LE): number of deleted entries.
+// - "lsmdb.deleted_bytes" (u64; LE): estimate of the bytes the deletions will
+//   free once they're compacted away: the deleted keys, plus an average value
+//   of the sstable for each of them.
+// - "lsmdb.creation_time" (u64; LE): when the sstable was written, in seconds
+//   since the Unix epoch.  Compactions record when they ran, not when their
+//   inputs were written.
+//
+// Properties from the DBConfig::table_properties_collectors are stored alongside
+// them.  Names starting with "lsmdb." are reserved for the ones above.
+//
+// Readers skip properties they don't know.  SSTables older than format version 2
+// have no properties.
+//
+// Properties block encoding:
+// ---------------------------------------
+// - number of properties (u32; LE)
+// - <property #1>
+//   name length (u32; LE),
+//   name (name length bytes),
+//   value length (u32; LE),
+//   value (value length bytes)
+// - <property #2>
+//   ..
+pub(crate) type Properties = BTreeMap<String, Vec<u8>>;
+
+pub(crate) const NUM_DELETIONS: &str = "lsmdb.num_deletions";
+pub(crate) const DELETED_BYTES: &str = "lsmdb.deleted_bytes";
+pub(crate) const CREATION_TIME: &str = "lsmdb.creation_time";
+const RESERVED_PREFIX: &str = "lsmdb.";
+
+// Computes properties of an sstable from its entries as it's written, e.g. the
+// range of timestamps in its values, so reads can tell from
+// `RawDB::table_properties()` which sstables to skip.  Flushes and compactions
+// each use a new collector (see TablePropertiesCollectorFactory) per sstable.
+//
+// Collectors are called while the database is locked, and must not call back
+// into it.
+pub trait TablePropertiesCollector: Send {
+    // Called with every entry, in key order.  Values moved into blob files are
+    // passed as EntryValue::Blob, without being read back.  Index entries and
+    // timestamped versions aren't passed.
+    fn add(&mut self, key: &str, entry: &EntryValue);
+
+    // Returns the properties to store once every entry was added.  Names starting
+    // with "lsmdb." are reserved, and left out.
+    fn finish(&mut self) -> Vec<(String, Vec<u8>)>;
+}
+
+pub trait TablePropertiesCollectorFactory: Send + Sync {
+    fn new_collector(&self) -> Box<dyn TablePropertiesCollector>;
+}
+
+// What an sstable holds; see `RawDB::table_properties()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    pub path: PathBuf,
+    pub level: usize,
+    pub file_size: u64,
+    // Number of entries, deleted ones included.
+    pub num_entries: u64,
+    // Number of deleted entries, and estimate of the bytes they'll free; None for
+    // sstables written before they were recorded.
+    pub num_deletions: Option<u64>,
+    pub deleted_bytes: Option<u64>,
+    pub num_range_tombstones: u64,
+    // When the sstable was written, in seconds since the Unix epoch; None for
+    // sstables written before it was recorded.
+    pub creation_time: Option<u64>,
+    // The properties of the DBConfig::table_properties_collectors the sstable was
+    // written with.
+    pub user_properties: BTreeMap<String, Vec<u8>>,
+}
+
+impl TableProperties {
+    // The share of the entries and range tombstones which are deletions, or None
+    // if it isn't known.
+    pub fn deletion_ratio(&self) -> Option<f64> {
+        let num_deletions = self.num_deletions? + self.num_range_tombstones;
+        let total = self.num_entries + self.num_range_tombstones;
+        Some(num_deletions as f64 / total.max(1) as f64)
+    }
+}
+
+// Decides from its properties whether a scan reads an sstable's entries; see
+// ReadOptions::table_filter.
+#[derive(Clone)]
+pub struct TableFilter(Arc<dyn Fn(&TableProperties) -> bool + Send + Sync>);
+
+impl TableFilter {
+    // `filter` returns false for the sstables to skip.
+    pub fn new(filter: impl Fn(&TableProperties) -> bool + Send + Sync + 'static) -> Self {
+        TableFilter(Arc::new(filter))
+    }
+
+    pub(crate) fn matches(&self, properties: &TableProperties) -> bool {
+        (self.0)(properties)
+    }
+}
+
+impl std::fmt::Debug for TableFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TableFilter")
+    }
+}
+
+// Filters are only equal to their clones.
+impl PartialEq for TableFilter {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(Arc::as_ptr(&self.0), Arc::as_ptr(&other.0))
+    }
+}
+
+impl Eq for TableFilter {}
+
+pub(crate) fn property_u64(properties: &Properties, name: &str) -> Option<u64> {
+    let bytes = properties.get(name)?;
+    Some(u64::from_le_bytes(bytes.as_slice().try_into().ok()?))
+}
+
+// Returns the properties which didn't come from lsmdb itself.
+pub(crate) fn user_properties(properties: &Properties) -> Properties {
+    properties
+        .iter()
+        .filter(|(name, _)| !name.starts_with(RESERVED_PREFIX))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+// Gathers the properties of an sstable as its entries are written.
+#[derive(Default)]
+pub(crate) struct PropertiesBuilder {
+    num_deletions: u64,
+    deleted_key_bytes: u64,
+    num_values: u64,
+    value_bytes: u64,
+    collectors: Vec<Box<dyn TablePropertiesCollector>>,
+}
+
+impl PropertiesBuilder {
+    pub fn new(collector_factories: &[Arc<dyn TablePropertiesCollectorFactory>]) -> Self {
+        PropertiesBuilder {
+            collectors: collector_factories
+                .iter()
+                .map(|factory| factory.new_collector())
+                .collect(),
+            ..PropertiesBuilder::default()
+        }
+    }
+
+    pub fn add(&mut self, key: &str, entry: &EntryValue) {
+        if !db::is_reserved_key(key) {
+            for collector in &mut self.collectors {
+                collector.add(key, entry);
+            }
+        }
+        match entry {
+            EntryValue::Deleted => {
+                self.num_deletions += 1;
+                self.deleted_key_bytes += key.len() as u64;
+            }
+            EntryValue::Present(value) => {
+                self.num_values += 1;
+                self.value_bytes += value.len() as u64;
+            }
+            EntryValue::Blob(blob_ref) => {
+                self.num_values += 1;
+                self.value_bytes += blob_ref.size;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Properties {
+        let mut properties: Properties = self
+            .collectors
+            .iter_mut()
+            .flat_map(|collector| collector.finish())
+            .filter(|(name, _)| !name.starts_with(RESERVED_PREFIX))
+            .collect();
+        let average_value_size = self.value_bytes / self.num_values.max(1);
+        let deleted_bytes = self.deleted_key_bytes + self.num_deletions * average_value_size;
+        properties.insert(
+            NUM_DELETIONS.to_string(),
+            self.num_deletions.to_le_bytes().to_vec(),
+        );
+        properties.insert(
+            DELETED_BYTES.to_string(),
+            deleted_bytes.to_le_bytes().to_vec(),
+        );
+        let creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        properties.insert(
+            CREATION_TIME.to_string(),
+            creation_time.to_le_bytes().to_vec(),
+        );
+        properties
+    }
+}
+
+// Writes out the properties block, and returns its size.
+pub(crate) fn encode(properties: &Properties, writer: &mut impl Write) -> std::io::Result<u32> {
+    writer.write_all(&(properties.len() as u32).to_le_bytes())?;
+    let mut size = size_of::<u32>();
+    for (name, value) in properties {
+        for bytes in [name.as_bytes(), value.as_slice()] {
+            writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+            writer.write_all(bytes)?;
+            size += size_of::<u32>() + bytes.len();
+        }
+    }
+    Ok(size as u32)
+}
+
+pub(crate) fn decode(mut reader: &[u8]) -> Result<Properties, std::io::Error> {
+    let mut properties = Properties::new();
+    for _ in 0..reader.read_u32_le()? {
+        let name_len = reader.read_u32_le()?;
+        let name = String::from_utf8(reader.read_u8s(name_len as usize)?)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        let value_len = reader.read_u32_le()?;
+        properties.insert(name, reader.read_u8s(value_len as usize)?);
+    }
+    Ok(properties)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{DBConfig, RawDB, ReadOptions};
+    use tempdir::TempDir;
+
+    // Records the smallest and largest of the values, which are u64s.
+    struct ValueRange {
+        range: Option<(u64, u64)>,
+    }
+
+    impl TablePropertiesCollector for ValueRange {
+        fn add(&mut self, _key: &str, entry: &EntryValue) {
+            let EntryValue::Present(value) = entry else {
+                return;
+            };
+            let value = u64::from_le_bytes(value.as_slice().try_into().unwrap());
+            self.range = Some(match self.range {
+                Some((smallest, largest)) => (smallest.min(value), largest.max(value)),
+                None => (value, value),
+            });
+        }
+
+        fn finish(&mut self) -> Vec<(String, Vec<u8>)> {
+            let Some((smallest, largest)) = self.range else {
+                return Vec::new();
+            };
+            vec![
+                ("min".to_string(), smallest.to_le_bytes().to_vec()),
+                ("max".to_string(), largest.to_le_bytes().to_vec()),
+                (NUM_DELETIONS.to_string(), Vec::new()),
+            ]
+        }
+    }
+
+    struct ValueRangeFactory;
+
+    impl TablePropertiesCollectorFactory for ValueRangeFactory {
+        fn new_collector(&self) -> Box<dyn TablePropertiesCollector> {
+            Box::new(ValueRange { range: None })
+        }
+    }
+
+    #[test]
+    fn table_properties_collectors() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let mut db = RawDB::open_with_config(
+            tempdir.path(),
+            DBConfig {
+                table_properties_collectors: vec![Arc::new(ValueRangeFactory)],
+                ..DBConfig::default()
+            },
+        )?;
+        let range = |properties: &TableProperties| {
+            let user_properties = &properties.user_properties;
+            (
+                property_u64(user_properties, "min"),
+                property_u64(user_properties, "max"),
+            )
+        };
+
+        db.put("/a", 20u64.to_le_bytes())?;
+        db.put("/b", 10u64.to_le_bytes())?;
+        // The collector isn't given timestamped versions, which aren't u64s.
+        db.put_with_ts("/t", 1, "not a u64")?;
+        db.flush()?;
+        db.put("/c", 30u64.to_le_bytes())?;
+        db.delete("/a")?;
+        db.flush()?;
+        let properties = db.table_properties();
+        assert_eq!(properties.len(), 2);
+        assert_eq!(range(&properties[0]), (Some(10), Some(20)));
+        assert_eq!(range(&properties[1]), (Some(30), Some(30)));
+        // The collector can't replace lsmdb's own properties.
+        assert_eq!(properties[1].num_deletions, Some(1));
+        assert_eq!(properties[1].user_properties.len(), 2);
+
+        // Compactions collect the properties of their outputs.
+        db.compact()?;
+        let properties = db.table_properties();
+        assert_eq!(properties.len(), 1);
+        assert_eq!(range(&properties[0]), (Some(10), Some(30)));
+        Ok(())
+    }
+
+    #[test]
+    fn table_filter() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let mut db = RawDB::open_with_config(
+            tempdir.path(),
+            DBConfig {
+                table_properties_collectors: vec![Arc::new(ValueRangeFactory)],
+                ..DBConfig::default()
+            },
+        )?;
+        db.put("/a", 10u64.to_le_bytes())?;
+        db.put("/b", 20u64.to_le_bytes())?;
+        db.flush()?;
+        db.put("/c", 30u64.to_le_bytes())?;
+        db.flush()?;
+        db.put("/d", 5u64.to_le_bytes())?;
+
+        // Only read the sstables with values of at least 25.
+        let options = ReadOptions {
+            table_filter: Some(TableFilter::new(|properties| {
+                property_u64(&properties.user_properties, "max").is_some_and(|max| max >= 25)
+            })),
+            ..ReadOptions::default()
+        };
+        assert_eq!(db.keys("/", &options)?, vec!["/c", "/d"]);
+        assert_eq!(db.keys("/", &ReadOptions::default())?.len(), 4);
+        assert_eq!(options.clone(), options);
+        Ok(())
+    }
+}
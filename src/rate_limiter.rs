@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::ThreadId,
+    time::{Duration, Instant},
+};
+
+use crate::storage::WritableFile;
+
+// Limits how fast flushes and compactions write, so that they don't take up all
+// of the disk's bandwidth and slow down reads.  Share it between databases
+// through DBConfig::rate_limiter to limit their combined writes.
+//
+// Writes are delayed, not split: each one waits until the writes before it would
+// have taken their time at `bytes_per_sec`.  Flushes and compactions of a DB run
+// under its lock, so there the wait is deferred until the lock is released (see
+// DeferredWait); only the thread which flushed or compacted is held up.
+pub struct RateLimiter {
+    // 0 means no limit.
+    bytes_per_sec: AtomicU64,
+    // When the writes let through so far will have taken their time.
+    next_write_time: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec: AtomicU64::new(bytes_per_sec),
+            next_write_time: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec.load(Ordering::Relaxed)
+    }
+
+    // Changes the limit; 0 removes it.  Takes effect from the next write.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: u64) {
+        self.bytes_per_sec.store(bytes_per_sec, Ordering::Relaxed);
+    }
+
+    // Waits until `num_bytes` may be written.
+    pub(crate) fn request(&self, num_bytes: usize) {
+        if let Some(start) = self.reserve(num_bytes) {
+            std::thread::sleep(start.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    // Returns when `num_bytes` may be written, or None if there's no limit.
+    fn reserve(&self, num_bytes: usize) -> Option<Instant> {
+        let bytes_per_sec = self.bytes_per_sec();
+        if bytes_per_sec == 0 {
+            return None;
+        }
+        let mut next_write_time = self
+            .next_write_time
+            .lock()
+            .expect("rate limiter lock is poisoned");
+        let start = (*next_write_time).max(Instant::now());
+        *next_write_time = start + Duration::from_secs_f64(num_bytes as f64 / bytes_per_sec as f64);
+        Some(start)
+    }
+}
+
+// The waits of writes made under a lock, to be slept out once it's released; also
+// used for the delays of slowed down writes (DBConfig::write_slowdown_delay).
+// Each thread only waits for its own writes.
+#[derive(Default)]
+pub(crate) struct DeferredWait {
+    until: Mutex<HashMap<ThreadId, Instant>>,
+}
+
+impl DeferredWait {
+    pub fn defer_until(&self, start: Instant) {
+        let mut until = self.until.lock().expect("deferred wait lock is poisoned");
+        let until = until.entry(std::thread::current().id()).or_insert(start);
+        *until = (*until).max(start);
+    }
+
+    // Sleeps until the latest write this thread deferred may start.
+    pub fn wait(&self) {
+        let until = self
+            .until
+            .lock()
+            .expect("deferred wait lock is poisoned")
+            .remove(&std::thread::current().id());
+        if let Some(until) = until {
+            std::thread::sleep(until.saturating_duration_since(Instant::now()));
+        }
+    }
+}
+
+// A file whose writes go through a RateLimiter.  With a DeferredWait, the writes
+// aren't delayed; their waits are left to it.
+pub(crate) struct RateLimitedFile {
+    file: Box<dyn WritableFile>,
+    rate_limiter: Arc<RateLimiter>,
+    deferred_wait: Option<Arc<DeferredWait>>,
+}
+
+impl RateLimitedFile {
+    pub fn new(
+        file: Box<dyn WritableFile>,
+        rate_limiter: Arc<RateLimiter>,
+        deferred_wait: Option<Arc<DeferredWait>>,
+    ) -> Self {
+        RateLimitedFile {
+            file,
+            rate_limiter,
+            deferred_wait,
+        }
+    }
+}
+
+impl Write for RateLimitedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &self.deferred_wait {
+            Some(deferred_wait) => {
+                if let Some(start) = self.rate_limiter.reserve(buf.len()) {
+                    deferred_wait.defer_until(start);
+                }
+            }
+            None => self.rate_limiter.request(buf.len()),
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl WritableFile for RateLimitedFile {
+    fn sync(&mut self) -> Result<(), std::io::Error> {
+        self.file.sync()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_limiter() {
+        let rate_limiter = RateLimiter::new(0);
+        let start = Instant::now();
+        rate_limiter.request(1 << 30);
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // The first write goes through at once; the next two wait 50ms each.
+        rate_limiter.set_bytes_per_sec(20 * 1024);
+        let start = Instant::now();
+        for _ in 0..3 {
+            rate_limiter.request(1024);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // Deferred, the writes go through at once, and the wait comes after.
+        let deferred_wait = DeferredWait::default();
+        let start = Instant::now();
+        for _ in 0..3 {
+            deferred_wait.defer_until(rate_limiter.reserve(1024).unwrap());
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        deferred_wait.wait();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    blob,
+    db::DBError,
+    manifest::{self, Manifest},
+    sstable::{SSTableError, SSTableReader, SSTableWriter, SalvagedSSTable},
+    storage::Storage,
+};
+
+// Repair
+// ======
+//
+// Repairing rebuilds a database whose MANIFEST or sstables were damaged, e.g. by a
+// partial disk failure, keeping whatever can still be read:
+//
+// - If the MANIFEST can be read, its levels are kept.  Otherwise every sstable
+//   goes into level 0, ordered by file number.  Without sequence numbers that's
+//   only a guess at their age: a key rewritten by a compaction may come back
+//   with an older value.
+// - SSTables which pass SSTableReader::verify() are kept as they are.  The
+//   readable blocks of the others are copied into new sstables.
+// - Files which can't be used are moved into the `lost` directory, rather than
+//   deleted.
+// - SSTables which the MANIFEST lists but which are missing are dropped from it,
+//   and reported, since their keys are lost.
+// - Every blob file is kept, since the sstables may refer to it.
+// - Files written by a newer version of lsmdb aren't damaged; repair stops with
+//   DBError::UnsupportedVersion instead of touching them.
+
+pub(crate) const LOST_DIRNAME: &str = "lost";
+
+// What `RawDB::repair()` did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    // Whether the MANIFEST was missing or unreadable, and was rebuilt from the files
+    // in the directory.
+    pub manifest_rebuilt: bool,
+    // Number of sstables kept as they were.
+    pub num_sstables_kept: usize,
+    // Number of damaged sstables whose readable blocks were copied into new sstables.
+    pub num_sstables_salvaged: usize,
+    // Number of blocks of the damaged sstables which couldn't be read.
+    pub num_blocks_lost: usize,
+    // The files moved into the `lost` directory.
+    pub lost_files: Vec<PathBuf>,
+    // The sstables listed in the MANIFEST which weren't in the directory.
+    pub missing_files: Vec<PathBuf>,
+}
+
+pub(crate) fn repair_database(
+    storage: &dyn Storage,
+    root_path: &Path,
+) -> Result<RepairReport, DBError> {
+    let dir_paths = storage
+        .list_dir(root_path)
+        .map_err(|io_err| DBError::InvalidRootPath(io_err.to_string()))?;
+    let mut sst_nums = Vec::new();
+    let mut blob_nums = Vec::new();
+    for path in &dir_paths {
+        if let Some(sst_num) = manifest::sstable_file_number(path) {
+            sst_nums.push(sst_num);
+        } else if let Some(blob_num) = blob::blob_file_number(path) {
+            blob_nums.push(blob_num);
+        }
+    }
+    sst_nums.sort();
+    blob_nums.sort();
+
+    let mut report = RepairReport::default();
+    let mut manifest = match Manifest::read_from(storage, root_path) {
+        Ok(Some(manifest)) => manifest,
+        Err(err @ DBError::UnsupportedVersion { .. }) => return Err(err),
+        _ => {
+            report.manifest_rebuilt = true;
+            Manifest {
+                next_file_number: 0,
+                levels: vec![sst_nums.clone()],
+                blob_files: Vec::new(),
+                incarnation: 0,
+            }
+        }
+    };
+    manifest.next_file_number = sst_nums
+        .iter()
+        .chain(&blob_nums)
+        .map(|num| num + 1)
+        .fold(manifest.next_file_number, u64::max);
+    manifest.blob_files = blob_nums;
+
+    // SSTables which the MANIFEST doesn't list are left over from flushes or
+    // compactions which didn't finish.
+    for &sst_num in &sst_nums {
+        if !manifest.levels.iter().any(|level| level.contains(&sst_num)) {
+            let path = manifest::sstable_path(root_path, sst_num);
+            report
+                .lost_files
+                .push(move_to_lost(storage, root_path, &path)?);
+        }
+    }
+
+    for level_index in 0..manifest.levels.len() {
+        let mut level = Vec::new();
+        for sst_num in std::mem::take(&mut manifest.levels[level_index]) {
+            let path = manifest::sstable_path(root_path, sst_num);
+            if !sst_nums.contains(&sst_num) {
+                report.missing_files.push(path);
+                continue;
+            }
+            let is_intact = match SSTableReader::from_path(storage, &path)
+                .and_then(|mut sstable| sstable.verify())
+            {
+                Ok(()) => true,
+                Err(err @ SSTableError::UnsupportedVersion { .. }) => {
+                    return Err(err.into_db_error())
+                }
+                Err(_) => false,
+            };
+            if is_intact {
+                report.num_sstables_kept += 1;
+                level.push(sst_num);
+                continue;
+            }
+
+            let salvaged = SSTableReader::salvage(storage, &path);
+            report
+                .lost_files
+                .push(move_to_lost(storage, root_path, &path)?);
+            let Ok(SalvagedSSTable {
+                entries,
+                range_tombstones,
+                num_lost_blocks,
+            }) = salvaged
+            else {
+                continue;
+            };
+            report.num_sstables_salvaged += 1;
+            report.num_blocks_lost += num_lost_blocks;
+            if entries.is_empty() && range_tombstones.is_empty() {
+                continue;
+            }
+
+            let new_sst_num = manifest.next_file_number;
+            manifest.next_file_number += 1;
+            let mut writer = SSTableWriter::new(BufWriter::new(
+                storage
+                    .create(&manifest::sstable_path(root_path, new_sst_num))
+                    .map_err(|io_err| DBError::Io(io_err.to_string()))?,
+            ));
+            for (key, entry) in &entries {
+                // Drop the keys of damaged blocks which are out of order.
+                match writer.add(key, entry) {
+                    Ok(()) | Err(SSTableError::KeyOrder { .. }) => {}
+                    Err(err) => return Err(DBError::SSTable(err.to_string())),
+                }
+            }
+            for tombstone in range_tombstones {
+                writer.add_range_tombstone(tombstone);
+            }
+            let mut file = writer
+                .finish()
+                .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?
+                .into_inner()
+                .map_err(|err| DBError::Io(err.into_error().to_string()))?;
+            file.sync()
+                .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+            level.push(new_sst_num);
+        }
+        manifest.levels[level_index] = level;
+    }
+
+    manifest
+        .write_to(storage, root_path)
+        .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+    Ok(report)
+}
+
+// Moves `path` into the `lost` directory, and returns its new path.
+pub(crate) fn move_to_lost(
+    storage: &dyn Storage,
+    root_path: &Path,
+    path: &Path,
+) -> Result<PathBuf, DBError> {
+    let lost_path = root_path.join(LOST_DIRNAME);
+    let new_path = lost_path.join(path.file_name().expect("no file name"));
+    storage
+        .create_dir_all(&lost_path)
+        .and_then(|()| storage.rename(path, &new_path))
+        .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+    Ok(new_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        compaction::CompactionStyle,
+        db::{DBConfig, RawDB},
+    };
+    use tempdir::TempDir;
+
+    #[test]
+    fn repair() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let config = || DBConfig {
+            compaction_style: CompactionStyle::None,
+            ..DBConfig::default()
+        };
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        // Several blocks' worth of keys in 0.sst, and a few more in 1.sst.
+        for i in 0..300 {
+            db.put(format!("/a/{:03}", i), vec![b'a'; 32])?;
+        }
+        db.flush()?;
+        db.put("/b", "b")?;
+        db.flush()?;
+        std::mem::drop(db);
+
+        // Damage the first block of 0.sst, leave a torn sstable behind, and lose
+        // the MANIFEST.
+        let sstable_path = manifest::sstable_path(tempdir.path(), 0);
+        let mut bytes = std::fs::read(&sstable_path)?;
+        bytes[..8].fill(0xff);
+        std::fs::write(&sstable_path, bytes)?;
+        std::fs::write(manifest::sstable_path(tempdir.path(), 7), [7u8; 10])?;
+        std::fs::remove_file(tempdir.path().join(manifest::MANIFEST_FILENAME))?;
+
+        let report = RawDB::repair(tempdir.path(), &config())?;
+        assert!(report.manifest_rebuilt);
+        assert_eq!(report.num_sstables_kept, 1);
+        assert_eq!(report.num_sstables_salvaged, 1);
+        assert_eq!(report.num_blocks_lost, 1);
+        let lost_path = tempdir.path().join(LOST_DIRNAME);
+        assert_eq!(
+            report.lost_files,
+            vec![lost_path.join("0.sst"), lost_path.join("7.sst")]
+        );
+
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        assert_eq!(db.get("/a/000")?, None);
+        assert_eq!(db.get("/a/299")?, Some(vec![b'a'; 32]));
+        assert_eq!(db.get("/b")?, Some(b"b".to_vec()));
+        // The salvaged keys are still older than 1.sst's.
+        db.put("/a/299", "new")?;
+        db.flush()?;
+        assert_eq!(db.get("/a/299")?, Some(b"new".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn repair_missing_sstable() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let config = || DBConfig {
+            compaction_style: CompactionStyle::None,
+            ..DBConfig::default()
+        };
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        db.put("/a", "a")?;
+        db.flush()?;
+        db.put("/b", "b")?;
+        db.flush()?;
+        std::mem::drop(db);
+
+        // The MANIFEST still lists 0.sst.
+        let sstable_path = manifest::sstable_path(tempdir.path(), 0);
+        std::fs::remove_file(&sstable_path)?;
+        let report = RawDB::repair(tempdir.path(), &config())?;
+        assert!(!report.manifest_rebuilt);
+        assert_eq!(report.num_sstables_kept, 1);
+        assert_eq!(report.missing_files, vec![sstable_path]);
+        assert!(report.lost_files.is_empty());
+
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        assert_eq!(db.get("/a")?, None);
+        assert_eq!(db.get("/b")?, Some(b"b".to_vec()));
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    db::{DBConfig, DBError, Key, PinnedIterator, Value, DB},
+    filter,
+    kv::{KvIterator, KvRead, KvWrite},
+    write_batch::WriteBatchWithIndex,
+};
+
+// Sharded databases
+// =================
+//
+// A DB serializes writes on its lock, so concurrent `put()`s don't go faster than
+// a single thread's.  A ShardedDB partitions keys by hash across several DBs,
+// each with its own lock, memtables and sstables, so writes of different keys
+// mostly run in parallel, and scans merge the shards back into key order.
+//
+// Shard `n` lives in the `shard-<n>` directory under the root path.  Keys are
+// placed by the hash of the whole key, so the number of shards can't change once
+// the database was written to.
+//
+// The trade-offs:
+// - Writes to several shards (`write()`, `delete_range()`) aren't atomic, and
+//   scans don't see all shards at the same instant.
+// - Each shard has its own memtables, so memtable_max_size_bytes and
+//   max_frozen_memtables apply per shard; share a WriteBufferManager to cap them
+//   all together.
+// - The shards share the config's Statistics, which are registered under its
+//   name once.
+pub struct ShardedDB {
+    shards: Vec<DB>,
+}
+
+fn shard_path(root_path: &Path, shard: usize) -> PathBuf {
+    root_path.join(format!("shard-{}", shard))
+}
+
+impl ShardedDB {
+    pub fn open(root_path: &Path, num_shards: usize) -> Result<ShardedDB, DBError> {
+        Self::open_with_config(root_path, num_shards, DBConfig::default())
+    }
+
+    // `root_path` is created if it's missing, and the shards' directories in it,
+    // in DBConfig::storage.
+    pub fn open_with_config(
+        root_path: &Path,
+        num_shards: usize,
+        config: DBConfig,
+    ) -> Result<ShardedDB, DBError> {
+        let num_shards = num_shards.max(1);
+        let storage = config.storage.clone();
+        if !storage
+            .exists(root_path)
+            .map_err(|io_err| DBError::InvalidRootPath(io_err.to_string()))?
+        {
+            storage
+                .create_dir_all(root_path)
+                .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        }
+        let mut existing_shards = BTreeSet::new();
+        for path in storage
+            .list_dir(root_path)
+            .map_err(|io_err| DBError::InvalidRootPath(io_err.to_string()))?
+        {
+            if let Some(shard) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("shard-"))
+                .and_then(|shard| shard.parse::<usize>().ok())
+            {
+                existing_shards.insert(shard);
+            }
+        }
+        if !existing_shards.is_empty() && !existing_shards.iter().copied().eq(0..num_shards) {
+            return Err(DBError::InvalidRootPath(format!(
+                "{} has {} shards, not {}",
+                root_path.to_string_lossy(),
+                existing_shards.len(),
+                num_shards
+            )));
+        }
+        let mut shards = Vec::with_capacity(num_shards);
+        for (shard, config) in Self::shard_configs(num_shards, config) {
+            shards.push(DB::open_with_config(&shard_path(root_path, shard), config)?);
+        }
+        Ok(ShardedDB { shards })
+    }
+
+    pub fn open_in_memory(num_shards: usize) -> Result<ShardedDB, DBError> {
+        Self::open_in_memory_with_config(num_shards, DBConfig::default())
+    }
+
+    pub fn open_in_memory_with_config(
+        num_shards: usize,
+        config: DBConfig,
+    ) -> Result<ShardedDB, DBError> {
+        let shards = Self::shard_configs(num_shards.max(1), config)
+            .map(|(_, config)| DB::open_in_memory_with_config(config))
+            .collect::<Result<_, _>>()?;
+        Ok(ShardedDB { shards })
+    }
+
+    fn shard_configs(
+        num_shards: usize,
+        config: DBConfig,
+    ) -> impl Iterator<Item = (usize, DBConfig)> {
+        (0..num_shards).map(move |shard| {
+            let name = if shard == 0 {
+                config.name.clone()
+            } else {
+                None
+            };
+            (
+                shard,
+                DBConfig {
+                    name,
+                    ..config.clone()
+                },
+            )
+        })
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    // The shards, e.g. for their stats.  Writes through them must go to the
+    // shard `shard_for()` returns, or they won't be found.
+    pub fn shards(&self) -> &[DB] {
+        &self.shards
+    }
+
+    // Returns the index of the shard holding `key`.
+    pub fn shard_for(&self, key: &str) -> usize {
+        (filter::hash(key.as_bytes()) % self.shards.len() as u64) as usize
+    }
+
+    fn shard(&self, key: &str) -> &DB {
+        &self.shards[self.shard_for(key)]
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Value>, DBError> {
+        self.shard(key).get(key)
+    }
+
+    pub fn put(&self, key: impl Into<Key>, value: impl Into<Value>) -> Result<(), DBError> {
+        let key = key.into();
+        self.shard(&key).put(key, value)
+    }
+
+    pub fn delete(&self, key: impl Into<Key>) -> Result<(), DBError> {
+        let key = key.into();
+        self.shard(&key).delete(key)
+    }
+
+    // Deletes every key in [`start`, `end`) from every shard, one after the other.
+    pub fn delete_range(&self, start: impl Into<Key>, end: impl Into<Key>) -> Result<(), DBError> {
+        let (start, end) = (start.into(), end.into());
+        for shard in &self.shards {
+            shard.delete_range(start.clone(), end.clone())?;
+        }
+        Ok(())
+    }
+
+    // Applies the writes of `batch`; those to the same shard are applied together,
+    // but a failure may leave the other shards' writes applied.
+    pub fn write(&self, batch: WriteBatchWithIndex) -> Result<(), DBError> {
+        let mut shard_batches: BTreeMap<usize, WriteBatchWithIndex> = BTreeMap::new();
+        for (key, value) in batch.into_entries() {
+            let shard_batch = shard_batches.entry(self.shard_for(&key)).or_default();
+            match value {
+                Some(value) => shard_batch.put(key, value),
+                None => shard_batch.delete(key),
+            }
+        }
+        for (shard, shard_batch) in shard_batches {
+            self.shards[shard].write(shard_batch)?;
+        }
+        Ok(())
+    }
+
+    // Iterates over the keys starting with `key_prefix`, and their values, in key
+    // order across the shards.  Like `DB::iter_pinned()`, doesn't hold any lock.
+    pub fn iter_pinned(&self, key_prefix: &str) -> ShardedIterator {
+        let mut shards: Vec<PinnedIterator> = self
+            .shards
+            .iter()
+            .map(|shard| shard.iter_pinned(key_prefix))
+            .collect();
+        let heads = shards.iter_mut().map(Iterator::next).collect();
+        ShardedIterator { shards, heads }
+    }
+
+    pub fn seek(&self, key_prefix: &str) -> Result<Vec<(Key, Value)>, DBError> {
+        self.iter_pinned(key_prefix).collect()
+    }
+
+    pub fn flush(&self) -> Result<(), DBError> {
+        for shard in &self.shards {
+            shard.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn compact(&self) -> Result<(), DBError> {
+        for shard in &self.shards {
+            shard.compact()?;
+        }
+        Ok(())
+    }
+}
+
+// Merges the shards' iterators.  No key is in more than one shard.
+pub struct ShardedIterator {
+    shards: Vec<PinnedIterator>,
+    // The next entry of each shard.
+    heads: Vec<Option<Result<(Key, Value), DBError>>>,
+}
+
+impl Iterator for ShardedIterator {
+    type Item = Result<(Key, Value), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut next: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let is_next = match (head, next.and_then(|next| self.heads[next].as_ref())) {
+                (None, _) => false,
+                // Errors come out first.
+                (Some(Err(_)), _) => {
+                    next = Some(i);
+                    break;
+                }
+                (Some(Ok((key, _))), Some(Ok((next_key, _)))) => key < next_key,
+                (Some(Ok(_)), _) => true,
+            };
+            if is_next {
+                next = Some(i);
+            }
+        }
+        let next = next?;
+        std::mem::replace(&mut self.heads[next], self.shards[next].next())
+    }
+}
+
+impl KvRead for ShardedDB {
+    fn get(&self, key: &str) -> Result<Option<Value>, DBError> {
+        ShardedDB::get(self, key)
+    }
+
+    fn seek(&self, key_prefix: &str) -> KvIterator<'_> {
+        Box::new(self.iter_pinned(key_prefix))
+    }
+}
+
+impl KvWrite for ShardedDB {
+    fn put(&self, key: Key, value: Value) -> Result<(), DBError> {
+        ShardedDB::put(self, key, value)
+    }
+
+    fn delete(&self, key: Key) -> Result<(), DBError> {
+        ShardedDB::delete(self, key)
+    }
+
+    fn delete_range(&self, start: Key, end: Key) -> Result<(), DBError> {
+        ShardedDB::delete_range(self, start, end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemStorage;
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    #[test]
+    fn sharded_db() -> anyhow::Result<()> {
+        let tmpdir = TempDir::new("lsmdb_test")?;
+        let db = ShardedDB::open(tmpdir.path(), 4)?;
+
+        // Writers on several threads, each to keys all over the shards.
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let db = &db;
+                scope.spawn(move || {
+                    for i in 0..250 {
+                        db.put(format!("/key/{:04}", i * 4 + thread), "value")
+                            .expect("couldnt put");
+                    }
+                });
+            }
+        });
+        assert!(db
+            .shards()
+            .iter()
+            .all(|shard| !shard.seek("/key/").unwrap().is_empty()));
+        db.delete("/key/0007")?;
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put("/key/1000", "batch");
+        batch.delete("/key/0008");
+        db.write(batch)?;
+        db.delete_range("/key/0100", "/key/0200")?;
+        db.flush()?;
+
+        let expected: Vec<Key> = (0..=1000)
+            .filter(|i| ![7, 8].contains(i) && !(100..200).contains(i))
+            .map(|i| format!("/key/{:04}", i))
+            .collect();
+        let keys = |db: &ShardedDB| -> Result<Vec<Key>, DBError> {
+            db.iter_pinned("/key/")
+                .map(|entry| entry.map(|(key, _)| key))
+                .collect()
+        };
+        assert_eq!(keys(&db)?, expected);
+        assert_eq!(db.get("/key/1000")?, Some(b"batch".to_vec()));
+
+        std::mem::drop(db);
+        let db = ShardedDB::open(tmpdir.path(), 4)?;
+        assert_eq!(keys(&db)?, expected);
+        std::mem::drop(db);
+        assert!(matches!(
+            ShardedDB::open(tmpdir.path(), 2),
+            Err(DBError::InvalidRootPath(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn sharded_db_storage() -> anyhow::Result<()> {
+        // The root path is created in the config's storage, not on disk.
+        let config = DBConfig {
+            storage: Arc::new(MemStorage::default()),
+            ..DBConfig::default()
+        };
+        let root_path = Path::new("/missing/sharded");
+        let db = ShardedDB::open_with_config(root_path, 3, config.clone())?;
+        db.put("/a", "1")?;
+        db.flush()?;
+        std::mem::drop(db);
+        assert!(!root_path.exists());
+
+        let db = ShardedDB::open_with_config(root_path, 3, config.clone())?;
+        assert_eq!(db.get("/a")?, Some(b"1".to_vec()));
+        std::mem::drop(db);
+        assert!(matches!(
+            ShardedDB::open_with_config(root_path, 2, config),
+            Err(DBError::InvalidRootPath(_))
+        ));
+        Ok(())
+    }
+}
@@ -1,188 +1,1221 @@
 use std::{
+    borrow::BorrowMut,
     fs::File,
-    io::{Read, Seek, SeekFrom, Write},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
     mem::size_of,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::Utf8Error,
     string::FromUtf8Error,
+    sync::{Arc, OnceLock},
 };
 
 use thiserror::Error;
 
-use crate::db::{EntryValue, Key, Memtable};
+use crate::{
+    blob::{BlobFileWriter, BlobRef},
+    checksum::{crc32c, crc32c_of_parts},
+    db::{DBError, EntryValue, Key, RangeTombstone, Value},
+    filter::{self, PrefixExtractor, PrefixFilter},
+    format::{
+        BASELINE_FOOTER_SIZE, BLOCK_RESTART_INTERVAL, INDEX_PARTITION_SIZE_MAX, LEGACY_FOOTER_SIZE,
+        LEGACY_PREFIX_FILTER_FOOTER_SIZE, PREFIX_FILTER_MAGIC, SSTABLE_FOOTER_SIZE,
+        SSTABLE_FORMAT_VERSION, SSTABLE_MAGIC, V1_FOOTER_SIZE,
+    },
+    memtable::Memtable,
+    properties::{
+        self, Properties, PropertiesBuilder, TableProperties, TablePropertiesCollectorFactory,
+    },
+    storage::{FileStorage, RandomAccessReader, Storage},
+};
 
-// SSTable file format
-// ===================
-//
-// Some additional context:
-// - All numbers are encoded in little-endian (LE)
-// - Strings (keys) are encoded as # of bytes (LE), followed by bytes.
-// - Blocks are sorted by their keyspan.
-// - Entries in a block are sorted in ascending order, by key.
-//
-// SSTable Encoding:
-// ---------------------------------------
-//
-// - <Block> #1
-// - <Block> #2
-// - ..
-// - <SSTable index>
+// Reads an sstable; see format.rs for the file format.
 //
-// Block format:
-// ---------------------------------------
-//   - entry #1
-//     - key length (u32; LE)
-//     - value length (u32; LE)
-//     - key (variable length)
-//     - indicator for isPresent (1) or deleted (0).  (u8)
-//     - value (val_len bytes)
-//   - entry #2
-//     ...
-//   - block footer:
-//     * byte offset inside block of entry #1 (u32; LE)
-//     * byte offset inside block of entry #2 (u32; LE)
-//       ...
-//     * number of entries (u32; little-endian)
-//
-// SSTable index format (i.e., the footer of an sstable file):
-// ---------------------------------------
-// - <block #1>
-//   byte size (u32; LE),
-//   last_key_length (u32; LE),
-//   last key in the block (last_key_length bytes)
-// - <block #2>
-//   ..
-// - ..
-// - size of sstable index in bytes (u32; LE)
+// Clones share the file, index and filter, so they're cheap to make, e.g. for
+// iterators which outlive the database's lock.
+#[derive(Clone)]
 pub(crate) struct SSTableReader {
-    file: File,
+    file: RandomAccessReader,
+    path: PathBuf,
+    file_size: u64,
+    format_version: u32,
+
+    // The top-level index, sorted by last_key.
+    index: Arc<Vec<IndexPartition>>,
+
+    // Range deletions which apply to older sstables.
+    range_tombstones: Vec<RangeTombstone>,
 
-    // (last_key, block byte offset, block size), sorted by last_key.
-    index: Vec<(String, u32, u32)>,
+    prefix_filter: Option<Arc<PrefixFilter>>,
+
+    // None for sstables older than format version 2.
+    properties: Option<Arc<Properties>>,
+
+    // The smallest and largest keys in the sstable, including the range tombstones'
+    // start and end keys.  None if the sstable is empty.
+    key_range: Option<(Key, Key)>,
+
+    // See `num_deletions()`; counted the first time it's needed.
+    num_deletions: Arc<OnceLock<u64>>,
+}
+
+// What SSTableReader::parse_index() reads from the end of an sstable.
+struct ParsedSSTable {
+    format_version: u32,
+    index: Vec<IndexPartition>,
+    range_tombstones: Vec<RangeTombstone>,
+    prefix_filter: Option<PrefixFilter>,
+    properties: Option<Properties>,
+}
+
+// A top-level index entry; describes a partition of the block index.
+pub(crate) struct IndexPartition {
+    last_key: String,
+    offset: u32,
+    size: u32,
+    num_entries: u64,
+    // The size of the partition's blocks.
+    data_size: u64,
+    // Read on first use, and shared by the reader's clones.
+    blocks: OnceLock<Vec<BlockMetadata>>,
+}
+
+impl IndexPartition {
+    // Returns the partition's blocks, reading them from `file` the first time.
+    fn blocks(&self, file: &mut RandomAccessReader) -> Result<&[BlockMetadata], SSTableError> {
+        if let Some(blocks) = self.blocks.get() {
+            return Ok(blocks);
+        }
+        file.seek(SeekFrom::Start(self.offset.into()))?;
+        let mut blocks = Vec::new();
+        let mut partition_pos = 0u64;
+        while partition_pos < self.size as u64 {
+            let offset = file.read_u32_le()?;
+            let size = file.read_u32_le()?;
+            let num_entries = file.read_u32_le()?;
+            let key_len = file.read_u32_le()?;
+            partition_pos += 4 * size_of::<u32>() as u64 + key_len as u64;
+            // Blocks come before the index partitions.
+            if partition_pos > self.size as u64 || offset as u64 + size as u64 > self.offset as u64
+            {
+                return Err(SSTableError::Corrupt("index partition is out of bounds"));
+            }
+            let last_key = String::from_utf8(file.read_u8s(key_len as usize)?)?;
+            blocks.push(BlockMetadata {
+                last_key,
+                offset,
+                size,
+                num_entries,
+            });
+        }
+        Ok(self.blocks.get_or_init(|| blocks))
+    }
+}
+
+// An sstable index entry; describes where a block is and what it holds.
+#[derive(Clone)]
+pub(crate) struct BlockMetadata {
+    last_key: String,
+    offset: u32,
+    size: u32,
+    num_entries: u32,
 }
 
 impl SSTableReader {
-    pub fn from_path(path: &PathBuf) -> Result<Self, SSTableError> {
-        let mut file = std::fs::File::open(path.clone())?;
-        let index = Self::parse_index(&mut file)?;
-        Ok(SSTableReader { file, index })
+    pub fn from_path(storage: &dyn Storage, path: &Path) -> Result<Self, SSTableError> {
+        let mut file = RandomAccessReader::new(storage.open(path)?.into())?;
+        let file_size = file.size();
+        let parsed = Self::parse_index(&mut file, path)?;
+        let mut reader = SSTableReader {
+            file,
+            path: path.to_path_buf(),
+            file_size,
+            format_version: parsed.format_version,
+            index: Arc::new(parsed.index),
+            range_tombstones: parsed.range_tombstones,
+            prefix_filter: parsed.prefix_filter.map(Arc::new),
+            properties: parsed.properties.map(Arc::new),
+            key_range: None,
+            num_deletions: Arc::new(OnceLock::new()),
+        };
+        reader.key_range = reader.read_key_range()?;
+        Ok(reader)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    // Returns the smallest and largest keys in the sstable, or None if it's empty.
+    pub fn key_range(&self) -> Option<(&str, &str)> {
+        self.key_range
+            .as_ref()
+            .map(|(smallest, largest)| (smallest.as_str(), largest.as_str()))
+    }
+
+    // Returns true if the sstable may hold keys in the inclusive range [smallest, largest].
+    pub fn overlaps(&self, smallest: &str, largest: &str) -> bool {
+        match self.key_range() {
+            Some((self_smallest, self_largest)) => {
+                self_smallest <= largest && smallest <= self_largest
+            }
+            None => false,
+        }
+    }
+
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    // Reads `readahead_size` bytes at a time from now on, e.g. for a compaction
+    // which reads the whole sstable; see RandomAccessReader::with_readahead().
+    pub fn with_readahead(mut self, readahead_size: usize) -> Self {
+        self.file = self.file.with_readahead(readahead_size);
+        self
+    }
+
+    // The sstable's properties, as found in `level`.
+    pub fn table_properties(&self, level: usize) -> TableProperties {
+        let property = |name| {
+            self.properties
+                .as_ref()
+                .and_then(|properties| properties::property_u64(properties, name))
+        };
+        TableProperties {
+            path: self.path.clone(),
+            level,
+            file_size: self.file_size,
+            num_entries: self.num_entries(),
+            num_deletions: property(properties::NUM_DELETIONS),
+            deleted_bytes: property(properties::DELETED_BYTES),
+            num_range_tombstones: self.range_tombstones.len() as u64,
+            creation_time: self.creation_time(),
+            user_properties: self
+                .properties
+                .as_deref()
+                .map(properties::user_properties)
+                .unwrap_or_default(),
+        }
+    }
+
+    // When the sstable was written, in seconds since the Unix epoch, if known.
+    pub fn creation_time(&self) -> Option<u64> {
+        self.properties
+            .as_ref()
+            .and_then(|properties| properties::property_u64(properties, properties::CREATION_TIME))
+    }
+
+    // Returns the number of deleted entries and range tombstones in the sstable.
+    // SSTables without properties have their entries counted by reading every
+    // block the first time, so this is only worth calling when deletions matter
+    // (see CompactionPriority).  Counting stops at the first block which can't be
+    // read.
+    pub fn num_deletions(&self) -> u64 {
+        if let Some(num_deleted_entries) = self
+            .properties
+            .as_ref()
+            .and_then(|properties| properties::property_u64(properties, properties::NUM_DELETIONS))
+        {
+            return num_deleted_entries + self.range_tombstones.len() as u64;
+        }
+        *self.num_deletions.get_or_init(|| {
+            let num_deleted_entries = self
+                .clone()
+                .into_iter_from("", true)
+                .map_while(Result::ok)
+                .filter(|(_, entry)| *entry == EntryValue::Deleted)
+                .count();
+            (num_deleted_entries + self.range_tombstones.len()) as u64
+        })
     }
 
-    fn parse_index(reader: &mut File) -> Result<Vec<(String, u32, u32)>, SSTableError> {
-        // Parse the sstable index size (last 4 bytes)
-        reader.seek(SeekFrom::End(-1 * (size_of::<u32>() as i64)))?;
+    // Returns false if the sstable definitely holds no keys with `prefix`, which
+    // `extractor` extracted.  Range tombstones aren't part of the filter.
+    pub fn may_contain_prefix(&self, extractor: &dyn PrefixExtractor, prefix: &str) -> bool {
+        match &self.prefix_filter {
+            Some(prefix_filter) => prefix_filter.may_contain(extractor, prefix),
+            None => true,
+        }
+    }
+
+    fn read_key_range(&mut self) -> Result<Option<(Key, Key)>, SSTableError> {
+        let index = self.index.clone();
+        let mut key_range: Option<(Key, Key)> = match (index.first(), index.last()) {
+            (Some(first_partition), Some(last_partition)) => {
+                let first_block = first_partition
+                    .blocks(&mut self.file)?
+                    .first()
+                    .ok_or(SSTableError::Corrupt("index partition is empty"))?;
+                let mut block_reader = BlockReader::new(
+                    &mut self.file,
+                    first_block.offset,
+                    first_block.size,
+                    self.format_version,
+                )?;
+                let (smallest, _) = block_reader.read_first_entry()?;
+                Some((smallest, last_partition.last_key.clone()))
+            }
+            _ => None,
+        };
+        for tombstone in &self.range_tombstones {
+            key_range = Some(match key_range {
+                None => (tombstone.start.clone(), tombstone.end.clone()),
+                Some((smallest, largest)) => (
+                    smallest.min(tombstone.start.clone()),
+                    largest.max(tombstone.end.clone()),
+                ),
+            });
+        }
+        Ok(key_range)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn parse_index(
+        reader: &mut RandomAccessReader,
+        path: &Path,
+    ) -> Result<ParsedSSTable, SSTableError> {
+        let file_size = reader.size();
+        if file_size < BASELINE_FOOTER_SIZE as u64 {
+            return Err(SSTableError::Corrupt("sstable is smaller than its footer"));
+        }
+        // The last 4 bytes tell the footers of the format versions apart.
+        reader.seek(SeekFrom::End(-(size_of::<u32>() as i64)))?;
+        let footer_size = match reader.read_u32_le()? {
+            SSTABLE_MAGIC => {
+                reader.seek(SeekFrom::End(-(2 * size_of::<u32>() as i64)))?;
+                if reader.read_u32_le()? >= 2 {
+                    SSTABLE_FOOTER_SIZE
+                } else {
+                    V1_FOOTER_SIZE
+                }
+            }
+            PREFIX_FILTER_MAGIC => LEGACY_PREFIX_FILTER_FOOTER_SIZE,
+            _ if Self::has_baseline_footer(reader, file_size)? => BASELINE_FOOTER_SIZE,
+            _ => LEGACY_FOOTER_SIZE,
+        };
+        if footer_size as u64 > file_size {
+            return Err(SSTableError::Corrupt("sstable is smaller than its footer"));
+        }
+        reader.seek(SeekFrom::End(-(footer_size as i64)))?;
 
         let index_size = reader.read_u32_le()?;
-        // Go to the beginning of the index
-        reader.seek(SeekFrom::End(
-            -1 * ((index_size + size_of::<u32>() as u32) as i64),
-        ))?;
+        let range_tombstones_size = if footer_size == BASELINE_FOOTER_SIZE {
+            0
+        } else {
+            reader.read_u32_le()?
+        };
+        let prefix_filter_size = if footer_size <= LEGACY_FOOTER_SIZE {
+            0
+        } else {
+            reader.read_u32_le()?
+        };
+        let properties_size = if footer_size == SSTABLE_FOOTER_SIZE {
+            reader.read_u32_le()?
+        } else {
+            0
+        };
+        let format_version = if footer_size >= V1_FOOTER_SIZE {
+            reader.read_u32_le()?
+        } else {
+            0
+        };
+        if format_version > SSTABLE_FORMAT_VERSION {
+            return Err(SSTableError::UnsupportedVersion {
+                path: path.to_path_buf(),
+                version: format_version,
+            });
+        }
+        // Go to the beginning of the index.  The sizes are u32s, so their sum
+        // can't overflow a u64.
+        let index_start = file_size
+            .checked_sub(
+                index_size as u64
+                    + range_tombstones_size as u64
+                    + prefix_filter_size as u64
+                    + properties_size as u64
+                    + footer_size as u64,
+            )
+            .ok_or(SSTableError::Corrupt(
+                "sstable is smaller than its index and footer",
+            ))?;
+        reader.seek(SeekFrom::Start(index_start))?;
 
-        // Parse the index;  a list of metadata about where each block is and its last key.
-        let mut index = Vec::<(String, u32, u32)>::new();
+        let index = if format_version == 0 {
+            let has_num_entries = footer_size != BASELINE_FOOTER_SIZE;
+            let index = Self::parse_legacy_index(reader, index_start, index_size, has_num_entries)?;
+            reader.seek(SeekFrom::Start(index_start + index_size as u64))?;
+            index
+        } else {
+            // The top-level index; where each index partition is and its last key.
+            let mut index = Vec::new();
+            let mut index_pos = 0u64;
+            while index_pos < index_size as u64 {
+                let offset = reader.read_u32_le()?;
+                let size = reader.read_u32_le()?;
+                let num_entries = reader.read_u64_le()?;
+                let data_size = reader.read_u64_le()?;
+                let key_len = reader.read_u32_le()?;
+                index_pos +=
+                    3 * size_of::<u32>() as u64 + 2 * size_of::<u64>() as u64 + key_len as u64;
+                // The index partitions come before the top-level index.
+                if index_pos > index_size as u64 || offset as u64 + size as u64 > index_start {
+                    return Err(SSTableError::Corrupt("top-level index is out of bounds"));
+                }
+                let last_key = String::from_utf8(reader.read_u8s(key_len as usize)?)?;
+                index.push(IndexPartition {
+                    last_key,
+                    offset,
+                    size,
+                    num_entries,
+                    data_size,
+                    blocks: OnceLock::new(),
+                });
+            }
+            index
+        };
+
+        // The range tombstones immediately follow the index.
+        let mut range_tombstones = Vec::new();
+        let mut range_tombstones_pos = 0u64;
+        while range_tombstones_pos < range_tombstones_size as u64 {
+            let start_len = reader.read_u32_le()?;
+            let start = String::from_utf8(reader.read_u8s(start_len as usize)?)?;
+            let end_len = reader.read_u32_le()?;
+            let end = String::from_utf8(reader.read_u8s(end_len as usize)?)?;
+            range_tombstones_pos += 2 * size_of::<u32>() as u64 + start_len as u64 + end_len as u64;
+            if range_tombstones_pos > range_tombstones_size as u64 {
+                return Err(SSTableError::Corrupt("range tombstones overrun their size"));
+            }
+
+            range_tombstones.push(RangeTombstone { start, end });
+        }
+
+        // The prefix filter follows the range tombstones.
+        let prefix_filter = if prefix_filter_size > 0 {
+            let bytes = reader.read_u8s(prefix_filter_size as usize)?;
+            Some(
+                PrefixFilter::decode(&bytes)
+                    .ok_or(SSTableError::Corrupt("invalid prefix filter"))?,
+            )
+        } else {
+            None
+        };
+
+        // The properties follow the prefix filter.
+        let properties = if footer_size == SSTABLE_FOOTER_SIZE {
+            Some(properties::decode(
+                &reader.read_u8s(properties_size as usize)?,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(ParsedSSTable {
+            format_version,
+            index,
+            range_tombstones,
+            prefix_filter,
+            properties,
+        })
+    }
+
+    // Tells the footer of a version 0 sstable from that of one written before
+    // range tombstones, which only holds the index size: the former's sizes lead
+    // to an index whose blocks fill the file up to it.
+    fn has_baseline_footer(
+        reader: &mut RandomAccessReader,
+        file_size: u64,
+    ) -> Result<bool, SSTableError> {
+        if file_size < LEGACY_FOOTER_SIZE as u64 {
+            return Ok(true);
+        }
+        reader.seek(SeekFrom::End(-(LEGACY_FOOTER_SIZE as i64)))?;
+        let index_size = reader.read_u32_le()?;
+        let range_tombstones_size = reader.read_u32_le()?;
+        let Some(index_start) = file_size.checked_sub(
+            index_size as u64 + range_tombstones_size as u64 + LEGACY_FOOTER_SIZE as u64,
+        ) else {
+            return Ok(true);
+        };
+        reader.seek(SeekFrom::Start(index_start))?;
+        Ok(Self::parse_legacy_index(reader, index_start, index_size, true).is_err())
+    }
+
+    // Parses the single-level index of a version 0 sstable, as a single partition
+    // which is already read.  Its blocks fill the file up to `index_start`.
+    // Without `has_num_entries`, the blocks' numbers of entries are read from
+    // their footers.
+    fn parse_legacy_index(
+        reader: &mut RandomAccessReader,
+        index_start: u64,
+        index_size: u32,
+        has_num_entries: bool,
+    ) -> Result<Vec<IndexPartition>, SSTableError> {
+        let mut blocks = Vec::<BlockMetadata>::new();
         let mut block_offset = 0u32;
-        let mut index_pos = 0;
-        while index_pos < index_size {
+        let mut index_pos = 0u64;
+        while index_pos < index_size as u64 {
             let block_size = reader.read_u32_le()?;
-            index_pos += 4;
-
+            let num_entries = if has_num_entries {
+                reader.read_u32_le()?
+            } else {
+                0
+            };
             let key_len = reader.read_u32_le()?;
-            index_pos += 4;
+            index_pos +=
+                if has_num_entries { 3 } else { 2 } * size_of::<u32>() as u64 + key_len as u64;
+            let block_end = block_offset
+                .checked_add(block_size)
+                .filter(|&block_end| {
+                    index_pos <= index_size as u64 && block_end as u64 <= index_start
+                })
+                .ok_or(SSTableError::Corrupt("index is out of bounds"))?;
+            let key = String::from_utf8(reader.read_u8s(key_len as usize)?)?;
 
-            let key_encoded = reader.read_u8s(key_len as usize)?;
-            index_pos += key_len;
+            blocks.push(BlockMetadata {
+                last_key: key,
+                offset: block_offset,
+                size: block_size,
+                num_entries,
+            });
+            block_offset = block_end;
+        }
+        if block_offset as u64 != index_start {
+            return Err(SSTableError::Corrupt(
+                "blocks don't end where the index starts",
+            ));
+        }
+        if !has_num_entries {
+            for block in &mut blocks {
+                if (block.size as usize) < LEGACY_BLOCK_FOOTER_SIZE {
+                    return Err(SSTableError::Corrupt("block is smaller than its footer"));
+                }
+                reader.seek(SeekFrom::Start(
+                    (block.offset + block.size - LEGACY_BLOCK_FOOTER_SIZE as u32).into(),
+                ))?;
+                block.num_entries = reader.read_u32_le()?;
+            }
+        }
+        let Some(last_block) = blocks.last() else {
+            return Ok(Vec::new());
+        };
+        Ok(vec![IndexPartition {
+            last_key: last_block.last_key.clone(),
+            offset: 0,
+            size: index_size,
+            num_entries: blocks.iter().map(|block| block.num_entries as u64).sum(),
+            data_size: blocks.iter().map(|block| block.size as u64).sum(),
+            blocks: OnceLock::from(blocks),
+        }])
+    }
 
-            let key = String::from_utf8(key_encoded)?;
+    // Returns how many of the index partitions were read, and how many there are.
+    #[cfg(test)]
+    pub(crate) fn num_partitions_read(&self) -> (usize, usize) {
+        let num_read = self
+            .index
+            .iter()
+            .filter(|partition| partition.blocks.get().is_some())
+            .count();
+        (num_read, self.index.len())
+    }
 
-            index.push((key, block_offset, block_size));
-            block_offset += block_size;
+    // Reads every index partition which wasn't read yet.
+    pub(crate) fn read_all_partitions(&mut self) -> Result<(), SSTableError> {
+        for partition in self.index.iter() {
+            partition.blocks(&mut self.file)?;
         }
+        Ok(())
+    }
 
-        Ok(index)
+    // Returns true if a range tombstone in this sstable covers `key`.
+    pub fn is_range_deleted(&self, key: &str) -> bool {
+        self.range_tombstones
+            .iter()
+            .any(|tombstone| tombstone.covers(key))
     }
 
     pub fn get(&mut self, key: &str) -> Result<Option<EntryValue>, SSTableError> {
-        Ok(match self.get_candidate_block(key) {
+        Ok(self.find(key, false)?.map(|(entry, _)| entry))
+    }
+
+    // Like `get()`, but without reading the value: returns the entry with an empty
+    // value, or the reference of a value stored in a blob file, and the value's size.
+    pub fn get_value_size(&mut self, key: &str) -> Result<Option<(EntryValue, u64)>, SSTableError> {
+        self.find(key, true)
+    }
+
+    fn find(
+        &mut self,
+        key: &str,
+        skip_value: bool,
+    ) -> Result<Option<(EntryValue, u64)>, SSTableError> {
+        Ok(match self.get_candidate_block(key)? {
             None => None,
             Some((offset, size)) => {
                 // TODO: cache the BlockReader
-                let mut block_reader = BlockReader::new(&mut self.file, offset, size)?;
-                block_reader.get(key)?
+                let mut block_reader =
+                    BlockReader::new(&mut self.file, offset, size, self.format_version)?;
+                block_reader.find(key, skip_value)?
             }
         })
     }
 
     // given a key, returns which block # might contain the key value pair
-    fn get_candidate_block(&self, key: &str) -> Option<(u32, u32)> {
-        if let Some(&ref last_entry) = self.index.last() {
-            if key > last_entry.0.as_str() {
-                return None;
+    fn get_candidate_block(&mut self, key: &str) -> Result<Option<(u32, u32)>, SSTableError> {
+        // Both levels of the index are sorted by last key, so the first entry whose
+        // last key isn't smaller than `key` is the only one which may hold it.
+        let index = self.index.clone();
+        let Some(partition) =
+            index.get(index.partition_point(|partition| partition.last_key.as_str() < key))
+        else {
+            return Ok(None);
+        };
+        let blocks = partition.blocks(&mut self.file)?;
+        Ok(blocks
+            .get(blocks.partition_point(|block| block.last_key.as_str() < key))
+            .map(|block| (block.offset, block.size)))
+    }
+
+    // Estimates the number of bytes used by keys in the half-open range [start, end),
+    // by summing up the sizes of the blocks which overlap the range.
+    pub fn approximate_size(&self, start: &str, end: &str) -> u64 {
+        let mut file = self.file.clone();
+        let mut size = 0u64;
+        let mut prev_last_key: Option<&str> = None;
+        for partition in self.index.iter() {
+            // A partition or block holds the keys in (prev_last_key, last_key]
+            let overlaps = |prev_last_key: Option<&str>, last_key: &str| {
+                last_key >= start && prev_last_key.is_none_or(|prev| prev < end)
+            };
+            let is_inside = prev_last_key.is_some_and(|prev| prev >= start)
+                && partition.last_key.as_str() < end;
+            if is_inside {
+                size += partition.data_size;
+            } else if overlaps(prev_last_key, &partition.last_key) {
+                match partition.blocks(&mut file) {
+                    Ok(blocks) => {
+                        let mut prev_block_last_key = prev_last_key;
+                        for block in blocks {
+                            if overlaps(prev_block_last_key, &block.last_key) {
+                                size += block.size as u64;
+                            }
+                            prev_block_last_key = Some(block.last_key.as_str());
+                        }
+                    }
+                    // It's only an estimate.
+                    Err(_) => size += partition.data_size,
+                }
             }
-        } else {
-            return None;
+            prev_last_key = Some(partition.last_key.as_str());
         }
+        size
+    }
 
-        match self
-            .index
-            .binary_search_by_key(&key, |(last_key, _, _)| last_key)
-        {
-            Ok(idx) | Err(idx) => {
-                // Found in this block.
-                let (_, offset, size) = self.index[idx];
-                Some((offset, size))
+    // Returns the number of entries (including deletions) stored in this sstable.
+    pub fn num_entries(&self) -> u64 {
+        self.index
+            .iter()
+            .map(|partition| partition.num_entries)
+            .sum()
+    }
+
+    // Reads every entry, checking that keys are in ascending order and match the
+    // index.  Catches sstables which would otherwise return wrong results silently.
+    pub fn verify(&mut self) -> Result<(), SSTableError> {
+        self.read_all_partitions()?;
+        let index = self.index.clone();
+        let key_range = self.key_range.clone();
+        let mut entries = self.iter();
+        let mut previous: Option<Key> = None;
+        for partition in index.iter() {
+            let blocks = partition
+                .blocks
+                .get()
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if blocks.last().map(|block| &block.last_key) != Some(&partition.last_key)
+                || blocks
+                    .iter()
+                    .map(|block| block.num_entries as u64)
+                    .sum::<u64>()
+                    != partition.num_entries
+            {
+                return Err(SSTableError::Corrupt(
+                    "index partition doesn't match its blocks",
+                ));
             }
+            for block in blocks {
+                let mut last_key = None;
+                for _ in 0..block.num_entries {
+                    let (key, _) = entries.next().ok_or(SSTableError::Corrupt(
+                        "block has fewer entries than indexed",
+                    ))??;
+                    if let Some(previous) = previous {
+                        if key <= previous {
+                            return Err(SSTableError::KeyOrder { previous, key });
+                        }
+                    }
+                    if key_range
+                        .as_ref()
+                        .is_some_and(|(smallest, largest)| key < *smallest || key > *largest)
+                    {
+                        return Err(SSTableError::Corrupt(
+                            "key is outside of the sstable's key range",
+                        ));
+                    }
+                    previous = Some(key.clone());
+                    last_key = Some(key);
+                }
+                if last_key.as_ref() != Some(&block.last_key) {
+                    return Err(SSTableError::Corrupt(
+                        "block's last key doesn't match the index",
+                    ));
+                }
+            }
+        }
+        if entries.next().is_some() {
+            return Err(SSTableError::Corrupt("block has more entries than indexed"));
+        }
+        Ok(())
+    }
+
+    // Reads the entries of every block which can still be read, to repair a damaged
+    // sstable; only its index and range tombstones must be intact.  A block is only
+    // kept if it holds as many entries as the index says, ending with the block's
+    // last key.
+    pub fn salvage(storage: &dyn Storage, path: &Path) -> Result<SalvagedSSTable, SSTableError> {
+        let mut file = RandomAccessReader::new(storage.open(path)?.into())?;
+        let ParsedSSTable {
+            format_version,
+            index,
+            range_tombstones,
+            ..
+        } = Self::parse_index(&mut file, path)?;
+        let mut entries = Vec::new();
+        let mut num_lost_blocks = 0;
+        for partition in &index {
+            for block in partition.blocks(&mut file)? {
+                let block_entries =
+                    BlockReader::new(&mut file, block.offset, block.size, format_version)
+                        .map_err(SSTableError::from)
+                        .and_then(|mut block_reader| block_reader.read_all());
+                match block_entries {
+                    Ok(block_entries)
+                        if block_entries.len() == block.num_entries as usize
+                            && block_entries.last().map(|(key, _)| key)
+                                == Some(&block.last_key) =>
+                    {
+                        entries.extend(block_entries)
+                    }
+                    _ => num_lost_blocks += 1,
+                }
+            }
+        }
+        Ok(SalvagedSSTable {
+            entries,
+            range_tombstones,
+            num_lost_blocks,
+        })
+    }
+
+    // Returns an iterator over all the entries in the sstable, in ascending key order.
+    // Range tombstones are not included; see SSTableReader::range_tombstones().
+    pub fn iter(&mut self) -> SSTableIterator<&mut SSTableReader> {
+        SSTableIterator {
+            reader: self,
+            next_partition: 0,
+            next_block: 0,
+            block_entries: Vec::new().into_iter(),
+            keys_only: false,
         }
     }
+
+    // Like `iter()`, but skips the blocks whose keys are all smaller than `key`.  The
+    // first block read may still hold smaller keys.  With `keys_only`, values are
+    // skipped over rather than read, and come back empty.
+    pub fn into_iter_from(mut self, key: &str, keys_only: bool) -> SSTableIterator<SSTableReader> {
+        let index = self.index.clone();
+        let next_partition = index.partition_point(|partition| partition.last_key.as_str() < key);
+        // If the partition can't be read, the iterator runs into the error itself.
+        let next_block = index
+            .get(next_partition)
+            .and_then(|partition| partition.blocks(&mut self.file).ok())
+            .map_or(0, |blocks| {
+                blocks.partition_point(|block| block.last_key.as_str() < key)
+            });
+        SSTableIterator {
+            reader: self,
+            next_partition,
+            next_block,
+            block_entries: Vec::new().into_iter(),
+            keys_only,
+        }
+    }
+}
+
+// What could be read from a damaged sstable, see SSTableReader::salvage().
+pub(crate) struct SalvagedSSTable {
+    pub entries: Vec<(Key, EntryValue)>,
+    pub range_tombstones: Vec<RangeTombstone>,
+    pub num_lost_blocks: usize,
+}
+
+// Iterates over an sstable's entries, reading one block at a time.  Holds either
+// a borrowed or an owned reader.
+pub(crate) struct SSTableIterator<R: BorrowMut<SSTableReader>> {
+    reader: R,
+    // index of the partition holding the next block to read
+    next_partition: usize,
+    // index of the next block to read within its partition
+    next_block: usize,
+    // the remaining entries of the current block
+    block_entries: std::vec::IntoIter<(Key, EntryValue)>,
+    keys_only: bool,
 }
 
+impl<R: BorrowMut<SSTableReader>> Iterator for SSTableIterator<R> {
+    type Item = Result<(Key, EntryValue), SSTableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.block_entries.next() {
+                return Some(Ok(entry));
+            }
+            let reader = self.reader.borrow_mut();
+            let index = reader.index.clone();
+            let partition = index.get(self.next_partition)?;
+            let entries = partition.blocks(&mut reader.file).and_then(|blocks| {
+                let Some(block) = blocks.get(self.next_block) else {
+                    return Ok(None);
+                };
+                BlockReader::new(
+                    &mut reader.file,
+                    block.offset,
+                    block.size,
+                    reader.format_version,
+                )
+                .map_err(SSTableError::from)
+                .and_then(|mut block_reader| block_reader.read_entries(self.keys_only))
+                .map(Some)
+            });
+            match entries {
+                Ok(Some(entries)) => {
+                    self.next_block += 1;
+                    self.block_entries = entries.into_iter();
+                }
+                Ok(None) => {
+                    self.next_partition += 1;
+                    self.next_block = 0;
+                }
+                Err(err) => {
+                    // Stop iterating after an error.
+                    self.next_partition = index.len();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+// Writes `memtable` out as an sstable.  With a `blob_writer`, large values are
+// moved into its blob file.  The sstable stores the properties of a collector
+// from each of the `collector_factories`.
 pub(crate) fn write_memtable_to_sstable(
     memtable: &Memtable,
     writer: &mut impl Write,
+    mut blob_writer: Option<&mut BlobFileWriter>,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    collector_factories: &[Arc<dyn TablePropertiesCollectorFactory>],
 ) -> Result<(), SSTableError> {
-    let mut block_writer = BlockWriter::new();
-    // `block_sizes` is a list of block size entries.
-    // each entry is:  # of bytes in the block, last key in the block.
-    let mut block_sizes: Vec<(usize, String)> = Vec::new();
-
-    // write out all the keys
-    for (key, entry) in memtable {
-        match block_writer.add_to_block(key, entry) {
-            Ok(()) => {}
+    let mut sstable_writer = SSTableWriter::new(writer)
+        .with_prefix_extractor(prefix_extractor)
+        .with_properties_collectors(collector_factories);
+    for (key, entry) in memtable.entries.iter() {
+        let entry = entry.to_entry_value();
+        match blob_writer.as_deref_mut() {
+            Some(blob_writer) => sstable_writer.add(key, &blob_writer.add(&entry)?)?,
+            None => sstable_writer.add(key, &entry)?,
+        }
+    }
+    for tombstone in &memtable.range_tombstones {
+        sstable_writer.add_range_tombstone(tombstone.clone());
+    }
+    sstable_writer.finish()?;
+    Ok(())
+}
+
+// Writes an sstable, one entry at a time.
+//
+// Entries must be added in ascending key order.
+pub(crate) struct SSTableWriter<W: Write> {
+    writer: W,
+    block_writer: BlockWriter,
+    // The blocks which have already been written out.
+    blocks: Vec<BlockMetadata>,
+    // Number of bytes of blocks which have already been written out.
+    blocks_size: u64,
+    range_tombstones: Vec<RangeTombstone>,
+    // The last key added, to check that keys are added in order.
+    last_key: Option<Key>,
+    // Hashes of the prefixes of the keys added, for the prefix filter.
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    prefix_hashes: Vec<u64>,
+    properties: PropertiesBuilder,
+    // SSTABLE_FORMAT_VERSION, but for tests of the older versions.
+    format_version: u32,
+}
+
+impl<W: Write> SSTableWriter<W> {
+    pub fn new(writer: W) -> Self {
+        SSTableWriter {
+            writer,
+            block_writer: BlockWriter::new(),
+            blocks: Vec::new(),
+            blocks_size: 0,
+            range_tombstones: Vec::new(),
+            last_key: None,
+            prefix_extractor: None,
+            prefix_hashes: Vec::new(),
+            properties: PropertiesBuilder::default(),
+            format_version: SSTABLE_FORMAT_VERSION,
+        }
+    }
+
+    // Writes a version 2 sstable, without block checksums.
+    #[cfg(test)]
+    fn without_block_checksums(mut self) -> Self {
+        self.format_version = 2;
+        self
+    }
+
+    // Writes a filter of the prefixes of the keys, deleted ones included, so reads
+    // can skip the sstable.
+    pub fn with_prefix_extractor(
+        mut self,
+        prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    ) -> Self {
+        self.prefix_extractor = prefix_extractor;
+        self
+    }
+
+    // Stores the properties of a new collector from each factory in the sstable.
+    pub fn with_properties_collectors(
+        mut self,
+        collector_factories: &[Arc<dyn TablePropertiesCollectorFactory>],
+    ) -> Self {
+        self.properties = PropertiesBuilder::new(collector_factories);
+        self
+    }
+
+    // Keys must be added in ascending order.
+    pub fn add(&mut self, key: &str, entry: &EntryValue) -> Result<(), SSTableError> {
+        if let Some(last_key) = &self.last_key {
+            if key <= last_key.as_str() {
+                return Err(SSTableError::KeyOrder {
+                    previous: last_key.clone(),
+                    key: key.to_string(),
+                });
+            }
+        }
+        self.last_key = Some(key.to_string());
+        self.properties.add(key, entry);
+        if let Some(prefix) = self
+            .prefix_extractor
+            .as_ref()
+            .and_then(|extractor| extractor.prefix(key))
+        {
+            // Keys with the same prefix are added one after the other.
+            let hash = filter::hash(prefix.as_bytes());
+            if self.prefix_hashes.last() != Some(&hash) {
+                self.prefix_hashes.push(hash);
+            }
+        }
+        match self.block_writer.add_to_block(key, entry) {
+            Ok(()) => Ok(()),
             Err(SSTableError::BlockSizeOverflow) => {
                 // flush the current block to the `writer`, make a new block and add entry to it.
-                block_sizes.push(block_writer.flush(writer)?);
-                block_writer = BlockWriter::new();
-                block_writer
-                    .add_to_block(key, entry)
-                    .expect("single key/value won't fit in block");
+                self.flush_block()?;
+                self.block_writer.add_to_block(key, entry)
             }
-            Err(err) => return Err(err),
+            Err(err) => Err(err),
         }
     }
 
-    // flush the last block.
-    block_sizes.push(block_writer.flush(writer)?);
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.range_tombstones.push(tombstone);
+    }
+
+    // Returns roughly how many bytes the sstable will take up, not counting the index.
+    pub fn estimated_size(&self) -> u64 {
+        self.blocks_size + self.block_writer.block_size() as u64
+    }
+
+    fn flush_block(&mut self) -> Result<(), SSTableError> {
+        let block_writer = std::mem::replace(&mut self.block_writer, BlockWriter::new());
+        let num_entries = block_writer.num_entries();
+        let (block_size, last_key) = block_writer.flush(&mut self.writer, self.format_version)?;
+        self.blocks.push(BlockMetadata {
+            last_key,
+            offset: self.blocks_size as u32,
+            size: block_size as u32,
+            num_entries,
+        });
+        self.blocks_size += block_size as u64;
+        Ok(())
+    }
+
+    // Writes out the last block, the index partitions, the top-level index, the range
+    // tombstones, the prefix filter, the properties, and the footer.
+    pub fn finish(mut self) -> Result<W, SSTableError> {
+        // flush the last block, unless there are only range tombstones.
+        if !self.block_writer.is_empty() {
+            self.flush_block()?;
+        }
+        let writer = &mut self.writer;
+
+        // write out the index partitions, each up to INDEX_PARTITION_SIZE_MAX bytes of:
+        // - block offset (4 bytes), block size in bytes (4 bytes),
+        //   number of entries (4 bytes), last key length (4 bytes),
+        //   last key (variable length)
+        // - block #2 ..
+        // - ..
+        let mut partitions: Vec<IndexPartition> = Vec::new();
+        let mut offset = self.blocks_size as u32;
+        for block in &self.blocks {
+            let entry_size = (4 * size_of::<u32>() + block.last_key.len()) as u32;
+            let partition = match partitions.last_mut() {
+                Some(partition)
+                    if (partition.size + entry_size) as usize <= INDEX_PARTITION_SIZE_MAX =>
+                {
+                    partition
+                }
+                _ => {
+                    partitions.push(IndexPartition {
+                        last_key: String::new(),
+                        offset,
+                        size: 0,
+                        num_entries: 0,
+                        data_size: 0,
+                        blocks: OnceLock::new(),
+                    });
+                    partitions.last_mut().unwrap()
+                }
+            };
+            writer.write_all(&block.offset.to_le_bytes())?;
+            writer.write_all(&block.size.to_le_bytes())?;
+            writer.write_all(&block.num_entries.to_le_bytes())?;
+            writer.write_all(&(block.last_key.len() as u32).to_le_bytes())?;
+            writer.write_all(block.last_key.as_bytes())?;
+            partition.last_key = block.last_key.clone();
+            partition.size += entry_size;
+            partition.num_entries += block.num_entries as u64;
+            partition.data_size += block.size as u64;
+            offset += entry_size;
+        }
 
-    // write out the sstable index (i.e., the footer):
-    // - block #1 size in bytes (4 bytes), last key length (4 bytes), last key (variable length)
-    // - block #2 ..
-    // - ..
-    // - sstable index size (4 bytes)
-    let mut index_size = 0u32;
-    for (block_size, last_key) in block_sizes {
-        index_size += size_of::<u32>() as u32;
-        writer.write_all(&(block_size as u32).to_le_bytes())?;
+        // write out the top-level index:
+        // - partition offset (4 bytes), partition size in bytes (4 bytes),
+        //   number of entries (8 bytes), size of its blocks in bytes (8 bytes),
+        //   last key length (4 bytes), last key (variable length)
+        // - partition #2 ..
+        // - ..
+        let mut index_size = 0u32;
+        for partition in &partitions {
+            writer.write_all(&partition.offset.to_le_bytes())?;
+            writer.write_all(&partition.size.to_le_bytes())?;
+            writer.write_all(&partition.num_entries.to_le_bytes())?;
+            writer.write_all(&partition.data_size.to_le_bytes())?;
+            writer.write_all(&(partition.last_key.len() as u32).to_le_bytes())?;
+            writer.write_all(partition.last_key.as_bytes())?;
+            index_size +=
+                (3 * size_of::<u32>() + 2 * size_of::<u64>() + partition.last_key.len()) as u32;
+        }
 
-        index_size += size_of::<u32>() as u32;
-        writer.write_all(&(last_key.len() as u32).to_le_bytes())?;
+        // write out the range tombstones:
+        // - start key length (4 bytes), start key (variable length),
+        //   end key length (4 bytes), end key (variable length)
+        // - ..
+        let mut range_tombstones_size = 0u32;
+        for tombstone in &self.range_tombstones {
+            for key in [&tombstone.start, &tombstone.end] {
+                let key_bytes = key.as_bytes();
+                range_tombstones_size += (size_of::<u32>() + key_bytes.len()) as u32;
+                writer.write_all(&(key_bytes.len() as u32).to_le_bytes())?;
+                writer.write_all(key_bytes)?;
+            }
+        }
 
-        let last_key_bytes = last_key.as_bytes();
-        index_size += last_key_bytes.len() as u32;
-        writer.write_all(last_key_bytes)?;
+        let prefix_filter_size = match &self.prefix_extractor {
+            Some(extractor) => {
+                PrefixFilter::build(extractor.name(), &self.prefix_hashes).encode(writer)?
+            }
+            None => 0,
+        };
+
+        let properties_size = properties::encode(&self.properties.finish(), writer)?;
+
+        // write out the footer:
+        // - top-level index size (4 bytes)
+        // - range tombstones size (4 bytes)
+        // - prefix filter size (4 bytes)
+        // - properties size (4 bytes)
+        // - SSTABLE_FORMAT_VERSION and SSTABLE_MAGIC (4 bytes each)
+        writer.write_all(&index_size.to_le_bytes())?;
+        writer.write_all(&range_tombstones_size.to_le_bytes())?;
+        writer.write_all(&prefix_filter_size.to_le_bytes())?;
+        writer.write_all(&properties_size.to_le_bytes())?;
+        writer.write_all(&self.format_version.to_le_bytes())?;
+        writer.write_all(&SSTABLE_MAGIC.to_le_bytes())?;
+
+        Ok(self.writer)
     }
-    writer.write_all(&index_size.to_le_bytes())?;
+}
 
-    Ok(())
+// Builds an sstable file outside of any database, to bulk load it with
+// `RawDB::ingest_sst()`.  Keys must be added in ascending order.
+pub struct SstWriter {
+    path: PathBuf,
+    writer: SSTableWriter<BufWriter<File>>,
+}
+
+impl SstWriter {
+    pub fn create(path: &Path) -> Result<Self, DBError> {
+        let file = File::create(path).map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        Ok(SstWriter {
+            path: path.to_path_buf(),
+            writer: SSTableWriter::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn put(&mut self, key: &str, value: impl Into<Value>) -> Result<(), DBError> {
+        self.writer
+            .add(key, &EntryValue::Present(value.into()))
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))
+    }
+
+    pub fn delete(&mut self, key: &str) -> Result<(), DBError> {
+        self.writer
+            .add(key, &EntryValue::Deleted)
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))
+    }
+
+    // Deletes the keys in [`start`, `end`) from the database the sstable is ingested
+    // into.  Keys put in this sstable aren't affected.
+    pub fn delete_range(&mut self, start: impl Into<Key>, end: impl Into<Key>) {
+        let tombstone = RangeTombstone {
+            start: start.into(),
+            end: end.into(),
+        };
+        if tombstone.start < tombstone.end {
+            self.writer.add_range_tombstone(tombstone);
+        }
+    }
+
+    // Writes out and syncs the sstable, and returns its path.
+    pub fn finish(self) -> Result<PathBuf, DBError> {
+        let writer = self
+            .writer
+            .finish()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))?;
+        let file = writer
+            .into_inner()
+            .map_err(|err| DBError::Io(err.into_error().to_string()))?;
+        file.sync_all()
+            .map_err(|io_err| DBError::Io(io_err.to_string()))?;
+        Ok(self.path)
+    }
+}
+
+// A block of an sstable, as described by its index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    // Where the block starts in the file, in bytes.
+    pub offset: u32,
+    pub size: u32,
+    pub num_entries: u32,
+    pub last_key: Key,
+}
+
+// Reads an sstable file directly, e.g. to inspect what's on disk.
+//
+// Entries are returned as they are stored.  There are no sequence numbers yet;
+// within an sstable a key has a single entry.
+pub struct SstReader {
+    reader: SSTableReader,
+}
+
+impl SstReader {
+    pub fn open(path: &Path) -> Result<Self, DBError> {
+        // Reads the whole index up front, to describe every block.
+        let reader = SSTableReader::from_path(&FileStorage, path)
+            .and_then(|mut reader| reader.read_all_partitions().map(|()| reader))
+            .map_err(SSTableError::into_db_error)?;
+        Ok(SstReader { reader })
+    }
+
+    // Returns the smallest and largest keys, including the range tombstones' keys,
+    // or None if the sstable is empty.
+    pub fn key_range(&self) -> Option<(&str, &str)> {
+        self.reader.key_range()
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.reader.file_size()
+    }
+
+    // Returns the number of entries, including deletions.
+    pub fn num_entries(&self) -> u64 {
+        self.reader.num_entries()
+    }
+
+    // Describes the sstable's blocks, from its index.
+    pub fn blocks(&self) -> Vec<BlockInfo> {
+        self.reader
+            .index
+            .iter()
+            .flat_map(|partition| partition.blocks.get().into_iter().flatten())
+            .map(|block| BlockInfo {
+                offset: block.offset,
+                size: block.size,
+                num_entries: block.num_entries,
+                last_key: block.last_key.clone(),
+            })
+            .collect()
+    }
+
+    pub fn has_prefix_filter(&self) -> bool {
+        self.reader.prefix_filter.is_some()
+    }
+
+    // Reads every entry, checking the blocks' checksums, and that keys are in
+    // ascending order and match the index.  Sstables older than format version 3
+    // have no checksums, so their damaged values go unnoticed.
+    pub fn verify(&mut self) -> Result<(), DBError> {
+        self.reader
+            .verify()
+            .map_err(|sstable_err| DBError::SSTable(sstable_err.to_string()))
+    }
+
+    // Returns the [start, end) ranges deleted by the sstable's range tombstones.
+    pub fn range_tombstones(&self) -> Vec<(Key, Key)> {
+        self.reader
+            .range_tombstones()
+            .iter()
+            .map(|tombstone| (tombstone.start.clone(), tombstone.end.clone()))
+            .collect()
+    }
+
+    // Returns every entry in key order, deletions included.  Values stored in blob
+    // files are returned as their EntryValue::Blob reference.
+    pub fn entries(&mut self) -> impl Iterator<Item = Result<(Key, EntryValue), DBError>> + '_ {
+        self.reader
+            .iter()
+            .map(|entry| entry.map_err(|sstable_err| DBError::SSTable(sstable_err.to_string())))
+    }
 }
 
 #[derive(Error, Debug)]
@@ -195,29 +1228,48 @@ pub(crate) enum SSTableError {
     FromUtf8Error(#[from] FromUtf8Error),
     #[error("block is too big. make a new block")]
     BlockSizeOverflow,
+    #[error("keys are out of order: {key:?} comes after {previous:?}")]
+    KeyOrder { previous: Key, key: Key },
+    #[error(
+        "{path:?} has format version {version}, which is newer than this version of lsmdb supports"
+    )]
+    UnsupportedVersion { path: PathBuf, version: u32 },
+    // The sstable's sizes or offsets don't add up, or its entries can't be decoded.
+    #[error("sstable is corrupt: {0}")]
+    Corrupt(&'static str),
     // TODO:  Replace `Custom` with specific error codes
     #[error("SSTableError: {0}")]
     Custom(&'static str),
 }
 
+impl SSTableError {
+    // Like DBError::SSTable(), but keeps unsupported versions apart, for callers
+    // which open sstables.
+    pub fn into_db_error(self) -> DBError {
+        match self {
+            SSTableError::UnsupportedVersion { path, version } => DBError::UnsupportedVersion {
+                file: path.to_string_lossy().to_string(),
+                version,
+            },
+            err => DBError::SSTable(err.to_string()),
+        }
+    }
+}
+
 const BLOCK_SIZE_MAX_KB: usize = 4 * 1024;
-const BLOCK_NUM_ENTRIES_SIZEOF: usize = size_of::<u32>();
+const BLOCK_FOOTER_SIZE: usize = 3 * size_of::<u32>();
+// Blocks of format versions 1 and 2 don't have a checksum.
+const V1_BLOCK_FOOTER_SIZE: usize = 2 * size_of::<u32>();
+const LEGACY_BLOCK_FOOTER_SIZE: usize = size_of::<u32>();
+// The most ReaderExt::read_u8s() allocates before reading.
+const READ_PREALLOCATE_MAX: usize = 64 * 1024;
+
+// Encodes a single block; see format.rs.
 pub(crate) struct BlockWriter {
-    // Encoding of a single block:
-    //   - entry #1
-    //     - key length (4 bytes)
-    //     - value length (4 bytes)
-    //     - key (variable length)
-    //     - indicator for Present (1) or Deleted (0).  (1 byte)
-    //     - value (variable length)
-    //   - entry #2
-    //     ...
-    //   - byte offset of entry #1 (4 bytes)
-    //   - byte offset of entry #2 (4 bytes)
-    //     ...
-    //   - number of entries (4 bytes)
     block_data: Vec<u8>,
-    block_footer: Vec<u8>,
+    // The byte offsets of the restart points.
+    restarts: Vec<u8>,
+    num_entries: u32,
     last_key: Option<String>,
 }
 
@@ -225,79 +1277,142 @@ impl BlockWriter {
     pub fn new() -> Self {
         BlockWriter {
             block_data: Vec::new(),
-            block_footer: Vec::new(),
+            restarts: Vec::new(),
+            num_entries: 0,
             last_key: None,
         }
     }
 
-    // Appends the given `entry` to the current block. Returns an error if there is not enough space for the entry
+    // Appends the given `entry` to the current block. Returns an error if there is not enough space for the entry.
+    // An entry bigger than a whole block goes into an empty block, which grows to hold it.
     pub fn add_to_block(&mut self, key: &str, entry: &EntryValue) -> Result<(), SSTableError> {
         let key_bytes = key.as_bytes();
-        let key_len = key_bytes.len();
+        if key_bytes.len() > u32::MAX as usize || entry.len() > u32::MAX as usize {
+            return Err(SSTableError::Custom("entry is too big for an sstable"));
+        }
+        let is_restart = self.num_entries.is_multiple_of(BLOCK_RESTART_INTERVAL);
+        let shared_len = match &self.last_key {
+            Some(last_key) if !is_restart => key_bytes
+                .iter()
+                .zip(last_key.as_bytes())
+                .take_while(|(a, b)| a == b)
+                .count(),
+            _ => 0,
+        };
+        let unshared_len = key_bytes.len() - shared_len;
         let value_len = entry.len();
         let entry_offset = self.block_data.len();
         let entry_size =
-              key_len
-            + value_len 
+              unshared_len
+            + value_len
             + 1 // presence bit (present or deleted)
-            + size_of::<u32>() // key length
+            + size_of::<u32>() // shared key length
+            + size_of::<u32>() // unshared key length
             + size_of::<u32>() // value length
-            + size_of::<u32>() // byte offset for block footer
+            + if is_restart { size_of::<u32>() } else { 0 } // restart offset in the block footer
             ;
-        if self.block_size() + entry_size > BLOCK_SIZE_MAX_KB {
+        if !self.is_empty() && self.block_size() + entry_size > BLOCK_SIZE_MAX_KB {
             return Err(SSTableError::BlockSizeOverflow);
         }
-        self.block_data.write_all(&(key_len as u32).to_le_bytes())?;
+        self.block_data
+            .write_all(&(shared_len as u32).to_le_bytes())?;
+        self.block_data
+            .write_all(&(unshared_len as u32).to_le_bytes())?;
         self.block_data
             .write_all(&(value_len as u32).to_le_bytes())?;
-        self.block_data.write_all(key_bytes)?;
+        self.block_data.write_all(&key_bytes[shared_len..])?;
         match entry {
             EntryValue::Present(value_bytes) => {
                 self.block_data.write_all(&1u8.to_le_bytes())?;
-                self.block_data.write_all(&value_bytes)?;
+                self.block_data.write_all(value_bytes)?;
             }
             EntryValue::Deleted => {
                 self.block_data.write_all(&0u8.to_le_bytes())?;
             }
+            EntryValue::Blob(blob_ref) => {
+                self.block_data.write_all(&2u8.to_le_bytes())?;
+                self.block_data.write_all(&blob_ref.encode())?;
+            }
         }
 
-        self.block_footer
-            .write_all(&(entry_offset as u32).to_le_bytes())?;
+        if is_restart {
+            self.restarts
+                .write_all(&(entry_offset as u32).to_le_bytes())?;
+        }
+        self.num_entries += 1;
         self.last_key = Some(key.to_string());
         Ok(())
     }
 
+    fn is_empty(&self) -> bool {
+        self.last_key.is_none()
+    }
+
+    fn num_entries(&self) -> u32 {
+        self.num_entries
+    }
+
+    fn num_restarts(&self) -> u32 {
+        (self.restarts.len() / size_of::<u32>()) as u32
+    }
+
     fn block_size(&self) -> usize {
-        self.block_data.len() + self.block_footer.len() + BLOCK_NUM_ENTRIES_SIZEOF
+        self.block_data.len() + self.restarts.len() + BLOCK_FOOTER_SIZE
     }
 
-    // Flushes the entire block using the given `writer`.
+    // Flushes the entire block using the given `writer`, encoded for
+    // `format_version` (1 or later).
     //
     // Returns:
     //  - number of bytes in the block
     //  - the last key in the block
-    pub fn flush(self, writer: &mut dyn Write) -> Result<(usize, String), std::io::Error> {
-        writer.write_all(&self.block_data)?;
-        writer.write_all(&self.block_footer)?;
-        let num_entries = (&self.block_footer).len() / size_of::<u32>();
-        writer.write(&(num_entries as u32).to_le_bytes())?;
-        let block_size = self.block_size();
-        Ok((block_size, self.last_key.unwrap()))
+    pub fn flush(
+        self,
+        writer: &mut dyn Write,
+        format_version: u32,
+    ) -> Result<(usize, String), std::io::Error> {
+        let counts = [self.num_restarts(), self.num_entries].map(u32::to_le_bytes);
+        let parts = [
+            self.block_data.as_slice(),
+            &self.restarts,
+            &counts[0],
+            &counts[1],
+        ];
+        for part in parts {
+            writer.write_all(part)?;
+        }
+        let mut block_size = self.block_size();
+        if format_version >= 3 {
+            writer.write_all(&crc32c_of_parts(parts).to_le_bytes())?;
+        } else {
+            block_size -= BLOCK_FOOTER_SIZE - V1_BLOCK_FOOTER_SIZE;
+        }
+        let last_key = self.last_key.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "block is empty")
+        })?;
+        Ok((block_size, last_key))
     }
 }
 
 pub(crate) struct BlockReader<'r, T: Read + Seek + 'r> {
     reader: &'r mut T,
+    format_version: u32,
     block_offset: u32,
     num_entries: u32,
-    entry_offsets: Vec<u32>,
+    // In a version 0 block, every entry is a restart point.
+    restart_offsets: Vec<u32>,
+    // Where the entries end, relative to the block.
+    data_size: u32,
 }
 
-trait ReaderExt {
+pub(crate) trait ReaderExt {
     // Read a little-endian-encoded u32
     fn read_u32_le(&mut self) -> Result<u32, std::io::Error>;
+    // Read a little-endian-encoded u64
+    fn read_u64_le(&mut self) -> Result<u64, std::io::Error>;
     fn read_u8(&mut self) -> Result<u8, std::io::Error>;
-    // Allocates a new vector of size `length` and reads into it.
+    // Reads `length` bytes into a new vector.  Lengths come from the file, so the
+    // vector grows as the bytes are read rather than being allocated up front.
     fn read_u8s(&mut self, length: usize) -> Result<Vec<u8>, std::io::Error>;
 }
 impl<T: Read> ReaderExt for T {
@@ -307,6 +1422,12 @@ impl<T: Read> ReaderExt for T {
         Ok(u32::from_le_bytes(encoded_num))
     }
 
+    fn read_u64_le(&mut self) -> Result<u64, std::io::Error> {
+        let mut encoded_num: [u8; 8] = Default::default();
+        self.read_exact(&mut encoded_num)?;
+        Ok(u64::from_le_bytes(encoded_num))
+    }
+
     fn read_u8(&mut self) -> Result<u8, std::io::Error> {
         let mut encoded_num: [u8; 1] = Default::default();
         self.read_exact(&mut encoded_num)?;
@@ -314,9 +1435,11 @@ impl<T: Read> ReaderExt for T {
     }
 
     fn read_u8s(&mut self, length: usize) -> Result<Vec<u8>, std::io::Error> {
-        let mut bytes = Vec::<u8>::new();
-        bytes.resize(length, 0);
-        self.read(bytes.as_mut_slice())?;
+        let mut bytes = Vec::with_capacity(length.min(READ_PREALLOCATE_MAX));
+        self.take(length as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < length {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
         Ok(bytes)
     }
 }
@@ -326,60 +1449,227 @@ impl<'r, T: Read + Seek + 'r> BlockReader<'r, T> {
         reader: &'r mut T,
         block_offset: u32,
         block_size: u32,
-    ) -> Result<BlockReader<T>, std::io::Error> {
-        reader.seek(SeekFrom::Start(
-            (block_offset + block_size - (BLOCK_NUM_ENTRIES_SIZEOF as u32)).into(),
-        ))?;
-        let num_entries = reader.read_u32_le()?;
+        format_version: u32,
+    ) -> Result<BlockReader<'r, T>, std::io::Error> {
+        let invalid_block =
+            || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid block");
+        let footer_size = match format_version {
+            0 => LEGACY_BLOCK_FOOTER_SIZE,
+            1 | 2 => V1_BLOCK_FOOTER_SIZE,
+            _ => BLOCK_FOOTER_SIZE,
+        } as u32;
+        let block_end = block_offset
+            .checked_add(block_size)
+            .ok_or_else(invalid_block)?;
+        if block_size < footer_size {
+            return Err(invalid_block());
+        }
+        if footer_size == BLOCK_FOOTER_SIZE as u32 {
+            // The checksum covers the rest of the block, so check it before
+            // trusting anything in it.
+            reader.seek(SeekFrom::Start(block_offset.into()))?;
+            let checksum_offset = block_size as usize - size_of::<u32>();
+            let bytes = reader.read_u8s(checksum_offset)?;
+            if crc32c(&bytes) != reader.read_u32_le()? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "block checksum mismatch",
+                ));
+            }
+        }
+        reader.seek(SeekFrom::Start((block_end - footer_size).into()))?;
+        let (num_restarts, num_entries) = if format_version == 0 {
+            let num_entries = reader.read_u32_le()?;
+            (num_entries, num_entries)
+        } else {
+            (reader.read_u32_le()?, reader.read_u32_le()?)
+        };
+        if num_restarts as usize * size_of::<u32>() > (block_size - footer_size) as usize
+            || num_restarts > num_entries
+            || (num_restarts == 0) != (num_entries == 0)
+        {
+            return Err(invalid_block());
+        }
 
-        let mut entry_offsets = Vec::<u32>::with_capacity(num_entries as usize);
-        reader.seek(SeekFrom::Start(block_offset as u64))?;
+        // The restart offsets are stored right before the footer.
+        let data_size = block_size - footer_size - num_restarts * size_of::<u32>() as u32;
+        let mut restart_offsets = Vec::<u32>::with_capacity(num_restarts as usize);
+        reader.seek(SeekFrom::Start((block_offset + data_size).into()))?;
         // TODO:  reduce to just 1 read() using read_vectored() or something custom
-        for _ in 0..num_entries {
-            let entry_offset = reader.read_u32_le()?;
-            entry_offsets.push(entry_offset);
+        for _ in 0..num_restarts {
+            let restart_offset = reader.read_u32_le()?;
+            if restart_offset >= data_size {
+                return Err(invalid_block());
+            }
+            restart_offsets.push(restart_offset);
         }
 
-        return Ok(Self {
+        Ok(Self {
             reader,
+            format_version,
             block_offset,
             num_entries,
-            entry_offsets,
-        });
+            restart_offsets,
+            data_size,
+        })
     }
 
+    #[cfg(test)]
     pub fn get(&mut self, key: &str) -> Result<Option<EntryValue>, SSTableError> {
-        self.reader
-            .seek(SeekFrom::Start(self.block_offset as u64))?;
+        Ok(self.find(key, false)?.map(|(entry, _)| entry))
+    }
 
-        // TODO: do a binary search instead
-        for _ in 0..self.num_entries {
-            let (entry_key, entry_val) = self.read_entry()?;
-            if key == entry_key {
-                return Ok(Some(entry_val));
+    // Returns the entry of `key` and the size of its value; with `skip_value`,
+    // values stored in the block come back empty.
+    pub fn find(
+        &mut self,
+        key: &str,
+        skip_value: bool,
+    ) -> Result<Option<(EntryValue, u64)>, SSTableError> {
+        // Restart points are sorted by key, so binary search for the last one which
+        // isn't past `key`.
+        let (mut low, mut high) = (0, self.restart_offsets.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            self.seek_to_restart(mid)?;
+            let (restart_key, _) = self.read_entry("", true)?;
+            if restart_key.as_str() <= key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            return Ok(None);
+        }
+
+        // Then scan the entries up to the next restart point.
+        let end = self
+            .restart_offsets
+            .get(low)
+            .copied()
+            .unwrap_or(self.data_size);
+        self.seek_to_restart(low - 1)?;
+        let mut previous_key = String::new();
+        while self.position()? < end {
+            let (entry_key, entry_val, val_len) =
+                self.read_entry_with_len(&previous_key, skip_value)?;
+            match entry_key.as_str().cmp(key) {
+                std::cmp::Ordering::Equal => {
+                    let size = match &entry_val {
+                        EntryValue::Blob(blob_ref) => blob_ref.size,
+                        EntryValue::Deleted => 0,
+                        EntryValue::Present(_) => val_len as u64,
+                    };
+                    return Ok(Some((entry_val, size)));
+                }
+                std::cmp::Ordering::Less => previous_key = entry_key,
+                std::cmp::Ordering::Greater => break,
             }
         }
 
         Ok(None)
     }
 
-    fn read_entry(&mut self) -> Result<(Key, EntryValue), SSTableError> {
-        let key_len = self.reader.read_u32_le()? as usize;
+    // Reads all the entries in the block, in order.
+    pub fn read_all(&mut self) -> Result<Vec<(Key, EntryValue)>, SSTableError> {
+        self.read_entries(false)
+    }
+
+    // Like `read_all()`; with `keys_only`, values are skipped over and come back empty.
+    pub fn read_entries(
+        &mut self,
+        keys_only: bool,
+    ) -> Result<Vec<(Key, EntryValue)>, SSTableError> {
+        self.reader
+            .seek(SeekFrom::Start(self.block_offset as u64))?;
+        let mut entries: Vec<(Key, EntryValue)> = Vec::with_capacity(self.num_entries as usize);
+        for _ in 0..self.num_entries {
+            let previous_key = entries.last().map_or("", |(key, _)| key.as_str());
+            let entry = self.read_entry(previous_key, keys_only)?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    // Reads the first entry in the block.
+    pub fn read_first_entry(&mut self) -> Result<(Key, EntryValue), SSTableError> {
+        if self.num_entries == 0 {
+            return Err(SSTableError::Corrupt("block is empty"));
+        }
+        self.seek_to_restart(0)?;
+        self.read_entry("", false)
+    }
+
+    fn seek_to_restart(&mut self, i: usize) -> Result<(), std::io::Error> {
+        self.reader.seek(SeekFrom::Start(
+            (self.block_offset + self.restart_offsets[i]).into(),
+        ))?;
+        Ok(())
+    }
+
+    // Returns the position of the reader, relative to the block.
+    fn position(&mut self) -> Result<u32, std::io::Error> {
+        Ok((self.reader.stream_position()? - self.block_offset as u64) as u32)
+    }
+
+    // Reads the entry at the reader's position, whose key may share a prefix with
+    // `previous_key`.
+    fn read_entry(
+        &mut self,
+        previous_key: &str,
+        skip_value: bool,
+    ) -> Result<(Key, EntryValue), SSTableError> {
+        let (key, entry, _) = self.read_entry_with_len(previous_key, skip_value)?;
+        Ok((key, entry))
+    }
+
+    // Like `read_entry()`, but also returns the length of the value as stored.
+    fn read_entry_with_len(
+        &mut self,
+        previous_key: &str,
+        skip_value: bool,
+    ) -> Result<(Key, EntryValue, usize), SSTableError> {
+        let shared_len = if self.format_version == 0 {
+            0
+        } else {
+            self.reader.read_u32_le()? as usize
+        };
+        let unshared_len = self.reader.read_u32_le()? as usize;
         let val_len = self.reader.read_u32_le()? as usize;
-        let key = self.reader.read_u8s(key_len)?;
+        // Every entry fits in its block; anything bigger is a damaged sstable.
+        if shared_len > previous_key.len()
+            || unshared_len.saturating_add(val_len) > self.data_size as usize
+        {
+            return Err(SSTableError::Corrupt("entry is bigger than a block"));
+        }
+        let mut key = previous_key.as_bytes()[..shared_len].to_vec();
+        key.extend(self.reader.read_u8s(unshared_len)?);
         let is_present = self.reader.read_u8()?;
         Ok((
             String::from_utf8(key)?,
             match is_present {
                 0 => EntryValue::Deleted,
+                1 if skip_value => {
+                    self.reader.seek(SeekFrom::Current(val_len as i64))?;
+                    EntryValue::Present(Vec::new())
+                }
                 1 => {
                     let val = self.reader.read_u8s(val_len)?;
                     EntryValue::Present(val)
                 }
+                2 => {
+                    let val = self.reader.read_u8s(val_len)?;
+                    EntryValue::Blob(
+                        BlobRef::decode(&val)
+                            .ok_or(SSTableError::Corrupt("invalid blob reference"))?,
+                    )
+                }
                 _ => {
-                    return Err(SSTableError::Custom("invalid isPresent"));
+                    return Err(SSTableError::Corrupt("invalid isPresent"));
                 }
             },
+            val_len,
         ))
     }
 }
@@ -387,10 +1677,11 @@ impl<'r, T: Read + Seek + 'r> BlockReader<'r, T> {
 #[cfg(test)]
 mod test {
     use crate::{
-        db::{DBConfig, DB},
+        db::{DBConfig, RawDB},
         sstable::*,
+        storage::FileStorage,
     };
-    use std::io::Cursor;
+    use std::{io::Cursor, path::PathBuf};
     use tempdir::TempDir;
 
     #[test]
@@ -400,11 +1691,12 @@ mod test {
         writer
             .add_to_block("/user/vardhan", &EntryValue::Present(b"vardhan".to_vec()))
             .expect("write to block");
-        let (bytes_written, last_key) = writer.flush(&mut buffer).expect("write to buffer");
-        assert_eq!(
-            buffer,
-            b"\x0d\x00\x00\x00\x07\x00\x00\x00/user/vardhan\x01vardhan\x00\x00\x00\x00\x01\x00\x00\x00"
-        );
+        let (bytes_written, last_key) = writer
+            .flush(&mut buffer, SSTABLE_FORMAT_VERSION)
+            .expect("write to buffer");
+        let block = b"\x00\x00\x00\x00\x0d\x00\x00\x00\x07\x00\x00\x00/user/vardhan\x01vardhan\x00\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00";
+        assert_eq!(buffer[..block.len()], block[..]);
+        assert_eq!(buffer[block.len()..], crc32c(block).to_le_bytes());
         assert_eq!(last_key, "/user/vardhan");
         assert_eq!(bytes_written, buffer.len());
     }
@@ -426,10 +1718,13 @@ mod test {
             .add_to_block("/key4", &EntryValue::Present(vec![7, 8, 9]))
             .expect("cant put /key4");
 
-        let (size, last_key) = writer.flush(&mut mem).expect("could not flush");
+        let (size, last_key) = writer
+            .flush(&mut mem, SSTABLE_FORMAT_VERSION)
+            .expect("could not flush");
         assert_eq!(last_key, "/key4");
 
-        let mut reader = BlockReader::new(&mut mem, 0, size as u32).expect("couldnt make reader");
+        let mut reader = BlockReader::new(&mut mem, 0, size as u32, SSTABLE_FORMAT_VERSION)
+            .expect("couldnt make reader");
         assert_eq!(
             reader.get("/key1").expect("cant find /key1"),
             Some(EntryValue::Present(vec![1, 2, 3]))
@@ -452,7 +1747,7 @@ mod test {
     #[test]
     fn block_overflow() {
         let mut writer = BlockWriter::new();
-        for i in 0..68 {
+        for i in 0..89 {
             assert_eq!(
                 writer
                     .add_to_block(
@@ -474,9 +1769,10 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_conversion)]
     fn write_to_sstable() {
         let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
-        let mut db = DB::open_with_config(
+        let mut db = RawDB::open_with_config(
             tempdir.path(),
             DBConfig {
                 // don't auto-write to sstable; this test triggers that manually
@@ -494,7 +1790,7 @@ mod test {
         };
 
         // generate <1 MB of key/value pairs.
-        let num_keys_to_generate = 7000u32; // from experimenting, this generates < 1MB
+        let num_keys_to_generate = 5000u32; // from experimenting, this generates < 1MB
         for i in 0..num_keys_to_generate {
             db.put(format!("/user/b_{i}", i = i), fn_generate_val_for_key(i))
                 .expect("could not put");
@@ -510,11 +1806,13 @@ mod test {
             .expect("couldnt read temp dir")
             .map(|dirent| dirent.unwrap().path())
             .into_iter()
+            .filter(|path| path.extension().is_some_and(|extension| extension == "sst"))
             .collect();
         assert!(all_sstable_paths.len() == 1);
         for path in all_sstable_paths {
             // let mut file = std::fs::File::open(path.clone()).expect("couldnt open file");
-            let mut sstable = SSTableReader::from_path(&path).expect("couldnt make sstable");
+            let mut sstable =
+                SSTableReader::from_path(&FileStorage, &path).expect("couldnt make sstable");
             for i in 0..num_keys_to_generate {
                 // this key should exist
                 assert_eq!(
@@ -539,4 +1837,298 @@ mod test {
             assert_eq!(sstable.get("/user/a").expect("couldnt get unknown"), None);
         }
     }
+
+    #[test]
+    fn entries_bigger_than_a_block() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let config = || DBConfig {
+            // Keep big values in the sstables.
+            min_blob_size: usize::MAX,
+            ..DBConfig::default()
+        };
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        let big_value = vec![7u8; 10 * 1024];
+        db.put("/a", "small")?;
+        db.put("/b", big_value.clone())?;
+        db.put("/c", "small")?;
+        db.put("/d", big_value.clone())?;
+        db.flush()?;
+        db.compact()?;
+        std::mem::drop(db);
+
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        assert_eq!(db.get("/a")?, Some(b"small".to_vec()));
+        assert_eq!(db.get("/b")?, Some(big_value.clone()));
+        assert_eq!(db.get("/d")?, Some(big_value));
+        assert_eq!(db.iter_pinned("/").count(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn partitioned_index() -> anyhow::Result<()> {
+        let key = |i: u32| format!("/user/{:05}", i);
+        let mut writer = SSTableWriter::new(Vec::new());
+        for i in 0..20000 {
+            writer.add(&key(i), &EntryValue::Present(vec![0u8; 64]))?;
+        }
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let path = tempdir.path().join("0.sst");
+        std::fs::write(&path, writer.finish()?)?;
+
+        let mut sstable = SSTableReader::from_path(&FileStorage, &path)?;
+        assert!(sstable.index.len() > 1);
+        // Only the first partition is read when the sstable is opened.
+        assert!(sstable.index[1].blocks.get().is_none());
+        assert_eq!(sstable.key_range(), Some(("/user/00000", "/user/19999")));
+        assert_eq!(sstable.num_entries(), 20000);
+        for i in (0..20000).step_by(7) {
+            assert_eq!(
+                sstable.get(&key(i))?,
+                Some(EntryValue::Present(vec![0u8; 64]))
+            );
+            assert_eq!(sstable.get(&format!("{}_", key(i)))?, None);
+        }
+        assert_eq!(sstable.get("/user/2")?, None);
+        sstable.verify()?;
+
+        let data_size: u64 = sstable
+            .index
+            .iter()
+            .map(|partition| partition.data_size)
+            .sum();
+        assert_eq!(sstable.approximate_size("/user/", "/user0"), data_size);
+        let half_size = sstable.approximate_size("/user/10000", "/user0");
+        assert!(half_size > data_size * 2 / 5 && half_size < data_size * 3 / 5);
+
+        let keys: Vec<Key> = sstable
+            .clone()
+            .into_iter_from(&key(15000), true)
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_, _>>()?;
+        assert!(keys.len() >= 5000 && keys.len() < 5100);
+        assert_eq!(keys.last(), Some(&key(19999)));
+        Ok(())
+    }
+
+    #[test]
+    fn sstable_format_versions() -> anyhow::Result<()> {
+        // A version 0 sstable: one block with full keys and the offset of every
+        // entry, then the index, no range tombstones and the footer.
+        let mut legacy = Vec::new();
+        legacy.extend(b"\x02\x00\x00\x00\x01\x00\x00\x00/a\x011");
+        legacy.extend(b"\x02\x00\x00\x00\x00\x00\x00\x00/b\x00");
+        legacy.extend(b"\x00\x00\x00\x00\x0c\x00\x00\x00\x02\x00\x00\x00");
+        legacy.extend(b"\x23\x00\x00\x00\x02\x00\x00\x00\x02\x00\x00\x00/b");
+        legacy.extend(b"\x0e\x00\x00\x00\x00\x00\x00\x00");
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let path = tempdir.path().join("0.sst");
+        std::fs::write(&path, &legacy)?;
+        let mut sstable = SSTableReader::from_path(&FileStorage, &path)?;
+        assert_eq!(sstable.format_version, 0);
+        assert_eq!(sstable.get("/a")?, Some(EntryValue::Present(b"1".to_vec())));
+        assert_eq!(sstable.get("/b")?, Some(EntryValue::Deleted));
+        assert_eq!(sstable.get("/c")?, None);
+        sstable.verify()?;
+
+        // The same block, from before range tombstones: the index doesn't have
+        // the number of entries, and the footer is only the index size.
+        let mut baseline = legacy[..0x23].to_vec();
+        baseline.extend(b"\x23\x00\x00\x00\x02\x00\x00\x00/b");
+        baseline.extend(b"\x0a\x00\x00\x00");
+        std::fs::write(&path, &baseline)?;
+        let mut sstable = SSTableReader::from_path(&FileStorage, &path)?;
+        assert_eq!(sstable.format_version, 0);
+        assert_eq!(sstable.num_entries(), 2);
+        assert_eq!(sstable.get("/a")?, Some(EntryValue::Present(b"1".to_vec())));
+        assert_eq!(sstable.get("/b")?, Some(EntryValue::Deleted));
+        sstable.verify()?;
+        // Databases of those sstables have no MANIFEST either.
+        let mut db = RawDB::open(tempdir.path())?;
+        assert_eq!(db.get("/a")?, Some(b"1".to_vec()));
+        assert_eq!(db.get("/b")?, None);
+        std::mem::drop(db);
+
+        // Sstables from a newer version are refused.
+        let mut writer = SSTableWriter::new(Vec::new());
+        writer.add("/a", &EntryValue::Deleted)?;
+        let mut bytes = writer.finish()?;
+        let version_offset = bytes.len() - 2 * size_of::<u32>();
+        bytes[version_offset..][..4].copy_from_slice(&(SSTABLE_FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, &bytes)?;
+        assert!(matches!(
+            SSTableReader::from_path(&FileStorage, &path),
+            Err(SSTableError::UnsupportedVersion { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_sstables() -> anyhow::Result<()> {
+        let mut writer = SSTableWriter::new(Vec::new());
+        writer.add("/a", &EntryValue::Present(b"1".to_vec()))?;
+        let bytes = writer.finish()?;
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let path = tempdir.path().join("0.sst");
+        let footer_offset = bytes.len() - SSTABLE_FOOTER_SIZE;
+        let footer_u32 = |i: usize| {
+            u32::from_le_bytes(bytes[footer_offset + 4 * i..][..4].try_into().unwrap()) as usize
+        };
+        let index_start =
+            footer_offset - footer_u32(0) - footer_u32(1) - footer_u32(2) - footer_u32(3);
+
+        // Sizes which overflow or run past the file are refused, rather than
+        // allocated or read.
+        let corrupt = |offset: usize, value: u32| -> anyhow::Result<SSTableError> {
+            let mut bytes = bytes.clone();
+            bytes[offset..][..4].copy_from_slice(&value.to_le_bytes());
+            std::fs::write(&path, &bytes)?;
+            Ok(SSTableReader::from_path(&FileStorage, &path)
+                .err()
+                .expect("corrupt sstable was opened"))
+        };
+        for (offset, value) in [
+            (footer_offset, u32::MAX),
+            (footer_offset + 4, u32::MAX),
+            // The last key's length in the top-level index.
+            (index_start + 24, u32::MAX),
+            // The index partition's offset.
+            (index_start, u32::MAX - 1),
+        ] {
+            assert!(matches!(corrupt(offset, value)?, SSTableError::Corrupt(_)));
+        }
+
+        // A damaged value is caught by the block's checksum.
+        let mut damaged = bytes.clone();
+        // After the entry's 3 lengths, its key and its indicator.
+        damaged[12 + 2 + 1] = b'2';
+        std::fs::write(&path, &damaged)?;
+        let err = SSTableReader::from_path(&FileStorage, &path)
+            .and_then(|mut sstable| sstable.get("/a"))
+            .expect_err("damaged value was read");
+        assert!(err.to_string().contains("checksum"));
+        Ok(())
+    }
+
+    #[test]
+    fn sstable_properties() -> anyhow::Result<()> {
+        let mut writer = SSTableWriter::new(Vec::new()).without_block_checksums();
+        writer.add("/a", &EntryValue::Present(b"1234".to_vec()))?;
+        writer.add("/b", &EntryValue::Deleted)?;
+        writer.add("/c", &EntryValue::Present(b"12".to_vec()))?;
+        let bytes = writer.finish()?;
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let path = tempdir.path().join("0.sst");
+        std::fs::write(&path, &bytes)?;
+        let sstable = SSTableReader::from_path(&FileStorage, &path)?;
+        assert_eq!(sstable.format_version, 2);
+        let properties = sstable.table_properties(1);
+        assert_eq!(properties.level, 1);
+        assert_eq!(properties.num_entries, 3);
+        assert_eq!(properties.num_deletions, Some(1));
+        // The deleted key, and the average of the 2 values.
+        assert_eq!(properties.deleted_bytes, Some(2 + 3));
+        assert_eq!(sstable.num_deletions(), 1);
+
+        // A version 1 sstable is the same without the properties.
+        let footer = &bytes[bytes.len() - SSTABLE_FOOTER_SIZE..];
+        let properties_size = u32::from_le_bytes(footer[12..16].try_into()?) as usize;
+        let mut v1 = bytes[..bytes.len() - SSTABLE_FOOTER_SIZE - properties_size].to_vec();
+        v1.extend(&footer[..12]);
+        v1.extend(1u32.to_le_bytes());
+        v1.extend(SSTABLE_MAGIC.to_le_bytes());
+        std::fs::write(&path, &v1)?;
+        let mut sstable = SSTableReader::from_path(&FileStorage, &path)?;
+        assert_eq!(sstable.format_version, 1);
+        assert_eq!(sstable.table_properties(1).num_deletions, None);
+        assert_eq!(sstable.num_deletions(), 1);
+        assert_eq!(
+            sstable.get("/c")?,
+            Some(EntryValue::Present(b"12".to_vec()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sstable_with_only_range_tombstones() {
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let path = tempdir.path().join("0.sst");
+        let mut memtable = Memtable::default();
+        memtable.add_range_tombstone(RangeTombstone {
+            start: "/a".to_string(),
+            end: "/c".to_string(),
+        });
+        let mut file = File::create(&path).expect("couldnt create sstable");
+        write_memtable_to_sstable(&memtable, &mut file, None, None, &[])
+            .expect("couldnt write sstable");
+        std::mem::drop(file);
+
+        let mut sstable =
+            SSTableReader::from_path(&FileStorage, &path).expect("couldnt make sstable");
+        assert_eq!(sstable.get("/b").expect("couldnt get"), None);
+        assert!(sstable.is_range_deleted("/a"));
+        assert!(sstable.is_range_deleted("/b"));
+        assert!(!sstable.is_range_deleted("/c"));
+    }
+
+    #[test]
+    fn sstable_key_order() {
+        let mut writer = SSTableWriter::new(Vec::new());
+        writer
+            .add("/b", &EntryValue::Present(vec![1]))
+            .expect("couldnt add");
+        assert!(matches!(
+            writer.add("/a", &EntryValue::Deleted),
+            Err(SSTableError::KeyOrder { .. })
+        ));
+        assert!(matches!(
+            writer.add("/b", &EntryValue::Deleted),
+            Err(SSTableError::KeyOrder { .. })
+        ));
+        writer.add("/c", &EntryValue::Deleted).expect("couldnt add");
+
+        let tempdir = TempDir::new("lsmdb_test").expect("couldnt make a temp dir");
+        let path = tempdir.path().join("0.sst");
+        std::fs::write(&path, writer.finish().expect("couldnt finish")).expect("couldnt write");
+        let mut sstable =
+            SSTableReader::from_path(&FileStorage, &path).expect("couldnt make sstable");
+        sstable.verify().expect("sstable is invalid");
+    }
+
+    #[test]
+    fn sst_reader() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let mut writer = SstWriter::create(&tempdir.path().join("1.sst"))?;
+        writer.put("/a", "1")?;
+        writer.delete("/b")?;
+        writer.delete_range("/c", "/d");
+        let path = writer.finish()?;
+
+        let mut reader = SstReader::open(&path)?;
+        assert_eq!(reader.num_entries(), 2);
+        assert_eq!(
+            reader.blocks(),
+            vec![BlockInfo {
+                offset: 0,
+                // 2 entries, 1 restart point and the block footer.
+                size: (16 + 14) + 4 + 12,
+                num_entries: 2,
+                last_key: "/b".to_string(),
+            }]
+        );
+        assert!(!reader.has_prefix_filter());
+        reader.verify()?;
+        assert_eq!(reader.key_range(), Some(("/a", "/d")));
+        assert_eq!(
+            reader.range_tombstones(),
+            vec![("/c".to_string(), "/d".to_string())]
+        );
+        assert_eq!(
+            reader.entries().collect::<Result<Vec<_>, _>>()?,
+            vec![
+                ("/a".to_string(), EntryValue::Present(b"1".to_vec())),
+                ("/b".to_string(), EntryValue::Deleted),
+            ]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,237 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+// Counters which are updated as the database is used.
+//
+// A Statistics collector can be shared between several databases (see
+// DBConfig::statistics), in which case it reports their combined activity.
+#[derive(Debug, Default)]
+pub struct Statistics {
+    // Key and value bytes passed to put() and delete().
+    bytes_written: AtomicU64,
+    // Value bytes returned by get() and iterators.
+    bytes_read: AtomicU64,
+    // Bytes written to sstable and blob files when flushing memtables.
+    flush_bytes_written: AtomicU64,
+    // Number of memtables flushed to sstables.
+    num_flushes: AtomicU64,
+    // Number of compactions run.
+    num_compactions: AtomicU64,
+    // Bytes of sstables merged by compactions.
+    compaction_bytes_read: AtomicU64,
+    // Bytes of sstables written by compactions.
+    compaction_bytes_written: AtomicU64,
+    // Number of writes delayed, and refused, because flushes and compactions
+    // fell behind.
+    num_write_slowdowns: AtomicU64,
+    num_write_stalls: AtomicU64,
+    // Number of sstables which reads skipped, because their prefix filter ruled
+    // out the prefix being read.
+    num_prefix_filter_skips: AtomicU64,
+    // Number of flushes asked for by DBConfig::write_buffer_manager.
+    num_write_buffer_flushes: AtomicU64,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn flush_bytes_written(&self) -> u64 {
+        self.flush_bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub fn num_flushes(&self) -> u64 {
+        self.num_flushes.load(Ordering::Relaxed)
+    }
+
+    pub fn num_compactions(&self) -> u64 {
+        self.num_compactions.load(Ordering::Relaxed)
+    }
+
+    pub fn compaction_bytes_read(&self) -> u64 {
+        self.compaction_bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn compaction_bytes_written(&self) -> u64 {
+        self.compaction_bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub fn num_write_slowdowns(&self) -> u64 {
+        self.num_write_slowdowns.load(Ordering::Relaxed)
+    }
+
+    pub fn num_write_stalls(&self) -> u64 {
+        self.num_write_stalls.load(Ordering::Relaxed)
+    }
+
+    pub fn num_prefix_filter_skips(&self) -> u64 {
+        self.num_prefix_filter_skips.load(Ordering::Relaxed)
+    }
+
+    pub fn num_write_buffer_flushes(&self) -> u64 {
+        self.num_write_buffer_flushes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_write(&self, num_bytes: usize) {
+        self.bytes_written
+            .fetch_add(num_bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_read(&self, num_bytes: usize) {
+        self.bytes_read
+            .fetch_add(num_bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_flush(&self, num_bytes: u64) {
+        self.flush_bytes_written
+            .fetch_add(num_bytes, Ordering::Relaxed);
+        self.num_flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_compaction(&self, bytes_read: u64, bytes_written: u64) {
+        self.num_compactions.fetch_add(1, Ordering::Relaxed);
+        self.compaction_bytes_read
+            .fetch_add(bytes_read, Ordering::Relaxed);
+        self.compaction_bytes_written
+            .fetch_add(bytes_written, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_write_slowdown(&self) {
+        self.num_write_slowdowns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_write_stall(&self) {
+        self.num_write_stalls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_prefix_filter_skip(&self) {
+        self.num_prefix_filter_skips.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_write_buffer_flush(&self) {
+        self.num_write_buffer_flushes
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// A point-in-time report of the database's state and activity, see RawDB::stats().
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DBStats {
+    // Number of bytes taken up by the active memtable.
+    pub active_memtable_size_bytes: usize,
+    pub num_frozen_memtables: usize,
+    // Number of sstables in each level; the first element is level 0.
+    pub num_sstables_per_level: Vec<usize>,
+
+    // The counters below are copied from the database's Statistics.
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+    pub flush_bytes_written: u64,
+    pub num_flushes: u64,
+    pub num_compactions: u64,
+    pub compaction_bytes_read: u64,
+    pub compaction_bytes_written: u64,
+    pub num_write_slowdowns: u64,
+    pub num_write_stalls: u64,
+    pub num_prefix_filter_skips: u64,
+    pub num_write_buffer_flushes: u64,
+}
+
+// Where the database's disk space goes, see RawDB::space_report().
+//
+// The data sizes count key and value bytes, before the sstable's encoding.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpaceReport {
+    // Size of the sstable files making up the database.
+    pub sstable_file_bytes: u64,
+    // Size of the blob files holding large values.
+    pub blob_file_bytes: u64,
+    // Size of the memtables, which aren't on disk yet.
+    pub memtable_bytes: u64,
+    // Keys and values which reads can return.
+    pub live_data_bytes: u64,
+    // Keys and values which were overwritten or deleted, and are waiting for
+    // compaction to drop them.
+    pub obsolete_data_bytes: u64,
+    // Deleted keys and range tombstones.
+    pub tombstone_bytes: u64,
+    // Files in the database's directory which it doesn't use, e.g. sstables left
+    // behind by a crash; they are deleted the next time the database is opened.
+    pub trash_bytes: u64,
+}
+
+// Databases opened with a name (see DBConfig::name), along with their statistics,
+// so that an application embedding several databases can report on each of them.
+static REGISTRY: Mutex<Vec<(u64, String, Arc<Statistics>)>> = Mutex::new(Vec::new());
+static NEXT_REGISTRATION_ID: AtomicU64 = AtomicU64::new(0);
+
+// Returns the name and statistics of every open database which has a name.
+// Several databases may share a name.
+pub fn registered_statistics() -> Vec<(String, Arc<Statistics>)> {
+    REGISTRY
+        .lock()
+        .expect("statistics registry lock is poisoned")
+        .iter()
+        .map(|(_, name, statistics)| (name.clone(), statistics.clone()))
+        .collect()
+}
+
+// Keeps a database in the registry until dropped.
+pub(crate) struct Registration {
+    id: u64,
+}
+
+impl Registration {
+    pub fn new(name: String, statistics: Arc<Statistics>) -> Self {
+        let id = NEXT_REGISTRATION_ID.fetch_add(1, Ordering::Relaxed);
+        REGISTRY
+            .lock()
+            .expect("statistics registry lock is poisoned")
+            .push((id, name, statistics));
+        Registration { id }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = REGISTRY.lock() {
+            registry.retain(|(id, _, _)| *id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_registered(name: &str) -> bool {
+        registered_statistics()
+            .iter()
+            .any(|(registered_name, _)| registered_name == name)
+    }
+
+    #[test]
+    fn registry() {
+        let statistics = Arc::new(Statistics::new());
+        statistics.record_write(3);
+        let registration = Registration::new("registry-test".to_string(), statistics);
+        assert!(is_registered("registry-test"));
+        assert!(registered_statistics()
+            .iter()
+            .any(|(name, statistics)| name == "registry-test" && statistics.bytes_written() == 3));
+
+        std::mem::drop(registration);
+        assert!(!is_registered("registry-test"));
+    }
+}
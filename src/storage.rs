@@ -0,0 +1,653 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// Storage
+// =======
+//
+// All of the database's file I/O goes through a Storage (see DBConfig::storage),
+// so it can run on something other than the local filesystem, e.g. MemStorage in
+// tests.
+//
+// Files are written once, front to back, and never change after they're synced;
+// the MANIFEST is replaced by renaming a new file over it.
+pub trait Storage: Send + Sync {
+    // Creates a file to write, truncating it if it exists.
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>, std::io::Error>;
+
+    fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>, std::io::Error>;
+
+    // Returns true if `path` is a file or a directory.
+    fn exists(&self, path: &Path) -> Result<bool, std::io::Error>;
+
+    fn file_size(&self, path: &Path) -> Result<u64, std::io::Error>;
+
+    // Renames `from` to `to`, replacing `to` if it exists.  The MANIFEST relies on
+    // this being atomic.
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), std::io::Error>;
+
+    fn remove_file(&self, path: &Path) -> Result<(), std::io::Error>;
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), std::io::Error>;
+
+    // Makes the directory's entries durable: the files created, renamed and removed
+    // in it so far.  Does nothing by default, for storages where they already are.
+    fn sync_dir(&self, _path: &Path) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    // Returns the paths of the directory's entries; fails if `path` isn't a directory.
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, std::io::Error>;
+
+    // Copies `from` to `to`, replacing `to`.  Implementations may share the data
+    // instead, e.g. by hard linking, since files never change.
+    fn link_or_copy(&self, from: &Path, to: &Path) -> Result<(), std::io::Error> {
+        let source = self.open(from)?;
+        let mut destination = self.create(to)?;
+        std::io::copy(
+            &mut RandomAccessReader::new(source.into())?,
+            &mut destination,
+        )?;
+        destination.sync()
+    }
+
+    // Drops the file's data from any cache the storage keeps, e.g. the page cache,
+    // since it won't be read again soon.  Unsynced data may stay cached.  Does
+    // nothing by default.
+    fn drop_from_cache(&self, _path: &Path) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    // Copies `local_path`, a file on the local filesystem, to `to`.
+    fn copy_from_local(&self, local_path: &Path, to: &Path) -> Result<(), std::io::Error> {
+        let mut source = File::open(local_path)?;
+        let mut destination = self.create(to)?;
+        std::io::copy(&mut source, &mut destination)?;
+        destination.sync()
+    }
+}
+
+pub trait WritableFile: Write + Send {
+    // Makes everything written so far durable.
+    fn sync(&mut self) -> Result<(), std::io::Error>;
+}
+
+pub trait RandomAccessFile: Send + Sync {
+    // Reads up to `buf.len()` bytes at `offset`; returns 0 at the end of the file.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, std::io::Error>;
+
+    fn size(&self) -> Result<u64, std::io::Error>;
+}
+
+// Reads a RandomAccessFile sequentially.  Clones share the file, but not the
+// position.
+#[derive(Clone)]
+pub(crate) struct RandomAccessReader {
+    file: Arc<dyn RandomAccessFile>,
+    position: u64,
+    size: u64,
+    // With a readahead size, reads are served from `readahead`, which holds the
+    // bytes from `readahead_offset`, and refilled with that many bytes at a time.
+    readahead_size: usize,
+    readahead: Vec<u8>,
+    readahead_offset: u64,
+}
+
+impl RandomAccessReader {
+    pub fn new(file: Arc<dyn RandomAccessFile>) -> Result<Self, std::io::Error> {
+        let size = file.size()?;
+        Ok(RandomAccessReader {
+            file,
+            position: 0,
+            size,
+            readahead_size: 0,
+            readahead: Vec::new(),
+            readahead_offset: 0,
+        })
+    }
+
+    // Reads `readahead_size` bytes at a time, for fewer and bigger reads when the
+    // file is read from front to back; 0 reads only what's asked for.
+    pub fn with_readahead(mut self, readahead_size: usize) -> Self {
+        self.readahead_size = readahead_size;
+        self.readahead.clear();
+        self
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn fill_readahead(&mut self) -> std::io::Result<()> {
+        let len = (self.readahead_size as u64).min(self.size.saturating_sub(self.position));
+        self.readahead.resize(len as usize, 0);
+        self.readahead_offset = self.position;
+        let mut num_read = 0;
+        while num_read < self.readahead.len() {
+            match self.file.read_at(
+                &mut self.readahead[num_read..],
+                self.position + num_read as u64,
+            )? {
+                0 => break,
+                n => num_read += n,
+            }
+        }
+        self.readahead.truncate(num_read);
+        Ok(())
+    }
+}
+
+impl Read for RandomAccessReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.readahead_size == 0 || buf.len() >= self.readahead_size {
+            let num_read = self.file.read_at(buf, self.position)?;
+            self.position += num_read as u64;
+            return Ok(num_read);
+        }
+        let readahead_end = self.readahead_offset + self.readahead.len() as u64;
+        if self.position < self.readahead_offset || self.position >= readahead_end {
+            self.fill_readahead()?;
+        }
+        let start = (self.position - self.readahead_offset) as usize;
+        let num_read = buf.len().min(self.readahead.len().saturating_sub(start));
+        buf[..num_read].copy_from_slice(&self.readahead[start..start + num_read]);
+        self.position += num_read as u64;
+        Ok(num_read)
+    }
+}
+
+impl Seek for RandomAccessReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+// The local filesystem.  Works on Unix and Windows; where they differ:
+// - rename() replaces the file on both, but on Windows only if no one holds it
+//   open without sharing it for deletion.  Files opened by lsmdb always share it.
+// - sync_dir() syncs the directory on Unix.  Windows can't, and doesn't need to:
+//   NTFS journals directory changes itself.
+// - drop_from_cache() only does something on 64-bit Linux.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileStorage;
+
+impl Storage for FileStorage {
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>, std::io::Error> {
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>, std::io::Error> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool, std::io::Error> {
+        path.try_exists()
+    }
+
+    fn file_size(&self, path: &Path) -> Result<u64, std::io::Error> {
+        Ok(std::fs::metadata(path)?.len())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), std::io::Error> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), std::io::Error> {
+        std::fs::remove_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), std::io::Error> {
+        std::fs::DirBuilder::new().recursive(true).create(path)
+    }
+
+    #[cfg(unix)]
+    fn sync_dir(&self, path: &Path) -> Result<(), std::io::Error> {
+        File::open(path)?.sync_all()
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        path.read_dir()?.map(|dirent| Ok(dirent?.path())).collect()
+    }
+
+    // Hard links `from` to `to` if they're on the same filesystem, and copies it
+    // otherwise.  The file is synced either way.
+    fn link_or_copy(&self, from: &Path, to: &Path) -> Result<(), std::io::Error> {
+        if to.exists() {
+            std::fs::remove_file(to)?;
+        }
+        if std::fs::hard_link(from, to).is_err() {
+            std::fs::copy(from, to)?;
+        }
+        // Windows only syncs files opened for writing.
+        std::fs::OpenOptions::new().write(true).open(to)?.sync_all()
+    }
+
+    fn copy_from_local(&self, local_path: &Path, to: &Path) -> Result<(), std::io::Error> {
+        self.link_or_copy(local_path, to)
+    }
+
+    // Advises the kernel to drop the file's pages with posix_fadvise().  Only
+    // implemented on 64-bit Linux.
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    fn drop_from_cache(&self, path: &Path) -> Result<(), std::io::Error> {
+        use std::os::unix::io::AsRawFd;
+        extern "C" {
+            fn posix_fadvise(fd: i32, offset: i64, len: i64, advice: i32) -> i32;
+        }
+        const POSIX_FADV_DONTNEED: i32 = 4;
+        let file = File::open(path)?;
+        // A length of 0 covers the whole file.
+        match unsafe { posix_fadvise(file.as_raw_fd(), 0, 0, POSIX_FADV_DONTNEED) } {
+            0 => Ok(()),
+            err => Err(std::io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+impl WritableFile for File {
+    fn sync(&mut self) -> Result<(), std::io::Error> {
+        self.sync_all()
+    }
+}
+
+impl RandomAccessFile for File {
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, std::io::Error> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, std::io::Error> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+
+    fn size(&self) -> Result<u64, std::io::Error> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+// Keeps every file in memory; nothing survives the MemStorage being dropped.
+// Clones share the same files, so a database can be reopened on a clone.
+//
+// `simulate_crash()` undoes the directory changes which weren't synced, like a
+// filesystem which loses them in a crash, to test that they're synced when they
+// need to be.
+#[derive(Clone, Default)]
+pub struct MemStorage {
+    inner: Arc<Mutex<MemStorageInner>>,
+}
+
+#[derive(Default)]
+struct MemStorageInner {
+    files: BTreeMap<PathBuf, Arc<Mutex<Vec<u8>>>>,
+    dirs: BTreeSet<PathBuf>,
+    // The files as of their directory's last sync_dir().
+    synced_files: BTreeMap<PathBuf, Arc<Mutex<Vec<u8>>>>,
+}
+
+impl MemStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Forgets the files created, renamed or removed since their directory was last
+    // synced.  Their contents stay as they are.
+    pub fn simulate_crash(&self) {
+        let mut inner = self.lock();
+        inner.files = inner.synced_files.clone();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MemStorageInner> {
+        self.inner.lock().expect("MemStorage lock is poisoned")
+    }
+}
+
+fn not_found(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} not found", path.to_string_lossy()),
+    )
+}
+
+impl Storage for MemStorage {
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>, std::io::Error> {
+        let data = Arc::new(Mutex::new(Vec::new()));
+        self.lock().files.insert(path.to_path_buf(), data.clone());
+        Ok(Box::new(MemFile(data)))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>, std::io::Error> {
+        let data = self.lock().files.get(path).cloned();
+        Ok(Box::new(MemFile(data.ok_or_else(|| not_found(path))?)))
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool, std::io::Error> {
+        let inner = self.lock();
+        Ok(inner.files.contains_key(path) || inner.dirs.contains(path))
+    }
+
+    fn file_size(&self, path: &Path) -> Result<u64, std::io::Error> {
+        self.open(path)?.size()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), std::io::Error> {
+        let mut inner = self.lock();
+        let data = inner.files.remove(from).ok_or_else(|| not_found(from))?;
+        inner.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), std::io::Error> {
+        self.lock()
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut inner = self.lock();
+        for dir in path.ancestors() {
+            inner.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        inner
+            .synced_files
+            .retain(|file, _| file.parent() != Some(path));
+        for (file, data) in &inner.files {
+            if file.parent() == Some(path) {
+                inner.synced_files.insert(file.clone(), data.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        let inner = self.lock();
+        if !inner.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        Ok(inner
+            .files
+            .keys()
+            .chain(inner.dirs.iter())
+            .filter(|entry| entry.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    // Files never change, so the copy shares the data.
+    fn link_or_copy(&self, from: &Path, to: &Path) -> Result<(), std::io::Error> {
+        let mut inner = self.lock();
+        let data = inner
+            .files
+            .get(from)
+            .cloned()
+            .ok_or_else(|| not_found(from))?;
+        inner.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+}
+
+struct MemFile(Arc<Mutex<Vec<u8>>>);
+
+impl MemFile {
+    fn data(&self) -> std::sync::MutexGuard<'_, Vec<u8>> {
+        self.0.lock().expect("MemFile lock is poisoned")
+    }
+}
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for MemFile {
+    fn sync(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+impl RandomAccessFile for MemFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, std::io::Error> {
+        let data = self.data();
+        let start = (offset as usize).min(data.len());
+        let num_read = buf.len().min(data.len() - start);
+        buf[..num_read].copy_from_slice(&data[start..start + num_read]);
+        Ok(num_read)
+    }
+
+    fn size(&self) -> Result<u64, std::io::Error> {
+        Ok(self.data().len() as u64)
+    }
+}
+
+// Wraps another Storage, and fails writes on demand, to test how a database
+// handles I/O errors: e.g. that a failed flush or compaction leaves it as it was.
+//
+// While writes fail, creating, renaming and removing files fails, and so do the
+// writes and syncs of every file created through it, including those created
+// before.  Reads always go through.
+pub struct FaultInjectionStorage {
+    storage: Arc<dyn Storage>,
+    fail_writes: Arc<AtomicBool>,
+}
+
+impl FaultInjectionStorage {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        FaultInjectionStorage {
+            storage,
+            fail_writes: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn set_fail_writes(&self, fail_writes: bool) {
+        self.fail_writes.store(fail_writes, Ordering::SeqCst);
+    }
+
+    fn check_writes(&self) -> Result<(), std::io::Error> {
+        check_writes(&self.fail_writes)
+    }
+}
+
+fn check_writes(fail_writes: &AtomicBool) -> Result<(), std::io::Error> {
+    if fail_writes.load(Ordering::SeqCst) {
+        return Err(std::io::Error::other("injected write error"));
+    }
+    Ok(())
+}
+
+impl Storage for FaultInjectionStorage {
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>, std::io::Error> {
+        self.check_writes()?;
+        Ok(Box::new(FaultInjectionFile {
+            file: self.storage.create(path)?,
+            fail_writes: self.fail_writes.clone(),
+        }))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>, std::io::Error> {
+        self.storage.open(path)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool, std::io::Error> {
+        self.storage.exists(path)
+    }
+
+    fn file_size(&self, path: &Path) -> Result<u64, std::io::Error> {
+        self.storage.file_size(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), std::io::Error> {
+        self.check_writes()?;
+        self.storage.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), std::io::Error> {
+        self.check_writes()?;
+        self.storage.remove_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), std::io::Error> {
+        self.check_writes()?;
+        self.storage.create_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<(), std::io::Error> {
+        self.check_writes()?;
+        self.storage.sync_dir(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        self.storage.list_dir(path)
+    }
+}
+
+struct FaultInjectionFile {
+    file: Box<dyn WritableFile>,
+    fail_writes: Arc<AtomicBool>,
+}
+
+impl Write for FaultInjectionFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        check_writes(&self.fail_writes)?;
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        check_writes(&self.fail_writes)?;
+        self.file.flush()
+    }
+}
+
+impl WritableFile for FaultInjectionFile {
+    fn sync(&mut self) -> Result<(), std::io::Error> {
+        check_writes(&self.fail_writes)?;
+        self.file.sync()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mem_storage() {
+        let storage = MemStorage::new();
+        let root_path = Path::new("/db");
+        assert!(storage.list_dir(root_path).is_err());
+        storage.create_dir_all(root_path).expect("couldnt make dir");
+
+        let mut file = storage
+            .create(&root_path.join("a"))
+            .expect("couldnt create");
+        file.write_all(b"hello world").expect("couldnt write");
+        file.sync().expect("couldnt sync");
+        storage
+            .rename(&root_path.join("a"), &root_path.join("b"))
+            .expect("couldnt rename");
+        storage
+            .link_or_copy(&root_path.join("b"), &root_path.join("c"))
+            .expect("couldnt copy");
+        storage
+            .remove_file(&root_path.join("c"))
+            .expect("couldnt remove");
+        assert_eq!(
+            storage.list_dir(root_path).expect("couldnt list"),
+            vec![root_path.join("b")]
+        );
+        assert!(!storage.exists(&root_path.join("a")).expect("couldnt check"));
+
+        let mut reader = RandomAccessReader::new(
+            storage
+                .open(&root_path.join("b"))
+                .expect("couldnt open")
+                .into(),
+        )
+        .expect("couldnt read");
+        assert_eq!(reader.size(), 11);
+        reader.seek(SeekFrom::End(-5)).expect("couldnt seek");
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).expect("couldnt read");
+        assert_eq!(contents, "world");
+    }
+
+    #[test]
+    fn readahead() -> anyhow::Result<()> {
+        let storage = MemStorage::new();
+        let path = Path::new("/a");
+        let mut file = storage.create(path)?;
+        file.write_all(b"0123456789")?;
+        let mut reader = RandomAccessReader::new(storage.open(path)?.into())?.with_readahead(4);
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"012");
+        // Crosses into the next readahead.
+        reader.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"345");
+        reader.seek(SeekFrom::Start(1))?;
+        reader.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"123");
+        let mut rest = Vec::new();
+        reader.seek(SeekFrom::End(-3))?;
+        reader.read_to_end(&mut rest)?;
+        assert_eq!(rest, b"789");
+        Ok(())
+    }
+
+    #[test]
+    fn mem_storage_crash() -> anyhow::Result<()> {
+        let storage = MemStorage::new();
+        let root_path = Path::new("/db");
+        storage.create_dir_all(root_path)?;
+        storage.create(&root_path.join("a"))?.write_all(b"a")?;
+        storage.sync_dir(root_path)?;
+        storage.create(&root_path.join("b"))?;
+        storage.rename(&root_path.join("a"), &root_path.join("c"))?;
+        storage.simulate_crash();
+        assert_eq!(storage.list_dir(root_path)?, vec![root_path.join("a")]);
+
+        // A database only relies on the directory entries it synced.
+        let config = || crate::db::DBConfig {
+            storage: Arc::new(storage.clone()),
+            ..crate::db::DBConfig::default()
+        };
+        let mut db = crate::db::RawDB::open_with_config(root_path, config())?;
+        db.put("/a", "1")?;
+        db.flush()?;
+        std::mem::drop(db);
+        storage.simulate_crash();
+        let mut db = crate::db::RawDB::open_with_config(root_path, config())?;
+        assert_eq!(db.get("/a")?, Some(b"1".to_vec()));
+        Ok(())
+    }
+}
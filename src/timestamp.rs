@@ -0,0 +1,156 @@
+use crate::db::{DBError, Key};
+
+// User timestamps
+// ===============
+//
+// Keys written with `RawDB::put_with_ts()` keep a version for every timestamp
+// they were written at, so they can be read as they were at any timestamp.  Each
+// version is an entry of its own, stored with the data under a reserved key:
+//
+//     "\0ts/<key>\0<inverted timestamp>"
+//
+// The inverted timestamp (u64::MAX - timestamp, as 16 hex digits) sorts a key's
+// versions newest first, so a read at a timestamp seeks to it and takes the first
+// version it finds.  Keys must not contain '\0', or their versions would mix with
+// those of longer keys.
+//
+// Deleting a key at a timestamp writes a deleted version, which compactions keep
+// like any other, so that it keeps hiding older versions.  Every version is kept
+// unless `DBConfig::max_versions` or `max_version_age` is set, in which case
+// compactions into the bottommost level drop the versions past them (see
+// `VersionRetention`); `RawDB::get_versions()` returns the ones left.
+//
+// Scans of other prefixes skip the reserved keys, and keys written without a
+// timestamp are separate from those written with one.
+
+pub(crate) const TIMESTAMP_KEY_PREFIX: &str = "\0ts/";
+
+pub(crate) fn is_timestamped_key(key: &str) -> bool {
+    key.starts_with(TIMESTAMP_KEY_PREFIX)
+}
+
+// Returns true if a scan of `prefix` should skip `key`, which only timestamped
+// reads may return.
+pub(crate) fn is_hidden(key: &str, prefix: &str) -> bool {
+    is_timestamped_key(key) && !is_timestamped_key(prefix)
+}
+
+// Keys (and key prefixes) read or written with a timestamp can't contain '\0'.
+pub(crate) fn check_key(key: &str) -> Result<(), DBError> {
+    if key.contains('\0') {
+        return Err(DBError::InvalidKey(format!(
+            "{:?} contains '\\0', which keys with timestamps can't",
+            key
+        )));
+    }
+    Ok(())
+}
+
+// The prefix of the versions of the keys starting with `key_prefix`.
+pub(crate) fn versions_prefix(key_prefix: &str) -> Key {
+    format!("{}{}", TIMESTAMP_KEY_PREFIX, key_prefix)
+}
+
+// The prefix of every version of `key`.
+pub(crate) fn key_versions_prefix(key: &str) -> Key {
+    format!("{}{}\0", TIMESTAMP_KEY_PREFIX, key)
+}
+
+// The key of `key`'s version at `timestamp`.
+pub(crate) fn versioned_key(key: &str, timestamp: u64) -> Key {
+    format!("{}{:016x}", key_versions_prefix(key), u64::MAX - timestamp)
+}
+
+// Splits a versioned key back into the key and its timestamp.
+pub(crate) fn parse_versioned_key(versioned_key: &str) -> Option<(&str, u64)> {
+    let (key, inverted_timestamp) = versioned_key
+        .strip_prefix(TIMESTAMP_KEY_PREFIX)?
+        .rsplit_once('\0')?;
+    let inverted_timestamp = u64::from_str_radix(inverted_timestamp, 16).ok()?;
+    Some((key, u64::MAX - inverted_timestamp))
+}
+
+// Decides which versions a compaction keeps, given its entries in order: since a
+// key's versions are newest first, the first one seen is the newest in the
+// compaction, and the ones after it are counted and aged against it.
+pub(crate) struct VersionRetention {
+    max_versions: usize,
+    max_age: u64,
+    // The key whose versions are being seen, its newest timestamp, and how many of
+    // its versions were kept.
+    current: Option<(Key, u64, usize)>,
+}
+
+impl VersionRetention {
+    // Zero disables either limit.
+    pub(crate) fn new(max_versions: usize, max_age: u64) -> Self {
+        VersionRetention {
+            max_versions,
+            max_age,
+            current: None,
+        }
+    }
+
+    // Returns false if `key` is a version past the limits.  Keys which aren't
+    // versions are always kept.
+    pub(crate) fn keep(&mut self, key: &str) -> bool {
+        if self.max_versions == 0 && self.max_age == 0 {
+            return true;
+        }
+        let Some((key, timestamp)) = parse_versioned_key(key) else {
+            return true;
+        };
+        match &mut self.current {
+            Some((current_key, newest, kept)) if current_key == key => {
+                if (self.max_versions != 0 && *kept >= self.max_versions)
+                    || (self.max_age != 0 && *newest - timestamp > self.max_age)
+                {
+                    return false;
+                }
+                *kept += 1;
+                true
+            }
+            _ => {
+                self.current = Some((key.to_string(), timestamp, 1));
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn versioned_keys() {
+        let newer = versioned_key("/a", 20);
+        let older = versioned_key("/a", 10);
+        assert!(newer < older);
+        assert!(older < versioned_key("/a/b", 30));
+        assert!(newer.starts_with(&key_versions_prefix("/a")));
+        assert!(newer.starts_with(&versions_prefix("/")));
+        assert_eq!(parse_versioned_key(&older), Some(("/a", 10)));
+        assert_eq!(
+            parse_versioned_key(&versioned_key("/a", 0)),
+            Some(("/a", 0))
+        );
+        assert_eq!(parse_versioned_key("/a"), None);
+        assert!(is_hidden(&newer, "") && !is_hidden(&newer, TIMESTAMP_KEY_PREFIX));
+    }
+
+    #[test]
+    fn version_retention() {
+        let mut retention = VersionRetention::new(2, 0);
+        assert!(retention.keep("/a"));
+        assert!(retention.keep(&versioned_key("/a", 30)));
+        assert!(retention.keep(&versioned_key("/a", 20)));
+        assert!(!retention.keep(&versioned_key("/a", 10)));
+        assert!(retention.keep(&versioned_key("/b", 10)));
+
+        let mut retention = VersionRetention::new(0, 15);
+        assert!(retention.keep(&versioned_key("/a", 30)));
+        assert!(retention.keep(&versioned_key("/a", 15)));
+        assert!(!retention.keep(&versioned_key("/a", 14)));
+    }
+}
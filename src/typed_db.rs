@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use crate::{
+    db::{DBError, Key, ReadOptions, DB},
+    key_encoding::KeyEncoding,
+};
+
+// A typed front-end to a DB, which stores values of one type through a Codec
+// rather than as bytes.  The DB is still there for the byte API, e.g. for keys
+// written some other way.
+//
+// Keys are encoded with DBConfig::key_encoding, if any, so they're ordered by it.
+
+// Converts values to and from the bytes stored in the database.
+pub trait Codec: Send + Sync {
+    type Value;
+
+    fn encode(&self, value: &Self::Value) -> Vec<u8>;
+
+    // Returns a description of the problem if `bytes` isn't a valid encoding.
+    fn decode(&self, bytes: &[u8]) -> Result<Self::Value, String>;
+}
+
+// Stores Strings as UTF-8.
+pub struct Utf8Codec;
+
+impl Codec for Utf8Codec {
+    type Value = String;
+
+    fn encode(&self, value: &String) -> Vec<u8> {
+        value.as_bytes().to_vec()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, String> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| err.to_string())
+    }
+}
+
+// A key and its value; None if it was deleted.
+pub type TypedEntry<C> = (Key, Option<<C as Codec>::Value>);
+
+pub struct TypedDB<C: Codec> {
+    db: DB,
+    codec: C,
+    key_encoding: Option<Arc<dyn KeyEncoding<Key = String>>>,
+}
+
+impl<C: Codec> TypedDB<C> {
+    pub fn new(db: DB, codec: C) -> Self {
+        let key_encoding = db.lock().key_encoding();
+        TypedDB {
+            db,
+            codec,
+            key_encoding,
+        }
+    }
+
+    // The database, for the byte API.
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> DB {
+        self.db
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<C::Value>, DBError> {
+        self.db
+            .get(&self.encode_key(key.to_string()))?
+            .map(|bytes| self.decode(key, &bytes))
+            .transpose()
+    }
+
+    pub fn put(&self, key: impl Into<Key>, value: &C::Value) -> Result<(), DBError> {
+        self.db
+            .put(self.encode_key(key.into()), self.codec.encode(value))
+    }
+
+    pub fn delete(&self, key: impl Into<Key>) -> Result<(), DBError> {
+        self.db.delete(self.encode_key(key.into()))
+    }
+
+    // See `RawDB::scan_prefix()`.  The prefix is encoded like a key, which only
+    // finds the keys starting with it if the encoding keeps prefixes, as
+    // CaseInsensitiveKey does; `options.start_after` is encoded too.
+    pub fn scan_prefix(
+        &self,
+        key_prefix: &str,
+        options: &ReadOptions,
+    ) -> Result<Vec<TypedEntry<C>>, DBError> {
+        let mut options = options.clone();
+        options.start_after = options.start_after.map(|key| self.encode_key(key));
+        self.db
+            .scan_prefix(&self.encode_key(key_prefix.to_string()), &options)?
+            .into_iter()
+            .map(|(key, bytes)| {
+                let key = self.decode_key(key)?;
+                let value = bytes.map(|bytes| self.decode(&key, &bytes)).transpose()?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn encode_key(&self, key: Key) -> Key {
+        match &self.key_encoding {
+            Some(key_encoding) => key_encoding.encode_key(&key),
+            None => key,
+        }
+    }
+
+    fn decode_key(&self, key: Key) -> Result<Key, DBError> {
+        match &self.key_encoding {
+            Some(key_encoding) => key_encoding
+                .decode_key(&key)
+                .map_err(|reason| DBError::InvalidKey(format!("{}: {}", key, reason))),
+            None => Ok(key),
+        }
+    }
+
+    fn decode(&self, key: &str, bytes: &[u8]) -> Result<C::Value, DBError> {
+        self.codec
+            .decode(bytes)
+            .map_err(|reason| DBError::InvalidValue(format!("{}: {}", key, reason)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{db::DBConfig, key_encoding::CaseInsensitiveKey};
+
+    // Stores points as two little-endian i32s.
+    struct PointCodec;
+
+    impl Codec for PointCodec {
+        type Value = (i32, i32);
+
+        fn encode(&self, &(x, y): &(i32, i32)) -> Vec<u8> {
+            [x.to_le_bytes(), y.to_le_bytes()].concat()
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<(i32, i32), String> {
+            let bytes: [u8; 8] = bytes
+                .try_into()
+                .map_err(|_| format!("expected 8 bytes, got {}", bytes.len()))?;
+            let (x, y) = bytes.split_at(4);
+            Ok((
+                i32::from_le_bytes(x.try_into().unwrap()),
+                i32::from_le_bytes(y.try_into().unwrap()),
+            ))
+        }
+    }
+
+    #[test]
+    fn typed_db() -> anyhow::Result<()> {
+        let db = TypedDB::new(DB::open_in_memory()?, PointCodec);
+        db.put("/a", &(1, -2))?;
+        db.put("/b", &(3, 4))?;
+        db.delete("/b")?;
+        assert_eq!(db.get("/a")?, Some((1, -2)));
+        assert_eq!(db.get("/b")?, None);
+        let options = ReadOptions {
+            include_deleted_markers: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            db.scan_prefix("/", &options)?,
+            vec![("/a".to_string(), Some((1, -2))), ("/b".to_string(), None)]
+        );
+
+        // Values written through the byte API which don't decode are errors.
+        db.db().put("/c", "abc")?;
+        assert!(matches!(db.get("/c"), Err(DBError::InvalidValue(_))));
+
+        let strings = TypedDB::new(db.into_inner(), Utf8Codec);
+        assert_eq!(strings.get("/c")?, Some("abc".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn key_encoding() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            key_encoding: Some(Arc::new(CaseInsensitiveKey)),
+            ..DBConfig::default()
+        })?;
+        let db = TypedDB::new(db, Utf8Codec);
+        db.put("/Bob", &"bob".to_string())?;
+        db.put("/al", &"al".to_string())?;
+        db.put("/Carol", &"carol".to_string())?;
+        db.put("/AL", &"AL".to_string())?;
+        assert_eq!(db.get("/BOB")?, Some("bob".to_string()));
+        db.db().flush()?;
+
+        // Scans and the byte API see the keys in case-insensitive order.
+        let keys = |entries: Vec<TypedEntry<Utf8Codec>>| -> Vec<Key> {
+            entries.into_iter().map(|(key, _)| key).collect()
+        };
+        assert_eq!(
+            keys(db.scan_prefix("/", &ReadOptions::default())?),
+            vec!["/al", "/bob", "/carol"]
+        );
+        assert_eq!(
+            keys(db.scan_prefix("/B", &ReadOptions::default().with_start_after("/AL"))?),
+            vec!["/bob"]
+        );
+        assert_eq!(db.db().get("/al")?, Some(b"AL".to_vec()));
+        db.delete("/CAROL")?;
+        assert_eq!(db.get("/carol")?, None);
+        Ok(())
+    }
+}
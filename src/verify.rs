@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    blob,
+    db::{DBError, EntryValue, Key},
+    manifest::{self, Manifest},
+    sstable::SSTableReader,
+    storage::Storage,
+};
+
+// Integrity checks
+// ================
+//
+// `RawDB::verify_integrity()` reads every file the MANIFEST lists, and reports
+// every problem it finds rather than stopping at the first one:
+//
+// - Every sstable is read in full by SSTableReader::verify(), which checks that
+//   its keys are in ascending order and match its index.  Entries don't have
+//   checksums yet, so damaged values go unnoticed.
+// - The sstables of level 1 and deeper must be ordered by key, without
+//   overlapping.
+// - Blob references must point inside a blob file the MANIFEST lists.
+//
+// There's no log yet, so there are no log segments to check.
+
+// A problem found by `RawDB::verify_integrity()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntegrityProblem {
+    // A file the MANIFEST lists doesn't exist.
+    MissingFile(PathBuf),
+    // An sstable couldn't be opened, or failed SSTableReader::verify().
+    DamagedSSTable {
+        path: PathBuf,
+        error: String,
+    },
+    // Two neighbouring sstables of a level past 0 overlap, or are out of order.
+    OverlappingSSTables {
+        level: usize,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    // The sstable's entry for `key` refers to a blob file the MANIFEST doesn't
+    // list, or to a value past its end.  Only the first one of each sstable is
+    // reported.
+    DanglingBlobRef {
+        sstable: PathBuf,
+        key: Key,
+    },
+}
+
+// What `RawDB::verify_integrity()` found.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub num_sstables_checked: usize,
+    pub num_blob_files_checked: usize,
+    pub problems: Vec<IntegrityProblem>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+pub(crate) fn verify_database(
+    storage: &dyn Storage,
+    root_path: &Path,
+    manifest: &Manifest,
+) -> Result<IntegrityReport, DBError> {
+    let mut report = IntegrityReport::default();
+    let io_error = |io_err: std::io::Error| DBError::Io(io_err.to_string());
+
+    // The size of each blob file, or None if it's missing.
+    let mut blob_file_sizes = HashMap::new();
+    for &file_number in &manifest.blob_files {
+        let path = blob::blob_path(root_path, file_number);
+        let size = if storage.exists(&path).map_err(io_error)? {
+            report.num_blob_files_checked += 1;
+            Some(storage.file_size(&path).map_err(io_error)?)
+        } else {
+            report.problems.push(IntegrityProblem::MissingFile(path));
+            None
+        };
+        blob_file_sizes.insert(file_number, size);
+    }
+
+    for (level, file_numbers) in manifest.levels.iter().enumerate() {
+        let mut previous: Option<(PathBuf, Key)> = None;
+        for &file_number in file_numbers {
+            let path = manifest::sstable_path(root_path, file_number);
+            if !storage.exists(&path).map_err(io_error)? {
+                report.problems.push(IntegrityProblem::MissingFile(path));
+                continue;
+            }
+            report.num_sstables_checked += 1;
+            let mut sstable = match SSTableReader::from_path(storage, &path)
+                .and_then(|mut sstable| sstable.verify().map(|()| sstable))
+            {
+                Ok(sstable) => sstable,
+                Err(err) => {
+                    report.problems.push(IntegrityProblem::DamagedSSTable {
+                        path,
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            if level > 0 {
+                if let Some((smallest, largest)) = sstable.key_range() {
+                    if let Some((previous_path, previous_largest)) = &previous {
+                        if previous_largest.as_str() >= smallest {
+                            report.problems.push(IntegrityProblem::OverlappingSSTables {
+                                level,
+                                first: previous_path.clone(),
+                                second: path.clone(),
+                            });
+                        }
+                    }
+                    previous = Some((path.clone(), largest.to_string()));
+                }
+            }
+
+            for entry in sstable.iter() {
+                let (key, value) = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        report.problems.push(IntegrityProblem::DamagedSSTable {
+                            path: path.clone(),
+                            error: err.to_string(),
+                        });
+                        break;
+                    }
+                };
+                let EntryValue::Blob(blob_ref) = value else {
+                    continue;
+                };
+                let is_dangling = match blob_file_sizes.get(&blob_ref.file_number) {
+                    None => true,
+                    Some(Some(size)) => blob_ref.offset.saturating_add(blob_ref.size) > *size,
+                    // Already reported as missing.
+                    Some(None) => false,
+                };
+                if is_dangling {
+                    report.problems.push(IntegrityProblem::DanglingBlobRef {
+                        sstable: path.clone(),
+                        key,
+                    });
+                    break;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        db::{DBConfig, RawDB},
+        storage::FileStorage,
+    };
+    use tempdir::TempDir;
+
+    #[test]
+    fn verify_integrity() -> anyhow::Result<()> {
+        let tempdir = TempDir::new("lsmdb_test")?;
+        let config = || DBConfig {
+            min_blob_size: 16,
+            ..DBConfig::default()
+        };
+        let mut db = RawDB::open_with_config(tempdir.path(), config())?;
+        for i in 0..300 {
+            db.put(format!("/a/{:03}", i), vec![b'a'; 8])?;
+        }
+        db.put("/b", "a value stored in a blob file")?;
+        db.compact()?;
+        db.put("/a/100", "1")?;
+        db.flush()?;
+        db.put("/a/050", "2")?;
+        db.put("/c", "3")?;
+        db.flush()?;
+        let report = db.verify_integrity()?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.num_sstables_checked, 3);
+        assert_eq!(report.num_blob_files_checked, 1);
+        std::mem::drop(db);
+
+        // Move level 0 into level 1, where its sstables overlap the compacted one,
+        // damage the compacted sstable's first block and lose the blob file.
+        let mut manifest =
+            Manifest::read_from(&FileStorage, tempdir.path())?.expect("database has a MANIFEST");
+        let level0 = std::mem::take(&mut manifest.levels[0]);
+        let compacted = manifest.levels[1][0];
+        manifest.levels[1].extend(level0.iter().copied());
+        manifest.write_to(&FileStorage, tempdir.path())?;
+        let compacted_path = manifest::sstable_path(tempdir.path(), compacted);
+        let mut bytes = std::fs::read(&compacted_path)?;
+        bytes[..8].fill(0xff);
+        std::fs::write(&compacted_path, bytes)?;
+        let blob_path = blob::blob_path(tempdir.path(), manifest.blob_files[0]);
+        std::fs::remove_file(&blob_path)?;
+
+        // Opening the database would already fail on the damaged sstable.
+        let report = verify_database(&FileStorage, tempdir.path(), &manifest)?;
+        assert_eq!(report.num_blob_files_checked, 0);
+        assert_eq!(report.problems.len(), 3, "{:?}", report);
+        assert_eq!(report.problems[0], IntegrityProblem::MissingFile(blob_path));
+        assert!(matches!(
+            &report.problems[1],
+            IntegrityProblem::DamagedSSTable { path, .. } if *path == compacted_path
+        ));
+        // The damaged sstable was skipped, but the level 0 sstables overlap each
+        // other: "/a/100" falls inside ["/a/050", "/c"].
+        assert_eq!(
+            report.problems[2],
+            IntegrityProblem::OverlappingSSTables {
+                level: 1,
+                first: manifest::sstable_path(tempdir.path(), level0[0]),
+                second: manifest::sstable_path(tempdir.path(), level0[1]),
+            }
+        );
+        Ok(())
+    }
+}
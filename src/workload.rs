@@ -0,0 +1,432 @@
+// Benchmarks and YCSB-like workloads, for measuring how changes to the memtables,
+// sstables and compactions affect performance.
+//
+// They're shared by the `bench` binary and `cargo bench`, so both measure the
+// same operations.  Keys and values come from the seeded generators (see
+// generators.rs), so runs with the same options are comparable.
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    db::{DBError, RawDB, ReadOptions},
+    generators::{KeyDistribution, KeyGenerator, Random, ValueGenerator},
+};
+
+// Every key the benchmarks and workloads write starts with this.
+const KEY_PREFIX: &str = "/key/";
+
+#[derive(Clone, Debug)]
+pub struct BenchmarkOptions {
+    // The number of keys in the key space, and of operations each benchmark runs.
+    pub num: u64,
+    pub value_size: usize,
+    // See ValueGenerator.
+    pub compression_ratio: f64,
+    // How readrandom and overwrite pick keys.
+    pub distribution: KeyDistribution,
+    pub seed: u64,
+    // The number of keys each seekscan operation reads.
+    pub scan_length: usize,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        BenchmarkOptions {
+            num: 100_000,
+            value_size: 100,
+            compression_ratio: 0.5,
+            distribution: KeyDistribution::Uniform,
+            seed: 0,
+            scan_length: 100,
+        }
+    }
+}
+
+// The benchmarks, named after db_bench's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Benchmark {
+    // Writes the keys in order.
+    FillSeq,
+    // Writes the keys in a random order, with repeats.
+    FillRandom,
+    // Rewrites random keys; run after a fill.
+    Overwrite,
+    // Reads random keys; run after a fill.
+    ReadRandom,
+    // Iterates over every key.
+    ReadSeq,
+    // Seeks to random keys, and reads the next `scan_length` keys.
+    SeekScan,
+}
+
+impl Benchmark {
+    pub const ALL: [Benchmark; 6] = [
+        Benchmark::FillSeq,
+        Benchmark::FillRandom,
+        Benchmark::Overwrite,
+        Benchmark::ReadRandom,
+        Benchmark::ReadSeq,
+        Benchmark::SeekScan,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Benchmark::FillSeq => "fillseq",
+            Benchmark::FillRandom => "fillrandom",
+            Benchmark::Overwrite => "overwrite",
+            Benchmark::ReadRandom => "readrandom",
+            Benchmark::ReadSeq => "readseq",
+            Benchmark::SeekScan => "seekscan",
+        }
+    }
+
+    pub fn run(&self, db: &mut RawDB, options: &BenchmarkOptions) -> Result<RunStats, DBError> {
+        let distribution = match self {
+            Benchmark::FillSeq => KeyDistribution::Sequential,
+            Benchmark::FillRandom | Benchmark::SeekScan => KeyDistribution::Uniform,
+            _ => options.distribution,
+        };
+        // Each benchmark picks different keys from the others with the same seed.
+        let seed = options.seed.wrapping_add(*self as u64);
+        let mut keys = KeyGenerator::new(options.num, distribution, seed)?;
+        let mut values = ValueGenerator::new(options.value_size, options.compression_ratio, seed);
+        let mut stats = RunStats::default();
+        let start = Instant::now();
+        match self {
+            Benchmark::FillSeq | Benchmark::FillRandom | Benchmark::Overwrite => {
+                for _ in 0..options.num {
+                    db.put(keys.next_key(), values.next_value())?;
+                    stats.writes += 1;
+                }
+            }
+            Benchmark::ReadRandom => {
+                for _ in 0..options.num {
+                    stats.record_read(db.get(&keys.next_key())?.is_some());
+                }
+            }
+            Benchmark::ReadSeq => {
+                for entry in db.iter_pinned(KEY_PREFIX) {
+                    entry?;
+                    stats.record_read(true);
+                }
+            }
+            Benchmark::SeekScan => {
+                for _ in 0..options.num {
+                    stats.record_scan(scan(db, keys.next_index(), options.scan_length)?);
+                }
+            }
+        }
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+}
+
+impl FromStr for Benchmark {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Benchmark::ALL
+            .into_iter()
+            .find(|benchmark| benchmark.name() == name)
+            .ok_or_else(|| format!("unknown benchmark {}", name))
+    }
+}
+
+// Reads up to `length` keys from the key at `start_index` on, and returns how many
+// there were.
+fn scan(db: &mut RawDB, start_index: u64, length: usize) -> Result<usize, DBError> {
+    let options = ReadOptions {
+        limit: Some(length),
+        start_after: start_index.checked_sub(1).map(KeyGenerator::key),
+        ..ReadOptions::default()
+    };
+    Ok(db.scan_prefix(KEY_PREFIX, &options)?.len())
+}
+
+// What a benchmark or workload did, and how long it took.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunStats {
+    pub reads: u64,
+    // The reads which found their key.
+    pub reads_found: u64,
+    pub writes: u64,
+    pub read_modify_writes: u64,
+    pub scans: u64,
+    // The number of keys the scans read.
+    pub keys_scanned: u64,
+    pub elapsed: Duration,
+}
+
+impl RunStats {
+    pub fn num_ops(&self) -> u64 {
+        self.reads + self.writes + self.read_modify_writes + self.scans
+    }
+
+    pub fn ops_per_second(&self) -> f64 {
+        self.num_ops() as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    fn record_read(&mut self, found: bool) {
+        self.reads += 1;
+        self.reads_found += found as u64;
+    }
+
+    fn record_scan(&mut self, num_keys: usize) {
+        self.scans += 1;
+        self.keys_scanned += num_keys as u64;
+    }
+}
+
+// A mix of operations, like YCSB's core workloads.  The proportions are relative
+// to their sum.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Workload {
+    pub read_proportion: f64,
+    pub update_proportion: f64,
+    // Inserts write keys past the ones loaded, which later operations may pick.
+    pub insert_proportion: f64,
+    // Reads a key, then writes it.
+    pub read_modify_write_proportion: f64,
+    pub scan_proportion: f64,
+    // Scans read between 1 and this many keys.
+    pub max_scan_length: usize,
+    // How reads, updates and scans pick keys.
+    pub distribution: KeyDistribution,
+}
+
+const YCSB_ZIPFIAN: KeyDistribution = KeyDistribution::Zipfian { theta: 0.99 };
+
+impl Workload {
+    fn mix(read: f64, update: f64, insert: f64, read_modify_write: f64, scan: f64) -> Self {
+        Workload {
+            read_proportion: read,
+            update_proportion: update,
+            insert_proportion: insert,
+            read_modify_write_proportion: read_modify_write,
+            scan_proportion: scan,
+            max_scan_length: 100,
+            distribution: YCSB_ZIPFIAN,
+        }
+    }
+
+    // Update heavy: 50% reads, 50% updates.
+    pub fn ycsb_a() -> Self {
+        Self::mix(0.5, 0.5, 0.0, 0.0, 0.0)
+    }
+
+    // Read mostly: 95% reads, 5% updates.
+    pub fn ycsb_b() -> Self {
+        Self::mix(0.95, 0.05, 0.0, 0.0, 0.0)
+    }
+
+    // Read only.
+    pub fn ycsb_c() -> Self {
+        Self::mix(1.0, 0.0, 0.0, 0.0, 0.0)
+    }
+
+    // Read latest: 95% reads, 5% inserts.  YCSB favours the latest keys here; this
+    // picks keys with a zipfian distribution like the other workloads.
+    pub fn ycsb_d() -> Self {
+        Self::mix(0.95, 0.0, 0.05, 0.0, 0.0)
+    }
+
+    // Short ranges: 95% scans, 5% inserts.
+    pub fn ycsb_e() -> Self {
+        Self::mix(0.0, 0.0, 0.05, 0.0, 0.95)
+    }
+
+    // Read-modify-write: 50% reads, 50% read-modify-writes.
+    pub fn ycsb_f() -> Self {
+        Self::mix(0.5, 0.0, 0.0, 0.5, 0.0)
+    }
+
+    // Returns the workload named "a" to "f".
+    pub fn ycsb(name: &str) -> Option<Self> {
+        match name {
+            "a" => Some(Self::ycsb_a()),
+            "b" => Some(Self::ycsb_b()),
+            "c" => Some(Self::ycsb_c()),
+            "d" => Some(Self::ycsb_d()),
+            "e" => Some(Self::ycsb_e()),
+            "f" => Some(Self::ycsb_f()),
+            _ => None,
+        }
+    }
+}
+
+enum Operation {
+    Read,
+    Update,
+    Insert,
+    ReadModifyWrite,
+    Scan,
+}
+
+// Runs a Workload against a database: `load()` writes the initial keys, then
+// `run()` runs the mix of operations on them.
+pub struct WorkloadDriver {
+    workload: Workload,
+    // The number of keys loaded; reads, updates and scans pick among these.
+    num_loaded: u64,
+    // The index of the next key to insert.
+    next_insert: u64,
+    keys: KeyGenerator,
+    values: ValueGenerator,
+    value_size: usize,
+    random: Random,
+}
+
+impl WorkloadDriver {
+    // `options.num` is the number of keys to load; `options.distribution` is
+    // ignored in favour of the workload's.
+    pub fn new(workload: Workload, options: &BenchmarkOptions) -> Result<Self, DBError> {
+        Ok(WorkloadDriver {
+            num_loaded: options.num,
+            next_insert: options.num,
+            keys: KeyGenerator::new(options.num, workload.distribution, options.seed)?,
+            values: ValueGenerator::new(
+                options.value_size,
+                options.compression_ratio,
+                options.seed,
+            ),
+            value_size: options.value_size,
+            random: Random::new(options.seed.wrapping_add(1)),
+            workload,
+        })
+    }
+
+    // Writes the keys the operations pick from, in order.
+    pub fn load(&mut self, db: &mut RawDB) -> Result<RunStats, DBError> {
+        let mut stats = RunStats::default();
+        let start = Instant::now();
+        for index in 0..self.num_loaded {
+            db.put(KeyGenerator::key(index), self.values.next_value())?;
+            stats.writes += 1;
+        }
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+
+    // Runs `num_ops` operations, picked at random in the workload's proportions.
+    pub fn run(&mut self, db: &mut RawDB, num_ops: u64) -> Result<RunStats, DBError> {
+        let mut stats = RunStats::default();
+        let start = Instant::now();
+        for _ in 0..num_ops {
+            match self.next_operation() {
+                Operation::Read => stats.record_read(db.get(&self.keys.next_key())?.is_some()),
+                Operation::Update => {
+                    db.put(self.keys.next_key(), self.values.next_value())?;
+                    stats.writes += 1;
+                }
+                Operation::Insert => {
+                    db.put(
+                        KeyGenerator::key(self.next_insert),
+                        self.values.next_value(),
+                    )?;
+                    self.next_insert += 1;
+                    stats.writes += 1;
+                }
+                Operation::ReadModifyWrite => {
+                    let key = self.keys.next_key();
+                    let mut value = db.get(&key)?.unwrap_or_default();
+                    value.truncate(value.len() / 2);
+                    value.extend(self.values.next_value());
+                    value.truncate(self.value_size);
+                    db.put(key, value)?;
+                    stats.read_modify_writes += 1;
+                }
+                Operation::Scan => {
+                    let length = 1 + self.random.below(self.workload.max_scan_length as u64);
+                    stats.record_scan(scan(db, self.keys.next_index(), length as usize)?);
+                }
+            }
+        }
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+
+    fn next_operation(&mut self) -> Operation {
+        let workload = &self.workload;
+        let proportions = [
+            (workload.read_proportion, Operation::Read),
+            (workload.update_proportion, Operation::Update),
+            (workload.insert_proportion, Operation::Insert),
+            (
+                workload.read_modify_write_proportion,
+                Operation::ReadModifyWrite,
+            ),
+            (workload.scan_proportion, Operation::Scan),
+        ];
+        let total: f64 = proportions.iter().map(|(proportion, _)| proportion).sum();
+        let mut pick = self.random.next_f64() * total;
+        for (proportion, operation) in proportions {
+            if pick < proportion {
+                return operation;
+            }
+            pick -= proportion;
+        }
+        Operation::Read
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options() -> BenchmarkOptions {
+        BenchmarkOptions {
+            num: 500,
+            value_size: 64,
+            scan_length: 10,
+            ..BenchmarkOptions::default()
+        }
+    }
+
+    #[test]
+    fn benchmarks() -> anyhow::Result<()> {
+        let mut db = RawDB::open_in_memory()?;
+        let mut run = |name: &str| name.parse::<Benchmark>().unwrap().run(&mut db, &options());
+        assert_eq!(run("fillseq")?.writes, 500);
+        assert_eq!(run("readseq")?.reads, 500);
+        let stats = run("readrandom")?;
+        assert_eq!((stats.reads, stats.reads_found), (500, 500));
+        let stats = run("seekscan")?;
+        assert_eq!(stats.scans, 500);
+        assert!(stats.keys_scanned > 500 * 9);
+        assert!("fillsequential".parse::<Benchmark>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn workload_driver() -> anyhow::Result<()> {
+        let run = |workload: Workload| -> anyhow::Result<(RunStats, u64)> {
+            let mut db = RawDB::open_in_memory()?;
+            let mut driver = WorkloadDriver::new(workload, &options())?;
+            assert_eq!(driver.load(&mut db)?.writes, 500);
+            let stats = driver.run(&mut db, 1000)?;
+            assert_eq!(stats.num_ops(), 1000);
+            Ok((stats, db.estimate_num_keys()))
+        };
+
+        let (stats, _) = run(Workload::ycsb_a())?;
+        assert_eq!(stats.reads, stats.reads_found);
+        assert!(stats.reads > 400 && stats.writes > 400);
+
+        // Inserts add keys, which scans and reads may then find.
+        let (stats, num_keys) = run(Workload::ycsb_e())?;
+        assert!(stats.scans > 900 && stats.writes > 20);
+        assert_eq!(num_keys, 500 + stats.writes);
+
+        let (stats, _) = run(Workload::ycsb_f())?;
+        assert!(stats.read_modify_writes > 400);
+
+        // The same seed runs the same operations.
+        assert_eq!(
+            run(Workload::ycsb_b())?.0.writes,
+            run(Workload::ycsb_b())?.0.writes
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::{cmp::Ordering, collections::BTreeMap, iter::Peekable};
+
+use crate::{
+    db::{DBError, Key, Value},
+    kv::{KvIterator, KvRead},
+};
+
+// Writes which are collected before being applied together with
+// `RawDB::write()`, and which can be read in the meantime: on their own with
+// `get()`, or overlaid on the database (or a Snapshot, or any KvRead) with
+// `get_from()` and `iter_merged()`, e.g. for a transaction to read its own writes
+// before it commits.
+//
+// Only the last write of each key is kept.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteBatchWithIndex {
+    // None for a deletion.
+    entries: BTreeMap<Key, Option<Value>>,
+}
+
+impl WriteBatchWithIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: impl Into<Key>, value: impl Into<Value>) {
+        self.entries.insert(key.into(), Some(value.into()));
+    }
+
+    pub fn delete(&mut self, key: impl Into<Key>) {
+        self.entries.insert(key.into(), None);
+    }
+
+    // Returns None if the batch doesn't write `key`, and Some(None) if it
+    // deletes it.
+    pub fn get(&self, key: &str) -> Option<Option<&Value>> {
+        self.entries.get(key).map(Option::as_ref)
+    }
+
+    // Reads `key` from `db`, as it will be once the batch is written.
+    pub fn get_from(&self, db: &impl KvRead, key: &str) -> Result<Option<Value>, DBError> {
+        match self.get(key) {
+            Some(value) => Ok(value.cloned()),
+            None => db.get(key),
+        }
+    }
+
+    // Iterates over the keys starting with `key_prefix` and their values in `db`,
+    // as they will be once the batch is written.
+    pub fn iter_merged<'a>(&'a self, db: &'a impl KvRead, key_prefix: &str) -> KvIterator<'a> {
+        let prefix = key_prefix.to_string();
+        Box::new(MergedIterator {
+            db: db.seek(key_prefix).peekable(),
+            batch: self
+                .entries
+                .range(prefix.clone()..)
+                .take_while(move |(key, _)| key.starts_with(prefix.as_str()))
+                .peekable(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn into_entries(self) -> impl Iterator<Item = (Key, Option<Value>)> {
+        self.entries.into_iter()
+    }
+}
+
+struct MergedIterator<'a, B: Iterator<Item = (&'a Key, &'a Option<Value>)>> {
+    db: Peekable<KvIterator<'a>>,
+    batch: Peekable<B>,
+}
+
+impl<'a, B: Iterator<Item = (&'a Key, &'a Option<Value>)>> Iterator for MergedIterator<'a, B> {
+    type Item = Result<(Key, Value), DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // The batch's entry wins over the database's for the same key.
+            let order = match (self.db.peek(), self.batch.peek()) {
+                (None, None) => return None,
+                (Some(Err(_)), _) | (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(Ok((db_key, _))), Some((batch_key, _))) => db_key.cmp(batch_key),
+            };
+            if order == Ordering::Less {
+                return self.db.next();
+            }
+            if order == Ordering::Equal {
+                self.db.next();
+            }
+            let (key, value) = self.batch.next()?;
+            if let Some(value) = value {
+                return Some(Ok((key.clone(), value.clone())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::DB;
+
+    #[test]
+    fn write_batch_with_index() -> anyhow::Result<()> {
+        let db = DB::open_in_memory()?;
+        db.put("/a", "db")?;
+        db.put("/b", "db")?;
+        db.flush()?;
+        db.put("/d", "db")?;
+
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put("/b", "batch");
+        batch.put("/c", "batch");
+        batch.delete("/d");
+        batch.put("/e", "batch");
+        batch.put("/other", "batch");
+        assert_eq!(batch.get("/b"), Some(Some(&b"batch".to_vec())));
+        assert_eq!(batch.get("/d"), Some(None));
+        assert_eq!(batch.get("/a"), None);
+        assert_eq!(batch.get_from(&db, "/a")?, Some(b"db".to_vec()));
+        assert_eq!(batch.get_from(&db, "/b")?, Some(b"batch".to_vec()));
+        assert_eq!(batch.get_from(&db, "/d")?, None);
+
+        let snapshot = db.snapshot();
+        let merged: Vec<(Key, Value)> = batch
+            .iter_merged(&snapshot, "/")
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            merged,
+            vec![
+                ("/a".to_string(), b"db".to_vec()),
+                ("/b".to_string(), b"batch".to_vec()),
+                ("/c".to_string(), b"batch".to_vec()),
+                ("/e".to_string(), b"batch".to_vec()),
+                ("/other".to_string(), b"batch".to_vec()),
+            ]
+        );
+
+        // Nothing's written until the batch is.
+        assert_eq!(db.get("/c")?, None);
+        db.write(batch)?;
+        let written: Vec<(Key, Value)> = KvRead::seek(&db, "/").collect::<Result<_, _>>()?;
+        assert_eq!(written, merged);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_batch_writes_nothing() -> anyhow::Result<()> {
+        let db = DB::open_in_memory()?;
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put("/a", "batch");
+        batch.put("\0index/reserved", "batch");
+        assert!(matches!(db.write(batch), Err(DBError::InvalidKey(_))));
+        assert_eq!(db.get("/a")?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// Caps the memory taken up by the memtables of several databases, e.g. dozens of
+// small databases in one process.  Share it between them through
+// DBConfig::write_buffer_manager.
+//
+// Memtable sizes count keys, values, range tombstones and their bookkeeping, like
+// DBConfig::memtable_max_size_bytes.  Once the total goes over `buffer_size`, the
+// database with the largest memtables is asked to flush them.  Databases can't
+// flush each other's memtables, so it flushes on its next write; other databases
+// don't flush in the meantime, since that would barely free any memory.  An idle
+// database keeps its memtables until it's written to or flushed, so the total may
+// stay over `buffer_size` until then.
+pub struct WriteBufferManager {
+    buffer_size: usize,
+    next_id: AtomicU64,
+    // Memtable size of each database, and whether it was asked to flush.
+    usage: Mutex<HashMap<u64, (usize, bool)>>,
+}
+
+impl WriteBufferManager {
+    pub fn new(buffer_size: usize) -> Self {
+        WriteBufferManager {
+            buffer_size,
+            next_id: AtomicU64::new(0),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    // Total size of the memtables of every open database.
+    pub fn memory_usage(&self) -> usize {
+        self.usage
+            .lock()
+            .expect("write buffer manager lock is poisoned")
+            .values()
+            .map(|(size, _)| size)
+            .sum()
+    }
+}
+
+// A database's entry in a WriteBufferManager, removed when it's dropped.
+pub(crate) struct WriteBufferHandle {
+    manager: Arc<WriteBufferManager>,
+    id: u64,
+}
+
+impl WriteBufferHandle {
+    pub fn new(manager: Arc<WriteBufferManager>) -> Self {
+        let id = manager.next_id.fetch_add(1, Ordering::Relaxed);
+        manager
+            .usage
+            .lock()
+            .expect("write buffer manager lock is poisoned")
+            .insert(id, (0, false));
+        WriteBufferHandle { manager, id }
+    }
+
+    // Records the size of the database's memtables, and returns whether it should
+    // flush them.
+    pub fn set_memory_usage(&self, size: usize) -> bool {
+        let mut usage = self
+            .manager
+            .usage
+            .lock()
+            .expect("write buffer manager lock is poisoned");
+        let flush_requested = match usage.get_mut(&self.id) {
+            Some(entry) => std::mem::replace(entry, (size, false)).1,
+            None => false,
+        };
+        if flush_requested {
+            return true;
+        }
+        if usage.values().map(|(size, _)| size).sum::<usize>() <= self.manager.buffer_size {
+            return false;
+        }
+        let Some((&largest_id, &(_, already_requested))) =
+            usage.iter().max_by_key(|(id, (size, _))| (*size, **id))
+        else {
+            return false;
+        };
+        if largest_id == self.id {
+            return size > 0;
+        }
+        if already_requested {
+            return false;
+        }
+        if let Some(largest) = usage.get_mut(&largest_id) {
+            largest.1 = true;
+        }
+        false
+    }
+}
+
+impl Drop for WriteBufferHandle {
+    fn drop(&mut self) {
+        if let Ok(mut usage) = self.manager.usage.lock() {
+            usage.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_buffer_manager() {
+        let manager = Arc::new(WriteBufferManager::new(100));
+        let a = WriteBufferHandle::new(manager.clone());
+        let b = WriteBufferHandle::new(manager.clone());
+        assert!(!a.set_memory_usage(60));
+        assert!(!b.set_memory_usage(30));
+        assert_eq!(manager.memory_usage(), 90);
+
+        // Over budget: `a` has the largest memtable, so it's asked to flush.
+        assert!(!b.set_memory_usage(50));
+        assert!(a.set_memory_usage(60));
+        assert!(!a.set_memory_usage(0));
+
+        // `b` has the largest memtable, and flushes itself.
+        assert!(b.set_memory_usage(110));
+        assert!(!b.set_memory_usage(0));
+
+        // `b` was asked to flush but hasn't written since; `a` doesn't flush in its
+        // place until it has the largest memtables.
+        assert!(!b.set_memory_usage(60));
+        assert!(!a.set_memory_usage(45));
+        assert!(!a.set_memory_usage(46));
+        assert!(a.set_memory_usage(61));
+        assert!(!a.set_memory_usage(0));
+
+        std::mem::drop(b);
+        assert_eq!(manager.memory_usage(), 0);
+    }
+}
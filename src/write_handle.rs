@@ -0,0 +1,241 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::db::DBError;
+
+// Write acknowledgement levels
+// ============================
+//
+// `put_async()` returns as soon as a write is in the active memtable, with a
+// WriteHandle which tells when the write becomes durable.  There's no log yet,
+// so that's once its memtable is flushed to a synced sstable: when the memtable
+// fills up, or on `flush()`.  Writers can pipeline many writes, and only wait for
+// the last one of a batch, which is synced no earlier than those before it.
+
+// How far a write has made it.  Without a log, there's no level for a write
+// which is in the log but not yet in a synced sstable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AckLevel {
+    // Readable, but lost if the process exits before its memtable is flushed.
+    InMemtable,
+    // In a synced sstable, so it survives a crash.
+    Synced,
+}
+
+// Counts the memtables flushed so far, and wakes whoever waits for them.  Each
+// memtable gets the number of memtables frozen before it, so a write in memtable
+// `n` is synced once more than `n` memtables were flushed.
+pub(crate) struct FlushProgress {
+    state: Mutex<FlushState>,
+    flushed: Condvar,
+}
+
+struct FlushState {
+    num_flushed: u64,
+    // The database was closed; memtables which weren't flushed never will be.
+    closed: bool,
+    wakers: Vec<Waker>,
+}
+
+impl FlushProgress {
+    fn update(&self, f: impl FnOnce(&mut FlushState)) {
+        let mut state = self.state.lock().expect("flush progress lock is poisoned");
+        f(&mut state);
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+        self.flushed.notify_all();
+    }
+}
+
+// Owned by the database, which reports its flushes through it; closes the
+// progress when dropped, so handles stop waiting.
+pub(crate) struct FlushNotifier(Arc<FlushProgress>);
+
+impl FlushNotifier {
+    pub fn new() -> Self {
+        FlushNotifier(Arc::new(FlushProgress {
+            state: Mutex::new(FlushState {
+                num_flushed: 0,
+                closed: false,
+                wakers: Vec::new(),
+            }),
+            flushed: Condvar::new(),
+        }))
+    }
+
+    // Every memtable up to `num_flushed` (exclusive) was flushed.
+    pub fn memtables_flushed(&self, num_flushed: u64) {
+        self.0
+            .update(|state| state.num_flushed = state.num_flushed.max(num_flushed));
+    }
+
+    // Returns a handle for a write to memtable `memtable_number`.
+    pub fn handle(&self, memtable_number: u64) -> WriteHandle {
+        WriteHandle {
+            progress: self.0.clone(),
+            memtable_number,
+        }
+    }
+}
+
+impl Drop for FlushNotifier {
+    fn drop(&mut self) {
+        self.0.update(|state| state.closed = true);
+    }
+}
+
+// Tells how far a write made it; see `put_async()`.  Handles don't keep the
+// database open.
+#[derive(Clone)]
+pub struct WriteHandle {
+    progress: Arc<FlushProgress>,
+    memtable_number: u64,
+}
+
+impl WriteHandle {
+    pub fn level(&self) -> AckLevel {
+        let state = self.state();
+        self.level_in(&state)
+    }
+
+    // Blocks until the write is synced.  Fails if the database is closed first,
+    // in which case the write was lost.
+    pub fn wait(&self) -> Result<(), DBError> {
+        let mut state = self.state();
+        loop {
+            if let Some(result) = self.result_in(&state) {
+                return result;
+            }
+            state = self
+                .progress
+                .flushed
+                .wait(state)
+                .expect("flush progress lock is poisoned");
+        }
+    }
+
+    // Like `wait()`, but gives up after `timeout`, returning the level reached.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<AckLevel, DBError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state();
+        loop {
+            if let Some(result) = self.result_in(&state) {
+                return result.map(|()| AckLevel::Synced);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(AckLevel::InMemtable);
+            }
+            state = self
+                .progress
+                .flushed
+                .wait_timeout(state, deadline - now)
+                .expect("flush progress lock is poisoned")
+                .0;
+        }
+    }
+
+    // Like `wait()`, as a future.
+    pub fn synced(&self) -> impl Future<Output = Result<(), DBError>> {
+        Synced(self.clone())
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FlushState> {
+        self.progress
+            .state
+            .lock()
+            .expect("flush progress lock is poisoned")
+    }
+
+    fn level_in(&self, state: &FlushState) -> AckLevel {
+        if state.num_flushed > self.memtable_number {
+            AckLevel::Synced
+        } else {
+            AckLevel::InMemtable
+        }
+    }
+
+    // Returns the outcome of the wait, or None if it's not over yet.
+    fn result_in(&self, state: &FlushState) -> Option<Result<(), DBError>> {
+        if self.level_in(state) == AckLevel::Synced {
+            Some(Ok(()))
+        } else if state.closed {
+            Some(Err(DBError::Io(
+                "the database was closed before the write was synced".to_string(),
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Debug for WriteHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WriteHandle")
+            .field("level", &self.level())
+            .finish()
+    }
+}
+
+struct Synced(WriteHandle);
+
+impl Future for Synced {
+    type Output = Result<(), DBError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.state();
+        match self.0.result_in(&state) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{DBConfig, DB};
+
+    #[test]
+    fn write_handles() -> anyhow::Result<()> {
+        let db = DB::open_in_memory_with_config(DBConfig {
+            memtable_max_size_bytes: 1024,
+            max_frozen_memtables: 0,
+            ..DBConfig::default()
+        })?;
+        let first = db.put_async("/first", "value")?;
+        let last = db.put_async("/last", "value")?;
+        assert_eq!(first.level(), AckLevel::InMemtable);
+        assert_eq!(
+            last.wait_timeout(Duration::from_millis(1))?,
+            AckLevel::InMemtable
+        );
+        assert_eq!(db.get("/last")?, Some(b"value".to_vec()));
+
+        // A flush on another thread syncs both writes.
+        std::thread::scope(|scope| {
+            scope.spawn(|| db.flush());
+            last.wait()
+        })?;
+        assert_eq!(first.level(), AckLevel::Synced);
+
+        // Filling up the memtable syncs the writes in it.
+        let handle = db.put_async("/key", vec![0u8; 2048])?;
+        assert_eq!(handle.level(), AckLevel::Synced);
+
+        // Writes which were never flushed are lost with the database.
+        let handle = db.put_async("/unsynced", "value")?;
+        std::mem::drop(db);
+        assert!(handle.wait().is_err());
+        Ok(())
+    }
+}
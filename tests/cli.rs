@@ -0,0 +1,29 @@
+// Runs the lsmdb-cli binary against a database on disk.
+
+use std::process::{Command, Output};
+
+use tempdir::TempDir;
+
+fn lsmdb_cli(args: &[&str]) -> anyhow::Result<Output> {
+    Ok(Command::new(env!("CARGO_BIN_EXE_lsmdb-cli"))
+        .args(args)
+        .output()?)
+}
+
+#[test]
+fn cli_needs_existing_database() -> anyhow::Result<()> {
+    let tempdir = TempDir::new("lsmdb_test")?;
+    let db_path = tempdir.path().to_str().unwrap();
+    assert!(lsmdb_cli(&[db_path, "put", "/a", "1"])?.status.success());
+    let output = lsmdb_cli(&[db_path, "get", "/a"])?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"1\n");
+
+    // A mistyped path is an error, not a new, empty database.
+    let missing_path = tempdir.path().join("missing");
+    let output = lsmdb_cli(&[missing_path.to_str().unwrap(), "get", "/a"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("not a database directory"));
+    assert!(!missing_path.exists());
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// Checks that failed flushes, compactions and write stall relief leave the
+// database as it was, by failing its writes through FaultInjectionStorage.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use lsmdb::{DBConfig, DBError, FaultInjectionStorage, MemStorage, RawDB};
+
+fn open(storage: &Arc<FaultInjectionStorage>, config: DBConfig) -> Result<RawDB, DBError> {
+    RawDB::open_with_config(
+        Path::new("/db"),
+        DBConfig {
+            storage: storage.clone(),
+            ..config
+        },
+    )
+}
+
+fn check_values(db: &mut RawDB, num_keys: usize) -> Result<(), DBError> {
+    for i in 0..num_keys {
+        assert_eq!(
+            db.get(&format!("/key/{:02}", i))?,
+            Some(format!("{}", i).into_bytes())
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn failed_flush() -> anyhow::Result<()> {
+    let storage = Arc::new(FaultInjectionStorage::new(Arc::new(MemStorage::new())));
+    let mut db = open(&storage, DBConfig::default())?;
+    for i in 0..10 {
+        db.put(format!("/key/{:02}", i), format!("{}", i))?;
+    }
+
+    storage.set_fail_writes(true);
+    assert!(db.flush().is_err());
+    assert_eq!(db.stats().num_flushes, 0);
+    check_values(&mut db, 10)?;
+
+    // The memtables are still there to be flushed once writes work again.
+    storage.set_fail_writes(false);
+    db.flush()?;
+    std::mem::drop(db);
+    let mut db = open(&storage, DBConfig::default())?;
+    check_values(&mut db, 10)?;
+    Ok(())
+}
+
+#[test]
+fn failed_compaction() -> anyhow::Result<()> {
+    let storage = Arc::new(FaultInjectionStorage::new(Arc::new(MemStorage::new())));
+    let config = || DBConfig {
+        level0_file_num_compaction_trigger: 100,
+        ..DBConfig::default()
+    };
+    let mut db = open(&storage, config())?;
+    for i in 0..10 {
+        db.put(format!("/key/{:02}", i), format!("{}", i))?;
+        db.flush()?;
+    }
+
+    storage.set_fail_writes(true);
+    assert!(db.compact().is_err());
+    assert_eq!(db.stats().num_sstables_per_level[0], 10);
+    check_values(&mut db, 10)?;
+
+    // Neither the MANIFEST nor the sstables were changed.
+    storage.set_fail_writes(false);
+    std::mem::drop(db);
+    let mut db = open(&storage, config())?;
+    check_values(&mut db, 10)?;
+    db.compact()?;
+    assert_eq!(db.stats().num_sstables_per_level[0], 0);
+    check_values(&mut db, 10)?;
+    Ok(())
+}
+
+#[test]
+fn failed_write_stall_relief() -> anyhow::Result<()> {
+    let storage = Arc::new(FaultInjectionStorage::new(Arc::new(MemStorage::new())));
+    let mut db = open(
+        &storage,
+        DBConfig {
+            // Every write freezes the memtable, and two frozen memtables stop writes.
+            memtable_max_size_bytes: 1,
+            max_frozen_memtables: 100,
+            frozen_memtables_stop_writes_trigger: 2,
+            ..DBConfig::default()
+        },
+    )?;
+    db.put("/key/00", "0")?;
+    db.put("/key/01", "1")?;
+
+    // The next write has to flush them first, and fails when it can't.
+    storage.set_fail_writes(true);
+    assert!(matches!(
+        db.put("/key/02", "2"),
+        Err(DBError::WriteStalled(_))
+    ));
+    check_values(&mut db, 2)?;
+    assert_eq!(db.get("/key/02")?, None);
+
+    storage.set_fail_writes(false);
+    db.put("/key/02", "2")?;
+    check_values(&mut db, 3)?;
+    Ok(())
+}
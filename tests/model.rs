@@ -0,0 +1,141 @@
+// Runs random sequences of operations against the database and against a
+// BTreeMap, which models what the database should hold, and checks that they
+// agree.
+//
+// Every run is seeded, so a failure can be reproduced from the seed in its message.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use lsmdb::{generators::Random, DBConfig, FixedLengthPrefix, RawDB, Statistics};
+use tempdir::TempDir;
+
+const NUM_KEYS: u64 = 200;
+const NUM_OPERATIONS: usize = 2_000;
+
+fn config(statistics: &Arc<Statistics>) -> DBConfig {
+    DBConfig {
+        statistics: statistics.clone(),
+        // Small memtables and sstables, so the runs go through many freezes and
+        // compactions.
+        memtable_max_size_bytes: 512,
+        target_file_size_base: 1024,
+        max_bytes_for_level_base: 4 * 1024,
+        max_bytes_for_level_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        // Only flush when the test does, so it knows which writes survive a reopen.
+        max_frozen_memtables: usize::MAX,
+        frozen_memtables_stop_writes_trigger: usize::MAX,
+        min_blob_size: 24,
+        // Groups the keys by tens ("/key/00", "/key/01", ..).
+        prefix_extractor: Some(Arc::new(FixedLengthPrefix(7))),
+        paranoid_checks: true,
+        ..DBConfig::default()
+    }
+}
+
+fn key(index: u64) -> String {
+    format!("/key/{:03}", index)
+}
+
+// Checks every key, both by getting it and by iterating over the database.
+fn check(db: &mut RawDB, model: &BTreeMap<String, Vec<u8>>, seed: u64, step: usize) {
+    let entries: BTreeMap<String, Vec<u8>> = db
+        .iter_pinned("")
+        .collect::<Result<_, _>>()
+        .expect("couldnt iterate");
+    assert_eq!(
+        &entries, model,
+        "seed {}, step {}: wrong entries",
+        seed, step
+    );
+    for index in 0..NUM_KEYS {
+        let key = key(index);
+        assert_eq!(
+            db.get(&key).expect("couldnt get"),
+            model.get(&key).cloned(),
+            "seed {}, step {}: wrong value for {}",
+            seed,
+            step,
+            key
+        );
+    }
+}
+
+// Leaves behind what a flush interrupted by a crash would: a torn sstable and
+// blob file which aren't in the MANIFEST yet, and a half written MANIFEST.tmp.
+fn simulate_crashed_flush(root_path: &Path) {
+    std::fs::write(root_path.join("1000000.sst"), [7u8; 100]).expect("couldnt write sstable");
+    std::fs::write(root_path.join("1000001.blob"), [7u8; 10]).expect("couldnt write blob");
+    std::fs::write(root_path.join("MANIFEST.tmp"), [7u8; 3]).expect("couldnt write MANIFEST");
+}
+
+fn run(seed: u64) {
+    let tempdir = TempDir::new("lsmdb_model").expect("couldnt make a temp dir");
+    let statistics = Arc::new(Statistics::new());
+    let mut db =
+        RawDB::open_with_config(tempdir.path(), config(&statistics)).expect("couldnt open");
+    let mut model = BTreeMap::new();
+    // What the model holds as of the last flush; the rest is lost by a reopen.
+    let mut durable_model = BTreeMap::new();
+    let mut random = Random::new(seed);
+
+    for step in 0..NUM_OPERATIONS {
+        let index = random.below(NUM_KEYS);
+        match random.below(100) {
+            0..=49 => {
+                let value_len = random.below(40) as usize;
+                let value = vec![random.below(256) as u8; value_len];
+                db.put(key(index), value.clone()).expect("couldnt put");
+                model.insert(key(index), value);
+            }
+            50..=69 => {
+                db.delete(key(index)).expect("couldnt delete");
+                model.remove(&key(index));
+            }
+            70..=74 => {
+                let (start, end) = (key(index), key((index + random.below(20)).min(NUM_KEYS)));
+                db.delete_range(start.clone(), end.clone())
+                    .expect("couldnt delete range");
+                model.retain(|key, _| !(start <= *key && *key < end));
+            }
+            75..=94 => {
+                assert_eq!(
+                    db.get(&key(index)).expect("couldnt get"),
+                    model.get(&key(index)).cloned(),
+                    "seed {}, step {}",
+                    seed,
+                    step
+                );
+            }
+            95..=97 => {
+                db.flush().expect("couldnt flush");
+                durable_model = model.clone();
+            }
+            _ => {
+                std::mem::drop(db);
+                if random.below(2) == 0 {
+                    simulate_crashed_flush(tempdir.path());
+                }
+                db = RawDB::open_with_config(tempdir.path(), config(&statistics))
+                    .expect("couldnt reopen");
+                model = durable_model.clone();
+                check(&mut db, &model, seed, step);
+            }
+        }
+    }
+    check(&mut db, &model, seed, NUM_OPERATIONS);
+    assert!(
+        statistics.num_compactions() > 0,
+        "seed {}: no compactions",
+        seed
+    );
+}
+
+#[test]
+fn database_matches_model() {
+    for seed in 0..8 {
+        run(seed);
+    }
+}
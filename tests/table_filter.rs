@@ -0,0 +1,31 @@
+// Skips sstables by their properties through the public API.
+
+use lsmdb::{DBConfig, RawDB, ReadOptions, TableFilter, TableProperties};
+use tempdir::TempDir;
+
+#[test]
+fn table_filter() -> anyhow::Result<()> {
+    let tempdir = TempDir::new("lsmdb_test")?;
+    let mut db = RawDB::open_with_config(tempdir.path(), DBConfig::default())?;
+    db.put("/a", "1")?;
+    db.put("/b", "2")?;
+    db.put("/c", "3")?;
+    db.flush()?;
+    db.put("/d", "4")?;
+    db.flush()?;
+    db.put("/e", "5")?;
+
+    // Only read the sstables with more than one entry; the memtables are always read.
+    let options = ReadOptions::default().with_table_filter(TableFilter::new(
+        |properties: &TableProperties| properties.num_entries > 1,
+    ));
+    assert_eq!(db.keys("/", &options)?, vec!["/a", "/b", "/c", "/e"]);
+    // The other options combine with it.
+    let options = options
+        .with_reverse(true)
+        .with_start_after("/e")
+        .with_limit(2);
+    assert_eq!(db.keys("/", &options)?, vec!["/c", "/b"]);
+    assert_eq!(db.keys("/", &ReadOptions::default())?.len(), 5);
+    Ok(())
+}